@@ -0,0 +1,395 @@
+//! Compact thesaurus representation for very large vocabularies
+//!
+//! A [`Thesaurus`] keeps every term and its normalized term as separate
+//! strings in a hashmap. A [`CompactThesaurus`] instead stores the terms in
+//! a finite state transducer (FST), which shares common prefixes and
+//! suffixes between terms, and maps each term to an index into a side table
+//! of the distinct normalized terms, so synonyms of a concept share one
+//! entry.
+//!
+//! The FST keeps the terms sorted, which makes prefix search for
+//! autocompletion a walk over a single subtree. For autocompletion despite
+//! typos, a [`TrigramIndex`] of the terms is built on first use, and for
+//! phrases typed in any word order a [`PhraseIndex`]. Neither is
+//! serialized.
+//!
+//! A compact thesaurus is handed from the server to clients, e.g. the WASM
+//! build of this crate in the browser, as bytes (see
+//! [`CompactThesaurus::to_bytes`]):
+//!
+//! | Bytes | Content                                                   |
+//! |-------|-----------------------------------------------------------|
+//! | 4     | The magic `TAC1`                                          |
+//! | 4     | Length `n` of the header, little endian                   |
+//! | n     | JSON header `{"name": ..., "concepts": [...]}`            |
+//! | rest  | The FST mapping each term to an index into `concepts`     |
+
+use std::sync::OnceLock;
+
+use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer};
+use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+use serde::{Deserialize, Serialize};
+
+use crate::phrase::PhraseIndex;
+use crate::trigram::TrigramIndex;
+use crate::{Result, TerraphimAutomataError};
+
+/// First bytes of a serialized compact thesaurus
+const MAGIC: &[u8; 4] = b"TAC1";
+
+/// What a serialized compact thesaurus stores besides the FST
+#[derive(Serialize, Deserialize)]
+struct Header<'a> {
+    #[serde(borrow)]
+    name: std::borrow::Cow<'a, str>,
+    concepts: std::borrow::Cow<'a, [NormalizedTerm]>,
+}
+
+/// An immutable thesaurus backed by an FST
+#[derive(Clone)]
+pub struct CompactThesaurus {
+    name: String,
+    /// Term -> index into `concepts`
+    terms: Map<Vec<u8>>,
+    /// The distinct normalized terms
+    concepts: Vec<NormalizedTerm>,
+    /// Term -> index into `concepts`, by trigrams
+    trigrams: OnceLock<TrigramIndex<u64>>,
+    /// Term -> index into `concepts`, by words
+    phrases: OnceLock<PhraseIndex<u64>>,
+}
+
+impl std::fmt::Debug for CompactThesaurus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompactThesaurus")
+            .field("name", &self.name)
+            .field("terms", &self.terms.len())
+            .field("concepts", &self.concepts.len())
+            .finish()
+    }
+}
+
+impl CompactThesaurus {
+    /// Build a compact thesaurus from a thesaurus
+    pub fn from_thesaurus(thesaurus: &Thesaurus) -> Result<Self> {
+        // The FST requires the terms in lexicographic order
+        let mut entries: Vec<(&NormalizedTermValue, &NormalizedTerm)> =
+            thesaurus.into_iter().collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        let mut concepts: Vec<NormalizedTerm> = Vec::new();
+        let mut concept_index = ahash::AHashMap::new();
+        let mut builder = MapBuilder::memory();
+        for (term, normalized_term) in entries {
+            let index = *concept_index
+                .entry((
+                    normalized_term.id,
+                    &normalized_term.value,
+                    normalized_term.weight,
+                ))
+                .or_insert_with(|| {
+                    concepts.push(normalized_term.clone());
+                    concepts.len() as u64 - 1
+                });
+            builder.insert(term.as_str(), index)?;
+        }
+        let terms = Map::new(builder.into_inner()?)?;
+
+        Ok(Self {
+            name: thesaurus.name().to_string(),
+            terms,
+            concepts,
+            trigrams: OnceLock::new(),
+            phrases: OnceLock::new(),
+        })
+    }
+
+    /// Get the name of the thesaurus
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of terms
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// Check if the thesaurus is empty
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Get the normalized term of a term
+    ///
+    /// The term is trimmed and lowercased first, like the keys of a
+    /// [`Thesaurus`].
+    pub fn get(&self, term: &str) -> Option<&NormalizedTerm> {
+        let term = NormalizedTermValue::from(term);
+        let index = self.terms.get(term.as_str())?;
+        self.concepts.get(index as usize)
+    }
+
+    /// All terms with their normalized terms, in lexicographic order
+    pub fn terms(&self) -> Vec<(String, &NormalizedTerm)> {
+        let mut terms = Vec::with_capacity(self.len());
+        let mut stream = self.terms.stream();
+        while let Some((term, index)) = stream.next() {
+            terms.push((
+                String::from_utf8_lossy(term).into_owned(),
+                &self.concepts[index as usize],
+            ));
+        }
+        terms
+    }
+
+    /// Terms starting with `prefix`, for autocompletion
+    ///
+    /// Returns at most `limit` terms in lexicographic order.
+    pub fn autocomplete(&self, prefix: &str, limit: usize) -> Vec<(String, &NormalizedTerm)> {
+        let prefix = NormalizedTermValue::from(prefix);
+        let automaton = fst::automaton::Str::new(prefix.as_str()).starts_with();
+        let mut stream = self.terms.search(automaton).into_stream();
+        let mut terms = Vec::new();
+        while terms.len() < limit {
+            let Some((term, index)) = stream.next() else {
+                break;
+            };
+            terms.push((
+                String::from_utf8_lossy(term).into_owned(),
+                &self.concepts[index as usize],
+            ));
+        }
+        terms
+    }
+
+    /// Terms similar to `query`, for autocompletion despite typos
+    ///
+    /// Finds terms containing most of the trigrams of `query`, see
+    /// [`TrigramIndex::search`]. Returns at most `limit` terms, the most
+    /// similar first, with their similarity between 0 and 1.
+    pub fn fuzzy_autocomplete(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Vec<(String, &NormalizedTerm, f64)> {
+        let query = NormalizedTermValue::from(query);
+        self.trigrams
+            .get_or_init(|| TrigramIndex::new(self.concept_indices()))
+            .search(query.as_str(), limit)
+            .into_iter()
+            .map(|(term, index, similarity)| {
+                (
+                    term.to_string(),
+                    &self.concepts[*index as usize],
+                    similarity,
+                )
+            })
+            .collect()
+    }
+
+    /// Terms with a word starting with each word of `query`, in any order
+    ///
+    /// Autocompletes `graph terraphim` to `terraphim graph scorer`, see
+    /// [`PhraseIndex::search`]. Returns at most `limit` terms, the shortest
+    /// first.
+    pub fn phrase_autocomplete(&self, query: &str, limit: usize) -> Vec<(String, &NormalizedTerm)> {
+        self.phrases
+            .get_or_init(|| PhraseIndex::new(self.concept_indices()))
+            .search(query, limit)
+            .into_iter()
+            .map(|(term, index)| (term.to_string(), &self.concepts[*index as usize]))
+            .collect()
+    }
+
+    /// All terms with the index of their normalized term, for the indices
+    /// built on first use
+    fn concept_indices(&self) -> Vec<(String, u64)> {
+        let mut terms = Vec::with_capacity(self.len());
+        let mut stream = self.terms.stream();
+        while let Some((term, index)) = stream.next() {
+            terms.push((String::from_utf8_lossy(term).into_owned(), index));
+        }
+        terms
+    }
+
+    /// Convert back into a regular thesaurus
+    pub fn to_thesaurus(&self) -> Thesaurus {
+        let mut thesaurus = Thesaurus::new(self.name.clone());
+        for (term, normalized_term) in self.terms() {
+            thesaurus.insert(NormalizedTermValue::from(term), normalized_term.clone());
+        }
+        thesaurus
+    }
+
+    /// Serialize the thesaurus for [`CompactThesaurus::from_bytes`]
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let header = serde_json::to_vec(&Header {
+            name: self.name.as_str().into(),
+            concepts: self.concepts.as_slice().into(),
+        })?;
+        let fst = self.terms.as_fst().as_bytes();
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + header.len() + fst.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(fst);
+        Ok(bytes)
+    }
+
+    /// Deserialize a thesaurus serialized with [`CompactThesaurus::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || TerraphimAutomataError::InvalidThesaurus("Not a compact thesaurus".into());
+        let rest = bytes.strip_prefix(MAGIC.as_slice()).ok_or_else(invalid)?;
+        if rest.len() < 4 {
+            return Err(invalid());
+        }
+        let (length, rest) = rest.split_at(4);
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        if rest.len() < length {
+            return Err(invalid());
+        }
+        let (header, fst) = rest.split_at(length);
+        let header: Header = serde_json::from_slice(header)?;
+        let terms = Map::new(fst.to_vec())?;
+        // Corrupt FSTs panic when searched
+        terms.as_fst().verify()?;
+        let concepts = header.concepts.into_owned();
+        if terms
+            .stream()
+            .into_values()
+            .iter()
+            .any(|&index| index as usize >= concepts.len())
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: header.name.into_owned(),
+            terms,
+            concepts,
+            trigrams: OnceLock::new(),
+            phrases: OnceLock::new(),
+        })
+    }
+
+    /// Approximate heap memory used by the thesaurus in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.terms.as_fst().size()
+            + self.concepts.capacity() * std::mem::size_of::<NormalizedTerm>()
+            + self
+                .concepts
+                .iter()
+                .map(|concept| concept.value.as_str().len())
+                .sum::<usize>()
+            + self.trigrams.get().map_or(0, TrigramIndex::size_in_bytes)
+            + self.phrases.get().map_or(0, PhraseIndex::size_in_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_thesaurus, AutomataPath};
+
+    #[tokio::test]
+    async fn test_compact_thesaurus() {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus).unwrap();
+
+        assert_eq!(compact.len(), thesaurus.len());
+        for (term, normalized_term) in &thesaurus {
+            assert_eq!(compact.get(term.as_str()), Some(normalized_term));
+        }
+        assert_eq!(compact.get("not a term in the thesaurus"), None);
+        assert_eq!(compact.to_thesaurus(), thesaurus);
+    }
+
+    #[tokio::test]
+    async fn test_bytes() {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus).unwrap();
+
+        let bytes = compact.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], b"TAC1");
+        let restored = CompactThesaurus::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.name(), compact.name());
+        assert_eq!(restored.to_thesaurus(), thesaurus);
+
+        assert!(CompactThesaurus::from_bytes(b"TAC1").is_err());
+        assert!(CompactThesaurus::from_bytes(&bytes[4..]).is_err());
+        assert!(CompactThesaurus::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_autocomplete() {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example())
+            .await
+            .unwrap();
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus).unwrap();
+
+        let terms: Vec<String> = compact
+            .autocomplete("BA", 10)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect();
+        assert_eq!(terms, vec!["bar", "baz"]);
+        assert_eq!(compact.autocomplete("ba", 1).len(), 1);
+        assert!(compact.autocomplete("qux", 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_autocomplete() {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus).unwrap();
+
+        // A typo in the first character defeats prefix search
+        assert!(compact.autocomplete("Vottle", 10).is_empty());
+        let found = compact.fuzzy_autocomplete("Vottle", 10);
+        assert_eq!(found[0].0, "bottleneck");
+        assert_eq!(found[0].1, compact.get("bottleneck").unwrap());
+        assert_eq!(found[0].2, 0.75);
+        assert!(compact.fuzzy_autocomplete("vo", 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_phrase_autocomplete() {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus).unwrap();
+
+        assert!(compact.autocomplete("budget proj", 10).is_empty());
+        let terms: Vec<String> = compact
+            .phrase_autocomplete("Budget proj", 10)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect();
+        assert_eq!(terms, vec!["project budget", "budget of a project"]);
+    }
+
+    #[tokio::test]
+    async fn test_find_matches_compact() {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus).unwrap();
+        let text = "I am a text with the word Organization strategic plan and bar";
+
+        let matches = crate::find_matches_compact(text, &compact, true).unwrap();
+        assert_eq!(
+            matches,
+            crate::find_matches(text, thesaurus.clone(), true).unwrap()
+        );
+        assert_eq!(
+            crate::replace_matches_compact(text, &compact).unwrap(),
+            crate::replace_matches(text, thesaurus).unwrap()
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Differences between two versions of a thesaurus
+//!
+//! Publishing a new automata artifact changes what every client matches,
+//! so [`thesaurus_diff`] lists what changed for a review first: the terms
+//! added and removed, the terms retargeted to another concept and the
+//! concepts whose ID changed.
+//!
+//! A thesaurus has no URLs of its own; links point at the concept of a term
+//! (see [`crate::markdown::concept_url`]), so the links of retargeted terms
+//! are the ones which change.
+
+use std::fmt;
+
+use ahash::{AHashMap, AHashSet};
+use serde::{Deserialize, Serialize};
+use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+use crate::markdown::concept_url;
+
+/// A term of only one of the thesauri
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TermChange {
+    pub term: NormalizedTermValue,
+    pub concept: NormalizedTerm,
+}
+
+/// A term which maps to another concept in the new thesaurus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetargetedTerm {
+    pub term: NormalizedTermValue,
+    pub old: NormalizedTerm,
+    pub new: NormalizedTerm,
+    /// The URL links to the term pointed at
+    pub old_url: String,
+    /// The URL links to the term point at now
+    pub new_url: String,
+}
+
+/// A concept with another ID in the new thesaurus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenumberedConcept {
+    pub concept: NormalizedTermValue,
+    pub old_id: u64,
+    pub new_id: u64,
+}
+
+/// What changed between two versions of a thesaurus, all sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThesaurusDiff {
+    /// Terms of the new thesaurus only
+    pub added: Vec<TermChange>,
+    /// Terms of the old thesaurus only
+    pub removed: Vec<TermChange>,
+    /// Terms of both thesauri which map to different concepts
+    pub retargeted: Vec<RetargetedTerm>,
+    /// Concepts of both thesauri with different IDs
+    pub renumbered: Vec<RenumberedConcept>,
+}
+
+impl ThesaurusDiff {
+    /// Whether the thesauri map the same terms to the same concepts
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.retargeted.is_empty()
+            && self.renumbered.is_empty()
+    }
+}
+
+/// Compare two versions of a thesaurus
+///
+/// Terms are compared by their normalized value, concepts by their
+/// normalized term, so a concept which only got another ID is renumbered
+/// rather than retargeting all of its terms.
+pub fn thesaurus_diff(old: &Thesaurus, new: &Thesaurus) -> ThesaurusDiff {
+    let mut diff = ThesaurusDiff::default();
+    for (term, old_concept) in old {
+        match new.get(term) {
+            None => diff.removed.push(TermChange {
+                term: term.clone(),
+                concept: old_concept.clone(),
+            }),
+            Some(new_concept) if new_concept.value != old_concept.value => {
+                diff.retargeted.push(RetargetedTerm {
+                    term: term.clone(),
+                    old: old_concept.clone(),
+                    new: new_concept.clone(),
+                    old_url: concept_url(old_concept.value.as_str()),
+                    new_url: concept_url(new_concept.value.as_str()),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for (term, new_concept) in new {
+        if old.get(term).is_none() {
+            diff.added.push(TermChange {
+                term: term.clone(),
+                concept: new_concept.clone(),
+            });
+        }
+    }
+
+    let concept_ids = |thesaurus: &Thesaurus| -> AHashMap<NormalizedTermValue, u64> {
+        thesaurus
+            .into_iter()
+            .map(|(_, concept)| (concept.value.clone(), concept.id))
+            .collect()
+    };
+    let new_ids = concept_ids(new);
+    let mut seen = AHashSet::new();
+    for (concept, old_id) in concept_ids(old) {
+        match new_ids.get(&concept) {
+            Some(new_id) if *new_id != old_id && seen.insert(concept.clone()) => {
+                diff.renumbered.push(RenumberedConcept {
+                    concept,
+                    old_id,
+                    new_id: *new_id,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    diff.added.sort_by(|a, b| a.term.cmp(&b.term));
+    diff.removed.sort_by(|a, b| a.term.cmp(&b.term));
+    diff.retargeted.sort_by(|a, b| a.term.cmp(&b.term));
+    diff.renumbered.sort_by(|a, b| a.concept.cmp(&b.concept));
+    diff
+}
+
+impl fmt::Display for ThesaurusDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        writeln!(f, "Added terms ({}):", self.added.len())?;
+        for change in &self.added {
+            writeln!(f, "  + {} -> {}", change.term, change.concept.value)?;
+        }
+        writeln!(f, "\nRemoved terms ({}):", self.removed.len())?;
+        for change in &self.removed {
+            writeln!(f, "  - {} -> {}", change.term, change.concept.value)?;
+        }
+        writeln!(f, "\nRetargeted terms ({}):", self.retargeted.len())?;
+        for term in &self.retargeted {
+            writeln!(
+                f,
+                "  ~ {}: {} -> {} ({} -> {})",
+                term.term, term.old.value, term.new.value, term.old_url, term.new_url
+            )?;
+        }
+        writeln!(f, "\nRenumbered concepts ({}):", self.renumbered.len())?;
+        for concept in &self.renumbered {
+            writeln!(
+                f,
+                "  # {}: {} -> {}",
+                concept.concept, concept.old_id, concept.new_id
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thesaurus(entries: &[(&str, u64, &str)]) -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("diff".to_string());
+        for (term, id, concept) in entries {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(*id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus
+    }
+
+    #[test]
+    fn test_thesaurus_diff() {
+        let old = thesaurus(&[
+            ("rust", 1, "rust"),
+            ("rustlang", 1, "rust"),
+            ("cargo", 2, "cargo"),
+            ("crate", 2, "cargo"),
+        ]);
+        let new = thesaurus(&[
+            ("rust", 1, "rust"),
+            ("cargo", 3, "cargo"),
+            ("crate", 4, "crates"),
+            ("crates.io", 4, "crates"),
+        ]);
+        assert!(thesaurus_diff(&old, &old).is_empty());
+
+        let diff = thesaurus_diff(&old, &new);
+        let terms = |changes: &[TermChange]| -> Vec<String> {
+            changes
+                .iter()
+                .map(|change| change.term.to_string())
+                .collect()
+        };
+        assert_eq!(terms(&diff.added), vec!["crates.io"]);
+        assert_eq!(terms(&diff.removed), vec!["rustlang"]);
+        assert_eq!(diff.retargeted.len(), 1);
+        assert_eq!(diff.retargeted[0].term.as_str(), "crate");
+        assert_eq!(diff.retargeted[0].old_url, "kg:cargo");
+        assert_eq!(diff.retargeted[0].new_url, "kg:crates");
+        assert_eq!(
+            diff.renumbered,
+            vec![RenumberedConcept {
+                concept: NormalizedTermValue::new("cargo".to_string()),
+                old_id: 2,
+                new_id: 3,
+            }]
+        );
+        assert!(diff.to_string().contains("~ crate: cargo -> crates"));
+    }
+}
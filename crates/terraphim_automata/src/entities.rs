@@ -0,0 +1,268 @@
+//! Rule-based extraction of named entities.
+//!
+//! Names are runs of capitalized words, like `Grace Hopper` or
+//! `Atomic Server`, and single words with an inner capital, like `GitHub`.
+//! A run is only an entity if its context says what it names: a title
+//! (`Dr. Grace Hopper`), a cue word before it (`by Grace Hopper`) or a cue
+//! word after it (`the Apollo project`, `the Atomic Server database`).
+//! Capitalized words without such context, e.g. at the start of a sentence
+//! or in a heading, are not entities.
+
+use serde::{Deserialize, Serialize};
+
+/// Runs longer than this are headings or titles rather than names
+const MAX_NAME_WORDS: usize = 4;
+
+/// Titles before the name of a person
+const TITLES: &[&str] = &["mr", "mrs", "ms", "dr", "prof", "sir"];
+
+/// Words before the name of a person
+const PERSON_CUES: &[&str] = &["by", "author", "owner", "maintainer", "contact", "cc"];
+
+/// Words before or after the name of a project
+const PROJECT_CUES: &[&str] = &["project", "initiative", "programme", "program"];
+
+/// Words after the name of a system
+const SYSTEM_CUES: &[&str] = &[
+    "system", "server", "service", "platform", "database", "cluster", "api", "engine", "pipeline",
+];
+
+/// Words after the name of a product
+const PRODUCT_CUES: &[&str] = &[
+    "app",
+    "application",
+    "product",
+    "tool",
+    "library",
+    "framework",
+    "plugin",
+    "sdk",
+];
+
+/// Words which start a run of capitalized words without being part of the
+/// name, e.g. at the start of a sentence
+const LEADING_STOPWORDS: &[&str] = &[
+    "the", "a", "an", "this", "that", "these", "our", "their", "its", "my", "your", "his", "her",
+];
+
+/// What a named entity names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind {
+    Person,
+    Product,
+    Project,
+    System,
+}
+
+/// A named entity found in a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entity {
+    /// The name as written
+    pub text: String,
+    pub kind: EntityKind,
+    /// Byte offset of the name in the text
+    pub start: usize,
+    /// Byte offset after the name in the text
+    pub end: usize,
+}
+
+/// A word of a text with its byte offsets
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+impl Word<'_> {
+    fn is_one_of(&self, words: &[&str]) -> bool {
+        words
+            .iter()
+            .any(|word| self.text.eq_ignore_ascii_case(word))
+    }
+
+    /// Whether the word starts with a capital or has an inner capital
+    fn is_name_like(&self) -> bool {
+        let mut chars = self.text.chars();
+        match chars.next() {
+            Some(first) if first.is_uppercase() => true,
+            Some(first) if first.is_alphabetic() => chars.any(char::is_uppercase),
+            _ => false,
+        }
+    }
+
+    /// Whether the word has a capital after its first letter, like `GitHub`
+    fn has_inner_capital(&self) -> bool {
+        self.text.chars().skip(1).any(char::is_uppercase)
+            && self.text.chars().any(char::is_lowercase)
+    }
+}
+
+fn split_words(text: &str) -> Vec<Word<'_>> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    let mut words = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices() {
+        match (is_word_char(c), start) {
+            (true, None) => start = Some(index),
+            (false, Some(word_start)) => {
+                words.push(Word {
+                    text: &text[word_start..index],
+                    start: word_start,
+                    end: index,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(word_start) = start {
+        words.push(Word {
+            text: &text[word_start..],
+            start: word_start,
+            end: text.len(),
+        });
+    }
+    words
+}
+
+/// Whether two words are only separated by a single space
+fn is_joined(text: &str, first: &Word, second: &Word) -> bool {
+    &text[first.end..second.start] == " "
+}
+
+/// Classify a run of capitalized words by its context
+///
+/// Returns the kind and the words of the name, without cue words.
+fn classify<'a>(
+    text: &str,
+    previous: Option<&Word>,
+    run: &'a [Word<'a>],
+    next: Option<&Word>,
+) -> Option<(EntityKind, &'a [Word<'a>])> {
+    if let Some(previous) = previous {
+        let separator = &text[previous.end..run[0].start];
+        if previous.is_one_of(TITLES) && (separator == " " || separator == ". ") {
+            return Some((EntityKind::Person, run));
+        }
+    }
+    if run.len() > 1 && run[0].is_one_of(PROJECT_CUES) {
+        return Some((EntityKind::Project, &run[1..]));
+    }
+    // The cue is the word after the run, or else its last capitalized word
+    let next_kind = next
+        .filter(|next| is_joined(text, &run[run.len() - 1], next))
+        .and_then(cue_kind);
+    if let Some(kind) = next_kind {
+        return Some((kind, run));
+    }
+    if let Some((last, name)) = run.split_last() {
+        if let Some(kind) = cue_kind(last).filter(|_| !name.is_empty()) {
+            return Some((kind, name));
+        }
+    }
+    let is_titlecase = run.iter().all(|word| !word.has_inner_capital());
+    if let Some(previous) = previous {
+        if previous.is_one_of(PERSON_CUES) && (2..=3).contains(&run.len()) && is_titlecase {
+            return Some((EntityKind::Person, run));
+        }
+    }
+    if run.len() == 1 && run[0].has_inner_capital() {
+        return Some((EntityKind::Product, run));
+    }
+    None
+}
+
+fn cue_kind(word: &Word) -> Option<EntityKind> {
+    if word.is_one_of(PROJECT_CUES) {
+        Some(EntityKind::Project)
+    } else if word.is_one_of(SYSTEM_CUES) {
+        Some(EntityKind::System)
+    } else if word.is_one_of(PRODUCT_CUES) {
+        Some(EntityKind::Product)
+    } else {
+        None
+    }
+}
+
+/// Extract the named entities of a text, in the order they appear
+pub fn extract_entities(text: &str) -> Vec<Entity> {
+    let words = split_words(text);
+    let mut entities = Vec::new();
+    let mut index = 0;
+    while index < words.len() {
+        if !words[index].is_name_like() {
+            index += 1;
+            continue;
+        }
+        let mut end = index + 1;
+        while end < words.len()
+            && words[end].is_name_like()
+            && is_joined(text, &words[end - 1], &words[end])
+        {
+            end += 1;
+        }
+        let mut start = index;
+        while start < end && words[start].is_one_of(LEADING_STOPWORDS) {
+            start += 1;
+        }
+        let run = &words[start..end];
+        if !run.is_empty() && run.len() <= MAX_NAME_WORDS {
+            let previous = index.checked_sub(1).map(|previous| &words[previous]);
+            if let Some((kind, name)) = classify(text, previous, run, words.get(end)) {
+                let (first, last) = (&name[0], &name[name.len() - 1]);
+                entities.push(Entity {
+                    text: text[first.start..last.end].to_string(),
+                    kind,
+                    start: first.start,
+                    end: last.end,
+                });
+            }
+        }
+        index = end;
+    }
+    entities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(text: &str) -> Vec<(String, EntityKind)> {
+        extract_entities(text)
+            .into_iter()
+            .map(|entity| (entity.text, entity.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_entities() {
+        let text = "The Apollo project was started by Grace Hopper. \
+                    Dr. Ada Lovelace moved the Billing Engine service to GitHub, \
+                    next to the Atomic Server database and the Terraphim app. \
+                    Project Orion is planned.";
+        assert_eq!(
+            names(text),
+            vec![
+                ("Apollo".to_string(), EntityKind::Project),
+                ("Grace Hopper".to_string(), EntityKind::Person),
+                ("Ada Lovelace".to_string(), EntityKind::Person),
+                ("Billing Engine".to_string(), EntityKind::System),
+                ("GitHub".to_string(), EntityKind::Product),
+                ("Atomic Server".to_string(), EntityKind::System),
+                ("Terraphim".to_string(), EntityKind::Product),
+                ("Orion".to_string(), EntityKind::Project),
+            ]
+        );
+        let entity = &extract_entities(text)[1];
+        assert_eq!(&text[entity.start..entity.end], "Grace Hopper");
+    }
+
+    #[test]
+    fn test_no_entities_without_context() {
+        assert!(extract_entities("Operation Report. Trained operators and maintainers").is_empty());
+        assert!(extract_entities("Validated System Requirements Document Template").is_empty());
+        assert!(extract_entities("").is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+//! Export of a thesaurus for ontology tools
+//!
+//! [`export_thesaurus`] writes the concepts of a thesaurus as OWL (RDF/XML),
+//! OBO or JSON-LD, so a knowledge graph can be opened in e.g. Protégé. Every
+//! concept is a class (a term in OBO, a `skos:Concept` in JSON-LD) labelled
+//! with its normalized term; its other terms are its synonyms. Relations, such
+//! as the co-occurrences of concepts in a rolegraph, are symmetric
+//! `skos:related` annotations (`related_to` relationships in OBO).
+//!
+//! Concepts are identified by their ID, under an IRI of the thesaurus, e.g.
+//! `https://terraphim.ai/kg/Engineer/42`, or `KG:0000042` in OBO.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use terraphim_types::Thesaurus;
+
+use crate::markdown::percent_encode;
+
+/// IRI under which the concepts of thesauri are exported
+const KG_BASE_IRI: &str = "https://terraphim.ai/kg/";
+
+/// Format of an exported thesaurus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// OWL in RDF/XML
+    Owl,
+    /// OBO flat file format 1.4
+    Obo,
+    /// JSON-LD with the SKOS vocabulary
+    JsonLd,
+}
+
+impl ExportFormat {
+    /// The media type of the format
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Owl => "application/rdf+xml",
+            ExportFormat::Obo => "text/plain; charset=utf-8",
+            ExportFormat::JsonLd => "application/ld+json",
+        }
+    }
+}
+
+/// A concept of the thesaurus with its synonyms and related concepts
+struct ExportedConcept<'a> {
+    value: &'a str,
+    synonyms: BTreeSet<&'a str>,
+    related: BTreeSet<u64>,
+}
+
+/// Export a thesaurus, relating the pairs of concept IDs in `relations`
+///
+/// Relations with a concept which is not in the thesaurus are left out.
+/// Concepts are written in the order of their IDs.
+pub fn export_thesaurus(
+    thesaurus: &Thesaurus,
+    relations: &[(u64, u64)],
+    format: ExportFormat,
+) -> String {
+    let mut concepts: BTreeMap<u64, ExportedConcept> = BTreeMap::new();
+    for (term, nterm) in thesaurus {
+        let concept = concepts.entry(nterm.id).or_insert_with(|| ExportedConcept {
+            value: nterm.value.as_str(),
+            synonyms: BTreeSet::new(),
+            related: BTreeSet::new(),
+        });
+        if term != &nterm.value {
+            concept.synonyms.insert(term.as_str());
+        }
+    }
+    for &(source, target) in relations {
+        if source == target || !concepts.contains_key(&source) || !concepts.contains_key(&target) {
+            continue;
+        }
+        for (concept, related) in [(source, target), (target, source)] {
+            if let Some(concept) = concepts.get_mut(&concept) {
+                concept.related.insert(related);
+            }
+        }
+    }
+
+    let base = format!("{KG_BASE_IRI}{}/", percent_encode(thesaurus.name()));
+    match format {
+        ExportFormat::Owl => owl(thesaurus.name(), &base, &concepts),
+        ExportFormat::Obo => obo(thesaurus.name(), &concepts),
+        ExportFormat::JsonLd => json_ld(thesaurus.name(), &base, &concepts),
+    }
+}
+
+fn owl(name: &str, base: &str, concepts: &BTreeMap<u64, ExportedConcept>) -> String {
+    let mut owl = String::new();
+    owl.push_str(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"\n         \
+         xmlns:rdfs=\"http://www.w3.org/2000/01/rdf-schema#\"\n         \
+         xmlns:owl=\"http://www.w3.org/2002/07/owl#\"\n         \
+         xmlns:skos=\"http://www.w3.org/2004/02/skos/core#\">\n",
+    );
+    let base = escape_xml(base);
+    let _ = writeln!(owl, "  <owl:Ontology rdf:about=\"{base}\">");
+    let _ = writeln!(owl, "    <rdfs:label>{}</rdfs:label>", escape_xml(name));
+    owl.push_str("  </owl:Ontology>\n");
+    for property in ["prefLabel", "altLabel", "related"] {
+        let _ = writeln!(
+            owl,
+            "  <owl:AnnotationProperty rdf:about=\"http://www.w3.org/2004/02/skos/core#{property}\"/>"
+        );
+    }
+    for (id, concept) in concepts {
+        let value = escape_xml(concept.value);
+        let _ = writeln!(owl, "  <owl:Class rdf:about=\"{base}{id}\">");
+        let _ = writeln!(owl, "    <rdfs:label>{value}</rdfs:label>");
+        let _ = writeln!(owl, "    <skos:prefLabel>{value}</skos:prefLabel>");
+        for synonym in &concept.synonyms {
+            let _ = writeln!(
+                owl,
+                "    <skos:altLabel>{}</skos:altLabel>",
+                escape_xml(synonym)
+            );
+        }
+        for related in &concept.related {
+            let _ = writeln!(owl, "    <skos:related rdf:resource=\"{base}{related}\"/>");
+        }
+        owl.push_str("  </owl:Class>\n");
+    }
+    owl.push_str("</rdf:RDF>\n");
+    owl
+}
+
+fn obo(name: &str, concepts: &BTreeMap<u64, ExportedConcept>) -> String {
+    let ontology: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let mut obo = String::new();
+    obo.push_str("format-version: 1.4\n");
+    let _ = writeln!(obo, "ontology: {ontology}");
+    for (id, concept) in concepts {
+        let _ = writeln!(obo, "\n[Term]\nid: {}", obo_id(*id));
+        let _ = writeln!(obo, "name: {}", escape_obo(concept.value));
+        for synonym in &concept.synonyms {
+            let _ = writeln!(obo, "synonym: \"{}\" EXACT []", escape_obo_quoted(synonym));
+        }
+        for related in &concept.related {
+            let _ = writeln!(obo, "relationship: related_to {}", obo_id(*related));
+        }
+    }
+    obo.push_str("\n[Typedef]\nid: related_to\nname: related to\nis_symmetric: true\n");
+    obo
+}
+
+fn json_ld(name: &str, base: &str, concepts: &BTreeMap<u64, ExportedConcept>) -> String {
+    let mut graph = vec![json!({
+        "@id": base,
+        "@type": "skos:ConceptScheme",
+        "prefLabel": name,
+    })];
+    for (id, concept) in concepts {
+        let mut node = json!({
+            "@id": format!("{base}{id}"),
+            "@type": "skos:Concept",
+            "prefLabel": concept.value,
+            "inScheme": base,
+        });
+        if !concept.synonyms.is_empty() {
+            node["altLabel"] = json!(concept.synonyms);
+        }
+        if !concept.related.is_empty() {
+            let related: Vec<String> = concept
+                .related
+                .iter()
+                .map(|related| format!("{base}{related}"))
+                .collect();
+            node["related"] = json!(related);
+        }
+        graph.push(node);
+    }
+    let document = json!({
+        "@context": {
+            "skos": "http://www.w3.org/2004/02/skos/core#",
+            "prefLabel": "skos:prefLabel",
+            "altLabel": "skos:altLabel",
+            "related": {"@id": "skos:related", "@type": "@id"},
+            "inScheme": {"@id": "skos:inScheme", "@type": "@id"},
+        },
+        "@graph": graph,
+    });
+    serde_json::to_string_pretty(&document).unwrap_or_default()
+}
+
+fn obo_id(id: u64) -> String {
+    format!("KG:{id:07}")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escape a tag value of an OBO stanza
+fn escape_obo(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Escape a quoted string of an OBO tag value
+fn escape_obo_quoted(text: &str) -> String {
+    escape_obo(text).replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{NormalizedTerm, NormalizedTermValue};
+
+    fn thesaurus() -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("Engineer".to_string());
+        for (term, id, concept) in [
+            ("operation", 1, "operation"),
+            ("operate the system", 1, "operation"),
+            ("maintenance", 2, "maintenance"),
+            ("upkeep & repair", 2, "maintenance"),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus
+    }
+
+    #[test]
+    fn test_export_thesaurus() {
+        let relations = [(1, 2), (2, 99)];
+
+        let owl = export_thesaurus(&thesaurus(), &relations, ExportFormat::Owl);
+        assert!(owl.contains("<owl:Class rdf:about=\"https://terraphim.ai/kg/Engineer/2\">"));
+        assert!(owl.contains("<skos:altLabel>upkeep &amp; repair</skos:altLabel>"));
+        assert!(owl.contains("<skos:related rdf:resource=\"https://terraphim.ai/kg/Engineer/1\"/>"));
+        assert!(!owl.contains("Engineer/99"));
+
+        let obo = export_thesaurus(&thesaurus(), &relations, ExportFormat::Obo);
+        assert!(obo.contains(
+            "[Term]\nid: KG:0000001\nname: operation\n\
+             synonym: \"operate the system\" EXACT []\n\
+             relationship: related_to KG:0000002\n"
+        ));
+
+        let json_ld = export_thesaurus(&thesaurus(), &[], ExportFormat::JsonLd);
+        let json_ld: serde_json::Value = serde_json::from_str(&json_ld).unwrap();
+        let concept = &json_ld["@graph"][1];
+        assert_eq!(concept["@id"], "https://terraphim.ai/kg/Engineer/1");
+        assert_eq!(concept["prefLabel"], "operation");
+        assert_eq!(concept["altLabel"], json!(["operate the system"]));
+        assert!(concept.get("related").is_none());
+    }
+}
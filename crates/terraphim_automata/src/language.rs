@@ -0,0 +1,267 @@
+//! Language detection and per-language text analysis.
+//!
+//! Documents get their language detected when they are indexed. Scoring then
+//! analyzes both the query and the document text with the analyzer of the
+//! document language, so that e.g. "operators" matches "operator" in English
+//! and "Betreiber" matches "Betreibers" in German.
+//!
+//! Detection counts the stopwords of every supported language in the text,
+//! which is cheap and reliable for paragraphs of prose.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+
+/// Number of words looked at when detecting the language of a text
+const DETECTION_WORDS: usize = 2000;
+
+/// Minimum number of stopwords a text must contain to detect its language
+const MIN_STOPWORDS: usize = 3;
+
+/// A language with a dedicated analyzer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Portuguese,
+    Dutch,
+}
+
+impl Language {
+    /// All supported languages
+    pub const ALL: [Language; 7] = [
+        Language::English,
+        Language::German,
+        Language::French,
+        Language::Spanish,
+        Language::Italian,
+        Language::Portuguese,
+        Language::Dutch,
+    ];
+
+    /// ISO 639-1 code of the language, e.g. `en`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::German => "de",
+            Language::French => "fr",
+            Language::Spanish => "es",
+            Language::Italian => "it",
+            Language::Portuguese => "pt",
+            Language::Dutch => "nl",
+        }
+    }
+
+    pub(crate) fn stemmer_algorithm(&self) -> Algorithm {
+        match self {
+            Language::English => Algorithm::English,
+            Language::German => Algorithm::German,
+            Language::French => Algorithm::French,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Italian => Algorithm::Italian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Dutch => Algorithm::Dutch,
+        }
+    }
+
+    /// The most common function words of the language
+    pub fn stopwords(&self) -> &'static [&'static str] {
+        match self {
+            Language::English => &[
+                "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "have",
+                "in", "is", "it", "its", "of", "on", "or", "that", "the", "this", "to", "was",
+                "were", "which", "will", "with",
+            ],
+            Language::German => &[
+                "auf", "aus", "bei", "das", "dass", "dem", "den", "der", "des", "die", "ein",
+                "eine", "einer", "für", "ist", "im", "mit", "nicht", "oder", "sich", "sind", "und",
+                "von", "wird", "zu", "zum", "zur",
+            ],
+            Language::French => &[
+                "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "est",
+                "et", "il", "la", "le", "les", "leur", "mais", "ne", "par", "pas", "pour", "qui",
+                "sont", "sur", "un", "une",
+            ],
+            Language::Spanish => &[
+                "al", "como", "con", "de", "del", "el", "en", "es", "esta", "la", "las", "lo",
+                "los", "más", "no", "para", "pero", "por", "que", "se", "su", "sus", "un", "una",
+                "y",
+            ],
+            Language::Italian => &[
+                "al", "alla", "che", "con", "da", "del", "della", "di", "e", "è", "gli", "i", "il",
+                "in", "la", "le", "non", "per", "più", "si", "sono", "su", "un", "una",
+            ],
+            Language::Portuguese => &[
+                "ao", "as", "com", "da", "das", "de", "do", "dos", "e", "é", "em", "mais", "na",
+                "nas", "no", "nos", "não", "o", "os", "para", "pela", "pelo", "por", "que", "se",
+                "um", "uma",
+            ],
+            Language::Dutch => &[
+                "aan", "als", "bij", "dat", "de", "die", "een", "en", "het", "in", "is", "met",
+                "niet", "nog", "of", "om", "ook", "op", "te", "van", "voor", "was", "wordt",
+                "zijn",
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    /// Parse an ISO 639-1 code or an English language name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        Language::ALL
+            .into_iter()
+            .find(|language| language.code() == s || format!("{language:?}").to_lowercase() == s)
+            .ok_or_else(|| format!("Unsupported language: {s}"))
+    }
+}
+
+/// Split text into lowercase words
+pub(crate) fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+/// Detect the language of a text
+///
+/// Returns `None` if the text contains too few stopwords of any supported
+/// language, e.g. because it is too short or written in another language.
+pub fn detect_language(text: &str) -> Option<Language> {
+    let mut counts = [0usize; Language::ALL.len()];
+    for word in words(text).take(DETECTION_WORDS) {
+        for (count, language) in counts.iter_mut().zip(Language::ALL) {
+            if language.stopwords().contains(&word.as_str()) {
+                *count += 1;
+            }
+        }
+    }
+    let (count, language) = counts
+        .into_iter()
+        .zip(Language::ALL)
+        .max_by_key(|(count, _)| *count)?;
+    (count >= MIN_STOPWORDS).then_some(language)
+}
+
+/// Turns text into normalized tokens for a language
+///
+/// Tokens are lowercased words with stopwords removed and suffixes stemmed.
+/// Without a language, text is only lowercased and split into words.
+pub struct Analyzer {
+    language: Option<Language>,
+    stemmer: Option<Stemmer>,
+}
+
+impl fmt::Debug for Analyzer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Analyzer")
+            .field("language", &self.language)
+            .finish()
+    }
+}
+
+impl Analyzer {
+    /// Create an analyzer for the given language
+    pub fn new(language: Option<Language>) -> Self {
+        Self {
+            language,
+            stemmer: language.map(|language| Stemmer::create(language.stemmer_algorithm())),
+        }
+    }
+
+    /// Create an analyzer for a language code as stored on documents
+    ///
+    /// Unknown codes fall back to the language-neutral analyzer.
+    pub fn for_code(code: Option<&str>) -> Self {
+        Self::new(code.and_then(|code| code.parse().ok()))
+    }
+
+    /// The language of the analyzer
+    pub fn language(&self) -> Option<Language> {
+        self.language
+    }
+
+    /// Split `text` into normalized tokens
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        let stopwords = self.language.map(|l| l.stopwords()).unwrap_or_default();
+        words(text)
+            .filter(|word| !stopwords.contains(&word.as_str()))
+            .map(|word| match &self.stemmer {
+                Some(stemmer) => stemmer.stem(&word).into_owned(),
+                None => word,
+            })
+            .collect()
+    }
+
+    /// Normalize `text` into a single string of space separated tokens
+    pub fn analyze(&self, text: &str) -> String {
+        self.tokens(text).join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(
+            detect_language("The operators are trained in the maintenance of the system."),
+            Some(Language::English)
+        );
+        assert_eq!(
+            detect_language(
+                "Die Betreiber sind für die Wartung der Anlage und des Systems zuständig."
+            ),
+            Some(Language::German)
+        );
+        assert_eq!(
+            detect_language(
+                "Les opérateurs sont formés pour la maintenance des systèmes et de la plateforme."
+            ),
+            Some(Language::French)
+        );
+        assert_eq!(detect_language("Paradigm Map"), None);
+        assert_eq!(detect_language(""), None);
+    }
+
+    #[test]
+    fn test_language_codes() {
+        for language in Language::ALL {
+            assert_eq!(language.code().parse::<Language>(), Ok(language));
+        }
+        assert_eq!("German".parse::<Language>(), Ok(Language::German));
+        assert!("tlh".parse::<Language>().is_err());
+    }
+
+    #[test]
+    fn test_analyzer() {
+        let english = Analyzer::new(Some(Language::English));
+        assert_eq!(
+            english.tokens("The Trained Operators of the system"),
+            vec!["train", "oper", "system"]
+        );
+        assert_eq!(english.analyze("operators"), english.analyze("Operator"));
+
+        let neutral = Analyzer::for_code(None);
+        assert_eq!(
+            neutral.analyze("The  Trained-Operators"),
+            "the trained operators"
+        );
+        assert_eq!(Analyzer::for_code(Some("xx")).language(), None);
+    }
+}
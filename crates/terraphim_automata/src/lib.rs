@@ -1,11 +1,33 @@
+pub mod compact;
+pub mod diff;
+pub mod entities;
+pub mod export;
+pub mod language;
+pub mod lint;
+pub mod markdown;
 pub mod matcher;
-
-pub use matcher::{find_matches, Matched};
+pub mod normalize;
+pub mod patterns;
+pub mod phrase;
+pub mod spelling;
+pub mod trigram;
+
+pub use compact::CompactThesaurus;
+pub use diff::{thesaurus_diff, ThesaurusDiff};
+pub use export::{export_thesaurus, ExportFormat};
+pub use lint::{validate_thesaurus, LintIssue};
+pub use markdown::{link_matches, link_matches_compact, LinkOptions, Linker};
+pub use matcher::{
+    find_matches, find_matches_compact, find_matches_normalized, replace_matches,
+    replace_matches_compact, Matched,
+};
+pub use normalize::{Normalization, TermMatcher};
+pub use patterns::PatternMatcher;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
-use std::fs;
 use std::path::PathBuf;
 
+#[cfg(feature = "remote")]
 use terraphim_types::Thesaurus;
 
 #[derive(thiserror::Error, Debug)]
@@ -24,6 +46,12 @@ pub enum TerraphimAutomataError {
 
     #[error("Aho-Corasick build error: {0}")]
     AhoCorasick(#[from] aho_corasick::BuildError),
+
+    #[error("FST error: {0}")]
+    Fst(#[from] fst::Error),
+
+    #[error("Invalid pattern: {0}")]
+    Regex(#[from] regex::Error),
 }
 
 pub type Result<T> = std::result::Result<T, TerraphimAutomataError>;
@@ -89,6 +117,7 @@ impl AutomataPath {
 // }
 
 /// Load a thesaurus from a file or URL
+#[cfg(feature = "remote")]
 pub async fn load_thesaurus(automata_path: &AutomataPath) -> Result<Thesaurus> {
     async fn read_url(url: String) -> Result<String> {
         log::debug!("Reading thesaurus from remote: {url}");
@@ -120,7 +149,7 @@ pub async fn load_thesaurus(automata_path: &AutomataPath) -> Result<Thesaurus> {
 
     log::debug!("Reading thesaurus from {automata_path}");
     let contents = match automata_path {
-        AutomataPath::Local(path) => fs::read_to_string(path)?,
+        AutomataPath::Local(path) => std::fs::read_to_string(path)?,
         AutomataPath::Remote(url) => read_url(url.clone()).await?,
     };
 
@@ -0,0 +1,231 @@
+//! Validation of a thesaurus before it is published
+//!
+//! A generated knowledge graph can have terms which do more harm than good:
+//! a term which refers to several concepts, an empty or one-character term
+//! which matches all over a text, or concepts which are synonyms of each
+//! other in a circle, so none of them is the canonical one.
+//! [`validate_thesaurus`] lists them, together with patterns which are no
+//! valid regular expressions.
+//!
+//! Concepts without a page defining them can only be found with the pages
+//! of the knowledge graph, so [`LintIssue::MissingDefinition`] is reported
+//! by whoever reads them, e.g. `TerraphimService::validate_kg`.
+
+use std::fmt;
+
+use ahash::{AHashMap, AHashSet};
+use serde::{Deserialize, Serialize};
+use terraphim_types::{NormalizedTermValue, Thesaurus};
+
+/// Terms with at most this many characters are too short
+const MAX_SHORT_TERM_LENGTH: usize = 1;
+
+/// A problem of a thesaurus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LintIssue {
+    /// A term which maps to one concept but is the name of concepts with
+    /// other IDs, all of which are listed
+    DuplicateTerm { term: String, concepts: Vec<u64> },
+    /// An empty or one-character term
+    ShortTerm { term: String, concept: u64 },
+    /// Concepts whose names are terms of the next concept, the last of the
+    /// first one
+    CircularReference { concepts: Vec<String> },
+    /// A pattern which is no valid regular expression
+    InvalidPattern { pattern: String, error: String },
+    /// A concept without a page defining it in the knowledge graph
+    MissingDefinition { concept: String, id: u64 },
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintIssue::DuplicateTerm { term, concepts } => {
+                let concepts: Vec<String> = concepts.iter().map(u64::to_string).collect();
+                write!(
+                    f,
+                    "term `{term}` refers to concepts {}",
+                    concepts.join(", ")
+                )
+            }
+            LintIssue::ShortTerm { term, concept } => {
+                write!(f, "term `{term}` of concept {concept} is too short")
+            }
+            LintIssue::CircularReference { concepts } => {
+                write!(
+                    f,
+                    "concepts refer to each other: {} -> {}",
+                    concepts.join(" -> "),
+                    concepts[0]
+                )
+            }
+            LintIssue::InvalidPattern { pattern, error } => {
+                write!(f, "pattern `{pattern}` is invalid: {error}")
+            }
+            LintIssue::MissingDefinition { concept, id } => {
+                write!(f, "concept `{concept}` ({id}) has no definition")
+            }
+        }
+    }
+}
+
+/// Find the problems of a thesaurus, sorted by kind and then by term
+pub fn validate_thesaurus(thesaurus: &Thesaurus) -> Vec<LintIssue> {
+    let mut ids: AHashMap<&NormalizedTermValue, AHashSet<u64>> = AHashMap::new();
+    for (_, concept) in thesaurus {
+        ids.entry(&concept.value).or_default().insert(concept.id);
+    }
+
+    let mut duplicates = Vec::new();
+    let mut short = Vec::new();
+    for (term, concept) in thesaurus {
+        let mut concepts: Vec<u64> = ids
+            .get(term)
+            .into_iter()
+            .flatten()
+            .copied()
+            .chain([concept.id])
+            .collect();
+        concepts.sort_unstable();
+        concepts.dedup();
+        if concepts.len() > 1 {
+            duplicates.push(LintIssue::DuplicateTerm {
+                term: term.to_string(),
+                concepts,
+            });
+        }
+        if term.as_str().chars().count() <= MAX_SHORT_TERM_LENGTH {
+            short.push(LintIssue::ShortTerm {
+                term: term.to_string(),
+                concept: concept.id,
+            });
+        }
+    }
+    duplicates.sort_by_cached_key(LintIssue::to_string);
+    short.sort_by_cached_key(LintIssue::to_string);
+
+    let mut issues = duplicates;
+    issues.extend(short);
+    issues.extend(circular_references(thesaurus, &ids));
+    for entry in thesaurus.patterns() {
+        if let Err(e) = regex::Regex::new(&entry.pattern) {
+            issues.push(LintIssue::InvalidPattern {
+                pattern: entry.pattern.clone(),
+                error: e.to_string(),
+            });
+        }
+    }
+    issues
+}
+
+/// Cycles of concepts whose names are terms of another concept
+///
+/// Every concept refers to at most one other concept, the one its name maps
+/// to, so following references from each concept finds every cycle.
+fn circular_references(
+    thesaurus: &Thesaurus,
+    ids: &AHashMap<&NormalizedTermValue, AHashSet<u64>>,
+) -> Vec<LintIssue> {
+    let next = |concept: &NormalizedTermValue| {
+        thesaurus
+            .get(concept)
+            .map(|target| &target.value)
+            .filter(|target| *target != concept)
+    };
+    let mut concepts: Vec<&NormalizedTermValue> = ids.keys().copied().collect();
+    concepts.sort_unstable();
+
+    let mut done: AHashSet<&NormalizedTermValue> = AHashSet::new();
+    let mut cycles = Vec::new();
+    for start in concepts {
+        let mut path: Vec<&NormalizedTermValue> = Vec::new();
+        let mut current = Some(start);
+        while let Some(concept) = current {
+            if done.contains(concept) {
+                break;
+            }
+            if let Some(index) = path.iter().position(|visited| *visited == concept) {
+                // Start with the smallest name, so every cycle reads the same
+                let cycle = &path[index..];
+                let first = (0..cycle.len()).min_by_key(|i| cycle[*i]).unwrap_or(0);
+                let concepts = cycle[first..]
+                    .iter()
+                    .chain(&cycle[..first])
+                    .map(|concept| concept.to_string())
+                    .collect();
+                cycles.push(LintIssue::CircularReference { concepts });
+                break;
+            }
+            path.push(concept);
+            current = next(concept);
+        }
+        done.extend(path);
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::NormalizedTerm;
+
+    #[test]
+    fn test_validate_thesaurus() {
+        let mut thesaurus = Thesaurus::new("lint".to_string());
+        for (term, id, concept) in [
+            ("rust", 1, "rust"),
+            ("rustlang", 1, "rust"),
+            ("r", 1, "rust"),
+            ("", 2, "cargo"),
+            ("cargo", 2, "cargo"),
+            // `crate` is the name of concept 4, but maps to concept 3
+            ("crate", 3, "crates"),
+            ("crates", 4, "crate"),
+            ("python", 5, "python"),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus.insert_pattern("(unclosed".to_string(), NormalizedTerm::new(6, "x".into()));
+
+        let issues = validate_thesaurus(&thesaurus);
+        assert_eq!(
+            issues[..4],
+            [
+                LintIssue::DuplicateTerm {
+                    term: "crate".to_string(),
+                    concepts: vec![3, 4],
+                },
+                LintIssue::DuplicateTerm {
+                    term: "crates".to_string(),
+                    concepts: vec![3, 4],
+                },
+                LintIssue::ShortTerm {
+                    term: String::new(),
+                    concept: 2,
+                },
+                LintIssue::ShortTerm {
+                    term: "r".to_string(),
+                    concept: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            issues[4],
+            LintIssue::CircularReference {
+                concepts: vec!["crate".to_string(), "crates".to_string()],
+            }
+        );
+        assert!(
+            matches!(&issues[5], LintIssue::InvalidPattern { pattern, .. } if pattern == "(unclosed")
+        );
+        assert_eq!(issues.len(), 6);
+        assert_eq!(
+            issues[4].to_string(),
+            "concepts refer to each other: crate -> crates -> crate"
+        );
+    }
+}
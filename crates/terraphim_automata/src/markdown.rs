@@ -0,0 +1,532 @@
+//! Linking the terms of a thesaurus in Markdown
+//!
+//! Replacing terms in the raw text of a Markdown document would put links
+//! into fenced and inline code, front matter, URLs and existing links.
+//! [`link_matches`] parses the document with `pulldown-cmark` instead and
+//! only links terms in its prose: text outside of code, links and images,
+//! and not part of a URL or wikilink. A term is linked as
+//! `[term](kg:normalized_term)`, with spaces in the normalized term written
+//! as underscores, unless [`LinkOptions::template`] sets another format.
+//!
+//! Linking every occurrence of every term makes long documents unreadable,
+//! so [`LinkOptions`] can limit the links to the first occurrences of each
+//! concept and cap the links of a document.
+//!
+//! The links are spliced into the source at the offsets of the prose rather
+//! than rendering the parsed document back to Markdown, so everything but
+//! the linked terms stays as it was written. [`Linker::concepts`] returns
+//! the terms which would be linked, with their offsets, for clients which
+//! render the links themselves.
+//!
+//! A linker given the definitions of concepts with
+//! [`Linker::with_definitions`] sets them as the titles of the links, e.g.
+//! `[term](kg:concept "Definition.")`, which Markdown renderers show as
+//! tooltips.
+
+use std::ops::Range;
+
+use ahash::AHashMap;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use terraphim_types::{KgConceptRef, NormalizedTerm, Thesaurus};
+
+use crate::normalize::{Normalization, TermMatcher};
+use crate::{CompactThesaurus, Result};
+
+/// How many terms are linked, and how
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkOptions {
+    /// Only link the first occurrences of each concept, in any of its terms
+    pub max_links_per_concept: Option<usize>,
+    /// Maximum number of links in a document
+    pub max_links: Option<usize>,
+    /// Template of the links, `kg:{concept}` with spaces written as
+    /// underscores if not set
+    ///
+    /// A template is the URL of a link, e.g. `https://wiki.internal/{concept}`
+    /// or `obsidian://open?file={concept}`, where `{concept}` is the
+    /// percent-encoded normalized term and `{id}` the ID of the concept. A
+    /// template in double brackets, e.g. `[[{concept}|{term}]]`, is a
+    /// wikilink which replaces the term, with `{concept}` the normalized term
+    /// and `{term}` the term as written.
+    pub template: Option<String>,
+    /// How terms and text are normalized before they are matched; ASCII
+    /// case is ignored if not set
+    pub normalization: Option<Normalization>,
+}
+
+/// Link the terms of a thesaurus in the prose of a Markdown document to
+/// their concepts
+pub fn link_matches(text: &str, thesaurus: &Thesaurus, options: &LinkOptions) -> Result<String> {
+    Ok(Linker::new(thesaurus, options.clone())?.link(text))
+}
+
+/// Like [`link_matches`], but for a [`CompactThesaurus`]
+pub fn link_matches_compact(
+    text: &str,
+    thesaurus: &CompactThesaurus,
+    options: &LinkOptions,
+) -> Result<String> {
+    Ok(Linker::from_compact(thesaurus, options.clone())?.link(text))
+}
+
+/// The `kg:` URL of a concept, the target of links to it
+pub fn concept_url(normalized_term: &str) -> String {
+    format!("kg:{}", normalized_term.replace(' ', "_"))
+}
+
+/// Whether a link template is a wikilink rather than a URL
+fn is_wikilink(template: &str) -> bool {
+    template.starts_with("[[") && template.ends_with("]]")
+}
+
+/// Fill in the placeholders of a link template
+fn render(template: &str, concept: &str, id: u64, term: &str) -> String {
+    template
+        .replace("{concept}", concept)
+        .replace("{id}", &id.to_string())
+        .replace("{term}", term)
+}
+
+/// Escape the backslashes and double quotes of a link title
+fn escape_title(title: &str) -> String {
+    title.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Percent-encode everything but the unreserved characters of URLs
+pub(crate) fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Links the terms of a thesaurus in Markdown documents, or lists them
+///
+/// Building the automaton of a thesaurus takes much longer than matching a
+/// document, so a linker is built once for many documents.
+#[derive(Debug, Clone)]
+pub struct Linker {
+    matcher: TermMatcher,
+    /// The concept of each pattern of the automaton
+    concepts: Vec<NormalizedTerm>,
+    options: LinkOptions,
+    /// Definitions by concept ID
+    definitions: AHashMap<u64, String>,
+}
+
+impl Linker {
+    /// Build a linker for the terms of a thesaurus
+    pub fn new(thesaurus: &Thesaurus, options: LinkOptions) -> Result<Self> {
+        let (patterns, concepts): (Vec<String>, Vec<NormalizedTerm>) = thesaurus
+            .into_iter()
+            .map(|(term, normalized_term)| (term.to_string(), normalized_term.clone()))
+            .unzip();
+        Self::build(patterns, concepts, options)
+    }
+
+    /// Like [`Linker::new`], but for a [`CompactThesaurus`]
+    pub fn from_compact(thesaurus: &CompactThesaurus, options: LinkOptions) -> Result<Self> {
+        let (patterns, concepts): (Vec<String>, Vec<NormalizedTerm>) = thesaurus
+            .terms()
+            .into_iter()
+            .map(|(term, normalized_term)| (term, normalized_term.clone()))
+            .unzip();
+        Self::build(patterns, concepts, options)
+    }
+
+    fn build(
+        patterns: Vec<String>,
+        concepts: Vec<NormalizedTerm>,
+        options: LinkOptions,
+    ) -> Result<Self> {
+        let matcher = TermMatcher::new(patterns, options.normalization)?;
+        Ok(Self {
+            matcher,
+            concepts,
+            options,
+            definitions: AHashMap::new(),
+        })
+    }
+
+    /// Set the definitions of concepts, by concept ID, as the titles of
+    /// their links
+    ///
+    /// Wikilinks have no titles, so definitions only show up in the
+    /// [`KgConceptRef::definition`] of their terms.
+    pub fn with_definitions(mut self, definitions: AHashMap<u64, String>) -> Self {
+        self.definitions = definitions;
+        self
+    }
+
+    /// Link the terms in the prose of a Markdown document to their concepts
+    pub fn link(&self, text: &str) -> String {
+        let wikilink = self.options.template.as_deref().filter(|t| is_wikilink(t));
+        let mut linked = String::with_capacity(text.len());
+        let mut copied = 0;
+        for concept in self.concepts(text) {
+            linked.push_str(&text[copied..concept.start]);
+            match wikilink {
+                Some(template) => linked.push_str(&render(
+                    template,
+                    concept.concept.value.as_str(),
+                    concept.concept.id,
+                    &concept.term,
+                )),
+                None => {
+                    linked.push('[');
+                    linked.push_str(&concept.term);
+                    linked.push_str("](");
+                    linked.push_str(&concept.url);
+                    if let Some(definition) = &concept.definition {
+                        linked.push_str(" \"");
+                        linked.push_str(&escape_title(definition));
+                        linked.push('"');
+                    }
+                    linked.push(')');
+                }
+            }
+            copied = concept.end;
+        }
+        linked.push_str(&text[copied..]);
+        linked
+    }
+
+    /// The URL of the links to a concept
+    ///
+    /// Wikilinks have no URL, so with a wikilink template it's the `kg:` URL.
+    fn url(&self, concept: &NormalizedTerm, term: &str) -> String {
+        match self.options.template.as_deref() {
+            Some(template) if !is_wikilink(template) => render(
+                template,
+                &percent_encode(concept.value.as_str()),
+                concept.id,
+                &percent_encode(term),
+            ),
+            _ => concept_url(concept.value.as_str()),
+        }
+    }
+
+    /// The terms [`Linker::link`] would link, in order, leaving the
+    /// document as it is
+    pub fn concepts(&self, text: &str) -> Vec<KgConceptRef> {
+        let wikilinks = wikilinks(text);
+        let mut concepts = Vec::new();
+        // Links by concept ID
+        let mut concept_links: AHashMap<u64, usize> = AHashMap::new();
+        'prose: for range in prose(text) {
+            for mat in self.matcher.find_iter(&text[range.clone()]) {
+                let (start, end) = (range.start + mat.start, range.start + mat.end);
+                if !is_word(text, start, end)
+                    || in_url(text, start, end)
+                    || wikilinks
+                        .iter()
+                        .any(|wikilink| wikilink.start < end && start < wikilink.end)
+                {
+                    continue;
+                }
+                if self
+                    .options
+                    .max_links
+                    .is_some_and(|max| concepts.len() >= max)
+                {
+                    break 'prose;
+                }
+                let concept = &self.concepts[mat.pattern];
+                let links = concept_links.entry(concept.id).or_default();
+                if self
+                    .options
+                    .max_links_per_concept
+                    .is_some_and(|max| *links >= max)
+                {
+                    continue;
+                }
+                *links += 1;
+                let term = &text[start..end];
+                concepts.push(KgConceptRef {
+                    term: term.to_string(),
+                    concept: concept.clone(),
+                    start,
+                    end,
+                    url: self.url(concept, term),
+                    definition: self.definitions.get(&concept.id).cloned(),
+                });
+            }
+        }
+        concepts
+    }
+}
+
+/// Byte ranges of the prose of a Markdown document, in order
+fn prose(text: &str) -> Vec<Range<usize>> {
+    let offset = front_matter_end(text);
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let mut ranges = Vec::new();
+    // Depth of code blocks, links and images the parser is in
+    let mut excluded = 0usize;
+    for (event, range) in Parser::new_ext(&text[offset..], options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => excluded += 1,
+            Event::End(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => {
+                excluded = excluded.saturating_sub(1)
+            }
+            Event::Text(_) if excluded == 0 => {
+                ranges.push(offset + range.start..offset + range.end)
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// Offset after the YAML (`---`) or TOML (`+++`) front matter at the start
+/// of a document, 0 if it has none
+fn front_matter_end(text: &str) -> usize {
+    let mut lines = text.split_inclusive('\n');
+    let Some(fence) = lines.next().map(str::trim_end) else {
+        return 0;
+    };
+    if fence != "---" && fence != "+++" {
+        return 0;
+    }
+    let mut offset = fence.len();
+    offset += text[offset..].len() - text[offset..].trim_start_matches(['\r', '\n']).len();
+    for line in lines {
+        offset += line.len();
+        if line.trim_end() == fence {
+            return offset;
+        }
+    }
+    // Not closed, so not front matter
+    0
+}
+
+/// Byte ranges of the `[[wikilinks]]` of a text
+fn wikilinks(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut from = 0;
+    while let Some(start) = text[from..].find("[[").map(|start| from + start) {
+        let Some(end) = text[start..].find("]]").map(|end| start + end + 2) else {
+            break;
+        };
+        if !text[start..end].contains('\n') {
+            ranges.push(start..end);
+        }
+        from = start + 2;
+    }
+    ranges
+}
+
+/// Whether a match is a whole word, not a part of one
+fn is_word(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+}
+
+/// Whether a match is part of a bare URL
+fn in_url(text: &str, start: usize, end: usize) -> bool {
+    let token_start = text[..start]
+        .rfind(char::is_whitespace)
+        .map_or(0, |space| space + 1);
+    let token_end = text[end..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |space| end + space);
+    let token = &text[token_start..token_end];
+    token.contains("://") || token.starts_with("www.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use terraphim_types::{NormalizedTerm, NormalizedTermValue};
+
+    fn thesaurus() -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("markdown".to_string());
+        for (id, term, concept) in [
+            (1, "rust", "rust"),
+            (2, "trained operators", "trained operators"),
+            (2, "operators", "trained operators"),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus
+    }
+
+    #[test]
+    fn test_link_matches() {
+        let text = "---\n\
+                    title: Rust\n\
+                    ---\n\
+                    # Rust for Operators\n\
+                    \n\
+                    Trained operators use `rust` and trust Rust.\n\
+                    \n\
+                    ```rust\n\
+                    fn operators() {}\n\
+                    ```\n\
+                    \n\
+                    See [the rust book](https://doc.rust-lang.org/book/), \
+                    https://example.com/rust and [[Rust]].\n";
+        let expected = "---\n\
+                        title: Rust\n\
+                        ---\n\
+                        # [Rust](kg:rust) for [Operators](kg:trained_operators)\n\
+                        \n\
+                        [Trained operators](kg:trained_operators) use `rust` and trust [Rust](kg:rust).\n\
+                        \n\
+                        ```rust\n\
+                        fn operators() {}\n\
+                        ```\n\
+                        \n\
+                        See [the rust book](https://doc.rust-lang.org/book/), \
+                        https://example.com/rust and [[Rust]].\n";
+        let options = LinkOptions::default();
+        assert_eq!(
+            link_matches(text, &thesaurus(), &options).unwrap(),
+            expected
+        );
+
+        let compact = CompactThesaurus::from_thesaurus(&thesaurus()).unwrap();
+        assert_eq!(
+            link_matches_compact(text, &compact, &options).unwrap(),
+            expected
+        );
+
+        // Without front matter, a thematic break is prose around it
+        assert_eq!(
+            link_matches("rust\n\n---\n\nrust", &thesaurus(), &options).unwrap(),
+            "[rust](kg:rust)\n\n---\n\n[rust](kg:rust)"
+        );
+    }
+
+    #[test]
+    fn test_concepts() {
+        let text = "Use `rust`, then Trained operators.";
+        let linker = Linker::new(&thesaurus(), LinkOptions::default()).unwrap();
+        let concepts = linker.concepts(text);
+        assert_eq!(concepts.len(), 1);
+        assert_eq!(concepts[0].term, "Trained operators");
+        assert_eq!(
+            &text[concepts[0].start..concepts[0].end],
+            "Trained operators"
+        );
+        assert_eq!(concepts[0].concept.id, 2);
+        assert_eq!(concepts[0].url, "kg:trained_operators");
+        assert_eq!(
+            linker.link(text),
+            "Use `rust`, then [Trained operators](kg:trained_operators)."
+        );
+    }
+
+    #[test]
+    fn test_link_density() {
+        let text = "Rust and operators.\n\nTrained operators use rust, rust and rust.";
+        let first = LinkOptions {
+            max_links_per_concept: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            link_matches(text, &thesaurus(), &first).unwrap(),
+            "[Rust](kg:rust) and [operators](kg:trained_operators).\n\n\
+             Trained operators use rust, rust and rust."
+        );
+
+        let capped = LinkOptions {
+            max_links_per_concept: Some(2),
+            max_links: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            link_matches(text, &thesaurus(), &capped).unwrap(),
+            "[Rust](kg:rust) and [operators](kg:trained_operators).\n\n\
+             [Trained operators](kg:trained_operators) use rust, rust and rust."
+        );
+    }
+
+    #[test]
+    fn test_link_templates() {
+        let text = "Trained operators use rust.";
+        let template = |template: &str| LinkOptions {
+            template: Some(template.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            link_matches(
+                text,
+                &thesaurus(),
+                &template("https://wiki.internal/{concept}")
+            )
+            .unwrap(),
+            "[Trained operators](https://wiki.internal/trained%20operators) use \
+             [rust](https://wiki.internal/rust)."
+        );
+        assert_eq!(
+            link_matches(
+                text,
+                &thesaurus(),
+                &template("obsidian://open?file={concept}&id={id}")
+            )
+            .unwrap(),
+            "[Trained operators](obsidian://open?file=trained%20operators&id=2) use \
+             [rust](obsidian://open?file=rust&id=1)."
+        );
+
+        let wikilinks = template("[[{concept}|{term}]]");
+        assert_eq!(
+            link_matches(text, &thesaurus(), &wikilinks).unwrap(),
+            "[[trained operators|Trained operators]] use [[rust|rust]]."
+        );
+        let linker = Linker::new(&thesaurus(), wikilinks).unwrap();
+        assert_eq!(linker.concepts(text)[0].url, "kg:trained_operators");
+    }
+
+    #[test]
+    fn test_link_normalized() {
+        let options = LinkOptions {
+            normalization: Some(Normalization {
+                stem: Some(crate::language::Language::English),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            link_matches("A trained operator uses Rust.", &thesaurus(), &options).unwrap(),
+            "A [trained operator](kg:trained_operators) uses [Rust](kg:rust)."
+        );
+    }
+
+    #[test]
+    fn test_link_definitions() {
+        let definitions = AHashMap::from([(1, "A \"systems\" language.".to_string())]);
+        let linker = Linker::new(&thesaurus(), LinkOptions::default())
+            .unwrap()
+            .with_definitions(definitions);
+        let text = "Trained operators use rust.";
+        assert_eq!(
+            linker.link(text),
+            "[Trained operators](kg:trained_operators) use \
+             [rust](kg:rust \"A \\\"systems\\\" language.\")."
+        );
+        let concepts = linker.concepts(text);
+        assert_eq!(concepts[0].definition, None);
+        assert_eq!(
+            concepts[1].definition.as_deref(),
+            Some("A \"systems\" language.")
+        );
+    }
+}
@@ -1,30 +1,53 @@
 use aho_corasick::{AhoCorasick, MatchKind};
+use serde::Serialize;
 use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
 
-use crate::{Result, TerraphimAutomataError};
+use crate::normalize::{Normalization, TermMatcher};
+use crate::patterns::{merge_matches, PatternMatcher};
+use crate::{CompactThesaurus, Result, TerraphimAutomataError};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct Matched {
     pub term: String,
     pub normalized_term: NormalizedTerm,
     pub pos: Option<(usize, usize)>,
 }
 
+/// Find the terms of a thesaurus in a text
+///
+/// The patterns of the thesaurus are matched too, with the matched text as
+/// the `term`, see [`crate::patterns`].
 pub fn find_matches(
     text: &str,
     thesaurus: Thesaurus,
     return_positions: bool,
 ) -> Result<Vec<Matched>> {
-    let patterns: Vec<NormalizedTermValue> = thesaurus.keys().cloned().collect();
+    find_matches_with(text, &thesaurus, None, return_positions)
+}
 
-    let ac = AhoCorasick::builder()
-        .match_kind(MatchKind::LeftmostLongest)
-        .ascii_case_insensitive(true)
-        .build(patterns.clone())?;
+/// Like [`find_matches`], but with the terms and the text normalized, e.g.
+/// to match "Straße" or "operators", see [`crate::normalize`]
+pub fn find_matches_normalized(
+    text: &str,
+    thesaurus: &Thesaurus,
+    normalization: Normalization,
+    return_positions: bool,
+) -> Result<Vec<Matched>> {
+    find_matches_with(text, thesaurus, Some(normalization), return_positions)
+}
+
+fn find_matches_with(
+    text: &str,
+    thesaurus: &Thesaurus,
+    normalization: Option<Normalization>,
+    return_positions: bool,
+) -> Result<Vec<Matched>> {
+    let patterns: Vec<NormalizedTermValue> = thesaurus.keys().cloned().collect();
+    let matcher = TermMatcher::new(patterns.iter().map(|term| term.as_str()), normalization)?;
 
     let mut matches: Vec<Matched> = Vec::new();
-    for mat in ac.find_iter(text) {
-        let term = &patterns[mat.pattern()];
+    for mat in matcher.find_iter(text) {
+        let term = &patterns[mat.pattern];
         let normalized_term = thesaurus
             .get(term)
             .ok_or_else(|| TerraphimAutomataError::Dict(format!("Unknown term {term}")))?;
@@ -32,17 +55,55 @@ pub fn find_matches(
         matches.push(Matched {
             term: term.to_string(),
             normalized_term: normalized_term.clone(),
-            pos: if return_positions {
-                Some((mat.start(), mat.end()))
-            } else {
-                None
-            },
+            pos: Some((mat.start, mat.end)),
         });
     }
+
+    let pattern_matches = PatternMatcher::from_thesaurus(thesaurus)?
+        .find_iter(text)
+        .into_iter()
+        .map(|mat| Matched {
+            term: text[mat.start..mat.end].to_string(),
+            normalized_term: thesaurus.patterns()[mat.pattern].normalized_term.clone(),
+            pos: Some((mat.start, mat.end)),
+        })
+        .collect();
+    let mut matches = merge_matches(matches, pattern_matches, |mat| mat.pos.unwrap_or_default());
+    if !return_positions {
+        for mat in &mut matches {
+            mat.pos = None;
+        }
+    }
     Ok(matches)
 }
 
-// // This function replacing instead of matching patterns
+/// Like [`find_matches`], but for a [`CompactThesaurus`]
+pub fn find_matches_compact(
+    text: &str,
+    thesaurus: &CompactThesaurus,
+    return_positions: bool,
+) -> Result<Vec<Matched>> {
+    let (patterns, normalized_terms): (Vec<String>, Vec<&NormalizedTerm>) =
+        thesaurus.terms().into_iter().unzip();
+
+    let ac = AhoCorasick::builder()
+        .match_kind(MatchKind::LeftmostLongest)
+        .ascii_case_insensitive(true)
+        .build(&patterns)?;
+
+    Ok(ac
+        .find_iter(text)
+        .map(|mat| Matched {
+            term: patterns[mat.pattern()].clone(),
+            normalized_term: normalized_terms[mat.pattern()].clone(),
+            pos: return_positions.then(|| (mat.start(), mat.end())),
+        })
+        .collect())
+}
+
+/// Replace the terms of a thesaurus in a text with the IDs of their concepts
+///
+/// Text matched by the patterns of the thesaurus is replaced too.
 pub fn replace_matches(text: &str, thesaurus: Thesaurus) -> Result<Vec<u8>> {
     let mut patterns: Vec<String> = Vec::new();
     let mut replace_with: Vec<String> = Vec::new();
@@ -55,8 +116,51 @@ pub fn replace_matches(text: &str, thesaurus: Thesaurus) -> Result<Vec<u8>> {
         .ascii_case_insensitive(true)
         .build(patterns)?;
 
-    let result = ac.replace_all_bytes(text.as_bytes(), &replace_with);
+    let pattern_matcher = PatternMatcher::from_thesaurus(&thesaurus)?;
+    if pattern_matcher.is_empty() {
+        let result = ac.replace_all_bytes(text.as_bytes(), &replace_with);
+        return Ok(result);
+    }
+
+    let literal = ac
+        .find_iter(text)
+        .map(|mat| (mat.start(), mat.end(), replace_with[mat.pattern()].as_str()))
+        .collect();
+    let pattern_ids: Vec<String> = thesaurus
+        .patterns()
+        .iter()
+        .map(|entry| entry.normalized_term.id.to_string())
+        .collect();
+    let matched = pattern_matcher
+        .find_iter(text)
+        .into_iter()
+        .map(|mat| (mat.start, mat.end, pattern_ids[mat.pattern].as_str()))
+        .collect();
+
+    let mut result = Vec::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end, id) in merge_matches(literal, matched, |mat| (mat.0, mat.1)) {
+        result.extend_from_slice(&text.as_bytes()[last..start]);
+        result.extend_from_slice(id.as_bytes());
+        last = end;
+    }
+    result.extend_from_slice(&text.as_bytes()[last..]);
     Ok(result)
 }
 
+/// Like [`replace_matches`], but for a [`CompactThesaurus`]
+pub fn replace_matches_compact(text: &str, thesaurus: &CompactThesaurus) -> Result<Vec<u8>> {
+    let (patterns, replace_with): (Vec<String>, Vec<String>) = thesaurus
+        .terms()
+        .into_iter()
+        .map(|(term, normalized_term)| (term, normalized_term.id.to_string()))
+        .unzip();
+    let ac = AhoCorasick::builder()
+        .match_kind(MatchKind::LeftmostLongest)
+        .ascii_case_insensitive(true)
+        .build(patterns)?;
+
+    Ok(ac.replace_all_bytes(text.as_bytes(), &replace_with))
+}
+
 // tests
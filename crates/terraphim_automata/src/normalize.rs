@@ -0,0 +1,253 @@
+//! Unicode-aware normalization of terms and text for matching
+//!
+//! Aho-Corasick automata only fold ASCII case, so "STRASSE" matches
+//! "strasse", but "Straße" doesn't match "strasse", "Café" doesn't match
+//! "cafe" and "operators" doesn't match "operator". A [`Normalization`]
+//! folds the case of all of Unicode and can strip diacritics and stem
+//! words; a [`TermMatcher`] applies it both to the terms of a thesaurus,
+//! when the automaton is built, and to the text matched, so both sides are
+//! always normalized the same way.
+//!
+//! Matches in normalized text are mapped back to byte offsets of the
+//! original text. A match which ends within a stemmed word extends to the
+//! end of the word, so "operator" matches all of "operators".
+//!
+//! Without a normalization a [`TermMatcher`] is a plain ASCII case
+//! insensitive automaton over the original text, as before.
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use rust_stemmers::Stemmer;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::language::Language;
+use crate::Result;
+
+/// How terms and text are normalized before they are matched
+///
+/// Case is always folded, for all of Unicode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Normalization {
+    /// Strip diacritics, so "café" matches "cafe"
+    #[serde(default)]
+    pub strip_diacritics: bool,
+    /// Stem words with the stemmer of this language, so "operators"
+    /// matches "operator"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stem: Option<Language>,
+}
+
+/// Normalized text with the offsets of the original text it came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NormalizedText {
+    pub text: String,
+    /// Offset in the original text of the character or stemmed word each
+    /// byte of the normalized text came from
+    starts: Vec<usize>,
+    /// Offset after that character or stemmed word
+    ends: Vec<usize>,
+}
+
+impl NormalizedText {
+    /// Byte offsets in the original text of a range of the normalized text
+    pub fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        (self.starts[start], self.ends[end - 1])
+    }
+
+    fn push(&mut self, text: &str, start: usize, end: usize) {
+        self.text.push_str(text);
+        self.starts.resize(self.text.len(), start);
+        self.ends.resize(self.text.len(), end);
+    }
+}
+
+impl Normalization {
+    /// Normalize a text, keeping track of the original offsets
+    pub fn normalize(&self, text: &str) -> NormalizedText {
+        let stemmer = self
+            .stem
+            .map(|language| Stemmer::create(language.stemmer_algorithm()));
+        let mut normalized = NormalizedText::default();
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if !c.is_alphanumeric() {
+                normalized.push(&self.fold(c), start, start + c.len_utf8());
+                continue;
+            }
+            let Some(stemmer) = &stemmer else {
+                normalized.push(&self.fold(c), start, start + c.len_utf8());
+                continue;
+            };
+            // Stem the whole word
+            let mut word = self.fold(c);
+            let mut end = start + c.len_utf8();
+            while let Some((offset, c)) = chars.next_if(|(_, c)| c.is_alphanumeric()) {
+                word.push_str(&self.fold(c));
+                end = offset + c.len_utf8();
+            }
+            normalized.push(&stemmer.stem(&word), start, end);
+        }
+        normalized
+    }
+
+    /// Normalize a term of a thesaurus, the pattern it is matched with
+    pub fn normalize_term(&self, term: &str) -> String {
+        self.normalize(term).text
+    }
+
+    /// Fold the case of a character and strip its diacritics if enabled
+    fn fold(&self, c: char) -> String {
+        let folded: String = match c {
+            // Lowercase, but not case folded, by `char::to_lowercase`
+            'ß' | 'ẞ' => "ss".to_string(),
+            _ => c.to_lowercase().collect(),
+        };
+        if self.strip_diacritics {
+            folded.nfd().filter(|c| !is_combining_mark(*c)).collect()
+        } else {
+            folded
+        }
+    }
+}
+
+/// A match of a [`TermMatcher`], with byte offsets into the original text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermMatch {
+    /// Index of the matched pattern
+    pub pattern: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// An Aho-Corasick automaton over terms normalized with a [`Normalization`]
+///
+/// Matches are leftmost-longest.
+#[derive(Debug, Clone)]
+pub struct TermMatcher {
+    ac: AhoCorasick,
+    normalization: Option<Normalization>,
+}
+
+impl TermMatcher {
+    /// Build a matcher for terms, in the order of their pattern indices
+    pub fn new<I, P>(terms: I, normalization: Option<Normalization>) -> Result<Self>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let builder = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .ascii_case_insensitive(normalization.is_none())
+            .clone();
+        let ac = match &normalization {
+            Some(normalization) => builder.build(
+                terms
+                    .into_iter()
+                    .map(|term| normalization.normalize_term(term.as_ref())),
+            )?,
+            None => builder.build(terms.into_iter().map(|term| term.as_ref().to_string()))?,
+        };
+        Ok(Self { ac, normalization })
+    }
+
+    /// The normalization of the matcher
+    pub fn normalization(&self) -> Option<&Normalization> {
+        self.normalization.as_ref()
+    }
+
+    /// Non-overlapping matches of the terms in a text, in order
+    pub fn find_iter(&self, text: &str) -> Vec<TermMatch> {
+        match &self.normalization {
+            Some(normalization) => {
+                let normalized = normalization.normalize(text);
+                self.ac
+                    .find_iter(&normalized.text)
+                    .map(|mat| {
+                        let (start, end) = normalized.original_range(mat.start(), mat.end());
+                        TermMatch {
+                            pattern: mat.pattern().as_usize(),
+                            start,
+                            end,
+                        }
+                    })
+                    .collect()
+            }
+            None => self
+                .ac
+                .find_iter(text)
+                .map(|mat| TermMatch {
+                    pattern: mat.pattern().as_usize(),
+                    start: mat.start(),
+                    end: mat.end(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The form of a term, or of matched text, the matcher compares
+    pub fn normalize_term(&self, term: &str) -> String {
+        match &self.normalization {
+            Some(normalization) => normalization.normalize_term(term),
+            None => term.to_ascii_lowercase(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalization() {
+        let folding = Normalization::default();
+        assert_eq!(folding.normalize_term("Straße"), "strasse");
+        assert_eq!(folding.normalize_term("ÉCOLE"), "école");
+
+        let plain = Normalization {
+            strip_diacritics: true,
+            ..Default::default()
+        };
+        assert_eq!(plain.normalize_term("Crème Brûlée"), "creme brulee");
+
+        let english = Normalization {
+            stem: Some(Language::English),
+            ..Default::default()
+        };
+        assert_eq!(
+            english.normalize_term("Trained Operators"),
+            english.normalize_term("trained operator")
+        );
+    }
+
+    #[test]
+    fn test_term_matcher() {
+        let terms = ["strasse", "cafe", "trained operator"];
+        let text = "Die STRASSE, die Straße, das Café; trained operators.";
+
+        let ascii = TermMatcher::new(terms, None).unwrap();
+        let matched = |matcher: &TermMatcher| -> Vec<&str> {
+            matcher
+                .find_iter(text)
+                .into_iter()
+                .map(|mat| &text[mat.start..mat.end])
+                .collect()
+        };
+        assert_eq!(matched(&ascii), vec!["STRASSE", "trained operator"]);
+
+        let normalization = Normalization {
+            strip_diacritics: true,
+            stem: Some(Language::English),
+        };
+        let unicode = TermMatcher::new(terms, Some(normalization)).unwrap();
+        assert_eq!(
+            matched(&unicode),
+            vec!["STRASSE", "Straße", "Café", "trained operators"]
+        );
+        assert_eq!(unicode.find_iter(text)[1].pattern, 0);
+        assert_eq!(
+            unicode.normalize_term("Operators"),
+            unicode.normalize_term("operator")
+        );
+    }
+}
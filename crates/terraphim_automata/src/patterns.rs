@@ -0,0 +1,158 @@
+//! Matching the regular expressions of a thesaurus
+//!
+//! Next to its literal terms, a thesaurus can hold patterns such as
+//! `PROJ-\d+` for ticket IDs or `v\d+\.\d+\.\d+` for versions, see
+//! [`Thesaurus::insert_pattern`](terraphim_types::Thesaurus::insert_pattern).
+//! A [`PatternMatcher`] finds them with a [`RegexSet`], which tells in one
+//! pass over a text which patterns occur in it, so only those are searched
+//! again for the positions of their matches.
+//!
+//! Matches of patterns and of literal terms are merged leftmost-longest, as
+//! the Aho-Corasick automata match terms: of overlapping matches the one
+//! which starts first wins, and of those the longest, a literal term on a
+//! tie.
+
+use regex::{Regex, RegexSet};
+use terraphim_types::Thesaurus;
+
+use crate::normalize::TermMatch;
+use crate::Result;
+
+/// The regular expressions of a thesaurus, compiled
+#[derive(Debug, Clone)]
+pub struct PatternMatcher {
+    set: RegexSet,
+    regexes: Vec<Regex>,
+}
+
+impl PatternMatcher {
+    /// Compile patterns; the index of a pattern is the `pattern` of its
+    /// matches
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let regexes = patterns
+            .into_iter()
+            .map(Regex::new)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let set = RegexSet::new(regexes.iter().map(|regex| regex.as_str()))?;
+        Ok(Self { set, regexes })
+    }
+
+    /// Compile the patterns of a thesaurus, in the order of
+    /// [`Thesaurus::patterns`]
+    pub fn from_thesaurus(thesaurus: &Thesaurus) -> Result<Self> {
+        Self::new(
+            thesaurus
+                .patterns()
+                .iter()
+                .map(|entry| entry.pattern.as_str()),
+        )
+    }
+
+    /// Whether there are no patterns
+    pub fn is_empty(&self) -> bool {
+        self.regexes.is_empty()
+    }
+
+    /// The non-overlapping matches of the patterns in a text, in order
+    ///
+    /// Empty matches are left out.
+    pub fn find_iter(&self, text: &str) -> Vec<TermMatch> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let matches = self
+            .set
+            .matches(text)
+            .into_iter()
+            .flat_map(|pattern| {
+                self.regexes[pattern]
+                    .find_iter(text)
+                    .filter(|mat| !mat.is_empty())
+                    .map(move |mat| TermMatch {
+                        pattern,
+                        start: mat.start(),
+                        end: mat.end(),
+                    })
+            })
+            .collect();
+        merge_matches(Vec::new(), matches, |mat| (mat.start, mat.end))
+    }
+}
+
+/// Merge the matches of patterns into those of literal terms, leftmost-longest
+///
+/// `span` is the start and end of a match; the merged matches don't overlap
+/// and are in the order of the text.
+pub(crate) fn merge_matches<T>(
+    literal: Vec<T>,
+    patterns: Vec<T>,
+    span: impl Fn(&T) -> (usize, usize),
+) -> Vec<T> {
+    if patterns.is_empty() {
+        return literal;
+    }
+    let mut matches = literal;
+    matches.extend(patterns);
+    // Stable, so literal terms come first on a tie
+    matches.sort_by(|a, b| {
+        let (a, b) = (span(a), span(b));
+        a.0.cmp(&b.0).then_with(|| b.1.cmp(&a.1))
+    });
+    let mut end = 0;
+    matches.retain(|mat| {
+        let (start, mat_end) = span(mat);
+        let keep = start >= end;
+        if keep {
+            end = mat_end;
+        }
+        keep
+    });
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{find_matches, replace_matches};
+    use terraphim_types::{NormalizedTerm, NormalizedTermValue};
+
+    fn thesaurus() -> Thesaurus {
+        let concept = |id, value: &str| NormalizedTerm::new(id, NormalizedTermValue::from(value));
+        let mut thesaurus = Thesaurus::new("patterns".to_string());
+        thesaurus.insert("release".into(), concept(1, "release"));
+        thesaurus.insert("proj-1 fix".into(), concept(3, "fix"));
+        thesaurus.insert_pattern(r"PROJ-\d+".to_string(), concept(2, "ticket"));
+        thesaurus.insert_pattern(r"v\d+\.\d+\.\d+".to_string(), concept(1, "release"));
+        thesaurus
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let text = "Release v1.2.0 closes PROJ-42, not proj-7, and PROJ-1 fix";
+        let matches = find_matches(text, thesaurus(), true).unwrap();
+        let found: Vec<(&str, u64, (usize, usize))> = matches
+            .iter()
+            .map(|mat| (mat.term.as_str(), mat.normalized_term.id, mat.pos.unwrap()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("release", 1, (0, 7)),
+                ("v1.2.0", 1, (8, 14)),
+                ("PROJ-42", 2, (22, 29)),
+                // The literal term is longer than the ticket ID
+                ("proj-1 fix", 3, (47, 57)),
+            ]
+        );
+
+        let replaced = replace_matches(text, thesaurus()).unwrap();
+        assert_eq!(
+            String::from_utf8(replaced).unwrap(),
+            "1 1 closes 2, not proj-7, and 3"
+        );
+
+        let mut invalid = thesaurus();
+        invalid.insert_pattern("(unclosed".to_string(), NormalizedTerm::new(4, "x".into()));
+        assert!(find_matches(text, invalid, false).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Word index for autocompleting phrases typed in any word order
+//!
+//! Prefix search only finds `terraphim graph scorer` while the words are
+//! typed in the order of the term. A [`PhraseIndex`] maps every word of a
+//! term to the terms containing it, so `graph terraphim` finds the term
+//! too: each typed word has to start a different word of the term.
+
+use ahash::AHashSet;
+
+use crate::language::words;
+
+/// Terms by their words
+#[derive(Debug, Clone, Default)]
+pub struct PhraseIndex<T> {
+    terms: Vec<(String, T)>,
+    /// Words of the terms in lexicographic order, with the terms
+    /// containing them
+    words: Vec<(String, Vec<u32>)>,
+}
+
+impl<T> PhraseIndex<T> {
+    /// Index terms with a value each, e.g. their concept
+    pub fn new(terms: impl IntoIterator<Item = (String, T)>) -> Self {
+        let terms: Vec<(String, T)> = terms.into_iter().collect();
+        let mut postings: Vec<(String, u32)> = terms
+            .iter()
+            .enumerate()
+            .flat_map(|(index, (term, _))| words(term).map(move |word| (word, index as u32)))
+            .collect();
+        postings.sort_unstable();
+        postings.dedup();
+
+        let mut words: Vec<(String, Vec<u32>)> = Vec::new();
+        for (word, index) in postings {
+            match words.last_mut() {
+                Some((last, indices)) if *last == word => indices.push(index),
+                _ => words.push((word, vec![index])),
+            }
+        }
+        Self { terms, words }
+    }
+
+    /// Terms with a word starting with `word`
+    fn starting_with(&self, word: &str) -> AHashSet<u32> {
+        let start = self
+            .words
+            .partition_point(|(known, _)| known.as_str() < word);
+        self.words[start..]
+            .iter()
+            .take_while(|(known, _)| known.starts_with(word))
+            .flat_map(|(_, indices)| indices.iter().copied())
+            .collect()
+    }
+
+    /// Terms which have a word starting with each word of `query`, a
+    /// different one for each
+    ///
+    /// Returns at most `limit` terms, the shortest first. Queries of a
+    /// single word find nothing, as prefix search already covers them.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(&str, &T)> {
+        let query: Vec<String> = words(query).collect();
+        if query.len() < 2 {
+            return Vec::new();
+        }
+        let mut candidates = self.starting_with(&query[0]);
+        for word in &query[1..] {
+            let found = self.starting_with(word);
+            candidates.retain(|index| found.contains(index));
+        }
+
+        let mut found: Vec<(&str, &T)> = candidates
+            .into_iter()
+            .map(|index| {
+                let (term, value) = &self.terms[index as usize];
+                (term.as_str(), value)
+            })
+            .filter(|(term, _)| matches_distinct_words(&query, term))
+            .collect();
+        found.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+        found.truncate(limit);
+        found
+    }
+
+    /// Approximate heap memory used by the index in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.terms.capacity() * std::mem::size_of::<(String, T)>()
+            + self.terms.iter().map(|(term, _)| term.len()).sum::<usize>()
+            + self
+                .words
+                .iter()
+                .map(|(word, indices)| {
+                    std::mem::size_of::<(String, Vec<u32>)>() + word.len() + indices.capacity() * 4
+                })
+                .sum::<usize>()
+    }
+}
+
+/// Whether each word of `query` starts a different word of `term`
+///
+/// Assigns words greedily, longest query word first. This is exact: two
+/// query words starting the same word of the term are prefixes of one
+/// another, so a shorter query word starts every word a longer one does,
+/// and whichever of them the longer one takes makes no difference to the
+/// rest.
+fn matches_distinct_words(query: &[String], term: &str) -> bool {
+    let mut unused: Vec<String> = words(term).collect();
+    let mut query: Vec<&String> = query.iter().collect();
+    query.sort_by_key(|word| std::cmp::Reverse(word.len()));
+    query.into_iter().all(|word| {
+        match unused
+            .iter()
+            .position(|known| known.starts_with(word.as_str()))
+        {
+            Some(position) => {
+                unused.swap_remove(position);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search() {
+        let index = PhraseIndex::new(
+            [
+                "terraphim graph scorer",
+                "terraphim graph",
+                "graph theory",
+                "graph of graphs",
+                "terraphim",
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(id, term)| (term.to_string(), id)),
+        );
+        let found: Vec<&str> = index
+            .search("Graph terraph", 10)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect();
+        assert_eq!(found, vec!["terraphim graph", "terraphim graph scorer"]);
+
+        let found: Vec<(&str, &usize)> = index.search("scorer graph", 10);
+        assert_eq!(found, vec![("terraphim graph scorer", &0)]);
+        // Each word of the query needs a word of its own
+        let found: Vec<(&str, &usize)> = index.search("graph graph", 10);
+        assert_eq!(found, vec![("graph of graphs", &3)]);
+        assert!(index.search("terraphim terraphim", 10).is_empty());
+        assert_eq!(index.search("graph graphs", 10), found);
+        assert!(index.search("graphs graphs", 10).is_empty());
+        assert!(index.search("graph", 10).is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Spelling correction against dictionaries of known words.
+//!
+//! A [`TermDictionary`] counts the words of a corpus or a thesaurus. Words
+//! of a query which are in none of the dictionaries are replaced by the
+//! closest known word, measured in edits (insertions, deletions,
+//! substitutions and transpositions of adjacent characters). Ties are
+//! broken by how often the known words occur.
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use terraphim_types::Thesaurus;
+
+use crate::language::words;
+
+/// Words shorter than this are never corrected
+const MIN_WORD_LENGTH: usize = 3;
+
+/// Known words with the number of times they were seen
+#[derive(Debug, Clone, Default)]
+pub struct TermDictionary {
+    words: AHashMap<String, u64>,
+}
+
+impl TermDictionary {
+    /// Create an empty dictionary
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a dictionary of the words in the terms of a thesaurus
+    pub fn from_thesaurus(thesaurus: &Thesaurus) -> Self {
+        let mut dictionary = Self::new();
+        for (term, normalized_term) in thesaurus {
+            dictionary.add_text(term.as_str());
+            dictionary.add_text(normalized_term.value.as_str());
+        }
+        dictionary
+    }
+
+    /// Count the words of a text
+    pub fn add_text(&mut self, text: &str) {
+        for word in words(text) {
+            *self.words.entry(word).or_default() += 1;
+        }
+    }
+
+    /// Number of times a word was seen
+    pub fn count(&self, word: &str) -> u64 {
+        self.words.get(word).copied().unwrap_or_default()
+    }
+
+    /// Number of distinct words
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Check if the dictionary is empty
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+/// A corrected query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Correction {
+    /// The query as given
+    pub original: String,
+    /// The query with misspelled words replaced
+    pub corrected: String,
+    /// How likely the correction is right, from 0 to 1
+    pub confidence: f64,
+}
+
+/// Number of edits to turn `a` into `b`, counting the transposition of two
+/// adjacent characters as one edit
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Three rows of the distance matrix: two rows back, the previous row and
+    // the current row
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Maximum number of edits to correct a word of the given length
+fn max_distance(length: usize) -> usize {
+    if length <= 4 {
+        1
+    } else {
+        2
+    }
+}
+
+/// The closest known word to `word` with the confidence of the correction
+///
+/// Returns `None` if the word is known, too short or has no known word
+/// close enough.
+pub fn correct_word(word: &str, dictionaries: &[&TermDictionary]) -> Option<(String, f64)> {
+    let length = word.chars().count();
+    if length < MIN_WORD_LENGTH
+        || dictionaries
+            .iter()
+            .any(|dictionary| dictionary.count(word) > 0)
+    {
+        return None;
+    }
+    let max = max_distance(length);
+
+    let mut counts: AHashMap<&str, u64> = AHashMap::new();
+    for dictionary in dictionaries {
+        for (known, count) in &dictionary.words {
+            *counts.entry(known.as_str()).or_default() += count;
+        }
+    }
+    let mut best_distance = usize::MAX;
+    let mut candidates: Vec<(&str, u64)> = Vec::new();
+    for (known, count) in counts {
+        if known.chars().count().abs_diff(length) > max {
+            continue;
+        }
+        let distance = edit_distance(word, known);
+        if distance > max || distance > best_distance {
+            continue;
+        }
+        if distance < best_distance {
+            best_distance = distance;
+            candidates.clear();
+        }
+        candidates.push((known, count));
+    }
+    // The most frequent candidate wins, then the first in alphabetical order
+    candidates.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    let (best, best_count) = *candidates.first()?;
+
+    let total: u64 = candidates.iter().map(|(_, count)| count).sum();
+    let longest = length.max(best.chars().count()) as f64;
+    let similarity = 1.0 - best_distance as f64 / longest;
+    let share = best_count as f64 / total as f64;
+    Some((best.to_string(), similarity * share))
+}
+
+/// Correct the misspelled words of a query
+///
+/// Returns `None` if no word needs correction. The confidence of the
+/// correction is the confidence of the least certain corrected word.
+pub fn correct(query: &str, dictionaries: &[&TermDictionary]) -> Option<Correction> {
+    let mut corrected_words = Vec::new();
+    let mut confidence: Option<f64> = None;
+    for word in words(query) {
+        match correct_word(&word, dictionaries) {
+            Some((corrected, word_confidence)) => {
+                confidence = Some(confidence.map_or(word_confidence, |c| c.min(word_confidence)));
+                corrected_words.push(corrected);
+            }
+            None => corrected_words.push(word),
+        }
+    }
+    Some(Correction {
+        original: query.to_string(),
+        corrected: corrected_words.join(" "),
+        confidence: confidence?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("operators", "operators"), 0);
+        assert_eq!(edit_distance("opertors", "operators"), 1);
+        assert_eq!(edit_distance("oeprators", "operators"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_correct() {
+        let mut corpus = TermDictionary::new();
+        corpus.add_text("Trained operators and maintainers. The operators maintain the plant.");
+        corpus.add_text("Operations of the plant");
+
+        let correction = correct("traned opertors", &[&corpus]).unwrap();
+        assert_eq!(correction.corrected, "trained operators");
+        assert!(correction.confidence > 0.8);
+
+        // Known and short words are left alone
+        assert_eq!(correct("trained operators", &[&corpus]), None);
+        assert_eq!(correct("to plant", &[&corpus]), None);
+        // Words without close known word are kept
+        assert_eq!(
+            correct("plantt xylophone", &[&corpus]).unwrap().corrected,
+            "plant xylophone"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_correct_with_thesaurus() {
+        let thesaurus = crate::load_thesaurus(&crate::AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let dictionary = TermDictionary::from_thesaurus(&thesaurus);
+        let correction = correct("projetc constrains", &[&dictionary]).unwrap();
+        assert_eq!(correction.corrected, "project constraints");
+    }
+}
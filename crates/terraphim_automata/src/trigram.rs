@@ -0,0 +1,120 @@
+//! Trigram index for autocompletion despite typos
+//!
+//! Prefix search finds nothing once the beginning of what was typed is
+//! wrong, e.g. `eust` for `rust`. A [`TrigramIndex`] maps every sequence
+//! of three characters of a term to the terms containing it, so terms
+//! sharing most of the trigrams of the query are found wherever the typo
+//! is.
+
+use ahash::{AHashMap, AHashSet};
+
+/// Share of the trigrams of a query a term must contain to be similar
+pub const MIN_SIMILARITY: f64 = 0.5;
+
+type Trigram = [char; 3];
+
+/// The distinct trigrams of a text
+fn trigrams(text: &str) -> AHashSet<Trigram> {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .windows(3)
+        .map(|window| [window[0], window[1], window[2]])
+        .collect()
+}
+
+/// Terms by their trigrams
+#[derive(Debug, Clone, Default)]
+pub struct TrigramIndex<T> {
+    terms: Vec<(String, T)>,
+    postings: AHashMap<Trigram, Vec<u32>>,
+}
+
+impl<T> TrigramIndex<T> {
+    /// Index terms with a value each, e.g. their concept
+    pub fn new(terms: impl IntoIterator<Item = (String, T)>) -> Self {
+        let terms: Vec<(String, T)> = terms.into_iter().collect();
+        let mut postings: AHashMap<Trigram, Vec<u32>> = AHashMap::new();
+        for (index, (term, _)) in terms.iter().enumerate() {
+            for trigram in trigrams(term) {
+                postings.entry(trigram).or_default().push(index as u32);
+            }
+        }
+        Self { terms, postings }
+    }
+
+    /// Terms containing at least [`MIN_SIMILARITY`] of the trigrams of
+    /// `query`, with the share they contain
+    ///
+    /// Returns at most `limit` terms, the most similar first, then the
+    /// shortest. Queries shorter than three characters have no trigrams
+    /// and find nothing.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(&str, &T, f64)> {
+        let query = trigrams(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut shared: AHashMap<u32, usize> = AHashMap::new();
+        for trigram in &query {
+            for index in self.postings.get(trigram).into_iter().flatten() {
+                *shared.entry(*index).or_default() += 1;
+            }
+        }
+        let mut similar: Vec<(&str, &T, f64)> = shared
+            .into_iter()
+            .map(|(index, count)| {
+                let (term, value) = &self.terms[index as usize];
+                (term.as_str(), value, count as f64 / query.len() as f64)
+            })
+            .filter(|(_, _, similarity)| *similarity >= MIN_SIMILARITY)
+            .collect();
+        similar.sort_by(|(a, _, a_similarity), (b, _, b_similarity)| {
+            b_similarity
+                .total_cmp(a_similarity)
+                .then(a.len().cmp(&b.len()))
+                .then(a.cmp(b))
+        });
+        similar.truncate(limit);
+        similar
+    }
+
+    /// Approximate heap memory used by the index in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.terms.capacity() * std::mem::size_of::<(String, T)>()
+            + self.terms.iter().map(|(term, _)| term.len()).sum::<usize>()
+            + self
+                .postings
+                .values()
+                .map(|terms| std::mem::size_of::<Trigram>() + terms.capacity() * 4)
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search() {
+        let index = TrigramIndex::new(
+            ["rust", "rustc", "trust", "python", "ru"]
+                .into_iter()
+                .enumerate()
+                .map(|(id, term)| (term.to_string(), id)),
+        );
+        let found: Vec<(&str, f64)> = index
+            .search("eustc", 10)
+            .into_iter()
+            .map(|(term, _, similarity)| (term, similarity))
+            .collect();
+        assert_eq!(found, vec![("rustc", 2.0 / 3.0)]);
+
+        let found: Vec<&str> = index
+            .search("eust", 10)
+            .into_iter()
+            .map(|(term, _, _)| term)
+            .collect();
+        assert_eq!(found, vec!["rust", "rustc", "trust"]);
+        assert!(index.search("ru", 10).is_empty());
+        assert!(index.search("java", 10).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! WASM bindings of `terraphim_automata`
+//!
+//! Autocomplete and knowledge graph matching for the browser, without a
+//! round trip to the server. The server hands out the thesaurus of a role as
+//! a serialized [`CompactThesaurus`] (`GET /roles/:role/automata`), which is
+//! loaded into an [`Automata`]:
+//!
+//! ```js
+//! import init, { Automata } from "terraphim_automata_wasm";
+//!
+//! await init();
+//! const response = await fetch("/roles/Engineer/automata");
+//! const automata = new Automata(new Uint8Array(await response.arrayBuffer()));
+//! automata.autocomplete("knowl", 10);
+//! ```
+//!
+//! Build with `wasm-pack build crates/terraphim_automata_wasm --target web`.
+
+use serde::Serialize;
+use terraphim_automata::{
+    find_matches_compact, link_matches_compact, replace_matches_compact, CompactThesaurus,
+    LinkOptions, Matched,
+};
+use wasm_bindgen::prelude::*;
+
+/// A term suggested for a prefix
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    pub term: String,
+    /// ID of the concept of the term
+    pub id: u64,
+    /// The normalized term of the concept
+    pub nterm: String,
+}
+
+/// The thesaurus of a role, for autocomplete and matching
+#[wasm_bindgen]
+pub struct Automata {
+    thesaurus: CompactThesaurus,
+}
+
+#[wasm_bindgen]
+impl Automata {
+    /// Load a thesaurus serialized by the server
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<Automata, JsError> {
+        Ok(Automata {
+            thesaurus: CompactThesaurus::from_bytes(bytes)?,
+        })
+    }
+
+    /// Name of the thesaurus
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.thesaurus.name().to_string()
+    }
+
+    /// Number of terms
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.thesaurus.len()
+    }
+
+    /// At most `limit` terms starting with `prefix`, as
+    /// `[{term, id, nterm}]`
+    pub fn autocomplete(&self, prefix: &str, limit: usize) -> Result<JsValue, JsError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.suggestions(prefix, limit),
+        )?)
+    }
+
+    /// Terms of the thesaurus in `text`, as
+    /// `[{term, normalized_term: {id, value}, pos: [start, end]}]`
+    ///
+    /// Positions are UTF-16 code unit offsets, like the indices of a
+    /// JavaScript string, so `text.slice(start, end)` is the matched text.
+    #[wasm_bindgen(js_name = findMatches)]
+    pub fn find_matches(&self, text: &str) -> Result<JsValue, JsError> {
+        Ok(serde_wasm_bindgen::to_value(&self.matches(text)?)?)
+    }
+
+    /// Replace the terms of the thesaurus in `text` by the IDs of their
+    /// concepts
+    #[wasm_bindgen(js_name = replaceMatches)]
+    pub fn replace_matches(&self, text: &str) -> Result<String, JsError> {
+        let replaced = replace_matches_compact(text, &self.thesaurus)?;
+        Ok(String::from_utf8_lossy(&replaced).into_owned())
+    }
+
+    /// Link the terms of the thesaurus in the prose of a Markdown `text` to
+    /// their concepts, leaving code, front matter, URLs and existing links
+    /// as they are
+    ///
+    /// Only the first `max_links_per_concept` occurrences of each concept
+    /// and at most `max_links` terms are linked, if given. `template` is the
+    /// format of the links, see [`LinkOptions::template`].
+    #[wasm_bindgen(js_name = linkMatches)]
+    pub fn link_matches(
+        &self,
+        text: &str,
+        max_links_per_concept: Option<usize>,
+        max_links: Option<usize>,
+        template: Option<String>,
+    ) -> Result<String, JsError> {
+        let options = LinkOptions {
+            max_links_per_concept,
+            max_links,
+            template,
+            ..Default::default()
+        };
+        Ok(link_matches_compact(text, &self.thesaurus, &options)?)
+    }
+}
+
+impl Automata {
+    fn suggestions(&self, prefix: &str, limit: usize) -> Vec<Suggestion> {
+        self.thesaurus
+            .autocomplete(prefix, limit)
+            .into_iter()
+            .map(|(term, normalized_term)| Suggestion {
+                term,
+                id: normalized_term.id,
+                nterm: normalized_term.value.to_string(),
+            })
+            .collect()
+    }
+
+    fn matches(&self, text: &str) -> terraphim_automata::Result<Vec<Matched>> {
+        let mut matches = find_matches_compact(text, &self.thesaurus, true)?;
+        to_utf16_positions(text, &mut matches);
+        Ok(matches)
+    }
+}
+
+/// Convert the byte offsets of `matches` into UTF-16 code unit offsets
+///
+/// The matches don't overlap and are in the order of the text, so the
+/// text is encoded once rather than from its start for every offset.
+fn to_utf16_positions(text: &str, matches: &mut [Matched]) {
+    let mut byte = 0;
+    let mut utf16 = 0;
+    let mut convert = |offset: usize| {
+        if offset < byte {
+            byte = 0;
+            utf16 = 0;
+        }
+        utf16 += text[byte..offset].encode_utf16().count();
+        byte = offset;
+        utf16
+    };
+    for mat in matches {
+        if let Some((start, end)) = mat.pos {
+            let start = convert(start);
+            mat.pos = Some((start, convert(end)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    fn automata() -> Automata {
+        let mut thesaurus = Thesaurus::new("Engineer".to_string());
+        for (term, id, nterm) in [
+            ("knowledge graph", 1, "knowledge graph"),
+            ("knowledge base", 1, "knowledge graph"),
+            ("café", 2, "café"),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::from(term),
+                NormalizedTerm::new(id, NormalizedTermValue::from(nterm)),
+            );
+        }
+        let bytes = CompactThesaurus::from_thesaurus(&thesaurus)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        Automata::new(&bytes).unwrap_or_else(|_| panic!("Failed to load the thesaurus"))
+    }
+
+    #[test]
+    fn test_new() {
+        let automata = automata();
+        assert_eq!(automata.name(), "Engineer");
+        assert_eq!(automata.length(), 3);
+    }
+
+    #[test]
+    fn test_autocomplete() {
+        let suggestions = automata().suggestions("knowl", 10);
+        let terms: Vec<&str> = suggestions.iter().map(|s| s.term.as_str()).collect();
+        assert_eq!(terms, ["knowledge base", "knowledge graph"]);
+        assert!(suggestions
+            .iter()
+            .all(|s| s.id == 1 && s.nterm == "knowledge graph"));
+        assert_eq!(automata().suggestions("knowl", 1).len(), 1);
+    }
+
+    #[test]
+    fn test_find_matches() {
+        // `é` is two bytes in UTF-8 but one code unit in UTF-16, `🦀` four
+        // bytes but two code units
+        let text = "🦀 café and knowledge graph";
+        let matches = automata().matches(text).unwrap();
+        let positions: Vec<_> = matches.iter().map(|mat| mat.pos.unwrap()).collect();
+        assert_eq!(positions, [(3, 7), (12, 27)]);
+
+        let utf16: Vec<u16> = text.encode_utf16().collect();
+        for (mat, (start, end)) in matches.iter().zip(positions) {
+            assert_eq!(String::from_utf16(&utf16[start..end]).unwrap(), mat.term);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Access control on documents and haystacks
+//!
+//! Haystacks and documents carry visibility labels. API principals are
+//! identified by their API key and are granted a set of labels. A document
+//! without labels is visible to everyone, a document with labels only to
+//! principals granted at least one of them. Documents found in a haystack
+//! carry the labels of the haystack.
+//!
+//! The knowledge graph of a role is built from all haystacks of the role,
+//! so only principals who may see every haystack of a role may look up its
+//! concepts.
+//!
+//! Without principals in the config, access control is off and everything
+//! is visible, like in the single-user desktop app.
+
+use serde::{Deserialize, Serialize};
+use terraphim_types::Document;
+
+use crate::{Config, Result, Role, TerraphimConfigError};
+
+/// A client of the API, identified by its API key
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// Name of the principal, for logs
+    pub name: String,
+    pub api_key: String,
+    /// Visibility labels the principal may see
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Admins see everything and may change the config
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// What a request may see
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// Everything, because access control is off or the principal is an
+    /// admin
+    #[default]
+    All,
+    /// Resources without labels and those with one of the given labels
+    Labels(Vec<String>),
+}
+
+impl Access {
+    /// Whether access is unrestricted
+    pub fn is_all(&self) -> bool {
+        matches!(self, Access::All)
+    }
+
+    /// Whether a resource with the given visibility labels is visible
+    pub fn allows(&self, labels: &[String]) -> bool {
+        match self {
+            Access::All => true,
+            Access::Labels(granted) => {
+                labels.is_empty() || labels.iter().any(|label| granted.contains(label))
+            }
+        }
+    }
+
+    /// Whether a document is visible
+    pub fn allows_document(&self, document: &Document) -> bool {
+        self.allows(&document.visibility)
+    }
+
+    /// Whether every haystack of a role, and so its knowledge graph, is
+    /// visible
+    pub fn allows_role(&self, role: &Role) -> bool {
+        role.haystacks
+            .iter()
+            .all(|haystack| self.allows(&haystack.visibility))
+    }
+
+    /// Whether all of the given labels are granted, so that a document
+    /// with these labels may be created
+    pub fn grants(&self, labels: &[String]) -> bool {
+        match self {
+            Access::All => true,
+            Access::Labels(granted) => labels.iter().all(|label| granted.contains(label)),
+        }
+    }
+}
+
+impl Config {
+    /// The access of a request with the given API key
+    ///
+    /// Requests without API key only see resources without labels, unless
+    /// access control is off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no principal has the API key.
+    pub fn access(&self, api_key: Option<&str>) -> Result<Access> {
+        if self.principals.is_empty() {
+            return Ok(Access::All);
+        }
+        let Some(api_key) = api_key else {
+            return Ok(Access::Labels(Vec::new()));
+        };
+        let principal = self
+            .principal(api_key)
+            .ok_or(TerraphimConfigError::UnknownApiKey)?;
+        log::debug!("Request by principal `{}`", principal.name);
+        if principal.admin {
+            Ok(Access::All)
+        } else {
+            Ok(Access::Labels(principal.labels.clone()))
+        }
+    }
+
+    /// The principal with the given API key
+    pub fn principal(&self, api_key: &str) -> Option<&Principal> {
+        self.principals
+            .iter()
+            .find(|principal| keys_match(&principal.api_key, api_key))
+    }
+}
+
+/// Compare API keys in time independent of where they differ
+fn keys_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|label| label.to_string()).collect()
+    }
+
+    fn config() -> Config {
+        Config {
+            principals: vec![
+                Principal {
+                    name: "finance".to_string(),
+                    api_key: "finance-key".to_string(),
+                    labels: labels(&["finance"]),
+                    admin: false,
+                },
+                Principal {
+                    name: "admin".to_string(),
+                    api_key: "admin-key".to_string(),
+                    labels: Vec::new(),
+                    admin: true,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_access() {
+        assert_eq!(Config::default().access(None).unwrap(), Access::All);
+
+        let config = config();
+        assert_eq!(config.access(Some("admin-key")).unwrap(), Access::All);
+        assert!(matches!(
+            config.access(Some("wrong-key")),
+            Err(TerraphimConfigError::UnknownApiKey)
+        ));
+
+        let anonymous = config.access(None).unwrap();
+        assert!(anonymous.allows(&[]));
+        assert!(!anonymous.allows(&labels(&["finance"])));
+
+        let finance = config.access(Some("finance-key")).unwrap();
+        assert!(finance.allows(&labels(&["finance"])));
+        assert!(finance.allows(&labels(&["hr", "finance"])));
+        assert!(!finance.allows(&labels(&["hr"])));
+        assert!(finance.grants(&labels(&["finance"])));
+        assert!(!finance.grants(&labels(&["hr", "finance"])));
+    }
+}
@@ -3,8 +3,8 @@ use std::path::PathBuf;
 
 use terraphim_automata::AutomataPath;
 use terraphim_config::{
-    ConfigBuilder, Haystack, KnowledgeGraph, KnowledgeGraphLocal, Result, Role, ServiceType,
-    TerraphimConfigError,
+    ConfigBuilder, Freshness, Haystack, KnowledgeGraph, KnowledgeGraphLocal, Result, Role,
+    ServiceType, TerraphimConfigError,
 };
 use terraphim_persistence::Persistable;
 use terraphim_types::{KnowledgeGraphInputType, RelevanceFunction};
@@ -29,11 +29,30 @@ async fn main() -> Result<()> {
                     knowledge_graph_local: None,
                     public: false,
                     publish: false,
+                    include_terms: Vec::new(),
+                    exclude_terms: Vec::new(),
+                    min_term_length: 0,
+                    max_kg_terms: None,
+                    max_links_per_concept: None,
+                    max_links: None,
+                    link_mode: None,
+                    link_template: None,
+                    link_definitions: false,
+                    normalization: None,
+                    centrality: None,
+                    edge_decay: None,
                 }),
                 haystacks: vec![Haystack {
                     path: PathBuf::from("localsearch"),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
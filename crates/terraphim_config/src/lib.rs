@@ -1,8 +1,12 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use terraphim_automata::{load_thesaurus, AutomataPath};
+use terraphim_automata::{load_thesaurus, AutomataPath, LinkOptions, Normalization};
 use terraphim_persistence::Persistable;
-use terraphim_rolegraph::{RoleGraph, RoleGraphSync};
+use terraphim_rolegraph::{CentralityBias, ConceptFilter, EdgeDecay, RoleGraph, RoleGraphSync};
 use terraphim_types::{
     Document, IndexedDocument, KnowledgeGraphInputType, RelevanceFunction, RoleName, SearchQuery,
 };
@@ -21,6 +25,10 @@ use opendal::Result as OpendalResult;
 type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
 use serde_json_any_key::*;
 
+pub mod access;
+
+pub use access::{Access, Principal};
+
 #[derive(Error, Debug)]
 pub enum TerraphimConfigError {
     #[error("Unable to load config")]
@@ -52,13 +60,19 @@ pub enum TerraphimConfigError {
 
     #[error("IO error")]
     Io(#[from] std::io::Error),
+
+    #[error("Invalid metadata: {0}")]
+    InvalidMetadata(String),
+
+    #[error("Unknown API key")]
+    UnknownApiKey,
 }
 
 /// A role is a collection of settings for a specific user
 ///
 /// It contains a user's knowledge graph, a list of haystacks, as
 /// well as preferences for the relevance function and theme
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Role {
     pub shortname: Option<String>,
     pub name: RoleName,
@@ -67,19 +81,222 @@ pub struct Role {
     pub theme: String,
     pub kg: Option<KnowledgeGraph>,
     pub haystacks: Vec<Haystack>,
+    /// Schema of the custom metadata (`Document::extra`) of documents
+    /// searched with this role
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata_schema: Vec<MetadataField>,
+    /// Optional stages of the search pipeline left out of searches with
+    /// this role
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_stages: Vec<SearchStageKind>,
+    /// Name of a scorer registered in the service which ranks search
+    /// results instead of the relevance function
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scorer: Option<String>,
+    /// Weights of the scorers of the hybrid relevance function; equal if
+    /// not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hybrid_weights: Option<HybridWeights>,
+    /// Parameters of BM25 ranking, used by the `bm25` and `hybrid`
+    /// relevance functions, and the recency boost, used by all; the common
+    /// defaults if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scoring: Option<ScoringParams>,
     #[serde(flatten)]
     pub extra: AHashMap<String, Value>,
 }
 
+impl Role {
+    /// Validate the custom metadata of a document against the metadata
+    /// schema of the role
+    ///
+    /// Fields which are not part of the schema are allowed.
+    pub fn validate_metadata(&self, metadata: &serde_json::Map<String, Value>) -> Result<()> {
+        for field in &self.metadata_schema {
+            match metadata.get(&field.name) {
+                Some(value) if !field.field_type.matches(value) => {
+                    return Err(TerraphimConfigError::InvalidMetadata(format!(
+                        "field `{}` of role `{}` must be of type {:?}, got `{value}`",
+                        field.name, self.name, field.field_type
+                    )));
+                }
+                None if field.required => {
+                    return Err(TerraphimConfigError::InvalidMetadata(format!(
+                        "field `{}` is required by role `{}`",
+                        field.name, self.name
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stage of the search pipeline which roles can leave out
+///
+/// Stages which keep documents a role may not see out of the results, or
+/// which apply the search query, can't be left out.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchStageKind {
+    /// Merge documents with the same content
+    Dedup,
+    /// Complete documents with their persisted copies
+    Enrichment,
+    /// Boost documents with the terms of `AND` queries close together
+    Proximity,
+    /// Count the documents linking to each document
+    Backlinks,
+    /// Boost documents by the recorded engagement with them
+    Feedback,
+    /// Find the matches of the search term
+    Highlighting,
+    /// Extract snippets around the matches
+    Snippets,
+}
+
+/// How the terms of the knowledge graph of a role are linked in the bodies
+/// of search results
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KgLinkMode {
+    /// Link the terms in the body as `[term](kg:concept)`
+    Inline,
+    /// Leave the body as it is and list the terms in `kg_concepts`, for
+    /// clients to link them their own way
+    Concepts,
+}
+
+/// Weights of the scorers whose rankings the hybrid relevance function
+/// fuses
+///
+/// Weights are relative to each other; a scorer with weight 0 is ignored.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct HybridWeights {
+    /// Weight of the similarity of titles to the search term
+    pub title: u32,
+    /// Weight of the BM25 score of titles and bodies
+    pub bm25: u32,
+    /// Weight of the rank in the knowledge graph
+    pub graph: u32,
+}
+
+impl Default for HybridWeights {
+    fn default() -> Self {
+        HybridWeights {
+            title: 1,
+            bm25: 1,
+            graph: 1,
+        }
+    }
+}
+
+/// Parameters of the ranking of search results: BM25 and the recency
+/// boost
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ScoringParams {
+    /// Saturation of the frequency of a term in a document, at least 0;
+    /// the higher, the more further occurrences of a term count
+    pub k1: f64,
+    /// Strength of the normalization by field length, from 0 (none) to
+    /// 1 (full)
+    pub b: f64,
+    /// Weights of the fields of documents
+    pub boosts: FieldBoosts,
+    /// Age in days at which the rank of a document is halved; documents
+    /// are not ranked by age if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recency_half_life_days: Option<f64>,
+}
+
+impl Default for ScoringParams {
+    fn default() -> Self {
+        ScoringParams {
+            k1: 1.2,
+            b: 0.75,
+            boosts: FieldBoosts::default(),
+            recency_half_life_days: None,
+        }
+    }
+}
+
+/// Weights of the fields of documents in BM25F ranking, at least 0
+///
+/// An occurrence of a term in a field with boost 2 counts twice as much as
+/// one in a field with boost 1; fields with boost 0 are ignored. Only the
+/// `bm25` and `hybrid` relevance functions rank by BM25F, so other roles
+/// ignore their boosts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct FieldBoosts {
+    pub title: f64,
+    pub body: f64,
+    pub description: f64,
+    pub tags: f64,
+}
+
+impl Default for FieldBoosts {
+    fn default() -> Self {
+        FieldBoosts {
+            title: 1.0,
+            body: 1.0,
+            description: 1.0,
+            tags: 1.0,
+        }
+    }
+}
+
+/// Type of a custom metadata field
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataType {
+    String,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl MetadataType {
+    /// Check whether a JSON value is of this type
+    pub fn matches(&self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (MetadataType::String, Value::String(_))
+                | (MetadataType::Number, Value::Number(_))
+                | (MetadataType::Boolean, Value::Bool(_))
+                | (MetadataType::Array, Value::Array(_))
+                | (MetadataType::Object, Value::Object(_))
+        )
+    }
+}
+
+/// Definition of a custom metadata field in the metadata schema of a role
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MetadataField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: MetadataType,
+    /// Documents without this field are rejected
+    #[serde(default)]
+    pub required: bool,
+}
+
 use anyhow::Context;
 /// The service used for indexing documents
 ///
 /// Each service assumes documents to be stored in a specific format
 /// and uses a specific indexing algorithm
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum ServiceType {
     /// Use ripgrep as the indexing service
     Ripgrep,
+    /// Use the indexer plugin registered under this name, see
+    /// `terraphim_middleware::indexer::IndexerRegistry`
+    Custom(String),
 }
 
 /// A haystack is a collection of documents that can be indexed and searched
@@ -92,6 +309,52 @@ pub struct Haystack {
     pub path: PathBuf,
     /// The service used for indexing documents in the haystack
     pub service: ServiceType,
+    /// How long cached copies of the documents in the haystack are served
+    #[serde(default, skip_serializing_if = "Freshness::is_default")]
+    pub freshness: Freshness,
+    /// Visibility labels of the documents in the haystack, see [`access`]
+    ///
+    /// Documents of a haystack without labels are visible to everyone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visibility: Vec<String>,
+}
+
+/// Staleness policy for cached copies of the documents in a haystack
+///
+/// Search results are completed with the persisted copies of their
+/// documents. Copies can be kept in memory, so that not every search reads
+/// them from the storage backends, at the risk of serving content which was
+/// edited elsewhere since.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Freshness {
+    /// Seconds a cached copy is served before it is reloaded
+    ///
+    /// Without a max age, copies aren't cached and are read on every search.
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Serve copies older than `max_age` and reload them in the background,
+    /// instead of waiting for the reload
+    #[serde(default)]
+    pub refresh_on_access: bool,
+    /// Seconds between background reloads of the cached copies
+    #[serde(default)]
+    pub refresh_interval: Option<u64>,
+}
+
+impl Freshness {
+    /// How long a cached copy is served before it is reloaded
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age.map(Duration::from_secs)
+    }
+
+    /// Time between background reloads of the cached copies
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval.map(Duration::from_secs)
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 /// A knowledge graph is the collection of documents which were indexed
@@ -104,12 +367,89 @@ pub struct KnowledgeGraph {
     pub knowledge_graph_local: Option<KnowledgeGraphLocal>,
     pub public: bool,
     pub publish: bool,
+    /// Terms documents are always tagged with when they contain them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_terms: Vec<String>,
+    /// Terms documents are never tagged with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_terms: Vec<String>,
+    /// Matches of terms shorter than this many characters don't count for
+    /// the tags of documents
+    #[serde(default)]
+    pub min_term_length: usize,
+    /// Maximum number of concept tags of a document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_kg_terms: Option<usize>,
+    /// Only link the first occurrences of each concept in a document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_links_per_concept: Option<usize>,
+    /// Maximum number of KG links in a document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_links: Option<usize>,
+    /// How terms are linked in the bodies of search results; they aren't
+    /// by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_mode: Option<KgLinkMode>,
+    /// Template of the links to concepts, e.g.
+    /// `https://wiki.internal/{concept}` or `[[{concept}]]`; `kg:` links if
+    /// not set, see [`LinkOptions::template`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_template: Option<String>,
+    /// Set the first sentence of the page of each concept in
+    /// `knowledge_graph_local` as the title of its links
+    #[serde(default)]
+    pub link_definitions: bool,
+    /// Unicode case folding, diacritic stripping and stemming of terms and
+    /// text before they are matched; only ASCII case is ignored if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<Normalization>,
+    /// Whether documents matching central concepts, which co-occur with
+    /// many others, rank higher (`surface`) or lower (`dampen`); all
+    /// concepts rank alike if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub centrality: Option<CentralityBias>,
+    /// Half-life of co-occurrences, e.g. `{"half_life_days": 180}`, so
+    /// that the `decay_edges` job weakens those of old documents; they are
+    /// kept as indexed if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edge_decay: Option<EdgeDecay>,
 }
 /// check KG set correctly
 impl KnowledgeGraph {
     fn is_set(&self) -> bool {
         self.automata_path.is_some() || self.knowledge_graph_local.is_some()
     }
+
+    /// Which terms documents are tagged with, see [`ConceptFilter`]
+    pub fn concept_filter(&self) -> ConceptFilter {
+        let default = ConceptFilter::default();
+        ConceptFilter {
+            include_terms: self.include_terms.clone(),
+            exclude_terms: self.exclude_terms.clone(),
+            min_term_length: self.min_term_length,
+            max_terms: self.max_kg_terms.unwrap_or(default.max_terms),
+        }
+    }
+
+    /// How many terms of a document are linked to their concepts, see
+    /// [`LinkOptions`]
+    pub fn link_options(&self) -> LinkOptions {
+        LinkOptions {
+            max_links_per_concept: self.max_links_per_concept,
+            max_links: self.max_links,
+            template: self.link_template.clone(),
+            normalization: self.normalization,
+        }
+    }
+
+    /// The directory of the concept pages whose definitions are set as the
+    /// titles of KG links, if the role links definitions
+    pub fn definitions_path(&self) -> Option<&Path> {
+        match &self.knowledge_graph_local {
+            Some(local) if self.link_definitions => Some(local.path.as_path()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -190,7 +530,14 @@ impl ConfigBuilder {
                 haystacks: vec![Haystack {
                     path: system_operator_haystack.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -209,11 +556,30 @@ impl ConfigBuilder {
                     }),
                     public: true,
                     publish: true,
+                    include_terms: Vec::new(),
+                    exclude_terms: Vec::new(),
+                    min_term_length: 0,
+                    max_kg_terms: None,
+                    max_links_per_concept: None,
+                    max_links: None,
+                    link_mode: None,
+                    link_template: None,
+                    link_definitions: false,
+                    normalization: None,
+                    centrality: None,
+                    edge_decay: None,
                 }),
                 haystacks: vec![Haystack {
                     path: system_operator_haystack.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -232,11 +598,30 @@ impl ConfigBuilder {
                     }),
                     public: true,
                     publish: true,
+                    include_terms: Vec::new(),
+                    exclude_terms: Vec::new(),
+                    min_term_length: 0,
+                    max_kg_terms: None,
+                    max_links_per_concept: None,
+                    max_links: None,
+                    link_mode: None,
+                    link_template: None,
+                    link_definitions: false,
+                    normalization: None,
+                    centrality: None,
+                    edge_decay: None,
                 }),
                 haystacks: vec![Haystack {
                     path: system_operator_haystack.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -267,7 +652,14 @@ impl ConfigBuilder {
                 haystacks: vec![Haystack {
                     path: docs_path.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -282,7 +674,14 @@ impl ConfigBuilder {
                 haystacks: vec![Haystack {
                     path: docs_path.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -303,11 +702,30 @@ impl ConfigBuilder {
                     }),
                     public: true,
                     publish: true,
+                    include_terms: Vec::new(),
+                    exclude_terms: Vec::new(),
+                    min_term_length: 0,
+                    max_kg_terms: None,
+                    max_links_per_concept: None,
+                    max_links: None,
+                    link_mode: None,
+                    link_template: None,
+                    link_definitions: false,
+                    normalization: None,
+                    centrality: None,
+                    edge_decay: None,
                 }),
                 haystacks: vec![Haystack {
                     path: docs_path.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -326,11 +744,30 @@ impl ConfigBuilder {
                     }),
                     public: true,
                     publish: true,
+                    include_terms: Vec::new(),
+                    exclude_terms: Vec::new(),
+                    min_term_length: 0,
+                    max_kg_terms: None,
+                    max_links_per_concept: None,
+                    max_links: None,
+                    link_mode: None,
+                    link_template: None,
+                    link_definitions: false,
+                    normalization: None,
+                    centrality: None,
+                    edge_decay: None,
                 }),
                 haystacks: vec![Haystack {
                     path: docs_path.clone(),
                     service: ServiceType::Ripgrep,
+                    freshness: Freshness::default(),
+                    visibility: Vec::new(),
                 }],
+                metadata_schema: Vec::new(),
+                skip_stages: Vec::new(),
+                scorer: None,
+                hybrid_weights: None,
+                scoring: None,
                 extra: AHashMap::new(),
             },
         )
@@ -403,6 +840,32 @@ pub enum ConfigId {
     Embedded,
 }
 
+/// A recurring maintenance job
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Index the haystacks of all roles, for spelling corrections and
+    /// backlinks
+    Reindex,
+    /// Rebuild the thesauri of the roles ranked by their knowledge graph
+    RebuildThesaurus,
+    /// Delete attachments no persisted document references
+    CollectGarbage,
+    /// Run the saved searches which are due and send their alerts
+    SavedSearches,
+    /// Weaken the co-occurrences of the rolegraphs of roles with an
+    /// `edge_decay`, see [`RoleGraph::rebuild_with_decay`]
+    DecayEdges,
+}
+
+/// When a maintenance job runs
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct JobSchedule {
+    pub job: JobKind,
+    /// Seconds between two runs of the job
+    pub interval: u64,
+}
+
 /// The Terraphim config is the main configuration for terraphim
 ///
 /// It contains the global shortcut, roles, and the default role
@@ -417,7 +880,20 @@ pub struct Config {
     pub roles: AHashMap<RoleName, Role>,
     /// The default role to use if no role is specified
     pub default_role: RoleName,
-    pub selected_role: RoleName
+    pub selected_role: RoleName,
+    /// API principals and the visibility labels they may see, see
+    /// [`access`]
+    ///
+    /// Without principals, access control is off.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub principals: Vec<Principal>,
+    /// Recurring maintenance jobs of the server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub jobs: Vec<JobSchedule>,
+    /// Number of persisted copies and KG links of search results looked up
+    /// at a time; 16 if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrichment_concurrency: Option<usize>,
 }
 
 impl Config {
@@ -427,7 +903,10 @@ impl Config {
             global_shortcut: "Ctrl+X".to_string(),
             roles: AHashMap::new(),
             default_role: RoleName::new("default"),
-            selected_role: RoleName::new("default")
+            selected_role: RoleName::new("default"),
+            principals: Vec::new(),
+            jobs: Vec::new(),
+            enrichment_concurrency: None,
         }
     }
 }
@@ -501,7 +980,7 @@ impl ConfigState {
             // FIXME: this looks like local KG is never re-build
             // check if role have configured local KG or automata_path
             // skip role if incorrectly configured
-            if role.relevance_function == RelevanceFunction::TerraphimGraph {
+            if role.relevance_function.uses_graph() {
                 if role.kg.as_ref().is_some_and(|kg| kg.is_set()) {
                     //FIXME: turn into errors
                     log::info!("Role {} is configured correctly", role_name);
@@ -515,7 +994,25 @@ impl ConfigState {
                         .clone();
                     log::info!("Loading Role `{}` - URL: {:?}", role_name, automata_url);
                     let thesaurus = load_thesaurus(&automata_url).await?;
-                    let rolegraph = RoleGraph::new(role_name.clone(), thesaurus).await?;
+                    let normalization = role.kg.as_ref().and_then(|kg| kg.normalization);
+                    let centrality = role.kg.as_ref().and_then(|kg| kg.centrality);
+                    let edge_decay = role.kg.as_ref().and_then(|kg| kg.edge_decay);
+                    let mut rolegraph = RoleGraph::new(role_name.clone(), thesaurus)
+                        .await?
+                        .with_normalization(normalization)?
+                        .with_centrality_bias(centrality)
+                        .with_edge_decay(edge_decay);
+                    // Documents indexed before a restart are restored
+                    // instead of being indexed again
+                    match rolegraph.load_snapshot().await {
+                        Ok(true) => log::info!("Restored the rolegraph of role {}", role_name),
+                        Ok(false) => {}
+                        Err(e) => log::debug!(
+                            "Starting with an empty rolegraph for role {}: {:?}",
+                            role_name,
+                            e
+                        ),
+                    }
                     roles.insert(role_name.clone(), RoleGraphSync::from(rolegraph));
                 } else {
                     log::info!("Role {} is configured to use KG ranking but is missing remote url or local configuration", role_name );
@@ -547,11 +1044,31 @@ impl ConfigState {
 
         for rolegraph_state in self.roles.values() {
             let mut rolegraph = rolegraph_state.lock().await;
-            rolegraph.insert_document(&id, document.clone());
+            rolegraph.insert_document(&id, document);
         }
         Ok(())
     }
 
+    /// Persist snapshots of all rolegraphs, which [`ConfigState::new`]
+    /// restores, see [`terraphim_rolegraph::snapshot`]
+    ///
+    /// Returns the number of rolegraphs saved.
+    pub async fn save_rolegraphs(&self) -> usize {
+        let mut saved = 0;
+        for (role_name, rolegraph) in &self.roles {
+            let snapshot = rolegraph.lock().await.snapshot();
+            match snapshot.save().await {
+                Ok(()) => saved += 1,
+                Err(e) => log::warn!(
+                    "Failed to persist the rolegraph of role {}: {:?}",
+                    role_name,
+                    e
+                ),
+            }
+        }
+        saved
+    }
+
     /// Search documents in rolegraph index using matching Knowledge Graph
     /// If knowledge graph isn't defined for the role, RoleGraph isn't build for the role
     pub async fn search_indexed_documents(
@@ -662,7 +1179,14 @@ mod tests {
                     haystacks: vec![Haystack {
                         path: PathBuf::from("localsearch"),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -677,7 +1201,14 @@ mod tests {
                     haystacks: vec![Haystack {
                         path: PathBuf::from("localsearch"),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -696,11 +1227,30 @@ mod tests {
                         }),
                         public: true,
                         publish: true,
+                        include_terms: Vec::new(),
+                        exclude_terms: Vec::new(),
+                        min_term_length: 0,
+                        max_kg_terms: None,
+                        max_links_per_concept: None,
+                        max_links: None,
+                        link_mode: None,
+                        link_template: None,
+                        link_definitions: false,
+                        normalization: None,
+                        centrality: None,
+                        edge_decay: None,
                     }),
                     haystacks: vec![Haystack {
                         path: PathBuf::from("/tmp/system_operator/pages/"),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -740,11 +1290,30 @@ mod tests {
                 knowledge_graph_local: None,
                 public: true,
                 publish: true,
+                include_terms: Vec::new(),
+                exclude_terms: Vec::new(),
+                min_term_length: 0,
+                max_kg_terms: None,
+                max_links_per_concept: None,
+                max_links: None,
+                link_mode: None,
+                link_template: None,
+                link_definitions: false,
+                normalization: None,
+                centrality: None,
+                edge_decay: None,
             }),
             haystacks: vec![Haystack {
                 path: PathBuf::from("localsearch"),
                 service: ServiceType::Ripgrep,
+                freshness: Freshness::default(),
+                visibility: Vec::new(),
             }],
+            metadata_schema: Vec::new(),
+            skip_stages: Vec::new(),
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
             extra: AHashMap::new(),
         }
     }
@@ -763,6 +1332,41 @@ mod tests {
         assert_eq!(config.roles[&RoleName::new("Father")], dummy_role());
     }
 
+    #[test]
+    async fn test_validate_metadata() {
+        let role = Role {
+            metadata_schema: serde_json::from_str(
+                r#"[
+                    { "name": "status", "type": "string", "required": true },
+                    { "name": "story_points", "type": "number" }
+                ]"#,
+            )
+            .unwrap(),
+            ..dummy_role()
+        };
+        let metadata = |value: Value| value.as_object().unwrap().clone();
+
+        assert!(role
+            .validate_metadata(&metadata(serde_json::json!({
+                "status": "In Progress",
+                "story_points": 3,
+                "commit": "8d5f2a1"
+            })))
+            .is_ok());
+        assert!(matches!(
+            role.validate_metadata(&metadata(serde_json::json!({ "story_points": 3 }))),
+            Err(TerraphimConfigError::InvalidMetadata(_))
+        ));
+        assert!(matches!(
+            role.validate_metadata(&metadata(serde_json::json!({
+                "status": "Done",
+                "story_points": "three"
+            }))),
+            Err(TerraphimConfigError::InvalidMetadata(_))
+        ));
+        assert!(dummy_role().validate_metadata(&Default::default()).is_ok());
+    }
+
     ///test to create config with different id - server, desktop, embedded
     #[tokio::test]
     async fn test_config_with_id_desktop() {
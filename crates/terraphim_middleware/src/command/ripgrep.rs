@@ -173,10 +173,19 @@ impl RipgrepCommand {
     /// JSON output. Learn more about ripgrep's JSON output here:
     /// https://docs.rs/grep-printer/0.2.1/grep_printer/struct.JSON.html
     pub async fn run(&self, needle: &str, haystack: &Path) -> Result<Vec<Message>> {
-        // Merge the default arguments with the needle and haystack
-        let args: Vec<String> = vec![needle.to_string(), haystack.to_string_lossy().to_string()]
-            .into_iter()
-            .chain(self.default_args.clone())
+        // Merge the default arguments with the needle and haystack. The
+        // needle is passed with `-e` and the haystack after `--`, so that
+        // neither is parsed as a flag of ripgrep.
+        let args: Vec<String> = self
+            .default_args
+            .iter()
+            .cloned()
+            .chain([
+                "-e".to_string(),
+                needle.to_string(),
+                "--".to_string(),
+                haystack.to_string_lossy().to_string(),
+            ])
             .collect();
 
         let mut child = Command::new(&self.command)
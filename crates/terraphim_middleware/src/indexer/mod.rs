@@ -1,21 +1,42 @@
+use sha2::{Digest, Sha256};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::path::Path;
-use terraphim_config::{ConfigState, ServiceType};
-use terraphim_types::{Index, SearchQuery};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use terraphim_config::{ConfigState, Haystack, Role, ServiceType};
+use terraphim_types::{DocumentSource, Index, QueryType, RoleName, SearchQuery};
 
 use crate::{Error, Result};
 
+mod plugin;
 mod ripgrep;
 
+#[cfg(feature = "dynamic-indexers")]
+pub use plugin::PLUGIN_CONSTRUCTOR;
+pub use plugin::{IndexerPlugin, IndexerRegistry};
 pub use ripgrep::RipgrepIndexer;
 
+/// Minimum time between two snapshots of the rolegraphs saved after
+/// indexing, so searches don't persist the whole graph every time
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
 fn hash_as_string<T: Hash>(t: &T) -> String {
     let mut s = DefaultHasher::new();
     t.hash(&mut s);
     format!("{:x}", s.finish())
 }
 
+/// Hash of the body of a document, see
+/// [`terraphim_types::Document::content_hash`]
+///
+/// Line endings and surrounding whitespace are ignored, so copies of a file
+/// checked out on different platforms are duplicates, too.
+pub fn content_hash(body: &str) -> String {
+    let body = body.trim().replace("\r\n", "\n");
+    format!("{:x}", Sha256::digest(body.as_bytes()))
+}
+
 /// A Middleware is a service that creates an index of documents from
 /// a haystack.
 ///
@@ -37,44 +58,179 @@ pub trait IndexMiddleware {
 
 /// Use Middleware to search through haystacks and return an index of documents
 /// that match the search query.
+#[tracing::instrument(skip_all, fields(search_term = %search_query.search_term))]
 pub async fn search_haystacks(
-    mut config_state: ConfigState,
+    config_state: ConfigState,
     search_query: SearchQuery,
 ) -> Result<Index> {
     let config = config_state.config.lock().await.clone();
-    let search_query_role = search_query.role.unwrap_or(config.default_role);
-    let needle = search_query.search_term.as_str();
+    let search_query_role = search_query.role.clone().unwrap_or(config.default_role);
+    let role = config
+        .roles
+        .get(&search_query_role)
+        .ok_or_else(|| Error::RoleNotFound(search_query_role.to_string()))?;
 
-    let ripgrep = RipgrepIndexer::default();
     let mut full_index = Index::new();
+    let haystacks = role
+        .haystacks
+        .iter()
+        .filter(|haystack| search_query.searches_haystack(&haystack.path));
+    for haystack in haystacks {
+        // Documents found in several haystacks are merged, keeping all sources
+        full_index.merge(index_haystack(role, haystack, &needle(&search_query)).await?);
+    }
+    add_to_role(config_state, &search_query_role, &mut full_index).await;
+    Ok(full_index)
+}
 
+/// Search through a single haystack of the role of the search query
+///
+/// Like [`search_haystacks`], for callers which handle the documents of
+/// each haystack as soon as it is searched.
+#[tracing::instrument(skip_all, fields(search_term = %search_query.search_term))]
+pub async fn search_haystack(
+    config_state: ConfigState,
+    search_query: &SearchQuery,
+    haystack: &Haystack,
+) -> Result<Index> {
+    let config = config_state.config.lock().await.clone();
+    let search_query_role = search_query.role.clone().unwrap_or(config.default_role);
     let role = config
         .roles
         .get(&search_query_role)
         .ok_or_else(|| Error::RoleNotFound(search_query_role.to_string()))?;
 
-    for haystack in &role.haystacks {
-        log::info!("Finding documents in haystack: {:#?}", haystack);
+    let mut index = index_haystack(role, haystack, &needle(search_query)).await?;
+    add_to_role(config_state, &search_query_role, &mut index).await;
+    Ok(index)
+}
 
-        let index = match haystack.service {
-            ServiceType::Ripgrep => {
-                // Search through documents using ripgrep
-                // This indexes the haystack using the ripgrep middleware
-                ripgrep.index(needle, &haystack.path).await?
+/// The needle to search haystacks for
+///
+/// Needles are regular expressions. The search term of a regex query is
+/// passed through, search terms are found as they are. Queries with a term
+/// expression find the documents with any of its terms, which are then
+/// narrowed down to the documents matching the expression.
+fn needle(search_query: &SearchQuery) -> String {
+    if search_query.query_type == QueryType::Regex {
+        return search_query.search_term.to_string();
+    }
+    match &search_query.terms {
+        Some(terms) => terms
+            .terms()
+            .into_iter()
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join("|"),
+        None => regex::escape(search_query.search_term.as_str()),
+    }
+}
+
+/// Index the documents of a haystack matching the needle
+async fn index_haystack(role: &Role, haystack: &Haystack, needle: &str) -> Result<Index> {
+    log::info!("Finding documents in haystack: {:#?}", haystack);
+
+    let mut index = match &haystack.service {
+        ServiceType::Ripgrep => {
+            // Search through documents using ripgrep
+            // This indexes the haystack using the ripgrep middleware
+            RipgrepIndexer::default()
+                .index(needle, &haystack.path)
+                .await?
+        }
+        ServiceType::Custom(name) => {
+            let plugin = IndexerRegistry::instance()
+                .get(name)
+                .ok_or_else(|| Error::UnknownIndexer(name.clone()))?;
+            let mut index = plugin.index(needle, haystack).await?;
+            let source = DocumentSource {
+                haystack: haystack.path.display().to_string(),
+                service: name.clone(),
+            };
+            for document in index.values_mut() {
+                if !document.sources.contains(&source) {
+                    Arc::make_mut(document).add_source(source.clone());
+                }
+                if document.content_hash.is_none() {
+                    Arc::make_mut(document).content_hash = Some(content_hash(&document.body));
+                }
             }
-        };
+            index
+        }
+    };
 
-        for indexed_doc in index.values() {
-            if let Err(e) = config_state.add_to_roles(indexed_doc).await {
+    // Documents are visible to whoever may see their haystack
+    if !haystack.visibility.is_empty() {
+        for document in index.values_mut() {
+            Arc::make_mut(document).visibility = haystack.visibility.clone();
+        }
+    }
+
+    // Documents with metadata that doesn't match the schema of the role
+    // are left out of the index
+    index.retain(
+        |_id, document| match role.validate_metadata(&document.extra) {
+            Ok(()) => true,
+            Err(e) => {
                 log::warn!(
-                    "Failed to insert document `{}` ({}): {e:?}",
-                    indexed_doc.title,
-                    indexed_doc.url
+                    "Skipping document `{}` ({}): {e}",
+                    document.title,
+                    document.url
                 );
+                false
+            }
+        },
+    );
+    Ok(index)
+}
+
+/// Add the documents of an index to the rolegraphs and tag them with the
+/// concepts of the role
+async fn add_to_role(mut config_state: ConfigState, role_name: &RoleName, index: &mut Index) {
+    for indexed_doc in index.values() {
+        if let Err(e) = config_state.add_to_roles(indexed_doc).await {
+            log::warn!(
+                "Failed to insert document `{}` ({}): {e:?}",
+                indexed_doc.title,
+                indexed_doc.url
+            );
+        }
+    }
+
+    // Documents are tagged with the top-level concepts of the role they
+    // belong to, for browsing by concept
+    let filter = config_state
+        .get_role(role_name)
+        .await
+        .and_then(|role| role.kg)
+        .map(|kg| kg.concept_filter())
+        .unwrap_or_default();
+    if let Some(rolegraph) = config_state.roles.get(role_name) {
+        let rolegraph = rolegraph.lock().await;
+        let classifier = rolegraph.concept_classifier().with_filter(filter);
+        for document in index.values_mut() {
+            let concepts = classifier.classify(document);
+            if !concepts.is_empty() {
+                Arc::make_mut(document).add_tags(concepts);
             }
         }
+    }
 
-        full_index.extend(index);
+    if !index.is_empty() && snapshot_due() {
+        tokio::spawn(async move {
+            config_state.save_rolegraphs().await;
+        });
     }
-    Ok(full_index)
+}
+
+/// Whether the rolegraphs should be saved after indexing, which is at most
+/// once every [`SNAPSHOT_INTERVAL`]
+fn snapshot_due() -> bool {
+    static LAST_SNAPSHOT: Mutex<Option<Instant>> = Mutex::new(None);
+    let mut last = LAST_SNAPSHOT.lock().unwrap();
+    if last.is_some_and(|last| last.elapsed() < SNAPSHOT_INTERVAL) {
+        return false;
+    }
+    *last = Some(Instant::now());
+    true
 }
@@ -0,0 +1,134 @@
+//! Haystack indexers of third parties
+//!
+//! Indexers of haystacks which aren't plain files, e.g. proprietary wikis or
+//! internal APIs, implement [`IndexerPlugin`] and are registered with the
+//! [`IndexerRegistry`] under a name. Haystacks with the service
+//! `{"Custom": "<name>"}` are indexed by the plugin of that name.
+//!
+//! Plugins are registered by the application embedding Terraphim before the
+//! first search. With the `dynamic-indexers` feature, they can also be
+//! loaded from shared libraries, see [`IndexerRegistry::load`].
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use terraphim_config::Haystack;
+use terraphim_types::Index;
+
+use crate::Result;
+
+/// An indexer of a kind of haystack
+#[async_trait]
+pub trait IndexerPlugin: Send + Sync {
+    /// Name of the service the plugin indexes, as used in
+    /// `ServiceType::Custom`
+    fn name(&self) -> &str;
+
+    /// Index the documents of the haystack matching the needle
+    ///
+    /// An empty needle matches every document.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the plugin fails to index the haystack
+    async fn index(&self, needle: &str, haystack: &Haystack) -> Result<Index>;
+}
+
+/// The indexer plugins of this process, by name
+#[derive(Default)]
+pub struct IndexerRegistry {
+    plugins: RwLock<AHashMap<String, Arc<dyn IndexerPlugin>>>,
+}
+
+impl IndexerRegistry {
+    /// The registry of this process
+    pub fn instance() -> &'static IndexerRegistry {
+        static REGISTRY: OnceLock<IndexerRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(IndexerRegistry::default)
+    }
+
+    /// Register a plugin under its name
+    ///
+    /// Returns the plugin it replaces, if one was registered under the same
+    /// name.
+    pub fn register(&self, plugin: Arc<dyn IndexerPlugin>) -> Option<Arc<dyn IndexerPlugin>> {
+        let name = plugin.name().to_string();
+        log::info!("Registering indexer plugin `{name}`");
+        self.plugins.write().unwrap().insert(name, plugin)
+    }
+
+    /// Get the plugin registered under a name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn IndexerPlugin>> {
+        self.plugins.read().unwrap().get(name).cloned()
+    }
+
+    /// Names of the registered plugins, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.plugins.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// Name of the function a shared library exports to create its plugin, see
+/// [`crate::declare_indexer_plugin`]
+#[cfg(feature = "dynamic-indexers")]
+pub const PLUGIN_CONSTRUCTOR: &[u8] = b"_terraphim_indexer_plugin";
+
+/// Export an indexer plugin from a shared library for
+/// [`IndexerRegistry::load`]
+///
+/// The constructor must return the plugin, e.g.
+/// `declare_indexer_plugin!(WikiIndexer::default)`. The library must be
+/// built with the same compiler and version of this crate as the
+/// application loading it.
+#[macro_export]
+macro_rules! declare_indexer_plugin {
+    ($constructor:path) => {
+        #[no_mangle]
+        pub extern "C" fn _terraphim_indexer_plugin(
+        ) -> *mut ::std::boxed::Box<dyn $crate::indexer::IndexerPlugin> {
+            let plugin: ::std::boxed::Box<dyn $crate::indexer::IndexerPlugin> =
+                ::std::boxed::Box::new($constructor());
+            ::std::boxed::Box::into_raw(::std::boxed::Box::new(plugin))
+        }
+    };
+}
+
+#[cfg(feature = "dynamic-indexers")]
+impl IndexerRegistry {
+    /// Load the plugin of a shared library and register it
+    ///
+    /// The library stays loaded until the process exits. Returns the name
+    /// of the plugin.
+    ///
+    /// # Safety
+    ///
+    /// The library runs arbitrary code when loaded. It must export its
+    /// plugin with [`crate::declare_indexer_plugin`] and be built with the
+    /// same compiler and version of this crate as the application.
+    pub unsafe fn load(&self, path: &std::path::Path) -> Result<String> {
+        type Constructor = unsafe extern "C" fn() -> *mut Box<dyn IndexerPlugin>;
+
+        let library = libloading::Library::new(path).map_err(|e| {
+            crate::Error::Indexation(format!(
+                "Failed to load indexer plugin {}: {e}",
+                path.display()
+            ))
+        })?;
+        let constructor = library
+            .get::<Constructor>(PLUGIN_CONSTRUCTOR)
+            .map_err(|e| {
+                crate::Error::Indexation(format!("{} is no indexer plugin: {e}", path.display()))
+            })?;
+        let plugin = *Box::from_raw(constructor());
+        // The code of the plugin must outlive it
+        std::mem::forget(library);
+
+        let plugin: Arc<dyn IndexerPlugin> = Arc::from(plugin);
+        let name = plugin.name().to_string();
+        self.register(plugin);
+        Ok(name)
+    }
+}
@@ -1,10 +1,14 @@
 use cached::proc_macro::cached;
+use serde_json::{Map, Value};
 use std::collections::HashSet;
 use std::fs::{self};
 use std::path::Path;
-use terraphim_types::{Document, Index};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use terraphim_automata::language::detect_language;
+use terraphim_types::{Document, DocumentSource, Index};
 
-use super::{hash_as_string, IndexMiddleware};
+use super::{content_hash, hash_as_string, IndexMiddleware};
 use crate::command::ripgrep::{Data, Message, RipgrepCommand};
 use crate::Result;
 
@@ -20,9 +24,14 @@ impl IndexMiddleware for RipgrepIndexer {
     /// # Errors
     ///
     /// Returns an error if the middleware fails to index the haystack
+    #[tracing::instrument(skip(self))]
     async fn index(&self, needle: &str, haystack: &Path) -> Result<Index> {
         let messages = self.command.run(needle, haystack).await?;
-        let documents = index_inner(messages);
+        let source = DocumentSource {
+            haystack: haystack.display().to_string(),
+            service: "ripgrep".to_string(),
+        };
+        let documents = index_inner(messages, source);
         Ok(documents)
     }
 }
@@ -30,7 +39,7 @@ impl IndexMiddleware for RipgrepIndexer {
 #[cached]
 /// This is the inner function that indexes the documents
 /// which allows us to cache requests to the index service
-fn index_inner(messages: Vec<Message>) -> Index {
+fn index_inner(messages: Vec<Message>, source: DocumentSource) -> Index {
     // Cache of already processed documents
     let mut index: Index = Index::default();
     let mut existing_paths: HashSet<String> = HashSet::new();
@@ -121,7 +130,14 @@ fn index_inner(messages: Vec<Message>) -> Index {
             Message::End(_) => {
                 // The `End` message could be received before the `Begin`
                 // message causing the document to be empty
-                index.insert(document.id.to_string(), document.clone());
+                document.language = detect_language(&document.body).map(|l| l.code().to_string());
+                document.extra = parse_properties(&document.body);
+                document.links = parse_links(&document.body);
+                (document.created, document.modified) = file_times(Path::new(&document.url));
+                document.content_hash = Some(content_hash(&document.body));
+                document.sources = vec![source.clone()];
+                let document = std::mem::take(&mut document);
+                index.insert(document.id.to_string(), Arc::new(document));
             }
             _ => {}
         };
@@ -129,3 +145,177 @@ fn index_inner(messages: Vec<Message>) -> Index {
 
     index
 }
+
+/// Creation and modification time of a file in milliseconds since the Unix
+/// epoch, where the file system records them
+fn file_times(path: &Path) -> (Option<u64>, Option<u64>) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return (None, None);
+    };
+    let millis = |time: std::io::Result<SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64)
+    };
+    (millis(metadata.created()), millis(metadata.modified()))
+}
+
+/// Parse the page properties at the top of a Logseq page, e.g.
+/// `type:: [[Business function]]`, into document metadata
+///
+/// Values which are a single page reference are unwrapped, numbers and
+/// booleans are parsed, everything else is kept as a string.
+/// `:LOGBOOK:` style drawers between the properties are skipped.
+fn parse_properties(body: &str) -> Map<String, Value> {
+    let mut properties = Map::new();
+    let mut in_drawer = false;
+    for line in body.lines().map(str::trim) {
+        if in_drawer {
+            in_drawer = line != ":END:";
+            continue;
+        }
+        if line.len() > 2 && line.starts_with(':') && line.ends_with(':') {
+            in_drawer = true;
+            continue;
+        }
+        let Some((key, value)) = line.split_once(":: ") else {
+            break;
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            break;
+        }
+        properties.insert(key.to_string(), property_value(value.trim()));
+    }
+    properties
+}
+
+fn property_value(value: &str) -> Value {
+    if let Some(page) = value
+        .strip_prefix("[[")
+        .and_then(|value| value.strip_suffix("]]"))
+        .filter(|page| !page.contains("[[") && !page.contains("]]"))
+    {
+        return Value::String(page.to_string());
+    }
+    if let Ok(value) = value.parse::<bool>() {
+        return Value::Bool(value);
+    }
+    if let Ok(number) = value.parse::<i64>() {
+        return Value::from(number);
+    }
+    match value
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        Some(number) => Value::Number(number),
+        None => Value::String(value.to_string()),
+    }
+}
+
+/// Parse the targets of the wikilinks and Markdown links of a body, in the
+/// order they first appear
+///
+/// `[[Page|alias]]` links to `Page`, `[text](path "title")` and
+/// `[text](<path with spaces>)` link to the path.
+/// Images, in-page anchors and links to external URLs are skipped.
+fn parse_links(body: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    let mut add = |target: &str| {
+        let target = target.trim();
+        if !target.is_empty() && !links.iter().any(|link| link == target) {
+            links.push(target.to_string());
+        }
+    };
+    let mut rest = body;
+    while let Some(start) = rest.find('[') {
+        let is_image = rest[..start].ends_with('!');
+        let after = &rest[start + 1..];
+        if let Some(page) = after.strip_prefix('[') {
+            if let Some(end) = page.find("]]") {
+                add(page[..end].split('|').next().unwrap_or_default());
+                rest = &page[end + 2..];
+                continue;
+            }
+        } else if let Some(text_end) = after.find("](") {
+            let target = &after[text_end + 2..];
+            if let Some(target_end) = target.find(')') {
+                let destination = target[..target_end].trim();
+                let path = match destination.strip_prefix('<') {
+                    Some(path) => path.split('>').next().unwrap_or_default(),
+                    None => destination.split_whitespace().next().unwrap_or_default(),
+                };
+                let is_external =
+                    path.contains("://") || path.starts_with("mailto:") || path.starts_with('#');
+                if !is_image && !is_external {
+                    add(path);
+                }
+                rest = &target[target_end + 1..];
+                continue;
+            }
+        }
+        rest = after;
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_properties() {
+        let body = "type:: [[Business function]]\n\
+                    :LOGBOOK:\n\
+                    CLOCK: [2023-03-14 Tue 13:31:11]\n\
+                    :END:\n\
+                    inputs:: [[Operation report]], [[Validated system]]\n\
+                    priority:: 2\n\
+                    reviewed:: true\n\
+                    - Maintenance\n\
+                    \tlogseq.order-list-type:: number\n";
+        let properties = parse_properties(body);
+        assert_eq!(properties.len(), 4);
+        assert_eq!(properties["type"], "Business function");
+        assert_eq!(
+            properties["inputs"],
+            "[[Operation report]], [[Validated system]]"
+        );
+        assert_eq!(properties["priority"], 2);
+        assert_eq!(properties["reviewed"], true);
+
+        assert!(parse_properties("# Title\n\nkey:: value").is_empty());
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let body = "See [[Trained operators|operators]] and [[Maintenance]].\n\
+                    Details in [the guide](../guides/operation-guide.md#setup \"Guide\"),\n\
+                    the [site](https://example.com), [top](#top) and ![diagram](diagram.png).\n\
+                    [[Maintenance]] again, and a [concept](<kg:life cycle>).";
+        assert_eq!(
+            parse_links(body),
+            vec![
+                "Trained operators",
+                "Maintenance",
+                "../guides/operation-guide.md#setup",
+                "kg:life cycle",
+            ]
+        );
+        assert!(parse_links("No [links] here").is_empty());
+    }
+
+    #[test]
+    fn test_file_times() {
+        let (created, modified) = file_times(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("Cargo.toml")
+                .as_path(),
+        );
+        assert!(modified.is_some_and(|modified| modified > 0));
+        if let Some(created) = created {
+            assert!(created <= modified.unwrap());
+        }
+        assert_eq!(file_times(Path::new("no/such/file.md")), (None, None));
+    }
+}
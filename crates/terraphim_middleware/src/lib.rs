@@ -5,7 +5,7 @@ mod command;
 pub mod indexer;
 pub mod thesaurus;
 
-pub use indexer::search_haystacks;
+pub use indexer::{search_haystack, search_haystacks};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -21,6 +21,9 @@ pub enum Error {
     #[error("Indexation error: {0}")]
     Indexation(String),
 
+    #[error("No indexer plugin registered for service `{0}`")]
+    UnknownIndexer(String),
+
     #[error("Config error: {0}")]
     Config(#[from] TerraphimConfigError),
 }
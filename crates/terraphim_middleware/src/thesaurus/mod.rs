@@ -24,14 +24,18 @@
 //! ```
 //! The logic as follows: if you ask for concept by name you get concept, if you ask (get) for any of the synonyms you will get concept with id,
 //! its pre-computed reverse tree traversal - any of the synonyms (leaf) maps into the concepts (root)
+//!
+//! A loose synonym can be given a weight between 0 and 1, e.g.
+//! `synonyms:: foo, bar^0.5`, so it contributes less to the rank of a
+//! document than the concept itself, see [`NormalizedTerm::weight`].
 
 use terraphim_automata::AutomataPath;
 use terraphim_config::ConfigState;
-use terraphim_config::Role;
+use terraphim_config::{KnowledgeGraph, Role};
 use terraphim_persistence::Persistable;
 use terraphim_rolegraph::{Error as RoleGraphError, RoleGraph, RoleGraphSync};
 use terraphim_types::SearchQuery;
-use terraphim_types::{Concept, NormalizedTerm, RoleName, Thesaurus};
+use terraphim_types::{Concept, NormalizedTerm, RoleName, TermWeight, Thesaurus};
 
 use crate::Result;
 use cached::proc_macro::cached;
@@ -45,6 +49,13 @@ use tokio::process::Command;
 use crate::command::ripgrep::{json_decode, Data, Message};
 use crate::Error;
 
+mod skos;
+mod streaming;
+
+pub use skos::{SkosBuilder, SkosConcept, SkosFormat, SkosVocabulary};
+pub use streaming::{BuildProgress, StreamingLogseq};
+
+#[tracing::instrument(skip_all, fields(role = ?search_query.role))]
 pub async fn build_thesaurus_from_haystack(
     config_state: &mut ConfigState,
     search_query: &SearchQuery,
@@ -91,7 +102,7 @@ pub async fn build_thesaurus_from_haystack(
         println!("Make sure thesaurus updated in a role {}", role_name);
         // TODO: may be re-building all thesaurus on change using inotify is easier
 
-        update_thesaurus(config_state, &role_name, thesaurus).await?;
+        update_thesaurus(config_state, &role_name, thesaurus, role.kg.as_ref()).await?;
     }
     Ok(())
 }
@@ -100,14 +111,30 @@ async fn update_thesaurus(
     config_state: &mut ConfigState,
     role_name: &RoleName,
     thesaurus: Thesaurus,
+    kg: Option<&KnowledgeGraph>,
 ) -> Result<()> {
     println!("Updating thesaurus for role: {}", role_name);
-    let mut rolegraphs = config_state.roles.clone();
-    let rolegraph = RoleGraph::new(role_name.clone(), thesaurus).await;
+    // Swap the thesaurus of a warm rolegraph in place to keep its indexed
+    // documents; only roles without a rolegraph get a new one
+    if let Some(rolegraph) = config_state.roles.get(role_name) {
+        if let Err(e) = rolegraph.lock().await.replace_thesaurus(thesaurus) {
+            log::error!("Failed to update role and thesaurus: {:?}", e);
+        }
+        return Ok(());
+    }
+    let rolegraph = RoleGraph::new(role_name.clone(), thesaurus)
+        .await
+        .and_then(|rolegraph| rolegraph.with_normalization(kg.and_then(|kg| kg.normalization)))
+        .map(|rolegraph| {
+            rolegraph
+                .with_centrality_bias(kg.and_then(|kg| kg.centrality))
+                .with_edge_decay(kg.and_then(|kg| kg.edge_decay))
+        });
     match rolegraph {
         Ok(rolegraph) => {
-            let rolegraph_value = RoleGraphSync::from(rolegraph);
-            rolegraphs.insert(role_name.clone(), rolegraph_value);
+            config_state
+                .roles
+                .insert(role_name.clone(), RoleGraphSync::from(rolegraph));
         }
         Err(e) => log::error!("Failed to update role and thesaurus: {:?}", e),
     }
@@ -148,6 +175,20 @@ const LOGSEQ_KEY_VALUE_DELIMITER: &str = "::";
 // FIXME: move to config item per role
 const LOGSEQ_SYNONYMS_KEYWORD: &str = "synonyms";
 
+/// Separates a synonym from its weight, e.g. `foo^0.5`
+const LOGSEQ_WEIGHT_DELIMITER: char = '^';
+
+/// Split a synonym from its weight, if it has one
+fn parse_synonym(synonym: &str) -> (String, Option<TermWeight>) {
+    let synonym = synonym.trim();
+    if let Some((term, weight)) = synonym.rsplit_once(LOGSEQ_WEIGHT_DELIMITER) {
+        if let Ok(weight) = weight.trim().parse::<f64>() {
+            return (term.trim().to_string(), TermWeight::new(weight));
+        }
+    }
+    (synonym.to_string(), None)
+}
+
 /// A builder for a knowledge graph, which knows how to handle Logseq input.
 #[derive(Default)]
 pub struct Logseq {
@@ -157,6 +198,7 @@ pub struct Logseq {
 impl ThesaurusBuilder for Logseq {
     /// Build the knowledge graph from the data source
     /// and store it in each rolegraph.
+    #[tracing::instrument(skip(self, haystack))]
     async fn build<P: Into<PathBuf> + Send>(&self, name: String, haystack: P) -> Result<Thesaurus> {
         let haystack = haystack.into();
         let messages = self
@@ -315,8 +357,8 @@ fn index_inner(name: String, messages: Vec<Message>) -> Thesaurus {
                     continue;
                 }
 
-                let synonyms: Vec<String> =
-                    synonym.split(',').map(|s| s.trim().to_string()).collect();
+                let synonyms: Vec<(String, Option<TermWeight>)> =
+                    synonym.split(',').map(parse_synonym).collect();
 
                 let concept = match current_concept {
                     Some(ref concept) => {
@@ -329,8 +371,9 @@ fn index_inner(name: String, messages: Vec<Message>) -> Thesaurus {
                         continue;
                     }
                 };
-                for synonym in synonyms {
-                    let nterm = NormalizedTerm::new(concept.id, concept.value.clone());
+                for (synonym, weight) in synonyms {
+                    let nterm =
+                        NormalizedTerm::new(concept.id, concept.value.clone()).with_weight(weight);
                     thesaurus.insert(synonym.into(), nterm);
                 }
             }
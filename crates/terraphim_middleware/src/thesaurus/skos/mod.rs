@@ -0,0 +1,389 @@
+//! SKOS vocabularies as thesauri
+//!
+//! Organizations often already have a controlled vocabulary, published as
+//! [SKOS](https://www.w3.org/TR/skos-reference/) in Turtle or RDF/XML.
+//! [`SkosBuilder`] reads it as a knowledge graph, without converting it to
+//! Logseq pages first:
+//!
+//! * every `skos:Concept`, or other resource with a SKOS label, is a concept
+//!   named after its `skos:prefLabel`, or its first `skos:altLabel` if it has
+//!   none; concepts without labels are skipped
+//! * its other labels, `skos:altLabel`s, `skos:hiddenLabel`s and the
+//!   preferred labels in other languages, are its synonyms
+//! * `skos:broader` and `skos:narrower` are kept as the broader concepts of
+//!   each [`SkosConcept`], since a [`Thesaurus`] has no hierarchy
+//!
+//! E.g.
+//!
+//! ```turtle
+//! @prefix skos: <http://www.w3.org/2004/02/skos/core#> .
+//! @prefix ex: <http://example.org/> .
+//!
+//! ex:ml a skos:Concept ;
+//!     skos:prefLabel "Machine learning"@en ;
+//!     skos:altLabel "ML"@en, "statistical learning"@en ;
+//!     skos:broader ex:ai .
+//! ```
+//!
+//! maps "machine learning", "ml" and "statistical learning" to the concept
+//! "machine learning".
+//!
+//! Concepts which share a name are one concept. A term which is the name of
+//! a concept always maps to that concept, even if it is a synonym of another
+//! one.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use terraphim_types::{Concept, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+use super::ThesaurusBuilder;
+use crate::{Error, Result};
+
+mod rdf_xml;
+mod turtle;
+
+const SKOS: &str = "http://www.w3.org/2004/02/skos/core#";
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// The object of a triple
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Object {
+    /// An IRI or a blank node, `_:name`
+    Resource(String),
+    Literal {
+        value: String,
+        language: Option<String>,
+    },
+}
+
+/// An RDF statement; the subject is an IRI or a blank node, `_:name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Triple {
+    subject: String,
+    predicate: String,
+    object: Object,
+}
+
+/// Serialization of a SKOS vocabulary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkosFormat {
+    Turtle,
+    RdfXml,
+}
+
+impl SkosFormat {
+    /// The format of a file by its extension: `.ttl` or `.turtle` for
+    /// Turtle, `.rdf`, `.owl` or `.xml` for RDF/XML
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ttl" | "turtle" => Some(Self::Turtle),
+            "rdf" | "owl" | "xml" => Some(Self::RdfXml),
+            _ => None,
+        }
+    }
+}
+
+/// A concept of a SKOS vocabulary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkosConcept {
+    /// ID of the concept in the thesaurus
+    pub id: u64,
+    /// IRI of the concept, or of the first one if several share its name
+    pub iri: String,
+    pub value: NormalizedTermValue,
+    pub synonyms: Vec<NormalizedTermValue>,
+    /// IDs of the broader concepts in the vocabulary
+    pub broader: Vec<u64>,
+}
+
+/// The concepts of a SKOS vocabulary
+#[derive(Debug, Clone, Default)]
+pub struct SkosVocabulary {
+    concepts: Vec<SkosConcept>,
+}
+
+impl SkosVocabulary {
+    /// Parse a vocabulary, keeping only labels in `language` or without a
+    /// language tag if given
+    pub fn parse(text: &str, format: SkosFormat, language: Option<&str>) -> Result<Self> {
+        let triples = parse_triples(text, format, None).map_err(Error::Indexation)?;
+        Ok(Self::from_triples(triples, language))
+    }
+
+    /// The concepts, in the order of their IRIs
+    pub fn concepts(&self) -> &[SkosConcept] {
+        &self.concepts
+    }
+
+    /// The thesaurus of the vocabulary, mapping every label to its concept
+    pub fn to_thesaurus(&self, name: String) -> Thesaurus {
+        let mut thesaurus = Thesaurus::new(name);
+        for concept in &self.concepts {
+            let nterm = NormalizedTerm::new(concept.id, concept.value.clone());
+            for synonym in &concept.synonyms {
+                thesaurus.insert(synonym.clone(), nterm.clone());
+            }
+        }
+        // Names last, so they map to their own concept
+        for concept in &self.concepts {
+            let nterm = NormalizedTerm::new(concept.id, concept.value.clone());
+            thesaurus.insert(concept.value.clone(), nterm);
+        }
+        thesaurus
+    }
+
+    fn from_triples(triples: Vec<Triple>, language: Option<&str>) -> Self {
+        let mut resources: BTreeMap<String, Resource> = BTreeMap::new();
+        for triple in triples {
+            let Some(property) = triple.predicate.strip_prefix(SKOS) else {
+                if triple.predicate == format!("{RDF}type")
+                    && triple.object == Object::Resource(format!("{SKOS}Concept"))
+                {
+                    resources.entry(triple.subject).or_default().is_concept = true;
+                }
+                continue;
+            };
+            match (property, triple.object) {
+                (
+                    "prefLabel" | "altLabel" | "hiddenLabel",
+                    Object::Literal {
+                        value,
+                        language: tag,
+                    },
+                ) => {
+                    if !accepts(language, tag.as_deref()) {
+                        continue;
+                    }
+                    let resource = resources.entry(triple.subject).or_default();
+                    let label = NormalizedTermValue::new(value);
+                    if label.as_str().is_empty() {
+                        continue;
+                    }
+                    if property == "prefLabel" {
+                        resource.pref_labels.push((label, tag));
+                    } else {
+                        resource.alt_labels.push(label);
+                    }
+                }
+                ("broader" | "broaderTransitive", Object::Resource(broader)) => {
+                    resources
+                        .entry(triple.subject)
+                        .or_default()
+                        .broader
+                        .push(broader);
+                }
+                ("narrower" | "narrowerTransitive", Object::Resource(narrower)) => {
+                    resources
+                        .entry(narrower)
+                        .or_default()
+                        .broader
+                        .push(triple.subject);
+                }
+                _ => {}
+            }
+        }
+
+        // Create the concepts first, so broader concepts can be looked up by IRI
+        let mut index_by_iri: AHashMap<&str, usize> = AHashMap::new();
+        let mut index_by_value: AHashMap<NormalizedTermValue, usize> = AHashMap::new();
+        let mut concepts: Vec<SkosConcept> = Vec::new();
+        for (iri, resource) in &resources {
+            let Some((value, synonyms)) = resource.labels(language) else {
+                if resource.is_concept {
+                    log::debug!("Skipping SKOS concept {iri} without labels");
+                }
+                continue;
+            };
+            let index = *index_by_value.entry(value.clone()).or_insert_with(|| {
+                concepts.push(SkosConcept {
+                    id: Concept::new(value.clone()).id,
+                    iri: iri.clone(),
+                    value,
+                    synonyms: Vec::new(),
+                    broader: Vec::new(),
+                });
+                concepts.len() - 1
+            });
+            let concept = &mut concepts[index];
+            for synonym in synonyms {
+                if synonym != concept.value && !concept.synonyms.contains(&synonym) {
+                    concept.synonyms.push(synonym);
+                }
+            }
+            index_by_iri.insert(iri, index);
+        }
+        for (iri, resource) in &resources {
+            let Some(&index) = index_by_iri.get(iri.as_str()) else {
+                continue;
+            };
+            for broader in &resource.broader {
+                let Some(&broader) = index_by_iri.get(broader.as_str()) else {
+                    log::debug!(
+                        "Skipping broader concept {broader} of {iri}, not in the vocabulary"
+                    );
+                    continue;
+                };
+                let broader = concepts[broader].id;
+                let concept = &mut concepts[index];
+                if broader != concept.id && !concept.broader.contains(&broader) {
+                    concept.broader.push(broader);
+                }
+            }
+        }
+        Self { concepts }
+    }
+}
+
+/// Labels and broader concepts of a resource, as read from the triples
+#[derive(Debug, Default)]
+struct Resource {
+    is_concept: bool,
+    pref_labels: Vec<(NormalizedTermValue, Option<String>)>,
+    alt_labels: Vec<NormalizedTermValue>,
+    broader: Vec<String>,
+}
+
+impl Resource {
+    /// The name and synonyms of the resource, if it has labels
+    ///
+    /// The name is the preferred label in `language`, or else without a
+    /// language tag, or else the first one.
+    fn labels(
+        &self,
+        language: Option<&str>,
+    ) -> Option<(NormalizedTermValue, Vec<NormalizedTermValue>)> {
+        let preferred = |tag: &Option<String>| match (language, tag) {
+            (Some(language), Some(tag)) if tag.eq_ignore_ascii_case(language) => 0,
+            (_, None) => 1,
+            _ => 2,
+        };
+        let name = self
+            .pref_labels
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, (_, tag))| (preferred(tag), *i))
+            .map(|(_, (label, _))| label)
+            .or(self.alt_labels.first())?
+            .clone();
+        let synonyms = self
+            .pref_labels
+            .iter()
+            .map(|(label, _)| label)
+            .chain(&self.alt_labels)
+            .filter(|label| **label != name)
+            .cloned()
+            .collect();
+        Some((name, synonyms))
+    }
+}
+
+/// Whether a label with a language tag is kept for `language`
+///
+/// Labels without a tag are always kept; "en" keeps "en-GB" labels, too.
+fn accepts(language: Option<&str>, tag: Option<&str>) -> bool {
+    let (Some(language), Some(tag)) = (language, tag) else {
+        return true;
+    };
+    let primary = tag.split('-').next().unwrap_or(tag);
+    tag.eq_ignore_ascii_case(language) || primary.eq_ignore_ascii_case(language)
+}
+
+fn parse_triples(
+    text: &str,
+    format: SkosFormat,
+    base: Option<&str>,
+) -> std::result::Result<Vec<Triple>, String> {
+    match format {
+        SkosFormat::Turtle => turtle::parse(text, base),
+        SkosFormat::RdfXml => rdf_xml::parse(text, base),
+    }
+}
+
+/// Resolve a relative IRI against a base IRI
+///
+/// Only the common cases are handled: absolute IRIs, fragments and paths
+/// relative to the directory of the base.
+fn resolve_iri(base: &str, iri: &str) -> String {
+    let is_absolute = iri
+        .find(':')
+        .is_some_and(|colon| !iri[..colon].contains(['/', '?', '#']));
+    if is_absolute || base.is_empty() {
+        return iri.to_string();
+    }
+    if iri.is_empty() || iri.starts_with('#') {
+        let document = base.split('#').next().unwrap_or(base);
+        return format!("{document}{iri}");
+    }
+    let directory = base.rfind('/').map_or(base, |slash| &base[..=slash]);
+    format!("{directory}{iri}")
+}
+
+/// A builder for a knowledge graph from a SKOS vocabulary, a file or a
+/// directory of files in Turtle or RDF/XML, see [`SkosFormat::from_path`]
+#[derive(Debug, Clone, Default)]
+pub struct SkosBuilder {
+    language: Option<String>,
+}
+
+impl SkosBuilder {
+    /// Only use labels in a language, e.g. "en", or without a language tag
+    pub fn with_language(language: impl Into<String>) -> Self {
+        Self {
+            language: Some(language.into()),
+        }
+    }
+
+    /// Parse the vocabulary of a file, or of all the files of a directory
+    ///
+    /// The files of a directory are read in the order of their names and
+    /// parsed as one vocabulary, so concepts may refer to broader concepts
+    /// of other files. Files of other formats are skipped.
+    pub async fn vocabulary<P: Into<PathBuf>>(&self, haystack: P) -> Result<SkosVocabulary> {
+        let haystack = haystack.into();
+        let paths = if tokio::fs::metadata(&haystack).await?.is_dir() {
+            let mut paths = Vec::new();
+            let mut entries = tokio::fs::read_dir(&haystack).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if SkosFormat::from_path(&path).is_some() {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            paths
+        } else {
+            vec![haystack]
+        };
+
+        let mut triples = Vec::new();
+        for path in paths {
+            let Some(format) = SkosFormat::from_path(&path) else {
+                return Err(Error::Indexation(format!(
+                    "Unknown SKOS format of {}",
+                    path.display()
+                )));
+            };
+            let text = tokio::fs::read_to_string(&path).await?;
+            let base = format!("file://{}", path.display());
+            triples.extend(parse_triples(&text, format, Some(&base)).map_err(|e| {
+                Error::Indexation(format!("Failed to parse {}: {e}", path.display()))
+            })?);
+        }
+        Ok(SkosVocabulary::from_triples(
+            triples,
+            self.language.as_deref(),
+        ))
+    }
+}
+
+impl ThesaurusBuilder for SkosBuilder {
+    /// Build the thesaurus of a SKOS vocabulary
+    #[tracing::instrument(skip(self, haystack))]
+    async fn build<P: Into<PathBuf> + Send>(&self, name: String, haystack: P) -> Result<Thesaurus> {
+        let vocabulary = self.vocabulary(haystack).await?;
+        Ok(vocabulary.to_thesaurus(name))
+    }
+}
@@ -0,0 +1,321 @@
+//! A parser for RDF/XML, as far as SKOS vocabularies use it
+//!
+//! Supports typed node elements and `rdf:Description`s with `rdf:about`,
+//! `rdf:ID` or `rdf:nodeID`, property attributes, property elements with a
+//! literal, an `rdf:resource`, an `rdf:nodeID`, a nested node element or
+//! `rdf:parseType="Resource"`, and `xml:lang` and `xml:base`.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{LocalName, ResolveResult};
+use quick_xml::NsReader;
+
+use super::{resolve_iri, Object, Triple, RDF};
+
+const XML: &str = "http://www.w3.org/XML/1998/namespace";
+
+type Result<T> = std::result::Result<T, String>;
+
+/// An open element
+enum Frame {
+    /// `rdf:RDF`
+    Root {
+        language: Option<String>,
+        base: String,
+    },
+    /// A node element, or the blank node of a property element with
+    /// `rdf:parseType="Resource"`, which is the object of `property`
+    Node {
+        subject: String,
+        property: Option<(String, String)>,
+        language: Option<String>,
+        base: String,
+    },
+    Property {
+        subject: String,
+        predicate: String,
+        object: Option<String>,
+        text: String,
+        language: Option<String>,
+        base: String,
+    },
+}
+
+impl Frame {
+    fn language(&self) -> Option<&String> {
+        match self {
+            Frame::Root { language, .. }
+            | Frame::Node { language, .. }
+            | Frame::Property { language, .. } => language.as_ref(),
+        }
+    }
+
+    fn base(&self) -> &str {
+        match self {
+            Frame::Root { base, .. } | Frame::Node { base, .. } | Frame::Property { base, .. } => {
+                base
+            }
+        }
+    }
+}
+
+/// Parse the triples of an RDF/XML document, resolving relative IRIs
+/// against `base`
+pub(super) fn parse(text: &str, base: Option<&str>) -> Result<Vec<Triple>> {
+    let mut reader = NsReader::from_str(text);
+    reader.expand_empty_elements(true);
+    let mut parser = Parser {
+        base: base.unwrap_or_default().to_string(),
+        stack: Vec::new(),
+        triples: Vec::new(),
+        blank_nodes: 0,
+    };
+    loop {
+        let position = reader.buffer_position();
+        let result = match reader.read_event() {
+            Ok(Event::Start(element)) => parser.start(&reader, &element),
+            Ok(Event::End(_)) => {
+                parser.end();
+                Ok(())
+            }
+            Ok(Event::Text(text)) => text
+                .unescape()
+                .map(|text| parser.text(&text))
+                .map_err(|e| e.to_string()),
+            Ok(Event::CData(text)) => {
+                parser.text(&String::from_utf8_lossy(&text.into_inner()));
+                Ok(())
+            }
+            Ok(Event::Eof) => return Ok(parser.triples),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            let line = text[..position.min(text.len())].matches('\n').count() + 1;
+            return Err(format!("line {line}: {e}"));
+        }
+    }
+}
+
+struct Parser {
+    base: String,
+    stack: Vec<Frame>,
+    triples: Vec<Triple>,
+    blank_nodes: usize,
+}
+
+impl Parser {
+    fn new_blank_node(&mut self) -> String {
+        self.blank_nodes += 1;
+        format!("_:#{}", self.blank_nodes)
+    }
+
+    fn push(&mut self, subject: &str, predicate: String, object: Object) {
+        self.triples.push(Triple {
+            subject: subject.to_string(),
+            predicate,
+            object,
+        });
+    }
+
+    fn start(&mut self, reader: &NsReader<&[u8]>, element: &BytesStart) -> Result<()> {
+        let name = resolve(reader.resolve_element(element.name()));
+        let (mut language, mut base) = match self.stack.last() {
+            Some(frame) => (frame.language().cloned(), frame.base().to_string()),
+            None => (None, self.base.clone()),
+        };
+        let mut attributes = Vec::new();
+        for attribute in element.attributes() {
+            let attribute = attribute.map_err(|e| e.to_string())?;
+            if attribute.key.as_namespace_binding().is_some() {
+                continue;
+            }
+            let key = resolve(reader.resolve_attribute(attribute.key));
+            let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+            match key.strip_prefix(XML) {
+                Some("lang") => language = (!value.is_empty()).then(|| value.to_string()),
+                Some("base") => base = resolve_iri(&base, &value),
+                Some(_) => {}
+                None => attributes.push((key, value.to_string())),
+            }
+        }
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key.strip_prefix(RDF) == Some(name))
+                .map(|(_, value)| value.as_str())
+        };
+
+        if name == format!("{RDF}RDF") {
+            self.stack.push(Frame::Root { language, base });
+            return Ok(());
+        }
+        match self.stack.last() {
+            // A property element of the node
+            Some(Frame::Node { subject, .. }) => {
+                let subject = subject.clone();
+                if attribute("parseType") == Some("Resource") {
+                    let node = self.new_blank_node();
+                    self.stack.push(Frame::Node {
+                        subject: node,
+                        property: Some((subject, name)),
+                        language,
+                        base,
+                    });
+                    return Ok(());
+                }
+                let object = match (attribute("resource"), attribute("nodeID")) {
+                    (Some(resource), _) => Some(resolve_iri(&base, resource)),
+                    (None, Some(node)) => Some(format!("_:{node}")),
+                    (None, None) => None,
+                };
+                self.stack.push(Frame::Property {
+                    subject,
+                    predicate: name,
+                    object,
+                    text: String::new(),
+                    language,
+                    base,
+                });
+            }
+            // A node element, at the top level or the object of a property
+            None | Some(Frame::Root { .. }) | Some(Frame::Property { .. }) => {
+                let subject = match (attribute("about"), attribute("ID"), attribute("nodeID")) {
+                    (Some(about), _, _) => resolve_iri(&base, about),
+                    (None, Some(id), _) => resolve_iri(&base, &format!("#{id}")),
+                    (None, None, Some(node)) => format!("_:{node}"),
+                    (None, None, None) => self.new_blank_node(),
+                };
+                if name != format!("{RDF}Description") {
+                    self.push(&subject, format!("{RDF}type"), Object::Resource(name));
+                }
+                for (key, value) in &attributes {
+                    let object = match key.strip_prefix(RDF) {
+                        Some("about" | "ID" | "nodeID") => continue,
+                        Some("type") => Object::Resource(resolve_iri(&base, value)),
+                        _ => Object::Literal {
+                            value: value.clone(),
+                            language: language.clone(),
+                        },
+                    };
+                    self.push(&subject, key.clone(), object);
+                }
+                if let Some(Frame::Property { object, .. }) = self.stack.last_mut() {
+                    *object = Some(subject.clone());
+                }
+                self.stack.push(Frame::Node {
+                    subject,
+                    property: None,
+                    language,
+                    base,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, value: &str) {
+        if let Some(Frame::Property { text, .. }) = self.stack.last_mut() {
+            text.push_str(value);
+        }
+    }
+
+    fn end(&mut self) {
+        match self.stack.pop() {
+            Some(Frame::Node {
+                subject,
+                property: Some((parent, predicate)),
+                ..
+            }) => self.push(&parent, predicate, Object::Resource(subject)),
+            Some(Frame::Property {
+                subject,
+                predicate,
+                object,
+                text,
+                language,
+                ..
+            }) => {
+                let object = match object {
+                    Some(object) => Object::Resource(object),
+                    None => Object::Literal {
+                        value: text.trim().to_string(),
+                        language,
+                    },
+                };
+                self.push(&subject, predicate, object);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The IRI of a resolved element or attribute name
+fn resolve((namespace, local): (ResolveResult, LocalName)) -> String {
+    let local = String::from_utf8_lossy(local.as_ref());
+    match namespace {
+        ResolveResult::Bound(namespace) => {
+            format!("{}{local}", String::from_utf8_lossy(namespace.as_ref()))
+        }
+        _ => local.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"<?xml version="1.0"?>
+            <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+                     xmlns:skos="http://www.w3.org/2004/02/skos/core#"
+                     xml:base="http://example.org/" xml:lang="en">
+              <skos:Concept rdf:about="ml" skos:notation="ML-1">
+                <skos:prefLabel>Machine learning</skos:prefLabel>
+                <skos:prefLabel xml:lang="fr">Apprentissage automatique</skos:prefLabel>
+                <skos:altLabel><![CDATA[M&L]]></skos:altLabel>
+                <skos:broader rdf:resource="ai"/>
+                <skos:narrower>
+                  <skos:Concept rdf:ID="dl"/>
+                </skos:narrower>
+              </skos:Concept>
+            </rdf:RDF>"#;
+        let triples = parse(text, None).unwrap();
+        let skos = |local: &str| format!("http://www.w3.org/2004/02/skos/core#{local}");
+        let objects = |subject: &str, predicate: &str| -> Vec<Object> {
+            triples
+                .iter()
+                .filter(|triple| triple.subject == subject && triple.predicate == predicate)
+                .map(|triple| triple.object.clone())
+                .collect()
+        };
+        let literal = |value: &str, language: &str| Object::Literal {
+            value: value.to_string(),
+            language: Some(language.to_string()),
+        };
+
+        let ml = "http://example.org/ml";
+        assert_eq!(
+            objects(ml, &format!("{RDF}type")),
+            vec![Object::Resource(skos("Concept"))]
+        );
+        assert_eq!(
+            objects(ml, &skos("prefLabel")),
+            vec![
+                literal("Machine learning", "en"),
+                literal("Apprentissage automatique", "fr")
+            ]
+        );
+        assert_eq!(objects(ml, &skos("altLabel")), vec![literal("M&L", "en")]);
+        assert_eq!(objects(ml, &skos("notation")), vec![literal("ML-1", "en")]);
+        assert_eq!(
+            objects(ml, &skos("broader")),
+            vec![Object::Resource("http://example.org/ai".to_string())]
+        );
+        assert_eq!(
+            objects(ml, &skos("narrower")),
+            vec![Object::Resource("http://example.org/#dl".to_string())]
+        );
+
+        assert!(parse("<rdf:RDF><unclosed></rdf:RDF>", None).is_err());
+    }
+}
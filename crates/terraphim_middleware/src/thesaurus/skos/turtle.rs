@@ -0,0 +1,468 @@
+//! A parser for Turtle, as far as SKOS vocabularies use it
+//!
+//! Supports `@prefix`/`PREFIX` and `@base`/`BASE`, IRIs, prefixed names,
+//! blank nodes, nested blank nodes (`[ ... ]`) and string, numeric and
+//! boolean literals. The items of collections (`( ... )`) are skipped; SKOS
+//! labels and hierarchies don't use them.
+
+use ahash::AHashMap;
+
+use super::{resolve_iri, Object, Triple, RDF};
+
+/// Parse the triples of a Turtle document, resolving relative IRIs
+/// against `base`
+pub(super) fn parse(text: &str, base: Option<&str>) -> std::result::Result<Vec<Triple>, String> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        base: base.unwrap_or_default().to_string(),
+        prefixes: AHashMap::new(),
+        triples: Vec::new(),
+        blank_nodes: 0,
+    };
+    match parser.document() {
+        Ok(()) => Ok(parser.triples),
+        Err(e) => Err(format!("line {}: {e}", parser.line())),
+    }
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+struct Parser<'a> {
+    text: &'a str,
+    /// Byte offset of the next character
+    pos: usize,
+    base: String,
+    prefixes: AHashMap<String, String>,
+    triples: Vec<Triple>,
+    blank_nodes: usize,
+}
+
+impl Parser<'_> {
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skip whitespace and comments
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                let end = self.rest().find('\n').unwrap_or(self.rest().len());
+                self.pos += end;
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Skip whitespace and a character if it is next
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{c}`")))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(c) => format!("expected {expected}, found `{c}`"),
+            None => format!("expected {expected}, found the end of the document"),
+        }
+    }
+
+    /// Skip a keyword, ignoring case, if it is next and followed by
+    /// whitespace
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let rest = self.rest();
+        let matches = rest.len() > keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            && rest[keyword.len()..].starts_with(char::is_whitespace);
+        if matches {
+            self.pos += keyword.len();
+        }
+        matches
+    }
+
+    fn new_blank_node(&mut self) -> String {
+        self.blank_nodes += 1;
+        // `#` can't be part of the label of a blank node in the document
+        format!("_:#{}", self.blank_nodes)
+    }
+
+    fn document(&mut self) -> Result<()> {
+        loop {
+            self.skip_whitespace();
+            if self.peek().is_none() {
+                return Ok(());
+            }
+            if self.eat_keyword("@prefix") {
+                self.prefix()?;
+                self.expect('.')?;
+            } else if self.eat_keyword("@base") {
+                self.base = self.iri_ref()?;
+                self.expect('.')?;
+            } else if self.eat_keyword("prefix") {
+                self.prefix()?;
+            } else if self.eat_keyword("base") {
+                self.base = self.iri_ref()?;
+            } else {
+                self.statement()?;
+            }
+        }
+    }
+
+    fn prefix(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        let end = self
+            .rest()
+            .find(|c: char| c == ':' || c.is_whitespace())
+            .unwrap_or(self.rest().len());
+        let prefix = self.rest()[..end].to_string();
+        self.pos += end;
+        if !self.eat(':') {
+            return Err(self.unexpected("`:` after the prefix"));
+        }
+        let iri = self.iri_ref()?;
+        self.prefixes.insert(prefix, iri);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let is_blank_node_list = self.peek() == Some('[');
+        let subject = self.resource()?;
+        self.skip_whitespace();
+        // `[ ... ] .` only holds the triples of the blank node
+        if !(is_blank_node_list && self.peek() == Some('.')) {
+            self.predicate_objects(&subject)?;
+        }
+        self.expect('.')
+    }
+
+    fn predicate_objects(&mut self, subject: &str) -> Result<()> {
+        loop {
+            let predicate = self.verb()?;
+            loop {
+                let object = self.object()?;
+                self.triples.push(Triple {
+                    subject: subject.to_string(),
+                    predicate: predicate.clone(),
+                    object,
+                });
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            if !self.eat(';') {
+                return Ok(());
+            }
+            while self.eat(';') {}
+            self.skip_whitespace();
+            if matches!(self.peek(), None | Some('.') | Some(']')) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn verb(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if self.rest().starts_with('a') && self.eat_keyword("a") {
+            return Ok(format!("{RDF}type"));
+        }
+        self.iri()
+    }
+
+    /// An IRI or a blank node, as a subject or object
+    fn resource(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('[') => self.blank_node_properties(),
+            Some('(') => self.collection(),
+            Some('_') if self.rest().starts_with("_:") => {
+                self.pos += 2;
+                Ok(format!("_:{}", self.name()))
+            }
+            _ => self.iri(),
+        }
+    }
+
+    fn object(&mut self) -> Result<Object> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let is_number = rest.starts_with(|c: char| c.is_ascii_digit())
+            || (rest.starts_with(['+', '-', '.'])
+                && rest[1..].starts_with(|c: char| c.is_ascii_digit()));
+        let is_boolean = ["true", "false"].iter().any(|keyword| {
+            rest.starts_with(keyword)
+                && !rest[keyword.len()..].starts_with(|c: char| is_name_char(c) || c == ':')
+        });
+        if rest.starts_with(['"', '\'']) {
+            self.literal()
+        } else if is_number || is_boolean {
+            let mut value = String::new();
+            while let Some(c) = self.peek() {
+                let is_point =
+                    c == '.' && self.rest()[1..].starts_with(|c: char| c.is_ascii_digit());
+                if !(c.is_ascii_alphanumeric() || matches!(c, '+' | '-') || is_point) {
+                    break;
+                }
+                value.push(c);
+                self.bump();
+            }
+            Ok(Object::Literal {
+                value,
+                language: None,
+            })
+        } else {
+            Ok(Object::Resource(self.resource()?))
+        }
+    }
+
+    fn literal(&mut self) -> Result<Object> {
+        let value = self.string()?;
+        let mut language = None;
+        if self.peek() == Some('@') {
+            self.bump();
+            let end = self
+                .rest()
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(self.rest().len());
+            language = Some(self.rest()[..end].to_string());
+            self.pos += end;
+        } else if self.rest().starts_with("^^") {
+            // Labels are plain text, whatever their datatype
+            self.pos += 2;
+            self.iri()?;
+        }
+        Ok(Object::Literal { value, language })
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let Some(quote) = self.bump() else {
+            return Err(self.unexpected("a string"));
+        };
+        let long_quote: String = [quote; 3].iter().collect();
+        let long = self.rest().starts_with(&long_quote[..2]);
+        if long {
+            self.pos += 2;
+        } else if self.peek() == Some(quote) {
+            self.bump();
+            return Ok(String::new());
+        }
+        let mut value = String::new();
+        loop {
+            if long && self.rest().starts_with(&long_quote) {
+                self.pos += 3;
+                return Ok(value);
+            }
+            match self.bump() {
+                None => return Err("unterminated string".to_string()),
+                Some(c) if c == quote && !long => return Ok(value),
+                Some('\n' | '\r') if !long => return Err("line break in a string".to_string()),
+                Some('\\') => value.push(self.escape()?),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char> {
+        let hex = |parser: &mut Self, digits: usize| -> Result<char> {
+            let code = parser
+                .rest()
+                .get(..digits)
+                .and_then(|code| u32::from_str_radix(code, 16).ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| "invalid unicode escape".to_string())?;
+            parser.pos += digits;
+            Ok(code)
+        };
+        match self.bump() {
+            Some('t') => Ok('\t'),
+            Some('b') => Ok('\u{8}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('f') => Ok('\u{c}'),
+            Some('u') => hex(self, 4),
+            Some('U') => hex(self, 8),
+            Some(c @ ('"' | '\'' | '\\')) => Ok(c),
+            _ => Err("invalid escape".to_string()),
+        }
+    }
+
+    /// An IRI or a prefixed name
+    fn iri(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if self.peek() == Some('<') {
+            return self.iri_ref();
+        }
+        let prefix = self.name();
+        if self.peek() != Some(':') {
+            return Err(self.unexpected("an IRI or a prefixed name"));
+        }
+        self.bump();
+        let mut local = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.bump();
+                local.extend(self.bump());
+            } else if is_name_char(c) || matches!(c, ':' | '%' | '.') {
+                local.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        // A trailing `.` ends the statement
+        while local.ends_with('.') {
+            local.pop();
+            self.pos -= 1;
+        }
+        let Some(namespace) = self.prefixes.get(&prefix) else {
+            return Err(format!("unknown prefix `{prefix}:`"));
+        };
+        Ok(format!("{namespace}{local}"))
+    }
+
+    fn iri_ref(&mut self) -> Result<String> {
+        if !self.eat('<') {
+            return Err(self.unexpected("an IRI"));
+        }
+        let Some(end) = self.rest().find('>') else {
+            return Err("unterminated IRI".to_string());
+        };
+        let iri = resolve_iri(&self.base, &self.rest()[..end]);
+        self.pos += end + 1;
+        Ok(iri)
+    }
+
+    /// The name of a prefix or blank node
+    fn name(&mut self) -> String {
+        let end = self
+            .rest()
+            .find(|c: char| !is_name_char(c))
+            .unwrap_or(self.rest().len());
+        let name = self.rest()[..end].to_string();
+        self.pos += end;
+        name
+    }
+
+    fn blank_node_properties(&mut self) -> Result<String> {
+        self.expect('[')?;
+        let node = self.new_blank_node();
+        if !self.eat(']') {
+            self.predicate_objects(&node)?;
+            self.expect(']')?;
+        }
+        Ok(node)
+    }
+
+    fn collection(&mut self) -> Result<String> {
+        self.expect('(')?;
+        while !self.eat(')') {
+            if self.peek().is_none() {
+                return Err(self.unexpected("`)`"));
+            }
+            self.object()?;
+        }
+        Ok(self.new_blank_node())
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+            @prefix skos: <http://www.w3.org/2004/02/skos/core#> .
+            PREFIX ex: <http://example.org/>
+            @base <http://example.org/base/> .
+
+            # A comment
+            ex:ml a skos:Concept ;
+                skos:prefLabel "Machine learning"@en, 'Apprentissage automatique'@fr ;
+                skos:altLabel """M.L."""@en-GB ;
+                skos:notation 42 ;
+                skos:broader <ai>, [ skos:prefLabel "Anonymous" ] .
+            <#dl> skos:broader ex:ml.
+        "#;
+        let triples = parse(text, None).unwrap();
+        let skos = |local: &str| format!("http://www.w3.org/2004/02/skos/core#{local}");
+        let objects = |subject: &str, predicate: &str| -> Vec<Object> {
+            triples
+                .iter()
+                .filter(|triple| triple.subject == subject && triple.predicate == predicate)
+                .map(|triple| triple.object.clone())
+                .collect()
+        };
+        let literal = |value: &str, language: &str| Object::Literal {
+            value: value.to_string(),
+            language: Some(language.to_string()),
+        };
+
+        let ml = "http://example.org/ml";
+        assert_eq!(
+            objects(ml, &format!("{RDF}type")),
+            vec![Object::Resource(skos("Concept"))]
+        );
+        assert_eq!(
+            objects(ml, &skos("prefLabel")),
+            vec![
+                literal("Machine learning", "en"),
+                literal("Apprentissage automatique", "fr")
+            ]
+        );
+        assert_eq!(
+            objects(ml, &skos("altLabel")),
+            vec![literal("M.L.", "en-GB")]
+        );
+        let broader = objects(ml, &skos("broader"));
+        assert_eq!(
+            broader[0],
+            Object::Resource("http://example.org/base/ai".to_string())
+        );
+        let Object::Resource(anonymous) = &broader[1] else {
+            panic!("Expected a blank node, got {:?}", broader[1]);
+        };
+        assert_eq!(objects(anonymous, &skos("prefLabel")).len(), 1);
+        assert_eq!(
+            objects("http://example.org/base/#dl", &skos("broader")),
+            vec![Object::Resource(ml.to_string())]
+        );
+
+        let error = parse("ex:ml a ex:Concept .", None).unwrap_err();
+        assert_eq!(error, "line 1: unknown prefix `ex:`");
+    }
+}
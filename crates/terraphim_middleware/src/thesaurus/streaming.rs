@@ -0,0 +1,304 @@
+//! Building the thesaurus of a very large Logseq knowledge graph
+//!
+//! [`Logseq`](super::Logseq) collects the output of ripgrep for the whole
+//! haystack before it builds the thesaurus. [`StreamingLogseq`] reads the
+//! Markdown files one at a time instead, in the order of their paths, so
+//! memory only grows with the thesaurus itself. It reports its progress to a
+//! callback and, with a checkpoint file, saves the thesaurus built so far
+//! every `checkpoint_interval` files; a build which was interrupted resumes
+//! after the last file of the checkpoint. The checkpoint is removed once the
+//! build is complete.
+//!
+//! Pages are read as by [`Logseq`](super::Logseq): the file stem is the
+//! concept and the `synonyms::` property lists its synonyms, optionally
+//! weighted as in `synonyms:: foo, bar^0.5`. Concept IDs are
+//! numbered from 1 in the order of the pages, so they stay unique across a
+//! resumed build.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+use super::{parse_synonym, ThesaurusBuilder, LOGSEQ_KEY_VALUE_DELIMITER, LOGSEQ_SYNONYMS_KEYWORD};
+use crate::Result;
+
+/// Number of files after which a checkpoint is saved by default
+const DEFAULT_CHECKPOINT_INTERVAL: usize = 1000;
+
+/// Progress of a streaming build, reported after every file
+#[derive(Debug, Clone)]
+pub struct BuildProgress {
+    /// Markdown files read so far, including those of a resumed checkpoint
+    pub files: usize,
+    /// Terms in the thesaurus so far
+    pub terms: usize,
+    /// The file just read
+    pub path: PathBuf,
+}
+
+type ProgressCallback = Arc<dyn Fn(&BuildProgress) + Send + Sync>;
+
+/// The state of an interrupted build
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    haystack: PathBuf,
+    /// The last file read; files up to it in path order are done
+    last_path: PathBuf,
+    files: usize,
+    next_id: u64,
+    thesaurus: Thesaurus,
+}
+
+/// A builder for a knowledge graph from Logseq pages, for haystacks too
+/// large to index in one pass, see the module documentation
+#[derive(Clone)]
+pub struct StreamingLogseq {
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: usize,
+    progress: Option<ProgressCallback>,
+}
+
+impl Default for StreamingLogseq {
+    fn default() -> Self {
+        Self {
+            checkpoint: None,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            progress: None,
+        }
+    }
+}
+
+impl StreamingLogseq {
+    /// Save the progress of builds to a checkpoint file and resume from it
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// Save a checkpoint every `files` files (1000 by default)
+    pub fn with_checkpoint_interval(mut self, files: usize) -> Self {
+        self.checkpoint_interval = files.max(1);
+        self
+    }
+
+    /// Report the progress of builds to a callback
+    pub fn with_progress(
+        mut self,
+        progress: impl Fn(&BuildProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// The checkpoint of an interrupted build of the same thesaurus and
+    /// haystack, if there is one
+    async fn load_checkpoint(&self, name: &str, haystack: &Path) -> Option<Checkpoint> {
+        let path = self.checkpoint.as_ref()?;
+        let json = tokio::fs::read_to_string(path).await.ok()?;
+        match serde_json::from_str::<Checkpoint>(&json) {
+            Ok(checkpoint)
+                if checkpoint.haystack == haystack && checkpoint.thesaurus.name() == name =>
+            {
+                log::info!(
+                    "Resuming the thesaurus build of {} after {} files",
+                    haystack.display(),
+                    checkpoint.files
+                );
+                Some(checkpoint)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Ignoring invalid checkpoint {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    async fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let Some(path) = &self.checkpoint else {
+            return Ok(());
+        };
+        // Write a new file first, so a crash never leaves half a checkpoint
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, serde_json::to_vec(checkpoint)?).await?;
+        tokio::fs::rename(&partial, path).await?;
+        Ok(())
+    }
+}
+
+impl ThesaurusBuilder for StreamingLogseq {
+    /// Build the knowledge graph from the data source, one file at a time
+    #[tracing::instrument(skip(self, haystack))]
+    async fn build<P: Into<PathBuf> + Send>(&self, name: String, haystack: P) -> Result<Thesaurus> {
+        let haystack = haystack.into();
+        let mut checkpoint = match self.load_checkpoint(&name, &haystack).await {
+            Some(checkpoint) => checkpoint,
+            None => Checkpoint {
+                haystack: haystack.clone(),
+                last_path: PathBuf::new(),
+                files: 0,
+                next_id: 1,
+                thesaurus: Thesaurus::new(name),
+            },
+        };
+        let resume_after = checkpoint.last_path.clone();
+
+        // Depth first, in path order; the smallest path is always on top
+        let mut pending = vec![haystack];
+        while let Some(path) = pending.pop() {
+            if tokio::fs::metadata(&path).await?.is_dir() {
+                // Directories before the checkpoint are done
+                if path < resume_after && !resume_after.starts_with(&path) {
+                    continue;
+                }
+                let mut entries = Vec::new();
+                let mut dir = tokio::fs::read_dir(&path).await?;
+                while let Some(entry) = dir.next_entry().await? {
+                    let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                    if !hidden {
+                        entries.push(entry.path());
+                    }
+                }
+                entries.sort_unstable_by(|a, b| b.cmp(a));
+                pending.extend(entries);
+                continue;
+            }
+            if !is_markdown(&path) || path <= resume_after {
+                continue;
+            }
+
+            match tokio::fs::read(&path).await {
+                Ok(page) => index_page(&mut checkpoint, &path, &String::from_utf8_lossy(&page)),
+                Err(e) => log::warn!("Failed to read {}: {e}. Skipping", path.display()),
+            }
+            checkpoint.files += 1;
+            checkpoint.last_path = path;
+            if let Some(progress) = &self.progress {
+                progress(&BuildProgress {
+                    files: checkpoint.files,
+                    terms: checkpoint.thesaurus.len(),
+                    path: checkpoint.last_path.clone(),
+                });
+            }
+            if checkpoint.files % self.checkpoint_interval == 0 {
+                self.save_checkpoint(&checkpoint).await?;
+            }
+        }
+
+        if let Some(path) = &self.checkpoint {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                log::debug!("No checkpoint {} to remove: {e}", path.display());
+            }
+        }
+        Ok(checkpoint.thesaurus)
+    }
+}
+
+/// Whether a file is a Markdown page
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("md") || extension.eq_ignore_ascii_case("markdown")
+        })
+}
+
+/// Add the concept of a page and its synonyms to the thesaurus
+fn index_page(checkpoint: &mut Checkpoint, path: &Path, page: &str) {
+    let Some(stem) = path.file_stem() else {
+        return;
+    };
+    let value = NormalizedTermValue::new(stem.to_string_lossy().to_string());
+    for line in page.lines() {
+        let Some((keyword, synonyms)) = line.trim_start().split_once(LOGSEQ_KEY_VALUE_DELIMITER)
+        else {
+            continue;
+        };
+        if keyword != LOGSEQ_SYNONYMS_KEYWORD {
+            continue;
+        }
+        let id = match checkpoint.thesaurus.get(&value) {
+            Some(nterm) if nterm.value == value => nterm.id,
+            _ => {
+                checkpoint.next_id += 1;
+                checkpoint.next_id - 1
+            }
+        };
+        let nterm = NormalizedTerm::new(id, value.clone());
+        checkpoint.thesaurus.insert(value.clone(), nterm.clone());
+        for (synonym, weight) in synonyms.split(',').map(parse_synonym) {
+            checkpoint
+                .thesaurus
+                .insert(synonym.into(), nterm.clone().with_weight(weight));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("terraphim_streaming_{}", std::process::id()));
+        let haystack = dir.join("kg");
+        tokio::fs::create_dir_all(haystack.join("b")).await.unwrap();
+        tokio::fs::write(haystack.join("a.md"), "synonyms:: alpha")
+            .await
+            .unwrap();
+        tokio::fs::write(haystack.join("b/c.md"), "synonyms:: gamma, ceta^0.5")
+            .await
+            .unwrap();
+        tokio::fs::write(haystack.join("d.md"), "title:: D\nsynonyms:: delta")
+            .await
+            .unwrap();
+
+        // A build interrupted after `a.md`, with a marker for what it read
+        let checkpoint_path = dir.join("checkpoint.json");
+        let mut thesaurus = Thesaurus::new("kg".to_string());
+        let a = NormalizedTerm::new(1, NormalizedTermValue::new("a".to_string()));
+        thesaurus.insert("checkpointed".to_string().into(), a);
+        let checkpoint = Checkpoint {
+            haystack: haystack.clone(),
+            last_path: haystack.join("a.md"),
+            files: 1,
+            next_id: 2,
+            thesaurus,
+        };
+        tokio::fs::write(&checkpoint_path, serde_json::to_vec(&checkpoint).unwrap())
+            .await
+            .unwrap();
+
+        let files = Arc::new(AtomicUsize::new(0));
+        let reported = files.clone();
+        let builder = StreamingLogseq::default()
+            .with_checkpoint(&checkpoint_path)
+            .with_checkpoint_interval(1)
+            .with_progress(move |progress| reported.store(progress.files, Ordering::SeqCst));
+        let thesaurus = builder.build("kg".to_string(), &haystack).await.unwrap();
+        let concept = |term: &str| {
+            thesaurus
+                .get(&NormalizedTermValue::new(term.to_string()))
+                .map(|nterm| (nterm.id, nterm.value.to_string()))
+        };
+        let weight = |term: &str| {
+            thesaurus
+                .get(&NormalizedTermValue::new(term.to_string()))
+                .map(|nterm| nterm.weight())
+        };
+
+        assert_eq!(concept("checkpointed"), Some((1, "a".to_string())));
+        assert_eq!(concept("alpha"), None);
+        assert_eq!(concept("ceta"), Some((2, "c".to_string())));
+        assert_eq!(weight("ceta"), Some(0.5));
+        assert_eq!(weight("gamma"), Some(1.0));
+        assert_eq!(concept("delta"), Some((3, "d".to_string())));
+        assert_eq!(files.load(Ordering::SeqCst), 3);
+        assert!(!checkpoint_path.exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod tests {
 
-    use terraphim_middleware::thesaurus::{Logseq, ThesaurusBuilder};
+    use terraphim_middleware::thesaurus::{Logseq, StreamingLogseq, ThesaurusBuilder};
 
     use terraphim_middleware::Result;
     use terraphim_types::NormalizedTermValue;
@@ -75,4 +75,32 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    /// Test creating the same thesaurus with the streaming builder, which
+    /// reads the Markdown files one at a time
+    async fn test_streaming_logseq_thesaurus() -> Result<()> {
+        let thesaurus = StreamingLogseq::default()
+            .build("some_role".to_string(), "fixtures/logseq")
+            .await?;
+        let concept = |term: &str| {
+            thesaurus
+                .get(&NormalizedTermValue::new(term.to_string()))
+                .unwrap()
+                .value
+                .clone()
+        };
+
+        assert_eq!(thesaurus.len(), 7);
+        assert_eq!(
+            concept("example bar"),
+            NormalizedTermValue::new("example".to_string())
+        );
+        assert_eq!(
+            concept("example"),
+            NormalizedTermValue::new("example".to_string())
+        );
+        assert_eq!(concept("ai"), NormalizedTermValue::new("ai".to_string()));
+        Ok(())
+    }
 }
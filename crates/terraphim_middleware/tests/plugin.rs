@@ -0,0 +1,111 @@
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use ahash::AHashMap;
+    use async_trait::async_trait;
+    use terraphim_config::{ConfigBuilder, ConfigState, Freshness, Haystack, Role, ServiceType};
+    use terraphim_middleware::indexer::{IndexerPlugin, IndexerRegistry};
+    use terraphim_middleware::{search_haystack, search_haystacks, Error, Result};
+    use terraphim_types::{Document, Index, NormalizedTermValue, RelevanceFunction, SearchQuery};
+
+    /// Serves a fixed page per haystack path, like a wiki would
+    struct WikiIndexer;
+
+    #[async_trait]
+    impl IndexerPlugin for WikiIndexer {
+        fn name(&self) -> &str {
+            "wiki"
+        }
+
+        async fn index(&self, needle: &str, haystack: &Haystack) -> Result<Index> {
+            let mut index = Index::new();
+            let body = format!("The wiki page about {needle}");
+            index.insert(
+                "wiki-page".to_string(),
+                Arc::new(Document {
+                    id: "wiki-page".to_string(),
+                    url: format!("{}/page", haystack.path.display()),
+                    title: "Wiki page".to_string(),
+                    body,
+                    ..Default::default()
+                }),
+            );
+            Ok(index)
+        }
+    }
+
+    terraphim_middleware::declare_indexer_plugin!(WikiIndexer::new);
+
+    impl WikiIndexer {
+        fn new() -> Self {
+            WikiIndexer
+        }
+    }
+
+    fn role(service: &str) -> Role {
+        Role {
+            shortname: None,
+            name: "Wiki".into(),
+            relevance_function: RelevanceFunction::TitleScorer,
+            theme: "lumen".to_string(),
+            kg: None,
+            haystacks: vec![Haystack {
+                path: PathBuf::from("https://wiki.example.com"),
+                service: ServiceType::Custom(service.to_string()),
+                freshness: Freshness::default(),
+                visibility: Vec::new(),
+            }],
+            metadata_schema: Vec::new(),
+            skip_stages: Vec::new(),
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
+            extra: AHashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_indexer() -> Result<()> {
+        IndexerRegistry::instance().register(Arc::new(WikiIndexer));
+        assert!(IndexerRegistry::instance()
+            .names()
+            .contains(&"wiki".to_string()));
+
+        let search_query = SearchQuery {
+            search_term: NormalizedTermValue::new("rust".to_string()),
+            role: Some("Wiki".into()),
+            ..Default::default()
+        };
+        let mut config = ConfigBuilder::new()
+            .add_role("Wiki", role("wiki"))
+            .build()?;
+        let config_state = ConfigState::new(&mut config).await?;
+        let index = search_haystacks(config_state.clone(), search_query.clone()).await?;
+        let document = &index["wiki-page"];
+        assert_eq!(document.url, "https://wiki.example.com/page");
+        assert_eq!(document.body, "The wiki page about rust");
+
+        let haystack = &role("wiki").haystacks[0];
+        let haystack_index = search_haystack(config_state, &search_query, haystack).await?;
+        assert_eq!(haystack_index["wiki-page"].body, document.body);
+
+        let mut config = ConfigBuilder::new()
+            .add_role("Wiki", role("unknown"))
+            .build()?;
+        let config_state = ConfigState::new(&mut config).await?;
+        assert!(matches!(
+            search_haystacks(config_state, search_query).await,
+            Err(Error::UnknownIndexer(name)) if name == "unknown"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_declare_indexer_plugin() {
+        // The constructor exported for dynamic loading creates the plugin
+        let plugin = unsafe { *Box::from_raw(_terraphim_indexer_plugin()) };
+        assert_eq!(plugin.name(), "wiki");
+    }
+}
@@ -5,12 +5,12 @@ mod tests {
     use ahash::AHashMap;
     use terraphim_automata::AutomataPath;
     use terraphim_config::{
-        ConfigBuilder, ConfigState, Haystack, KnowledgeGraph, KnowledgeGraphLocal, Role,
+        ConfigBuilder, ConfigState, Freshness, Haystack, KnowledgeGraph, KnowledgeGraphLocal, Role,
         ServiceType,
     };
     use terraphim_middleware::search_haystacks;
+    use terraphim_types::{FacetFilters, NormalizedTermValue, QueryType, SearchQuery, SortBy};
     use terraphim_types::{IndexedDocument, KnowledgeGraphInputType, RelevanceFunction};
-    use terraphim_types::{NormalizedTermValue, SearchQuery};
 
     use terraphim_middleware::Result;
 
@@ -36,6 +36,18 @@ mod tests {
                 )),
                 public: true,
                 publish: true,
+                include_terms: Vec::new(),
+                exclude_terms: Vec::new(),
+                min_term_length: 0,
+                max_kg_terms: None,
+                max_links_per_concept: None,
+                max_links: None,
+                link_mode: None,
+                link_template: None,
+                link_definitions: false,
+                normalization: None,
+                centrality: None,
+                edge_decay: None,
                 knowledge_graph_local: Some(KnowledgeGraphLocal {
                     input_type: KnowledgeGraphInputType::Markdown,
                     path: docs_path.join("kg"),
@@ -44,7 +56,14 @@ mod tests {
             haystacks: vec![Haystack {
                 path: docs_path.clone(),
                 service: ServiceType::Ripgrep,
+                freshness: Freshness::default(),
+                visibility: Vec::new(),
             }],
+            metadata_schema: Vec::new(),
+            skip_stages: Vec::new(),
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
             extra: AHashMap::new(),
         };
         let mut config = ConfigBuilder::new()
@@ -60,6 +79,20 @@ mod tests {
             role: Some(role_name.clone().into()),
             skip: Some(0),
             limit: Some(10),
+            language: None,
+            metadata: Vec::new(),
+            auto_correct: false,
+            omit_body: false,
+            boost_backlinks: false,
+            facets: FacetFilters::default(),
+            after: None,
+            before: None,
+            sort_by: SortBy::Relevance,
+            exclude_terms: Vec::new(),
+            terms: None,
+            haystacks: Vec::new(),
+            query_type: QueryType::Term,
+            mmr_lambda: None,
         };
         println!("Searching documents with query: {search_query:?} {role_name}");
 
@@ -90,11 +123,30 @@ mod tests {
                 }),
                 public: true,
                 publish: true,
+                include_terms: Vec::new(),
+                exclude_terms: Vec::new(),
+                min_term_length: 0,
+                max_kg_terms: None,
+                max_links_per_concept: None,
+                max_links: None,
+                link_mode: None,
+                link_template: None,
+                link_definitions: false,
+                normalization: None,
+                centrality: None,
+                edge_decay: None,
             }),
             haystacks: vec![Haystack {
                 path: PathBuf::from("/tmp/system_operator/pages/"),
                 service: ServiceType::Ripgrep,
+                freshness: Freshness::default(),
+                visibility: Vec::new(),
             }],
+            metadata_schema: Vec::new(),
+            skip_stages: Vec::new(),
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
             extra: AHashMap::new(),
         };
         let mut config = ConfigBuilder::new()
@@ -110,7 +162,14 @@ mod tests {
                     haystacks: vec![Haystack {
                         path: PathBuf::from("/tmp/system_operator/pages/"),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -125,6 +184,20 @@ mod tests {
             role: Some(role_name.clone().into()),
             skip: Some(0),
             limit: Some(10),
+            language: None,
+            metadata: Vec::new(),
+            auto_correct: false,
+            omit_body: false,
+            boost_backlinks: false,
+            facets: FacetFilters::default(),
+            after: None,
+            before: None,
+            sort_by: SortBy::Relevance,
+            exclude_terms: Vec::new(),
+            terms: None,
+            haystacks: Vec::new(),
+            query_type: QueryType::Term,
+            mmr_lambda: None,
         };
         println!("Searching documents with query: {search_query:?} {role_name}");
 
@@ -137,4 +210,66 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_overlapping_haystacks_keep_all_sources() -> Result<()> {
+        let mut docs_path = std::env::current_dir().unwrap();
+        docs_path.pop();
+        docs_path.pop();
+        docs_path = docs_path.join("docs/src");
+        let haystack = |path: PathBuf| Haystack {
+            path,
+            service: ServiceType::Ripgrep,
+            freshness: Freshness::default(),
+            visibility: Vec::new(),
+        };
+        let role = Role {
+            shortname: None,
+            name: "Docs".into(),
+            relevance_function: RelevanceFunction::TitleScorer,
+            theme: "lumen".to_string(),
+            kg: None,
+            // The second haystack is contained in the first one
+            haystacks: vec![haystack(docs_path.clone()), haystack(docs_path.join("kg"))],
+            metadata_schema: Vec::new(),
+            skip_stages: Vec::new(),
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
+            extra: AHashMap::new(),
+        };
+        let mut config = ConfigBuilder::new().add_role("Docs", role).build()?;
+        let config_state = ConfigState::new(&mut config).await?;
+
+        let search_query = SearchQuery {
+            search_term: NormalizedTermValue::new("haystack".to_string()),
+            role: Some("Docs".into()),
+            ..Default::default()
+        };
+        let index = search_haystacks(config_state, search_query).await?;
+
+        let document = index
+            .values()
+            .find(|document| document.url.ends_with("kg/haystack.md"))
+            .expect("kg/haystack.md is found");
+        let haystacks: Vec<&str> = document
+            .sources
+            .iter()
+            .map(|source| source.haystack.as_str())
+            .collect();
+        assert_eq!(haystacks.len(), 2);
+        assert!(haystacks[0].ends_with("docs/src"));
+        assert!(haystacks[1].ends_with("docs/src/kg"));
+        assert!(document
+            .sources
+            .iter()
+            .all(|source| source.service == "ripgrep"));
+
+        // Documents only found in the outer haystack have a single source
+        assert!(index
+            .values()
+            .filter(|document| !document.url.contains("/kg/"))
+            .all(|document| document.sources.len() == 1));
+        Ok(())
+    }
 }
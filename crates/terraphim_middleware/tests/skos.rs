@@ -0,0 +1,64 @@
+#[cfg(test)]
+mod tests {
+
+    use terraphim_middleware::thesaurus::{SkosBuilder, ThesaurusBuilder};
+
+    use terraphim_middleware::Result;
+    use terraphim_types::NormalizedTermValue;
+
+    #[tokio::test]
+    /// Test creating a thesaurus from SKOS vocabularies, in Turtle and RDF/XML
+    /// Uses `fixtures/skos` as the haystack
+    async fn test_skos_thesaurus() -> Result<()> {
+        let skos = SkosBuilder::with_language("en");
+        let thesaurus = skos.build("some_role".to_string(), "fixtures/skos").await?;
+        let concept = |term: &str| {
+            thesaurus
+                .get(&NormalizedTermValue::new(term.to_string()))
+                .map(|nterm| nterm.value.to_string())
+        };
+
+        assert_eq!(thesaurus.len(), 9);
+        assert_eq!(concept("operate the system").as_deref(), Some("operation"));
+        assert_eq!(concept("servicing").as_deref(), Some("maintenance"));
+        assert_eq!(concept("maintainance").as_deref(), Some("maintenance"));
+        assert_eq!(concept("customer service").as_deref(), Some("support"));
+        assert_eq!(concept("operations").as_deref(), Some("operations"));
+        // Labels in other languages are left out
+        assert_eq!(concept("betrieb"), None);
+        assert_eq!(concept("kundendienst"), None);
+
+        // Broader concepts are kept, also from `skos:narrower` in other files
+        let vocabulary = skos.vocabulary("fixtures/skos").await?;
+        let id = |value: &str| {
+            vocabulary
+                .concepts()
+                .iter()
+                .find(|concept| concept.value.as_str() == value)
+                .unwrap()
+                .id
+        };
+        let maintenance = vocabulary
+            .concepts()
+            .iter()
+            .find(|concept| concept.value.as_str() == "maintenance")
+            .unwrap();
+        assert_eq!(maintenance.broader, vec![id("operation"), id("support")]);
+        let thesaurus = vocabulary.to_thesaurus("some_role".to_string());
+        assert_eq!(
+            thesaurus.get(&"upkeep".to_string().into()).unwrap().id,
+            maintenance.id
+        );
+
+        // Without a language, all labels are synonyms
+        let thesaurus = SkosBuilder::default()
+            .build("some_role".to_string(), "fixtures/skos/operations.ttl")
+            .await?;
+        assert_eq!(
+            thesaurus.get(&"betrieb".to_string().into()).unwrap().value,
+            NormalizedTermValue::new("operation".to_string())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Built autocomplete indices, so they needn't be rebuilt on every launch
+//!
+//! An index is stored as the bytes of a compact thesaurus (see
+//! `terraphim_automata::CompactThesaurus::to_bytes`), keyed by the role and
+//! a fingerprint of the thesaurus it was built from. When the thesaurus of
+//! a role changes, so does its fingerprint: the stored index is not found
+//! and is replaced by the rebuilt one.
+
+use crate::{DeviceStorage, Result};
+
+/// Directory of the stored indices
+const DIRECTORY: &str = "autocomplete/";
+
+/// The part of the key of an index naming the role
+fn role_prefix(role: &str) -> String {
+    let slug: String = role
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{slug}_")
+}
+
+fn index_name(role: &str, fingerprint: u64) -> String {
+    format!("{}{fingerprint:016x}.fst", role_prefix(role))
+}
+
+/// Whether `name` is the name of an index of a role with another
+/// fingerprint than `current`
+fn is_stale(name: &str, role: &str, current: &str) -> bool {
+    let Some(fingerprint) = name
+        .strip_prefix(&role_prefix(role))
+        .and_then(|rest| rest.strip_suffix(".fst"))
+    else {
+        return false;
+    };
+    fingerprint.len() == 16 && fingerprint.chars().all(|c| c.is_ascii_hexdigit()) && name != current
+}
+
+/// Save the index of a role to all profiles, replacing the indices of
+/// other thesauri of the role
+#[tracing::instrument(skip(bytes), fields(size = bytes.len()))]
+pub async fn save_autocomplete_index(role: &str, fingerprint: u64, bytes: &[u8]) -> Result<()> {
+    let storage = DeviceStorage::instance().await?;
+    let name = index_name(role, fingerprint);
+    for (op, _time) in storage.ops.values() {
+        op.write(&format!("{DIRECTORY}{name}"), bytes.to_vec())
+            .await?;
+        for entry in op.list(DIRECTORY).await? {
+            if is_stale(entry.name(), role, &name) {
+                op.delete(entry.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Load the index of a role built from the thesaurus with the given
+/// fingerprint from the fastest operator
+#[tracing::instrument]
+pub async fn load_autocomplete_index(role: &str, fingerprint: u64) -> Result<Vec<u8>> {
+    let storage = DeviceStorage::instance().await?;
+    let bytes = storage
+        .fastest_op
+        .read(&format!("{DIRECTORY}{}", index_name(role, fingerprint)))
+        .await?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_stale() {
+        let current = index_name("Rust Engineer", 1);
+        assert_eq!(current, "rustengineer_0000000000000001.fst");
+        assert!(is_stale(
+            &index_name("Rust Engineer", 2),
+            "Rust Engineer",
+            &current
+        ));
+        assert!(!is_stale(&current, "Rust Engineer", &current));
+        assert!(!is_stale(&index_name("Rust", 2), "Rust Engineer", &current));
+        assert!(!is_stale(
+            &index_name("Rust Engineer 2", 2),
+            "Rust Engineer",
+            &current
+        ));
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_save_and_load_autocomplete_index() -> Result<()> {
+        save_autocomplete_index("Test Role", 1, b"first").await?;
+        save_autocomplete_index("Test Role", 2, b"second").await?;
+        assert_eq!(load_autocomplete_index("Test Role", 2).await?, b"second");
+        let err = load_autocomplete_index("Test Role", 1).await.unwrap_err();
+        assert!(err.is_not_found());
+        Ok(())
+    }
+}
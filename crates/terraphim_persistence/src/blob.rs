@@ -0,0 +1,415 @@
+//! Binary blobs, e.g. the content of document attachments
+//!
+//! Blobs are content-addressed: the ID of a blob is the SHA-256 hash of its
+//! content, so storing the same file twice only keeps one copy.
+//! The metadata of an attachment (name, MIME type, size) is persisted
+//! separately as JSON for every upload, keyed by the blob ID and the ID of
+//! the upload, so uploading the same content under another name doesn't
+//! rename the attachments of other documents.
+//! Next to the metadata, the IDs of the documents an upload is attached to
+//! are recorded when a document is created (see [`add_references`]), so
+//! access to an attachment is checked without loading all documents.
+//!
+//! Blobs which no persisted document references any more are deleted by
+//! [`collect_garbage`].
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use opendal::Operator;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use terraphim_types::{Attachment, Document};
+use tokio::sync::Mutex;
+use ulid::Ulid;
+
+use crate::{load_many, DeviceStorage, Error, Persistable, Result, LOAD_MANY_CONCURRENCY};
+
+/// Serializes updates of the references of uploads, so documents created
+/// at the same time don't overwrite each other's references
+static REFERENCES: Mutex<()> = Mutex::const_new(());
+
+/// Returns the ID of a blob with the given content
+pub fn blob_id(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Check that a blob ID is a hex encoded SHA-256 hash, so it can safely be
+/// used as (part of) a storage key
+fn validate_blob_id(blob_id: &str) -> Result<()> {
+    if blob_id.len() == 64 && blob_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(Error::InvalidBlobId(blob_id.to_string()))
+    }
+}
+
+/// Check that the ID of an upload is a ULID, or empty for attachments
+/// uploaded before uploads had IDs
+fn validate_attachment_id(id: &str) -> Result<()> {
+    if id.is_empty() || Ulid::from_string(id).is_ok() {
+        Ok(())
+    } else {
+        Err(Error::InvalidAttachmentId(id.to_string()))
+    }
+}
+
+fn blob_key(blob_id: &str) -> String {
+    format!("blobs/{blob_id}")
+}
+
+/// Save a blob to all profiles and return its ID
+#[tracing::instrument(skip(content), fields(size = content.len()))]
+pub async fn save_blob(content: &[u8]) -> Result<String> {
+    let storage = DeviceStorage::instance().await?;
+    let blob_id = blob_id(content);
+    let key = blob_key(&blob_id);
+    for (op, _time) in storage.ops.values() {
+        op.write(&key, content.to_vec()).await?;
+    }
+    Ok(blob_id)
+}
+
+/// Load a blob from the fastest operator
+#[tracing::instrument]
+pub async fn load_blob(blob_id: &str) -> Result<Vec<u8>> {
+    validate_blob_id(blob_id)?;
+    let storage = DeviceStorage::instance().await?;
+    let content = storage.fastest_op.read(&blob_key(blob_id)).await?;
+    Ok(content)
+}
+
+/// Store `content` as a blob and persist the metadata of the attachment
+pub async fn save_attachment(name: &str, mime_type: &str, content: &[u8]) -> Result<Attachment> {
+    let attachment = Attachment {
+        name: name.to_string(),
+        mime_type: mime_type.to_string(),
+        size: content.len() as u64,
+        blob_id: save_blob(content).await?,
+        id: Ulid::new().to_string(),
+    };
+    attachment.save().await?;
+    Ok(attachment)
+}
+
+/// Load the metadata of the upload `id` of a blob
+pub async fn load_attachment(blob_id: &str, id: &str) -> Result<Attachment> {
+    validate_blob_id(blob_id)?;
+    validate_attachment_id(id)?;
+    let mut attachment = Attachment::new(blob_id.to_string());
+    attachment.id = id.to_string();
+    attachment.load().await
+}
+
+/// The IDs of the documents an upload of a blob is attached to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct AttachmentReferences {
+    blob_id: String,
+    id: String,
+    documents: Vec<String>,
+}
+
+impl AttachmentReferences {
+    async fn load_or_default(blob_id: &str, id: &str) -> Result<Self> {
+        let mut references = AttachmentReferences {
+            blob_id: blob_id.to_string(),
+            id: id.to_string(),
+            documents: Vec::new(),
+        };
+        match references.load().await {
+            Ok(loaded) => Ok(loaded),
+            Err(e) if e.is_not_found() => Ok(references),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Record that `document` references its attachments
+///
+/// Called whenever a document is persisted.
+pub async fn add_references(document: &Document) -> Result<()> {
+    let _guard = REFERENCES.lock().await;
+    for attachment in &document.attachments {
+        validate_blob_id(&attachment.blob_id)?;
+        validate_attachment_id(&attachment.id)?;
+        let mut references =
+            AttachmentReferences::load_or_default(&attachment.blob_id, &attachment.id).await?;
+        if !references.documents.contains(&document.id) {
+            references.documents.push(document.id.clone());
+            references.save().await?;
+        }
+    }
+    Ok(())
+}
+
+/// The documents persisted in an operator
+async fn persisted_documents(op: &Operator) -> Result<Vec<Document>> {
+    let mut documents = Vec::new();
+    for entry in op.list("/").await? {
+        let name = entry.name();
+        if !(name.starts_with("document_") && name.ends_with(".json")) {
+            continue;
+        }
+        documents.push(serde_json::from_slice(&op.read(entry.path()).await?)?);
+    }
+    Ok(documents)
+}
+
+/// The persisted documents which the upload `id` of a blob is attached to
+///
+/// Only the documents recorded by [`add_references`] are loaded; those
+/// which have since been replaced by a version without the attachment are
+/// left out.
+pub async fn referencing_documents(blob_id: &str, id: &str) -> Result<Vec<Document>> {
+    validate_blob_id(blob_id)?;
+    validate_attachment_id(id)?;
+    let references = AttachmentReferences::load_or_default(blob_id, id).await?;
+    let mut documents = Vec::new();
+    for document in load_many::<Document>(references.documents, LOAD_MANY_CONCURRENCY).await {
+        match document {
+            Ok(document) => documents.push(document),
+            Err(e) if e.is_not_found() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    documents.retain(|document| {
+        document
+            .attachments
+            .iter()
+            .any(|attachment| attachment.blob_id == blob_id && attachment.id == id)
+    });
+    Ok(documents)
+}
+
+/// The IDs of the blobs attached to the documents persisted in an operator
+async fn referenced_blobs(op: &Operator) -> Result<HashSet<String>> {
+    Ok(persisted_documents(op)
+        .await?
+        .into_iter()
+        .flat_map(|document| document.attachments)
+        .map(|attachment| attachment.blob_id)
+        .collect())
+}
+
+/// The ID of the blob stored under a name in `blobs/`, for the blob and
+/// the metadata of its uploads
+fn stored_blob_id(name: &str) -> Option<&str> {
+    let blob_id = name.strip_suffix(".json").unwrap_or(name);
+    let blob_id = blob_id
+        .split_once('.')
+        .map_or(blob_id, |(blob_id, _)| blob_id);
+    validate_blob_id(blob_id).ok().map(|_| blob_id)
+}
+
+/// Delete the blobs which no persisted document references, with the
+/// metadata of their attachments, from all profiles
+///
+/// Blobs younger than `min_age` are kept, because an attachment is uploaded
+/// before the document referencing it is created. So are blobs whose
+/// profile doesn't know when they were written. Returns the number of
+/// blobs deleted.
+#[tracing::instrument]
+pub async fn collect_garbage(min_age: Duration) -> Result<usize> {
+    let storage = DeviceStorage::instance().await?;
+    let referenced = referenced_blobs(&storage.fastest_op).await?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let mut deleted = HashSet::new();
+    for (op, _time) in storage.ops.values() {
+        for entry in op.list("blobs/").await? {
+            let Some(blob_id) = stored_blob_id(entry.name()) else {
+                continue;
+            };
+            if referenced.contains(blob_id) {
+                continue;
+            }
+            let modified = op.stat(entry.path()).await?.last_modified();
+            let is_old = modified.is_some_and(|t| now - t.timestamp() >= min_age.as_secs() as i64);
+            if is_old {
+                op.delete(entry.path()).await?;
+                deleted.insert(blob_id.to_string());
+            }
+        }
+    }
+    log::debug!("Deleted {} unreferenced blobs", deleted.len());
+    Ok(deleted.len())
+}
+
+#[async_trait]
+impl Persistable for Attachment {
+    fn new(key: String) -> Self {
+        Attachment {
+            blob_id: key,
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> Result<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> Result<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> Result<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        if self.id.is_empty() {
+            format!("blobs/{}.json", self.blob_id)
+        } else {
+            format!("blobs/{}.{}.json", self.blob_id, self.id)
+        }
+    }
+}
+
+#[async_trait]
+impl Persistable for AttachmentReferences {
+    fn new(key: String) -> Self {
+        AttachmentReferences {
+            blob_id: key,
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> Result<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> Result<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> Result<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    /// Stored with the metadata of the upload, so garbage collection
+    /// deletes it with the blob
+    fn get_key(&self) -> String {
+        if self.id.is_empty() {
+            format!("blobs/{}.documents.json", self.blob_id)
+        } else {
+            format!("blobs/{}.{}.documents.json", self.blob_id, self.id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_id() {
+        assert_eq!(
+            blob_id(b"hello"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert!(validate_blob_id(&blob_id(b"hello")).is_ok());
+        assert!(validate_blob_id("../../etc/passwd").is_err());
+
+        let blob_id = blob_id(b"hello");
+        assert_eq!(stored_blob_id(&blob_id), Some(blob_id.as_str()));
+        assert_eq!(
+            stored_blob_id(&format!("{blob_id}.json")),
+            Some(blob_id.as_str())
+        );
+        assert_eq!(
+            stored_blob_id(&format!("{blob_id}.{}.json", Ulid::new())),
+            Some(blob_id.as_str())
+        );
+        assert_eq!(
+            stored_blob_id(&format!("{blob_id}.{}.documents.json", Ulid::new())),
+            Some(blob_id.as_str())
+        );
+        assert_eq!(stored_blob_id("notes.json"), None);
+        assert!(validate_attachment_id(&Ulid::new().to_string()).is_ok());
+        assert!(validate_attachment_id("../notes").is_err());
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_save_and_load_attachment() -> Result<()> {
+        let content = b"%PDF-1.4 not really a pdf";
+        let attachment = save_attachment("report.pdf", "application/pdf", content).await?;
+        assert_eq!(attachment.size, content.len() as u64);
+        assert_eq!(attachment.blob_id, blob_id(content));
+
+        assert_eq!(load_blob(&attachment.blob_id).await?, content);
+        assert_eq!(
+            load_attachment(&attachment.blob_id, &attachment.id).await?,
+            attachment
+        );
+
+        // Uploading the same content again keeps the metadata of both uploads
+        let copy = save_attachment("copy.html", "text/html", content).await?;
+        assert_eq!(copy.blob_id, attachment.blob_id);
+        assert_ne!(copy.id, attachment.id);
+        assert_eq!(
+            load_attachment(&attachment.blob_id, &attachment.id)
+                .await?
+                .name,
+            "report.pdf"
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_referencing_documents() -> Result<()> {
+        let attachment = save_attachment("notes.txt", "text/plain", b"referenced notes").await?;
+        let other = save_attachment("other.txt", "text/plain", b"other notes").await?;
+        assert!(referencing_documents(&attachment.blob_id, &attachment.id)
+            .await?
+            .is_empty());
+
+        let mut document = Document {
+            id: "document with notes".to_string(),
+            attachments: vec![attachment.clone()],
+            ..Default::default()
+        };
+        document.save().await?;
+        add_references(&document).await?;
+        add_references(&document).await?;
+        let documents = referencing_documents(&attachment.blob_id, &attachment.id).await?;
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].id, document.id);
+        assert!(referencing_documents(&other.blob_id, &other.id)
+            .await?
+            .is_empty());
+
+        // A new version of the document without the attachment no longer
+        // references it
+        document.attachments = vec![other.clone()];
+        document.save().await?;
+        add_references(&document).await?;
+        assert!(referencing_documents(&attachment.blob_id, &attachment.id)
+            .await?
+            .is_empty());
+        assert_eq!(
+            referencing_documents(&other.blob_id, &other.id)
+                .await?
+                .len(),
+            1
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use crate::Result;
+use async_trait::async_trait;
+use terraphim_types::Document;
+
+use crate::Persistable;
+
+#[async_trait]
+impl Persistable for Document {
+    fn new(key: String) -> Self {
+        Document {
+            id: key,
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> Result<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> Result<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> Result<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    /// returns `document_` + normalized id + `.json`
+    fn get_key(&self) -> String {
+        format!("document_{}.json", self.normalize_key(&self.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_many;
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_save_and_load_many() -> Result<()> {
+        let documents = ["first document", "second document"].map(|id| Document {
+            id: id.to_string(),
+            title: id.to_string(),
+            body: format!("Body of the {id}"),
+            ..Default::default()
+        });
+        for document in &documents {
+            document.save().await?;
+        }
+
+        let keys = ["second document", "missing document", "first document"];
+        let loaded = load_many::<Document>(keys.map(String::from).to_vec(), 2).await;
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded[0].as_ref().unwrap().body, documents[1].body);
+        assert!(loaded[1].as_ref().unwrap_err().is_not_found());
+        assert_eq!(loaded[2].as_ref().unwrap().body, documents[0].body);
+        Ok(())
+    }
+}
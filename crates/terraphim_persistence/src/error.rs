@@ -19,6 +19,19 @@ pub enum Error {
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+
+    #[error("Invalid blob ID: {0}")]
+    InvalidBlobId(String),
+
+    #[error("Invalid attachment ID: {0}")]
+    InvalidAttachmentId(String),
+}
+
+impl Error {
+    /// Whether the error means that the requested key doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::OpenDal(e) if e.kind() == opendal::ErrorKind::NotFound)
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,9 +1,13 @@
+pub mod autocomplete;
+pub mod blob;
+pub mod document;
 pub mod error;
 pub mod settings;
 pub mod thesaurus;
 
 use async_once_cell::OnceCell as AsyncOnceCell;
 use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
 use opendal::Operator;
 use serde::{de::DeserializeOwned, Serialize};
 use terraphim_settings::DeviceSettings;
@@ -52,6 +56,35 @@ async fn init_device_storage() -> Result<DeviceStorage> {
     Ok(DeviceStorage { ops, fastest_op })
 }
 
+/// Number of objects [`load_many`] loads concurrently by default
+pub const LOAD_MANY_CONCURRENCY: usize = 16;
+
+/// Load the objects with the given keys, with at most `concurrency` loads
+/// in flight at a time
+///
+/// Returns one result per key in the order of the keys. Missing objects
+/// are errors, see [`Error::is_not_found`].
+pub async fn load_many<T>(keys: Vec<String>, concurrency: usize) -> Vec<Result<T>>
+where
+    T: Persistable + Send,
+{
+    // A slow load doesn't hold back starting the loads after it
+    let mut loaded: Vec<(usize, Result<T>)> = stream::iter(keys.into_iter().enumerate())
+        .map(|(i, key)| async move {
+            let mut obj = T::new(key);
+            (i, obj.load().await)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    loaded.sort_by_key(|(i, _)| *i);
+    let mut results = Vec::with_capacity(loaded.len());
+    for (_, result) in loaded {
+        results.push(result);
+    }
+    results
+}
+
 /// A trait for persisting objects
 ///
 /// This trait is used to save and load objects to and from the fastest operator
@@ -80,6 +113,7 @@ pub trait Persistable: Serialize + DeserializeOwned {
     }
 
     /// Save to all profiles
+    #[tracing::instrument(skip_all, fields(key = %self.get_key()))]
     async fn save_to_all(&self) -> Result<()> {
         let (ops, _fastest_op) = &self.load_config().await?;
         let key = self.get_key();
@@ -92,6 +126,7 @@ pub trait Persistable: Serialize + DeserializeOwned {
     }
 
     /// Save to a single profile
+    #[tracing::instrument(skip(self), fields(key = %self.get_key()))]
     async fn save_to_profile(&self, profile_name: &str) -> Result<()> {
         let (ops, _fastest_op) = &self.load_config().await?;
         let key = self.get_key();
@@ -116,6 +151,7 @@ pub trait Persistable: Serialize + DeserializeOwned {
     }
 
     /// Load from the fastest operator
+    #[tracing::instrument(skip(self, _op))]
     async fn load_from_operator(&self, key: &str, _op: &Operator) -> Result<Self>
     where
         Self: Sized,
@@ -37,7 +37,7 @@ async fn get_rolegraph() -> RoleGraph {
     let thesaurus = load_thesaurus(&AutomataPath::remote_example())
         .await
         .unwrap();
-    let rolegraph = RoleGraph::new(role, thesaurus).await;
+    let rolegraph = RoleGraph::new(role.into(), thesaurus).await;
     rolegraph.unwrap()
 }
 
@@ -56,6 +56,20 @@ fn dummy_document(id: String, body: String) -> Document {
         description: None,
         stub: None,
         rank: None,
+        score: None,
+        attachments: Vec::new(),
+        language: None,
+        extra: serde_json::Map::new(),
+        sources: Vec::new(),
+        visibility: Vec::new(),
+        highlights: Vec::new(),
+        snippets: Vec::new(),
+        kg_concepts: Vec::new(),
+        links: Vec::new(),
+        backlinks: None,
+        created: None,
+        modified: None,
+        content_hash: None,
         tags: None,
         body,
     }
@@ -99,7 +113,7 @@ fn bench_parse_document_to_pair(c: &mut Criterion) {
 
     let mut rolegraph = block_on(get_rolegraph());
     c.bench_function("parse_document_to_pair", |b| {
-        b.iter(|| rolegraph.insert_document(&id, document.clone()))
+        b.iter(|| rolegraph.insert_document(&id, &document))
     });
 }
 
@@ -121,7 +135,7 @@ fn bench_throughput(c: &mut Criterion) {
         group.bench_with_input(
             BenchmarkId::new("parse_document_to_pair", size),
             size,
-            |b, _| b.iter(|| rolegraph.insert_document(&id, document.clone())),
+            |b, _| b.iter(|| rolegraph.insert_document(&id, &document)),
         );
     }
     group.finish();
@@ -145,7 +159,7 @@ fn bench_throughput_corpus(c: &mut Criterion) {
         group.bench_with_input(
             BenchmarkId::new("parse_document_to_pair", size),
             &document,
-            |b, document| b.iter(|| rolegraph.insert_document(&id, document.clone())),
+            |b, document| b.iter(|| rolegraph.insert_document(&id, document)),
         );
     }
     group.finish();
@@ -158,7 +172,7 @@ fn bench_query_throughput(c: &mut Criterion) {
     let document = dummy_document(id.clone(), body.to_string());
 
     let mut rolegraph = block_on(get_rolegraph());
-    rolegraph.insert_document(&id, document);
+    rolegraph.insert_document(&id, &document);
     let query_term = "Life cycle concepts and project direction".to_string();
 
     for size in &[1, 10, 100, 1000] {
@@ -178,7 +192,7 @@ fn bench_query(c: &mut Criterion) {
     let body = "I am a text with the word Life cycle concepts and bar and Trained operators and maintainers, project direction, some bingo words Paradigm Map and project planning, then again: some bingo words Paradigm Map and project planning, then repeats: Trained operators and maintainers, project direction";
     let document = dummy_document(id.clone(), body.to_string());
 
-    rolegraph.insert_document(&id, document);
+    rolegraph.insert_document(&id, &document);
     let query_term = "Life cycle concepts and project direction".to_string();
     c.bench_function("query_response", |b| {
         b.iter(|| rolegraph.query_graph(&query_term, None, None))
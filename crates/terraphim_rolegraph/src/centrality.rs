@@ -0,0 +1,216 @@
+//! Centrality of the concepts of a rolegraph
+//!
+//! Some concepts co-occur with almost everything else, e.g. `project` in a
+//! project management graph. They match many documents without saying
+//! much about any of them. [`RoleGraph::centrality`] is the PageRank of
+//! every concept over the co-occurrence graph, where an edge weighs as much
+//! as the two concepts co-occur, relative to the average concept: `1.0` is
+//! average, `3.0` three times as central.
+//!
+//! Roles choose a [`CentralityBias`] to surface central concepts in ranking
+//! or to dampen them: the rank a concept contributes to a document is then
+//! multiplied by its centrality raised to [`CENTRALITY_EXPONENT`], or to its
+//! negative.
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{magic_unpair, RoleGraph};
+
+/// Probability of following an edge rather than jumping to any concept
+pub const DAMPING: f64 = 0.85;
+
+/// Exponent of the centrality of a concept in its ranking factor
+pub const CENTRALITY_EXPONENT: f64 = 0.5;
+
+/// Maximum number of PageRank iterations
+const MAX_ITERATIONS: usize = 100;
+
+/// PageRank stops once no score changes by more than this in an iteration
+const TOLERANCE: f64 = 1e-9;
+
+/// How the centrality of concepts counts in ranking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CentralityBias {
+    /// Documents matching central concepts rank higher
+    Surface,
+    /// Documents matching central concepts rank lower, so specific
+    /// concepts stand out
+    Dampen,
+}
+
+impl CentralityBias {
+    /// Factor of the rank contributed by a concept of the given centrality
+    pub fn factor(&self, centrality: f64) -> f64 {
+        match self {
+            CentralityBias::Surface => centrality.powf(CENTRALITY_EXPONENT),
+            CentralityBias::Dampen => centrality.powf(-CENTRALITY_EXPONENT),
+        }
+    }
+}
+
+/// A concept with its centrality
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConceptCentrality {
+    /// Node ID (the concept ID from the thesaurus)
+    pub id: u64,
+    /// The normalized term of the concept
+    pub label: String,
+    /// PageRank relative to the average concept
+    pub centrality: f64,
+    /// Number of co-occurrences of the concept
+    pub rank: u64,
+}
+
+impl RoleGraph {
+    /// The centrality of every concept with an edge, see [`centrality`](self)
+    ///
+    /// Computed on first use after the graph changed.
+    pub fn centrality(&self) -> &AHashMap<u64, f64> {
+        self.centrality.get_or_init(|| {
+            let mut weights: AHashMap<u64, Vec<(u64, f64)>> = AHashMap::new();
+            for (edge_id, edge) in &self.edges {
+                let (source, target) = magic_unpair(*edge_id);
+                let weight = edge.doc_hash.values().sum::<u64>() as f64;
+                weights.entry(source).or_default().push((target, weight));
+                if source != target {
+                    weights.entry(target).or_default().push((source, weight));
+                }
+            }
+            pagerank(&weights)
+        })
+    }
+
+    /// The `n` most central concepts, the most central first
+    pub fn top_concepts(&self, n: usize) -> Vec<ConceptCentrality> {
+        let mut concepts: Vec<ConceptCentrality> = self
+            .centrality()
+            .iter()
+            .filter_map(|(id, centrality)| {
+                Some(ConceptCentrality {
+                    id: *id,
+                    label: self.ac_reverse_nterm.get(id)?.to_string(),
+                    centrality: *centrality,
+                    rank: self.nodes.get(id).map_or(0, |node| node.rank),
+                })
+            })
+            .collect();
+        concepts.sort_by(|a, b| {
+            b.centrality
+                .total_cmp(&a.centrality)
+                .then_with(|| a.label.cmp(&b.label))
+        });
+        concepts.truncate(n);
+        concepts
+    }
+
+    /// Factor of the rank a concept contributes to a document, given the
+    /// [`CentralityBias`] of the rolegraph
+    pub(crate) fn centrality_factor(&self, node_id: u64) -> f64 {
+        match self.centrality_bias {
+            Some(bias) => bias.factor(self.centrality().get(&node_id).copied().unwrap_or(1.0)),
+            None => 1.0,
+        }
+    }
+}
+
+/// Weighted PageRank of an undirected graph given as the weighted
+/// neighbours of every node, scaled so that the average node scores 1
+fn pagerank(weights: &AHashMap<u64, Vec<(u64, f64)>>) -> AHashMap<u64, f64> {
+    let count = weights.len() as f64;
+    if weights.is_empty() {
+        return AHashMap::new();
+    }
+    let totals: AHashMap<u64, f64> = weights
+        .iter()
+        .map(|(node, neighbours)| (*node, neighbours.iter().map(|(_, w)| w).sum()))
+        .collect();
+
+    let mut scores: AHashMap<u64, f64> = weights.keys().map(|node| (*node, 1.0 / count)).collect();
+    for _ in 0..MAX_ITERATIONS {
+        let mut next: AHashMap<u64, f64> = weights
+            .keys()
+            .map(|node| (*node, (1.0 - DAMPING) / count))
+            .collect();
+        for (node, neighbours) in weights {
+            let total = totals[node];
+            if total <= 0.0 {
+                continue;
+            }
+            let share = DAMPING * scores[node] / total;
+            for (neighbour, weight) in neighbours {
+                *next.get_mut(neighbour).expect("neighbours are nodes") += share * weight;
+            }
+        }
+        let change = next
+            .iter()
+            .map(|(node, score)| (score - scores[node]).abs())
+            .fold(0.0, f64::max);
+        scores = next;
+        if change < TOLERANCE {
+            break;
+        }
+    }
+
+    let sum: f64 = scores.values().sum();
+    scores
+        .into_iter()
+        .map(|(node, score)| (node, score * count / sum))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{Document, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    #[tokio::test]
+    async fn test_centrality() {
+        let mut thesaurus = Thesaurus::new("centrality".to_string());
+        for (term, id) in [("project", 1), ("budget", 2), ("risk", 3), ("schedule", 4)] {
+            let value = NormalizedTermValue::from(term);
+            thesaurus.insert(value.clone(), NormalizedTerm::new(id, value));
+        }
+        let mut rolegraph = RoleGraph::new("Manager".into(), thesaurus).await.unwrap();
+        // `project` is a hub, co-occurring with everything
+        for (id, body) in [
+            ("a", "project budget"),
+            ("b", "project risk"),
+            ("c", "project schedule"),
+            ("d", "budget project"),
+        ] {
+            let document = Document {
+                id: id.to_string(),
+                body: body.to_string(),
+                ..Default::default()
+            };
+            rolegraph.insert_document(id, &document);
+        }
+
+        let top = rolegraph.top_concepts(2);
+        assert_eq!(top[0].label, "project");
+        assert_eq!(top[1].label, "budget");
+        assert!(top[0].centrality > 1.0);
+        let sum: f64 = rolegraph.centrality().values().sum();
+        assert!((sum - 4.0).abs() < 1e-9);
+
+        // Documents only matching the hub rank lower once it is dampened
+        let ranks = |rolegraph: &RoleGraph| -> AHashMap<String, u64> {
+            rolegraph
+                .query_graph("project risk", None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(id, document)| (id, document.rank))
+                .collect()
+        };
+        let plain = ranks(&rolegraph);
+        let rolegraph = rolegraph.with_centrality_bias(Some(CentralityBias::Dampen));
+        let dampened = ranks(&rolegraph);
+        assert!(dampened["a"] < plain["a"]);
+        // `risk` is less central than average, so it counts more
+        let risk = rolegraph.centrality()[&3];
+        assert!(risk < 1.0);
+        assert!(CentralityBias::Dampen.factor(risk) > 1.0);
+    }
+}
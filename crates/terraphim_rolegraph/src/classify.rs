@@ -0,0 +1,274 @@
+//! Classification of documents by the top-level concepts of a rolegraph
+//!
+//! Every group of co-occurring concepts, a connected component of the
+//! rolegraph, is represented by its top-level concept, the concept of the
+//! group with the highest rank. A document is scored against a group by the
+//! concepts of the group it mentions: every match counts `1 / (n + 1)`
+//! for a concept `n` hops away from the top-level concept, so matches of
+//! the top-level concept itself count fully. A match of a weighted synonym
+//! counts its weight times as much, and not at all with a weight of 0.
+//! Concepts which never co-occur with another concept are top-level
+//! concepts of their own.
+//!
+//! The top-level concepts of the communities with a large enough share of
+//! the score of a document are its concept tags.
+//!
+//! A [`ConceptFilter`] tunes which terms documents are tagged with: terms
+//! to always tag documents containing them with, terms to ignore, a
+//! minimum length of matches and the number of tags.
+
+use std::collections::VecDeque;
+
+use ahash::AHashMap;
+use terraphim_types::Document;
+
+use crate::graph_data::connected_components;
+use crate::RoleGraph;
+
+/// Maximum number of concept tags of a document by default
+pub const MAX_CONCEPT_TAGS: usize = 3;
+
+/// Minimum share of the score of a document for a concept tag
+pub const MIN_CONCEPT_SHARE: f64 = 0.2;
+
+/// Which terms documents are tagged with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConceptFilter {
+    /// Normalized terms documents are tagged with whenever they contain
+    /// them, before their top-level concepts
+    pub include_terms: Vec<String>,
+    /// Normalized terms documents are never tagged with; matches of them
+    /// don't count for a top-level concept either
+    pub exclude_terms: Vec<String>,
+    /// Matches shorter than this many characters are ignored
+    pub min_term_length: usize,
+    /// Maximum number of tags of a document
+    pub max_terms: usize,
+}
+
+impl Default for ConceptFilter {
+    fn default() -> Self {
+        ConceptFilter {
+            include_terms: Vec::new(),
+            exclude_terms: Vec::new(),
+            min_term_length: 0,
+            max_terms: MAX_CONCEPT_TAGS,
+        }
+    }
+}
+
+impl ConceptFilter {
+    fn includes(&self, term: &str) -> bool {
+        self.include_terms
+            .iter()
+            .any(|include| include.eq_ignore_ascii_case(term))
+    }
+
+    fn excludes(&self, term: &str) -> bool {
+        self.exclude_terms
+            .iter()
+            .any(|exclude| exclude.eq_ignore_ascii_case(term))
+    }
+}
+
+/// Classifies documents by the top-level concepts of a rolegraph
+///
+/// Created with [`RoleGraph::concept_classifier`], which finds the top-level
+/// concepts once for any number of documents.
+#[derive(Debug)]
+pub struct ConceptClassifier<'a> {
+    rolegraph: &'a RoleGraph,
+    /// Top-level concept of every node and the hops to it
+    top_level: AHashMap<u64, (u64, usize)>,
+    filter: ConceptFilter,
+}
+
+impl RoleGraph {
+    /// Create a classifier for the current communities of the graph
+    pub fn concept_classifier(&self) -> ConceptClassifier<'_> {
+        let adjacency = self.adjacency();
+        let mut communities: AHashMap<usize, Vec<u64>> = AHashMap::new();
+        for (node_id, community) in connected_components(&adjacency) {
+            communities.entry(community).or_default().push(node_id);
+        }
+
+        let mut top_level = AHashMap::new();
+        for members in communities.values() {
+            let rank = |id: &u64| self.nodes.get(id).map(|node| node.rank).unwrap_or_default();
+            // Ties go to the lowest ID, so the top-level concept is stable
+            let Some(top) = members
+                .iter()
+                .max_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| b.cmp(a)))
+            else {
+                continue;
+            };
+            for (node_id, hops) in distances(&adjacency, *top) {
+                top_level.insert(node_id, (*top, hops));
+            }
+        }
+        ConceptClassifier {
+            rolegraph: self,
+            top_level,
+            filter: ConceptFilter::default(),
+        }
+    }
+}
+
+impl ConceptClassifier<'_> {
+    /// Tag documents with the terms the filter selects
+    pub fn with_filter(mut self, filter: ConceptFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Concept tags of a document, the best matching first
+    ///
+    /// Returns the included terms the document contains, then its top-level
+    /// concepts with at least [`MIN_CONCEPT_SHARE`] of the score of the
+    /// document, at most [`ConceptFilter::max_terms`] normalized terms in
+    /// all.
+    pub fn classify(&self, document: &Document) -> Vec<String> {
+        let rolegraph = self.rolegraph;
+        let filter = &self.filter;
+        let mut included: Vec<String> = Vec::new();
+        let mut scores: AHashMap<u64, f64> = AHashMap::new();
+        for text in [&document.title, &document.body] {
+            for mat in rolegraph.ac.find_iter(text.as_str()) {
+                if text[mat.start..mat.end].chars().count() < filter.min_term_length {
+                    continue;
+                }
+                let concept = rolegraph.aho_corasick_values[mat.pattern];
+                let weight = rolegraph.aho_corasick_weights[mat.pattern];
+                if weight <= 0.0 {
+                    continue;
+                }
+                let Some(term) = rolegraph.ac_reverse_nterm.get(&concept) else {
+                    continue;
+                };
+                let term = term.as_str();
+                if filter.excludes(term) {
+                    continue;
+                }
+                if filter.includes(term) && !included.iter().any(|tag| tag == term) {
+                    included.push(term.to_string());
+                }
+                let (top, hops) = self
+                    .top_level
+                    .get(&concept)
+                    .copied()
+                    .unwrap_or((concept, 0));
+                *scores.entry(top).or_default() += weight / (hops + 1) as f64;
+            }
+        }
+        let total: f64 = scores.values().sum();
+
+        let mut tags: Vec<(f64, String)> = scores
+            .into_iter()
+            .filter(|(_, score)| score / total >= MIN_CONCEPT_SHARE)
+            .filter_map(|(id, score)| {
+                let term = rolegraph.ac_reverse_nterm.get(&id)?;
+                Some((score, term.to_string()))
+            })
+            .filter(|(_, term)| !filter.excludes(term) && !included.contains(term))
+            .collect();
+        tags.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        included.extend(tags.into_iter().map(|(_, term)| term));
+        included.truncate(filter.max_terms);
+        included
+    }
+}
+
+/// Hops from `start` to every node reachable from it
+fn distances(adjacency: &AHashMap<u64, Vec<u64>>, start: u64) -> AHashMap<u64, usize> {
+    let mut distances = AHashMap::from([(start, 0)]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(node_id) = queue.pop_front() {
+        let distance = distances[&node_id];
+        for neighbour in adjacency.get(&node_id).into_iter().flatten() {
+            if !distances.contains_key(neighbour) {
+                distances.insert(*neighbour, distance + 1);
+                queue.push_back(*neighbour);
+            }
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    fn thesaurus() -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("classify".to_string());
+        for (id, term, concept) in [
+            (1, "rust", "rust"),
+            (1, "rustlang", "rust"),
+            (2, "cargo", "cargo"),
+            (3, "crates", "crates"),
+            (4, "haskell", "haskell"),
+            (5, "ghc", "ghc"),
+            (6, "cabal", "cabal"),
+            (7, "python", "python"),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus
+    }
+
+    fn document(body: &str) -> Document {
+        Document {
+            id: body.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_classify() {
+        let mut rolegraph = RoleGraph::new("classify".into(), thesaurus())
+            .await
+            .unwrap();
+        for body in ["rust cargo rustlang crates", "haskell ghc cabal haskell"] {
+            rolegraph.insert_document(body, &document(body));
+        }
+        let classifier = rolegraph.concept_classifier();
+
+        assert_eq!(
+            classifier.classify(&document("cargo and crates, a bit of ghc")),
+            vec!["rust", "haskell"]
+        );
+        // Haskell has too small a share of the score
+        assert_eq!(
+            classifier.classify(&document("Rust, rust, rust, rust and ghc")),
+            vec!["rust"]
+        );
+        // Python never co-occurs, so it is a top-level concept of its own
+        assert_eq!(classifier.classify(&document("python")), vec!["python"]);
+        assert!(classifier.classify(&document("nothing known")).is_empty());
+
+        let classifier = rolegraph.concept_classifier().with_filter(ConceptFilter {
+            include_terms: vec!["Cargo".to_string()],
+            exclude_terms: vec!["haskell".to_string()],
+            min_term_length: 4,
+            max_terms: 2,
+        });
+        // Cargo is included before its top-level concept
+        assert_eq!(
+            classifier.classify(&document("cargo and crates, a bit of ghc")),
+            vec!["cargo", "rust"]
+        );
+        // Haskell is excluded, and `ghc` is too short to count
+        assert!(classifier
+            .classify(&document("haskell, haskell and ghc"))
+            .is_empty());
+        assert_eq!(
+            classifier.classify(&document("python, python and cargo")),
+            vec!["cargo", "python"]
+        );
+    }
+}
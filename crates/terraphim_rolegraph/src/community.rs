@@ -0,0 +1,195 @@
+//! Communities of concepts, for grouping and coloring the graph view
+//!
+//! Connected components lump a whole knowledge graph together as soon as a
+//! few documents mention concepts of different topics. Communities are
+//! found by weighted label propagation instead: every concept starts in a
+//! community of its own and repeatedly joins the community it co-occurs
+//! with most, until no concept moves. Concepts are visited in order of
+//! their IDs and ties go to the lowest community, so the communities of a
+//! graph are always the same.
+//!
+//! A community is named after its concept with the highest rank, and
+//! communities are numbered by size, the largest first. The number is the
+//! [`GraphNode::community`](crate::GraphNode::community) of its concepts.
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{magic_unpair, RoleGraph};
+
+/// Maximum number of label propagation rounds
+const MAX_ROUNDS: usize = 20;
+
+/// A group of concepts which co-occur more with each other than with others
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Community {
+    /// Number of the community, see [`GraphNode::community`](crate::GraphNode::community)
+    pub id: usize,
+    /// Name of the community: the normalized term of its concept with the
+    /// highest rank
+    pub name: String,
+    /// IDs of the concepts of the community, the highest ranked first
+    pub concepts: Vec<u64>,
+}
+
+impl RoleGraph {
+    /// The communities of the concepts with an edge, the largest first
+    pub fn communities(&self) -> Vec<Community> {
+        let mut members: AHashMap<u64, Vec<u64>> = AHashMap::new();
+        for (node_id, label) in self.propagate_labels() {
+            members.entry(label).or_default().push(node_id);
+        }
+
+        let rank = |id: &u64| self.nodes.get(id).map(|node| node.rank).unwrap_or_default();
+        let mut communities: Vec<Vec<u64>> = members
+            .into_values()
+            .map(|mut concepts| {
+                // Ties go to the lowest ID, so the name is stable
+                concepts.sort_by(|a, b| rank(b).cmp(&rank(a)).then_with(|| a.cmp(b)));
+                concepts
+            })
+            .collect();
+        communities.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+
+        communities
+            .into_iter()
+            .enumerate()
+            .map(|(id, concepts)| Community {
+                id,
+                name: self
+                    .ac_reverse_nterm
+                    .get(&concepts[0])
+                    .map(|term| term.to_string())
+                    .unwrap_or_default(),
+                concepts,
+            })
+            .collect()
+    }
+
+    /// The number of the community of every concept with an edge, see
+    /// [`RoleGraph::communities`]
+    pub(crate) fn community_ids(&self) -> AHashMap<u64, usize> {
+        self.communities()
+            .into_iter()
+            .flat_map(|community| {
+                community
+                    .concepts
+                    .into_iter()
+                    .map(move |concept| (concept, community.id))
+            })
+            .collect()
+    }
+
+    /// The community label of every concept with an edge, the ID of one of
+    /// its concepts
+    fn propagate_labels(&self) -> AHashMap<u64, u64> {
+        let mut weights: AHashMap<u64, Vec<(u64, u64)>> = AHashMap::new();
+        for (edge_id, edge) in &self.edges {
+            let (source, target) = magic_unpair(*edge_id);
+            let weight = edge.doc_hash.values().sum::<u64>();
+            weights.entry(source).or_default();
+            weights.entry(target).or_default();
+            // Repeated occurrences of a concept say nothing about its community
+            if source != target {
+                weights.get_mut(&source).unwrap().push((target, weight));
+                weights.get_mut(&target).unwrap().push((source, weight));
+            }
+        }
+
+        let mut node_ids: Vec<u64> = weights.keys().copied().collect();
+        node_ids.sort_unstable();
+        let mut labels: AHashMap<u64, u64> = node_ids.iter().map(|id| (*id, *id)).collect();
+        for _ in 0..MAX_ROUNDS {
+            let mut moved = false;
+            for node_id in &node_ids {
+                let mut votes: AHashMap<u64, u64> = AHashMap::new();
+                for (neighbour, weight) in &weights[node_id] {
+                    *votes.entry(labels[neighbour]).or_default() += weight;
+                }
+                let current = labels[node_id];
+                let Some((best, best_votes)) =
+                    votes.into_iter().max_by(|(a, a_votes), (b, b_votes)| {
+                        a_votes.cmp(b_votes).then_with(|| b.cmp(a))
+                    })
+                else {
+                    continue;
+                };
+                // Only move for strictly more votes, so that labels settle
+                let current_votes = weights[node_id]
+                    .iter()
+                    .filter(|(neighbour, _)| labels[neighbour] == current)
+                    .map(|(_, weight)| weight)
+                    .sum::<u64>();
+                if best != current && best_votes > current_votes {
+                    labels.insert(*node_id, best);
+                    moved = true;
+                }
+            }
+            if !moved {
+                break;
+            }
+        }
+        labels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{Document, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    #[tokio::test]
+    async fn test_communities() {
+        let mut thesaurus = Thesaurus::new("communities".to_string());
+        let terms = [
+            ("rust", 1),
+            ("cargo", 2),
+            ("crate", 3),
+            ("budget", 4),
+            ("risk", 5),
+            ("schedule", 6),
+        ];
+        for (term, id) in terms {
+            let value = NormalizedTermValue::from(term);
+            thesaurus.insert(value.clone(), NormalizedTerm::new(id, value));
+        }
+        let mut rolegraph = RoleGraph::new("Engineer".into(), thesaurus).await.unwrap();
+        // Two tight groups, joined by a single co-occurrence
+        for (id, body) in [
+            ("a", "rust cargo crate rust"),
+            ("b", "cargo crate rust cargo"),
+            ("c", "budget risk schedule budget"),
+            ("d", "risk schedule budget risk"),
+            ("e", "crate budget"),
+        ] {
+            let document = Document {
+                id: id.to_string(),
+                body: body.to_string(),
+                ..Default::default()
+            };
+            rolegraph.insert_document(id, &document);
+        }
+
+        let communities = rolegraph.communities();
+        assert_eq!(communities.len(), 2);
+        let mut groups: Vec<Vec<u64>> = communities
+            .iter()
+            .map(|community| {
+                let mut concepts = community.concepts.clone();
+                concepts.sort_unstable();
+                concepts
+            })
+            .collect();
+        groups.sort();
+        assert_eq!(groups, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+        assert!(communities.iter().all(|community| community.name
+            == rolegraph.ac_reverse_nterm[&community.concepts[0]].to_string()));
+        assert_eq!(rolegraph.communities(), communities);
+
+        // The graph view groups nodes by the same communities
+        let ids = rolegraph.community_ids();
+        for node in rolegraph.graph_data(None, 0).nodes {
+            assert_eq!(node.community, ids[&node.id]);
+        }
+    }
+}
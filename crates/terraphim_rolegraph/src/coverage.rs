@@ -0,0 +1,350 @@
+//! Coverage of documents by the knowledge graph of a role
+//!
+//! The coverage report cross-references the thesaurus of a rolegraph with a
+//! set of documents, typically all documents in the haystacks of the role.
+//! It counts how often each concept occurs, lists the terms which never
+//! occur, the documents which contribute no concept and the concepts with a
+//! single term, and summarizes how well each haystack is covered.
+
+use std::fmt;
+
+use ahash::{AHashMap, AHashSet};
+use serde::{Deserialize, Serialize};
+use terraphim_types::Document;
+
+use crate::RoleGraph;
+
+/// Coverage of the documents of a haystack
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HaystackCoverage {
+    /// Location of the haystack
+    pub haystack: String,
+    /// Number of documents in the haystack
+    pub documents: usize,
+    /// Number of documents in which at least one concept occurs
+    pub documents_with_concepts: usize,
+    /// Number of distinct concepts which occur in the haystack
+    pub concepts: usize,
+}
+
+impl HaystackCoverage {
+    /// Share of the documents in which at least one concept occurs
+    pub fn coverage(&self) -> f64 {
+        if self.documents == 0 {
+            return 0.0;
+        }
+        self.documents_with_concepts as f64 / self.documents as f64
+    }
+}
+
+/// How often a concept occurs in a set of documents
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConceptCoverage {
+    /// The normalized term of the concept
+    pub concept: String,
+    /// Number of documents in which the concept occurs
+    pub documents: usize,
+    /// Number of matches of its terms in all documents
+    pub matches: usize,
+}
+
+/// Report on how the thesaurus of a rolegraph covers a set of documents
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CoverageReport {
+    /// Number of terms in the thesaurus
+    pub terms: usize,
+    /// Number of concepts in the thesaurus
+    pub concepts: usize,
+    /// Number of documents
+    pub documents: usize,
+    /// Concepts which occur in the documents, the most matched first
+    #[serde(default)]
+    pub matched_concepts: Vec<ConceptCoverage>,
+    /// Terms which occur in none of the documents, sorted
+    pub unmatched_terms: Vec<String>,
+    /// Concepts none of whose terms occur in the documents, sorted
+    pub unmatched_concepts: Vec<String>,
+    /// Concepts with a single term, i.e. without synonyms, sorted
+    pub single_term_concepts: Vec<String>,
+    /// URLs of the documents in which no concept occurs, sorted
+    pub documents_without_concepts: Vec<String>,
+    /// Coverage per haystack, by haystack location
+    pub haystacks: Vec<HaystackCoverage>,
+}
+
+impl RoleGraph {
+    /// Report on how the thesaurus covers the given documents
+    pub fn coverage_report<'a>(
+        &self,
+        documents: impl IntoIterator<Item = &'a Document>,
+    ) -> CoverageReport {
+        let mut matched_terms: AHashSet<String> = AHashSet::new();
+        // Documents and matches by concept ID
+        let mut matched_concepts: AHashMap<u64, (usize, usize)> = AHashMap::new();
+        let mut documents_without_concepts = Vec::new();
+        let mut haystacks: AHashMap<&str, (HaystackCoverage, AHashSet<u64>)> = AHashMap::new();
+        let mut document_count = 0;
+
+        for document in documents {
+            document_count += 1;
+            let mut concepts = AHashSet::new();
+            for text in [&document.title, &document.body] {
+                for mat in self.ac.find_iter(text.as_str()) {
+                    let id = self.aho_corasick_values[mat.pattern];
+                    matched_concepts.entry(id).or_default().1 += 1;
+                    concepts.insert(id);
+                    // Compared in the form the automaton matches them in
+                    matched_terms.insert(self.ac.normalize_term(&text[mat.start..mat.end]));
+                }
+            }
+            if concepts.is_empty() {
+                documents_without_concepts.push(document.url.clone());
+            }
+            for source in &document.sources {
+                let (coverage, haystack_concepts) = haystacks
+                    .entry(source.haystack.as_str())
+                    .or_insert_with(|| {
+                        (
+                            HaystackCoverage {
+                                haystack: source.haystack.clone(),
+                                documents: 0,
+                                documents_with_concepts: 0,
+                                concepts: 0,
+                            },
+                            AHashSet::new(),
+                        )
+                    });
+                coverage.documents += 1;
+                if !concepts.is_empty() {
+                    coverage.documents_with_concepts += 1;
+                }
+                haystack_concepts.extend(concepts.iter().copied());
+            }
+            for id in concepts {
+                matched_concepts.entry(id).or_default().0 += 1;
+            }
+        }
+
+        let mut terms_per_concept: AHashMap<u64, usize> = AHashMap::new();
+        let mut unmatched_terms = Vec::new();
+        for (term, normalized_term) in &self.thesaurus {
+            *terms_per_concept.entry(normalized_term.id).or_default() += 1;
+            if !matched_terms.contains(&self.ac.normalize_term(term.as_str())) {
+                unmatched_terms.push(term.to_string());
+            }
+        }
+        let concept_labels = |ids: Vec<u64>| -> Vec<String> {
+            let mut labels: Vec<String> = ids
+                .into_iter()
+                .filter_map(|id| self.ac_reverse_nterm.get(&id))
+                .map(|term| term.to_string())
+                .collect();
+            labels.sort();
+            labels.dedup();
+            labels
+        };
+        let unmatched_concepts = concept_labels(
+            terms_per_concept
+                .keys()
+                .filter(|id| !matched_concepts.contains_key(id))
+                .copied()
+                .collect(),
+        );
+        let single_term_concepts = concept_labels(
+            terms_per_concept
+                .iter()
+                .filter(|(_, terms)| **terms == 1)
+                .map(|(id, _)| *id)
+                .collect(),
+        );
+
+        // Concepts are looked up by ID, so their terms are merged
+        let mut concept_coverage: AHashMap<String, ConceptCoverage> = AHashMap::new();
+        for (id, (documents, matches)) in matched_concepts {
+            let Some(concept) = self.ac_reverse_nterm.get(&id) else {
+                continue;
+            };
+            let coverage = concept_coverage
+                .entry(concept.to_string())
+                .or_insert_with(|| ConceptCoverage {
+                    concept: concept.to_string(),
+                    documents: 0,
+                    matches: 0,
+                });
+            coverage.documents += documents;
+            coverage.matches += matches;
+        }
+        let mut matched_concepts: Vec<ConceptCoverage> = concept_coverage.into_values().collect();
+        matched_concepts.sort_by(|a, b| {
+            b.matches
+                .cmp(&a.matches)
+                .then_with(|| a.concept.cmp(&b.concept))
+        });
+
+        unmatched_terms.sort();
+        documents_without_concepts.sort();
+        let mut haystacks: Vec<HaystackCoverage> = haystacks
+            .into_values()
+            .map(|(mut coverage, concepts)| {
+                coverage.concepts = concepts.len();
+                coverage
+            })
+            .collect();
+        haystacks.sort_by(|a, b| a.haystack.cmp(&b.haystack));
+
+        CoverageReport {
+            terms: self.thesaurus.len(),
+            concepts: terms_per_concept.len(),
+            documents: document_count,
+            matched_concepts,
+            unmatched_terms,
+            unmatched_concepts,
+            single_term_concepts,
+            documents_without_concepts,
+            haystacks,
+        }
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} terms of {} concepts against {} documents",
+            self.terms, self.concepts, self.documents
+        )?;
+        writeln!(
+            f,
+            "{:<48} {:>10} {:>14} {:>10} {:>10}",
+            "haystack", "documents", "with concepts", "concepts", "coverage"
+        )?;
+        for haystack in &self.haystacks {
+            writeln!(
+                f,
+                "{:<48} {:>10} {:>14} {:>10} {:>9.1}%",
+                haystack.haystack,
+                haystack.documents,
+                haystack.documents_with_concepts,
+                haystack.concepts,
+                haystack.coverage() * 100.0
+            )?;
+        }
+        writeln!(f, "\nConcepts matched ({}):", self.matched_concepts.len())?;
+        for concept in &self.matched_concepts {
+            writeln!(
+                f,
+                "  {} ({} matches in {} documents)",
+                concept.concept, concept.matches, concept.documents
+            )?;
+        }
+        let lists = [
+            ("Terms never matched", &self.unmatched_terms),
+            ("Concepts never matched", &self.unmatched_concepts),
+            ("Concepts with a single term", &self.single_term_concepts),
+            (
+                "Documents without concepts",
+                &self.documents_without_concepts,
+            ),
+        ];
+        for (title, items) in lists {
+            writeln!(f, "\n{title} ({}):", items.len())?;
+            for item in items {
+                writeln!(f, "  {item}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use terraphim_types::{DocumentSource, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    fn thesaurus() -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("coverage".to_string());
+        for (id, term, concept) in [
+            (1, "rust", "rust"),
+            (1, "rustlang", "rust"),
+            (2, "cargo", "cargo"),
+            (3, "haskell", "haskell"),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus
+    }
+
+    fn document(url: &str, haystack: &str, body: &str) -> Document {
+        Document {
+            id: url.to_string(),
+            url: url.to_string(),
+            body: body.to_string(),
+            sources: vec![DocumentSource {
+                haystack: haystack.to_string(),
+                service: "ripgrep".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coverage_report() {
+        let rolegraph = RoleGraph::new("coverage".into(), thesaurus())
+            .await
+            .unwrap();
+        let documents = vec![
+            document("a.md", "docs", "Rust and Cargo"),
+            document("b.md", "docs", "Nothing known here"),
+            document("c.md", "notes", "More RUST and rust"),
+        ];
+        let report = rolegraph.coverage_report(&documents);
+
+        assert_eq!(report.terms, 4);
+        assert_eq!(report.concepts, 3);
+        assert_eq!(report.documents, 3);
+        assert_eq!(
+            report.matched_concepts,
+            vec![
+                ConceptCoverage {
+                    concept: "rust".to_string(),
+                    documents: 2,
+                    matches: 3,
+                },
+                ConceptCoverage {
+                    concept: "cargo".to_string(),
+                    documents: 1,
+                    matches: 1,
+                },
+            ]
+        );
+        assert_eq!(report.unmatched_terms, vec!["haskell", "rustlang"]);
+        assert_eq!(report.unmatched_concepts, vec!["haskell"]);
+        assert_eq!(report.single_term_concepts, vec!["cargo", "haskell"]);
+        assert_eq!(report.documents_without_concepts, vec!["b.md"]);
+        assert_eq!(
+            report.haystacks,
+            vec![
+                HaystackCoverage {
+                    haystack: "docs".to_string(),
+                    documents: 2,
+                    documents_with_concepts: 1,
+                    concepts: 2,
+                },
+                HaystackCoverage {
+                    haystack: "notes".to_string(),
+                    documents: 1,
+                    documents_with_concepts: 1,
+                    concepts: 1,
+                },
+            ]
+        );
+        assert_eq!(report.haystacks[0].coverage(), 0.5);
+        let text = report.to_string();
+        assert!(text.contains("rust (3 matches in 2 documents)"));
+        assert!(text.contains("Terms never matched (2)"));
+    }
+}
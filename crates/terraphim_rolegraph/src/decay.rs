@@ -0,0 +1,171 @@
+//! Decay of co-occurrences, so long-lived graphs follow recent content
+//!
+//! Every edge records when it was last created or strengthened (see
+//! [`Edge::updated`](terraphim_types::Edge::updated)). A rolegraph with an
+//! [`EdgeDecay`] halves the co-occurrence counts of its edges every half-life
+//! since then when [`RoleGraph::rebuild_with_decay`] is run, e.g. by the
+//! `decay_edges` maintenance job. Counts are whole numbers, rounded to the
+//! nearest; documents whose count reaches 0 are dropped from an edge, edges
+//! without documents from the graph, and concepts without edges with them.
+//! The rank of a concept shrinks in proportion to the counts of its edges.
+//!
+//! Edges indexed before their time was recorded are left as they are.
+
+use std::sync::OnceLock;
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use terraphim_types::unix_now;
+
+use crate::{magic_unpair, RoleGraph};
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// How fast co-occurrences lose weight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EdgeDecay {
+    /// Days after which the co-occurrence counts of an edge are halved
+    pub half_life_days: u64,
+}
+
+impl EdgeDecay {
+    /// Factor of the counts of an edge last updated `age` seconds ago
+    pub fn factor(&self, age: u64) -> f64 {
+        if self.half_life_days == 0 {
+            return 1.0;
+        }
+        0.5_f64.powf(age as f64 / (self.half_life_days as f64 * SECONDS_PER_DAY))
+    }
+}
+
+impl RoleGraph {
+    /// Decay co-occurrences when rebuilding the graph, see [`decay`](self);
+    /// `None` keeps them as indexed
+    pub fn with_edge_decay(mut self, edge_decay: Option<EdgeDecay>) -> Self {
+        self.edge_decay = edge_decay;
+        self
+    }
+
+    /// Decay the co-occurrences of the graph as of now, see [`decay`](self)
+    ///
+    /// Returns the number of edges dropped; nothing changes without an
+    /// [`EdgeDecay`].
+    pub fn rebuild_with_decay(&mut self) -> usize {
+        self.decay_at(unix_now())
+    }
+
+    /// Decay the co-occurrences of the graph as of `now`, in seconds since
+    /// the Unix epoch
+    pub(crate) fn decay_at(&mut self, now: u64) -> usize {
+        let Some(decay) = self.edge_decay else {
+            return 0;
+        };
+
+        // Counts of the edges of every concept before and after decaying
+        let mut totals: AHashMap<u64, (u64, u64)> = AHashMap::new();
+        for (edge_id, edge) in self.edges.iter_mut() {
+            let before: u64 = edge.doc_hash.values().sum();
+            if edge.updated > 0 && edge.updated < now {
+                let factor = decay.factor(now - edge.updated);
+                let mut changed = false;
+                for count in edge.doc_hash.values_mut() {
+                    let decayed = (*count as f64 * factor).round() as u64;
+                    changed |= decayed != *count;
+                    *count = decayed;
+                }
+                edge.doc_hash.retain(|_, count| *count > 0);
+                // Until rounding changes a count, the decay keeps adding up
+                if changed {
+                    edge.updated = now;
+                }
+            }
+            let after: u64 = edge.doc_hash.values().sum();
+            let (source, target) = magic_unpair(*edge_id);
+            for node_id in [source, target] {
+                let total = totals.entry(node_id).or_default();
+                total.0 += before;
+                total.1 += after;
+            }
+        }
+
+        let edges = self.edges.len();
+        self.edges.retain(|_, edge| !edge.doc_hash.is_empty());
+        let dropped = edges - self.edges.len();
+
+        self.nodes.retain(|node_id, node| {
+            node.connected_with
+                .retain(|edge_id| self.edges.contains_key(edge_id));
+            if let Some((before, after)) = totals.get(node_id) {
+                if *before > 0 {
+                    node.rank = (node.rank as f64 * *after as f64 / *before as f64).round() as u64;
+                }
+            }
+            !node.connected_with.is_empty()
+        });
+        self.centrality = OnceLock::new();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{Document, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[tokio::test]
+    async fn test_decay() {
+        let mut thesaurus = Thesaurus::new("decay".to_string());
+        for (term, id) in [("rust", 1), ("cargo", 2), ("tokio", 3)] {
+            let value = NormalizedTermValue::from(term);
+            thesaurus.insert(value.clone(), NormalizedTerm::new(id, value));
+        }
+        let mut rolegraph = RoleGraph::new("Engineer".into(), thesaurus)
+            .await
+            .unwrap()
+            .with_edge_decay(Some(EdgeDecay { half_life_days: 30 }));
+        for (id, body) in [
+            ("old", "rust cargo rust cargo rust cargo"),
+            ("new", "rust tokio"),
+        ] {
+            let document = Document {
+                id: id.to_string(),
+                body: body.to_string(),
+                ..Default::default()
+            };
+            rolegraph.insert_document(id, &document);
+        }
+        let now = unix_now();
+        let old = crate::magic_pair(1, 2);
+        let new = crate::magic_pair(1, 3);
+        for edge in rolegraph.edges.values_mut() {
+            if edge.doc_hash.contains_key("old") {
+                edge.updated = now - 60 * DAY;
+            }
+        }
+        let rank = rolegraph.nodes[&2].rank;
+
+        // Two half-lives quarter the counts of the old edge
+        assert_eq!(rolegraph.decay_at(now), 0);
+        let count = rolegraph.edges[&old].doc_hash["old"];
+        assert!(count > 0);
+        assert_eq!(rolegraph.edges[&old].updated, now);
+        assert!(rolegraph.nodes[&2].rank < rank);
+        assert_eq!(rolegraph.edges[&new].doc_hash["new"], 1);
+
+        // Stale co-occurrences are dropped, with their concepts
+        assert_eq!(rolegraph.decay_at(now + 365 * DAY), 3);
+        assert!(rolegraph.edges.is_empty());
+        assert!(rolegraph.nodes.is_empty());
+
+        // Without a decay, nothing changes
+        assert_eq!(
+            RoleGraph::new("Engineer".into(), Thesaurus::new("empty".to_string()))
+                .await
+                .unwrap()
+                .rebuild_with_decay(),
+            0
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Export of a rolegraph for graph tools
+//!
+//! [`RoleGraph::export`] writes the concepts of a rolegraph and their
+//! co-occurrences as GraphML (e.g. for Gephi or yEd), Graphviz DOT or Neo4j
+//! Cypher statements. Every concept is a node with its normalized term as
+//! label and its rank; every co-occurrence is an undirected edge with its
+//! rank, its weight (the number of co-occurrences over all documents, as in
+//! [`GraphEdge::weight`](crate::GraphEdge::weight)) and the IDs of the
+//! documents the concepts co-occur in.
+//!
+//! Nodes and edges are written in the order of their IDs, so exports of the
+//! same graph are identical.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{magic_unpair, RoleGraph};
+
+/// Format of an exported rolegraph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    /// GraphML
+    GraphMl,
+    /// Graphviz DOT
+    Dot,
+    /// Neo4j Cypher statements
+    Cypher,
+}
+
+impl GraphFormat {
+    /// The media type of the format
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            GraphFormat::GraphMl => "application/graphml+xml",
+            GraphFormat::Dot => "text/vnd.graphviz",
+            GraphFormat::Cypher => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "dot" => Ok(GraphFormat::Dot),
+            "cypher" => Ok(GraphFormat::Cypher),
+            _ => Err(format!(
+                "Unknown graph format `{format}`, expected graphml, dot or cypher"
+            )),
+        }
+    }
+}
+
+/// A concept of the rolegraph
+struct ExportedNode {
+    id: u64,
+    label: String,
+    rank: u64,
+}
+
+/// A co-occurrence of two concepts
+struct ExportedEdge {
+    id: u64,
+    source: u64,
+    target: u64,
+    rank: u64,
+    weight: u64,
+    documents: Vec<String>,
+}
+
+impl RoleGraph {
+    /// Export the concepts and co-occurrences of the rolegraph, see
+    /// [`export`](self)
+    pub fn export(&self, format: GraphFormat) -> String {
+        let mut nodes: Vec<ExportedNode> = self
+            .nodes
+            .values()
+            .map(|node| ExportedNode {
+                id: node.id,
+                label: self
+                    .ac_reverse_nterm
+                    .get(&node.id)
+                    .map(|term| term.to_string())
+                    .unwrap_or_default(),
+                rank: node.rank,
+            })
+            .collect();
+        nodes.sort_unstable_by_key(|node| node.id);
+
+        let mut edges: Vec<ExportedEdge> = self
+            .edges
+            .iter()
+            .map(|(edge_id, edge)| {
+                let (source, target) = magic_unpair(*edge_id);
+                let mut documents: Vec<String> = edge.doc_hash.keys().cloned().collect();
+                documents.sort_unstable();
+                ExportedEdge {
+                    id: *edge_id,
+                    source,
+                    target,
+                    rank: edge.rank,
+                    weight: edge.doc_hash.values().sum(),
+                    documents,
+                }
+            })
+            .collect();
+        edges.sort_unstable_by_key(|edge| edge.id);
+
+        let role = self.role.original.as_str();
+        match format {
+            GraphFormat::GraphMl => graphml(role, &nodes, &edges),
+            GraphFormat::Dot => dot(role, &nodes, &edges),
+            GraphFormat::Cypher => cypher(role, &nodes, &edges),
+        }
+    }
+}
+
+fn graphml(role: &str, nodes: &[ExportedNode], edges: &[ExportedEdge]) -> String {
+    let mut graphml = String::new();
+    graphml.push_str(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+         <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n  \
+         <key id=\"rank\" for=\"node\" attr.name=\"rank\" attr.type=\"long\"/>\n  \
+         <key id=\"edge_rank\" for=\"edge\" attr.name=\"rank\" attr.type=\"long\"/>\n  \
+         <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"long\"/>\n  \
+         <key id=\"documents\" for=\"edge\" attr.name=\"documents\" attr.type=\"string\"/>\n",
+    );
+    let _ = writeln!(
+        graphml,
+        "  <graph id=\"{}\" edgedefault=\"undirected\">",
+        escape_xml(role)
+    );
+    for node in nodes {
+        let _ = writeln!(graphml, "    <node id=\"n{}\">", node.id);
+        let _ = writeln!(
+            graphml,
+            "      <data key=\"label\">{}</data>",
+            escape_xml(&node.label)
+        );
+        let _ = writeln!(graphml, "      <data key=\"rank\">{}</data>", node.rank);
+        graphml.push_str("    </node>\n");
+    }
+    for edge in edges {
+        let _ = writeln!(
+            graphml,
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+            edge.id, edge.source, edge.target
+        );
+        let _ = writeln!(
+            graphml,
+            "      <data key=\"edge_rank\">{}</data>",
+            edge.rank
+        );
+        let _ = writeln!(graphml, "      <data key=\"weight\">{}</data>", edge.weight);
+        // A JSON array, as document IDs may contain any character
+        let _ = writeln!(
+            graphml,
+            "      <data key=\"documents\">{}</data>",
+            escape_xml(&serde_json::to_string(&edge.documents).unwrap_or_default())
+        );
+        graphml.push_str("    </edge>\n");
+    }
+    graphml.push_str("  </graph>\n</graphml>\n");
+    graphml
+}
+
+fn dot(role: &str, nodes: &[ExportedNode], edges: &[ExportedEdge]) -> String {
+    let mut dot = String::new();
+    let _ = writeln!(dot, "graph \"{}\" {{", escape_dot(role));
+    for node in nodes {
+        let _ = writeln!(
+            dot,
+            "  {} [label=\"{}\", rank={}];",
+            node.id,
+            escape_dot(&node.label),
+            node.rank
+        );
+    }
+    for edge in edges {
+        let _ = writeln!(
+            dot,
+            "  {} -- {} [rank={}, weight={}, documents=\"{}\"];",
+            edge.source,
+            edge.target,
+            edge.rank,
+            edge.weight,
+            escape_dot(&serde_json::to_string(&edge.documents).unwrap_or_default())
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Statements merging the concepts of the role into a Neo4j database, so
+/// running them again updates the graph rather than duplicating it
+fn cypher(role: &str, nodes: &[ExportedNode], edges: &[ExportedEdge]) -> String {
+    let role = escape_cypher(role);
+    let mut cypher = String::new();
+    for node in nodes {
+        let _ = writeln!(
+            cypher,
+            "MERGE (c:Concept {{role: '{role}', id: {}}}) SET c.label = '{}', c.rank = {};",
+            node.id,
+            escape_cypher(&node.label),
+            node.rank
+        );
+    }
+    for edge in edges {
+        let documents: Vec<String> = edge
+            .documents
+            .iter()
+            .map(|document| format!("'{}'", escape_cypher(document)))
+            .collect();
+        let _ = writeln!(
+            cypher,
+            "MATCH (a:Concept {{role: '{role}', id: {}}}), (b:Concept {{role: '{role}', id: {}}}) \
+             MERGE (a)-[r:CO_OCCURS {{id: {}}}]-(b) \
+             SET r.rank = {}, r.weight = {}, r.documents = [{}];",
+            edge.source,
+            edge.target,
+            edge.id,
+            edge.rank,
+            edge.weight,
+            documents.join(", ")
+        );
+    }
+    cypher
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escape a quoted DOT string
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escape a single quoted Cypher string
+fn escape_cypher(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{Document, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    #[tokio::test]
+    async fn test_export() {
+        let mut thesaurus = Thesaurus::new("export".to_string());
+        for (term, id) in [("rust", 1), ("cargo & crates", 2)] {
+            let value = NormalizedTermValue::from(term);
+            thesaurus.insert(value.clone(), NormalizedTerm::new(id, value));
+        }
+        let mut rolegraph = RoleGraph::new("Rust Engineer".into(), thesaurus)
+            .await
+            .unwrap();
+        let document = Document {
+            id: "guide's \"intro\"".to_string(),
+            body: "rust with cargo & crates".to_string(),
+            ..Default::default()
+        };
+        rolegraph.insert_document(&document.id, &document);
+        let edge = crate::magic_pair(1, 2);
+        let (source, target) = magic_unpair(edge);
+
+        let graphml = rolegraph.export(GraphFormat::GraphMl);
+        assert!(graphml.contains("<graph id=\"Rust Engineer\" edgedefault=\"undirected\">"));
+        assert!(graphml.contains("<data key=\"label\">cargo &amp; crates</data>"));
+        assert!(graphml.contains(&format!(
+            "<edge id=\"e{edge}\" source=\"n{source}\" target=\"n{target}\">"
+        )));
+        assert!(graphml.contains(
+            "<data key=\"documents\">[&quot;guide's \\&quot;intro\\&quot;&quot;]</data>"
+        ));
+
+        let dot = rolegraph.export(GraphFormat::Dot);
+        assert!(dot.starts_with("graph \"Rust Engineer\" {\n"));
+        assert!(dot.contains("  1 [label=\"rust\", rank=1];"));
+        assert!(dot.contains(&format!("  {source} -- {target} [rank=1, weight=1, ")));
+
+        let cypher = rolegraph.export(GraphFormat::Cypher);
+        assert!(cypher.contains(
+            "MERGE (c:Concept {role: 'Rust Engineer', id: 2}) SET c.label = 'cargo & crates', c.rank = 1;"
+        ));
+        assert!(cypher.contains("r.documents = ['guide\\'s \"intro\"'];"));
+
+        assert_eq!("GraphML".parse(), Ok(GraphFormat::GraphMl));
+        assert!("svg".parse::<GraphFormat>().is_err());
+    }
+}
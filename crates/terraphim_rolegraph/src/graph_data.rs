@@ -0,0 +1,302 @@
+//! Layout-ready export of a `RoleGraph` for visualisation.
+//!
+//! The rolegraph stores edges keyed by the `magic_pair` of the two node IDs
+//! they connect, so the endpoints of every edge can be recovered with
+//! `magic_unpair`. This module turns that internal representation into a
+//! plain list of labelled nodes and weighted edges that a frontend can feed
+//! straight into a force-directed layout.
+
+use ahash::{AHashMap, AHashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::{magic_unpair, RoleGraph};
+
+/// A concept node ready to be rendered
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GraphNode {
+    /// Node ID (the concept ID from the thesaurus)
+    pub id: u64,
+    /// Human readable label (the normalized term of the concept)
+    pub label: String,
+    /// Number of co-occurrences of the concept
+    pub rank: u64,
+    /// Community the node belongs to, see [`RoleGraph::communities`]
+    pub community: usize,
+}
+
+/// A weighted, undirected edge between two concept nodes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GraphEdge {
+    /// Edge ID
+    pub id: u64,
+    /// ID of the first node
+    pub source: u64,
+    /// ID of the second node
+    pub target: u64,
+    /// Weight of the edge: the number of co-occurrences over all documents
+    pub weight: u64,
+    /// Number of documents in which the two concepts co-occur
+    pub documents: usize,
+}
+
+/// Nodes and edges of a rolegraph (or a neighbourhood of it)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct GraphData {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl RoleGraph {
+    /// Returns the endpoints of all edges, resolved from the edge IDs
+    fn edge_endpoints(&self) -> Vec<(u64, u64, u64)> {
+        self.edges
+            .keys()
+            .map(|edge_id| {
+                let (source, target) = magic_unpair(*edge_id);
+                (*edge_id, source, target)
+            })
+            .collect()
+    }
+
+    /// Pairs of concepts which co-occur in a document, the endpoints of all
+    /// edges, sorted
+    pub fn concept_relations(&self) -> Vec<(u64, u64)> {
+        let mut relations: Vec<(u64, u64)> = self
+            .edge_endpoints()
+            .into_iter()
+            .map(|(_, source, target)| (source, target))
+            .collect();
+        relations.sort_unstable();
+        relations
+    }
+
+    /// Returns the neighbours of every node which has an edge
+    pub(crate) fn adjacency(&self) -> AHashMap<u64, Vec<u64>> {
+        let mut adjacency: AHashMap<u64, Vec<u64>> = AHashMap::new();
+        for (_, source, target) in self.edge_endpoints() {
+            adjacency.entry(source).or_default().push(target);
+            adjacency.entry(target).or_default().push(source);
+        }
+        adjacency
+    }
+
+    /// Export the graph as a list of labelled nodes and weighted edges.
+    ///
+    /// If `center` is given, only the neighbourhood of the concepts matched
+    /// in `center` is returned, up to `depth` hops away. Otherwise the whole
+    /// graph is returned.
+    pub fn graph_data(&self, center: Option<&str>, depth: usize) -> GraphData {
+        let endpoints = self.edge_endpoints();
+        let adjacency = self.adjacency();
+
+        let selected: AHashSet<u64> = match center {
+            Some(center) => {
+                let start = self.find_matching_node_ids(center);
+                neighbourhood(&adjacency, &start, depth)
+                    .into_iter()
+                    .filter(|id| self.nodes.contains_key(id))
+                    .collect()
+            }
+            None => self.nodes.keys().copied().collect(),
+        };
+
+        let communities = self.community_ids();
+
+        let mut nodes: Vec<GraphNode> = selected
+            .iter()
+            .filter_map(|id| self.nodes.get(id))
+            .map(|node| GraphNode {
+                id: node.id,
+                label: self
+                    .ac_reverse_nterm
+                    .get(&node.id)
+                    .map(|term| term.to_string())
+                    .unwrap_or_default(),
+                rank: node.rank,
+                community: communities.get(&node.id).copied().unwrap_or_default(),
+            })
+            .collect();
+        nodes.sort_by_key(|node| std::cmp::Reverse(node.rank));
+
+        let mut edges: Vec<GraphEdge> = endpoints
+            .into_iter()
+            .filter(|(_, source, target)| selected.contains(source) && selected.contains(target))
+            .filter_map(|(edge_id, source, target)| {
+                let edge = self.edges.get(&edge_id)?;
+                Some(GraphEdge {
+                    id: edge_id,
+                    source,
+                    target,
+                    weight: edge.doc_hash.values().sum(),
+                    documents: edge.doc_hash.len(),
+                })
+            })
+            .collect();
+        edges.sort_by_key(|edge| std::cmp::Reverse(edge.weight));
+
+        GraphData { nodes, edges }
+    }
+
+    /// Concepts which co-occur with the concepts matched in `text`
+    ///
+    /// Returns at most `limit` nodes, the most strongly connected first.
+    /// Their rank is the summed weight of the edges to the matched concepts.
+    pub fn related_concepts(&self, text: &str, limit: usize) -> Vec<GraphNode> {
+        let matched: AHashSet<u64> = self.find_matching_node_ids(text).into_iter().collect();
+
+        let mut weights: AHashMap<u64, u64> = AHashMap::new();
+        for node_id in &matched {
+            let Some(node) = self.nodes.get(node_id) else {
+                continue;
+            };
+            for edge_id in &node.connected_with {
+                let Some(edge) = self.edges.get(edge_id) else {
+                    continue;
+                };
+                let (source, target) = magic_unpair(*edge_id);
+                let other = if source == *node_id { target } else { source };
+                if !matched.contains(&other) {
+                    *weights.entry(other).or_default() += edge.doc_hash.values().sum::<u64>();
+                }
+            }
+        }
+
+        let mut related: Vec<GraphNode> = weights
+            .into_iter()
+            .filter_map(|(id, rank)| {
+                let label = self.ac_reverse_nterm.get(&id)?.to_string();
+                Some(GraphNode {
+                    id,
+                    label,
+                    rank,
+                    community: 0,
+                })
+            })
+            .collect();
+        related.sort_by(|a, b| b.rank.cmp(&a.rank).then_with(|| a.label.cmp(&b.label)));
+        related.truncate(limit);
+        related
+    }
+}
+
+/// All nodes reachable from `start` in at most `depth` hops (including `start`)
+fn neighbourhood(
+    adjacency: &AHashMap<u64, Vec<u64>>,
+    start: &[u64],
+    depth: usize,
+) -> AHashSet<u64> {
+    let mut visited: AHashSet<u64> = start.iter().copied().collect();
+    let mut queue: VecDeque<(u64, usize)> = start.iter().map(|id| (*id, 0)).collect();
+    while let Some((node_id, distance)) = queue.pop_front() {
+        if distance >= depth {
+            continue;
+        }
+        for neighbour in adjacency.get(&node_id).into_iter().flatten() {
+            if visited.insert(*neighbour) {
+                queue.push_back((*neighbour, distance + 1));
+            }
+        }
+    }
+    visited
+}
+
+/// Assigns every node in `adjacency` the index of its connected component
+pub(crate) fn connected_components(adjacency: &AHashMap<u64, Vec<u64>>) -> AHashMap<u64, usize> {
+    // Sort the node IDs so that component numbers are stable between calls
+    let mut node_ids: Vec<u64> = adjacency.keys().copied().collect();
+    node_ids.sort_unstable();
+
+    let mut components = AHashMap::new();
+    let mut next_component = 0;
+    for node_id in node_ids {
+        if components.contains_key(&node_id) {
+            continue;
+        }
+        for member in neighbourhood(adjacency, &[node_id], usize::MAX) {
+            components.insert(member, next_component);
+        }
+        next_component += 1;
+    }
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_automata::{load_thesaurus, AutomataPath};
+    use terraphim_types::Document;
+
+    async fn sample_rolegraph() -> RoleGraph {
+        let thesaurus = load_thesaurus(&AutomataPath::local_example_full())
+            .await
+            .unwrap();
+        let mut rolegraph = RoleGraph::new("system operator".into(), thesaurus)
+            .await
+            .unwrap();
+        let document = Document {
+            id: "doc1".to_string(),
+            title: "Life cycle concepts and project direction".to_string(),
+            body: "Life cycle concepts and Trained operators and maintainers, project direction, some bingo words Paradigm Map and project planning".to_string(),
+            ..Default::default()
+        };
+        rolegraph.insert_document("doc1", &document);
+        rolegraph
+    }
+
+    #[tokio::test]
+    async fn test_graph_data_contains_all_nodes_and_edges() {
+        let rolegraph = sample_rolegraph().await;
+        let data = rolegraph.graph_data(None, 0);
+        assert_eq!(data.nodes.len(), rolegraph.nodes.len());
+        assert_eq!(data.edges.len(), rolegraph.edges.len());
+        for edge in &data.edges {
+            assert!(rolegraph.nodes.contains_key(&edge.source));
+            assert!(rolegraph.nodes.contains_key(&edge.target));
+        }
+        assert!(data.nodes.iter().all(|node| !node.label.is_empty()));
+
+        let relations = rolegraph.concept_relations();
+        assert_eq!(relations.len(), data.edges.len());
+        assert!(data
+            .edges
+            .iter()
+            .all(|edge| relations.contains(&(edge.source, edge.target))));
+    }
+
+    #[tokio::test]
+    async fn test_graph_data_neighbourhood() {
+        let rolegraph = sample_rolegraph().await;
+        let full = rolegraph.graph_data(None, 0);
+        let center = full.nodes[0].label.clone();
+
+        let data = rolegraph.graph_data(Some(&center), 0);
+        assert_eq!(data.nodes.len(), 1);
+        // Only self-loops (repeated occurrences of the concept) remain
+        let center_id = data.nodes[0].id;
+        assert!(data
+            .edges
+            .iter()
+            .all(|edge| edge.source == center_id && edge.target == center_id));
+
+        let data = rolegraph.graph_data(Some(&center), 1);
+        assert!(data.nodes.len() > 1);
+        assert!(!data.edges.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_related_concepts() {
+        let rolegraph = sample_rolegraph().await;
+        let full = rolegraph.graph_data(None, 0);
+        let center = &full.nodes[0];
+
+        let related = rolegraph.related_concepts(&center.label, 10);
+        assert!(!related.is_empty());
+        assert!(related.iter().all(|node| node.id != center.id));
+        assert!(related.windows(2).all(|pair| pair[0].rank >= pair[1].rank));
+        assert_eq!(rolegraph.related_concepts(&center.label, 1).len(), 1);
+        assert!(rolegraph
+            .related_concepts("no concepts here", 10)
+            .is_empty());
+    }
+}
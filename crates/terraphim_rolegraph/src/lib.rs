@@ -1,17 +1,35 @@
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use itertools::Itertools;
 use memoize::memoize;
 use regex::Regex;
 use std::collections::hash_map::Entry;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use terraphim_automata::{Normalization, TermMatcher};
 use terraphim_types::{
-    Document, Edge, IndexedDocument, Node, NormalizedTermValue, RoleName, Thesaurus,
+    unix_now, Document, Edge, IndexedDocument, Node, NormalizedTermValue, RoleName, Thesaurus,
 };
 use tokio::sync::{Mutex, MutexGuard};
+pub mod centrality;
+pub mod classify;
+pub mod community;
+pub mod coverage;
+pub mod decay;
+pub mod export;
+pub mod graph_data;
 pub mod input;
-use aho_corasick::{AhoCorasick, MatchKind};
+pub mod snapshot;
 use unicode_segmentation::UnicodeSegmentation;
 
+pub use centrality::{CentralityBias, ConceptCentrality};
+pub use classify::{ConceptClassifier, ConceptFilter};
+pub use community::Community;
+pub use coverage::{ConceptCoverage, CoverageReport, HaystackCoverage};
+pub use decay::EdgeDecay;
+pub use export::GraphFormat;
+pub use graph_data::{GraphData, GraphEdge, GraphNode};
+pub use snapshot::RoleGraphSnapshot;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("The given node ID was not found")]
@@ -28,6 +46,13 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Last version given to the thesaurus of a rolegraph of this process
+static THESAURUS_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_thesaurus_version() -> u64 {
+    THESAURUS_VERSION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
 /// A `RoleGraph` is a graph of concepts and their relationships.
 ///
 /// It is used to index documents and search for them.
@@ -47,36 +72,33 @@ pub struct RoleGraph {
     pub thesaurus: Thesaurus,
     /// Aho-Corasick values
     aho_corasick_values: Vec<u64>,
+    /// Weight of each Aho-Corasick pattern, see
+    /// [`NormalizedTerm::weight`](terraphim_types::NormalizedTerm::weight)
+    aho_corasick_weights: Vec<f64>,
     /// Aho-Corasick automata
-    pub ac: AhoCorasick,
+    pub ac: TermMatcher,
     /// reverse lookup - matched id into normalized term
     pub ac_reverse_nterm: AHashMap<u64, NormalizedTermValue>,
+    /// Version of the thesaurus, see [`RoleGraph::thesaurus_version`]
+    thesaurus_version: u64,
+    /// Centrality of the concepts, see [`RoleGraph::centrality`]; reset
+    /// whenever the graph changes
+    centrality: OnceLock<AHashMap<u64, f64>>,
+    /// How the centrality of concepts counts in [`RoleGraph::query_graph`]
+    centrality_bias: Option<CentralityBias>,
+    /// How fast co-occurrences lose weight, see [`RoleGraph::rebuild_with_decay`]
+    edge_decay: Option<EdgeDecay>,
 }
 
 impl RoleGraph {
     /// Creates a new `RoleGraph` with the given role and thesaurus
     pub async fn new(role: RoleName, thesaurus: Thesaurus) -> Result<Self> {
-        // We need to iterate over keys and values at the same time
-        // because the order of entries is not guaranteed
-        // when using `.keys()` and `.values()`.
-        // let (keys, values): (Vec<&str>, Vec<Id>) = thesaurus
-        //     .iter()
-        //     .map(|(key, value)| (key.as_str(), value.id))
-        //     .unzip();
-        let mut keys = Vec::new();
-        let mut values = Vec::new();
-        let mut ac_reverse_nterm = AHashMap::new();
-
-        for (key, normalized_term) in &thesaurus {
-            keys.push(key);
-            values.push(normalized_term.id);
-            ac_reverse_nterm.insert(normalized_term.id, normalized_term.value.clone());
-        }
-
-        let ac = AhoCorasick::builder()
-            .match_kind(MatchKind::LeftmostLongest)
-            .ascii_case_insensitive(true)
-            .build(keys)?;
+        let Automata {
+            ac,
+            values: aho_corasick_values,
+            weights: aho_corasick_weights,
+            reverse_nterm: ac_reverse_nterm,
+        } = cached_automata(&thesaurus, None)?;
 
         Ok(Self {
             role,
@@ -84,12 +106,82 @@ impl RoleGraph {
             edges: AHashMap::new(),
             documents: AHashMap::new(),
             thesaurus,
-            aho_corasick_values: values,
+            aho_corasick_values,
+            aho_corasick_weights,
             ac,
             ac_reverse_nterm,
+            thesaurus_version: next_thesaurus_version(),
+            centrality: OnceLock::new(),
+            centrality_bias: None,
+            edge_decay: None,
         })
     }
 
+    /// Match terms and text normalized with `normalization`, e.g. to match
+    /// "Straße" or "operators", rather than ignoring ASCII case only
+    ///
+    /// The automata are rebuilt; the normalization is kept when the
+    /// thesaurus is replaced.
+    pub fn with_normalization(mut self, normalization: Option<Normalization>) -> Result<Self> {
+        let automata = cached_automata(&self.thesaurus, normalization)?;
+        self.ac = automata.ac;
+        self.aho_corasick_values = automata.values;
+        self.aho_corasick_weights = automata.weights;
+        self.ac_reverse_nterm = automata.reverse_nterm;
+        Ok(self)
+    }
+
+    /// Scale the rank each concept contributes in
+    /// [`RoleGraph::query_graph`] by its centrality, see
+    /// [`CentralityBias`]; `None` ranks all concepts alike
+    pub fn with_centrality_bias(mut self, centrality_bias: Option<CentralityBias>) -> Self {
+        self.centrality_bias = centrality_bias;
+        self
+    }
+
+    /// Version of the thesaurus of the rolegraph, unique within this process
+    ///
+    /// A new rolegraph and every replaced thesaurus get a new version, so
+    /// anything derived from the thesaurus can be rebuilt when it changes.
+    pub fn thesaurus_version(&self) -> u64 {
+        self.thesaurus_version
+    }
+
+    /// Replaces the thesaurus in place, keeping the indexed documents
+    ///
+    /// The automata are rebuilt from the new thesaurus. Nodes of concepts
+    /// which are no longer in the thesaurus are dropped together with their
+    /// edges; everything else stays as indexed. Documents are not matched
+    /// against concepts which are new in the thesaurus until they are
+    /// inserted again.
+    pub fn replace_thesaurus(&mut self, thesaurus: Thesaurus) -> Result<()> {
+        let Automata {
+            ac,
+            values: aho_corasick_values,
+            weights: aho_corasick_weights,
+            reverse_nterm: ac_reverse_nterm,
+        } = cached_automata(&thesaurus, self.ac.normalization().copied())?;
+
+        self.edges.retain(|edge_id, _| {
+            let (x, y) = magic_unpair(*edge_id);
+            ac_reverse_nterm.contains_key(&x) && ac_reverse_nterm.contains_key(&y)
+        });
+        self.nodes.retain(|node_id, node| {
+            node.connected_with
+                .retain(|edge_id| self.edges.contains_key(edge_id));
+            ac_reverse_nterm.contains_key(node_id) && !node.connected_with.is_empty()
+        });
+
+        self.thesaurus = thesaurus;
+        self.thesaurus_version = next_thesaurus_version();
+        self.aho_corasick_values = aho_corasick_values;
+        self.aho_corasick_weights = aho_corasick_weights;
+        self.ac = ac;
+        self.ac_reverse_nterm = ac_reverse_nterm;
+        self.centrality = OnceLock::new();
+        Ok(())
+    }
+
     /// Find all matches in the rolegraph for the given text
     ///
     /// Returns a list of IDs of the matched nodes
@@ -97,7 +189,56 @@ impl RoleGraph {
         log::trace!("Finding matching node IDs for text: '{text}'");
         self.ac
             .find_iter(text)
-            .map(|mat| self.aho_corasick_values[mat.pattern()])
+            .into_iter()
+            .map(|mat| self.aho_corasick_values[mat.pattern])
+            .collect()
+    }
+
+    /// Find all matches in the rolegraph for the given text, with the weight
+    /// of each matched term
+    pub fn find_weighted_node_ids(&self, text: &str) -> Vec<(u64, f64)> {
+        self.ac
+            .find_iter(text)
+            .into_iter()
+            .map(|mat| {
+                (
+                    self.aho_corasick_values[mat.pattern],
+                    self.aho_corasick_weights[mat.pattern],
+                )
+            })
+            .collect()
+    }
+
+    /// Number of matches of any synonym of a concept in a text
+    pub fn count_matches(&self, node_id: u64, text: &str) -> usize {
+        self.ac
+            .find_iter(text)
+            .into_iter()
+            .filter(|mat| self.aho_corasick_values[mat.pattern] == node_id)
+            .count()
+    }
+
+    /// Find the concepts of a query in a text
+    ///
+    /// Any synonym of a concept matched in `query` counts. Returns the
+    /// start and end byte offsets of every match in `text` with the
+    /// normalized term of its concept.
+    pub fn find_query_spans(&self, query: &str, text: &str) -> Vec<(usize, usize, String)> {
+        let query_ids: AHashSet<u64> = self.find_matching_node_ids(query).into_iter().collect();
+        if query_ids.is_empty() {
+            return Vec::new();
+        }
+        self.ac
+            .find_iter(text)
+            .into_iter()
+            .filter_map(|mat| {
+                let id = self.aho_corasick_values[mat.pattern];
+                if !query_ids.contains(&id) {
+                    return None;
+                }
+                let term = self.ac_reverse_nterm.get(&id)?;
+                Some((mat.start, mat.end, term.to_string()))
+            })
             .collect()
     }
 
@@ -146,7 +287,10 @@ impl RoleGraph {
     /// Performs a query on the graph using the query string.
     ///
     /// Returns a list of document IDs ranked and weighted by the weighted mean
-    /// average of node rank, edge rank, and document rank.
+    /// average of node rank, edge rank, and document rank. The rank of every
+    /// concept matched in the query is scaled by the weight of the matched
+    /// term, so a loose synonym counts less than the normalized term, and by
+    /// its centrality if the rolegraph has a [`CentralityBias`].
     pub fn query_graph(
         &self,
         query_string: &str,
@@ -154,15 +298,16 @@ impl RoleGraph {
         limit: Option<usize>,
     ) -> Result<Vec<(String, IndexedDocument)>> {
         log::debug!("Performing graph query with string: '{query_string}'");
-        let node_ids = self.find_matching_node_ids(query_string);
+        let node_ids = self.find_weighted_node_ids(query_string);
 
         let mut results = AHashMap::new();
-        for node_id in node_ids {
+        for (node_id, weight) in node_ids {
             let node = self.nodes.get(&node_id).ok_or(Error::NodeIdNotFound)?;
             let Some(normalized_term) = self.ac_reverse_nterm.get(&node_id) else {
                 return Err(Error::NodeIdNotFound);
             };
             log::debug!("Processing node ID: {:?} with rank: {}", node_id, node.rank);
+            let weight = weight * self.centrality_factor(node_id);
 
             for edge_id in &node.connected_with {
                 let edge = self.edges.get(edge_id).ok_or(Error::EdgeIdNotFound)?;
@@ -171,6 +316,7 @@ impl RoleGraph {
                 for (document_id, document_rank) in &edge.doc_hash {
                     // For now, this sums up over nodes and edges
                     let total_rank = node.rank + edge.rank + document_rank;
+                    let total_rank = (total_rank as f64 * weight).round() as u64;
                     match results.entry(document_id.clone()) {
                         Entry::Vacant(e) => {
                             e.insert(IndexedDocument {
@@ -216,7 +362,7 @@ impl RoleGraph {
     // }
 
     /// Inserts an document into the rolegraph
-    pub fn insert_document(&mut self, document_id: &str, document: Document) {
+    pub fn insert_document(&mut self, document_id: &str, document: &Document) {
         let matches = self.find_matching_node_ids(&document.to_string());
         for (a, b) in matches.into_iter().tuple_windows() {
             self.add_or_update_document(document_id, a, b);
@@ -224,6 +370,7 @@ impl RoleGraph {
     }
 
     pub fn add_or_update_document(&mut self, document_id: &str, x: u64, y: u64) {
+        self.centrality = OnceLock::new();
         let edge = magic_pair(x, y);
         let edge = self.init_or_update_edge(edge, document_id);
         self.init_or_update_node(x, &edge);
@@ -254,6 +401,7 @@ impl RoleGraph {
             Entry::Occupied(entry) => {
                 let edge = entry.into_mut();
                 *edge.doc_hash.entry(document_id.to_string()).or_insert(1) += 1;
+                edge.updated = unix_now();
 
                 edge.clone()
             }
@@ -262,6 +410,86 @@ impl RoleGraph {
     }
 }
 
+/// The Aho-Corasick automata of a thesaurus
+#[derive(Clone)]
+struct Automata {
+    ac: TermMatcher,
+    /// The concept ID of each pattern
+    values: Vec<u64>,
+    /// The weight of each pattern
+    weights: Vec<f64>,
+    /// The reverse lookup from concept IDs to normalized terms
+    reverse_nterm: AHashMap<u64, NormalizedTermValue>,
+}
+
+/// Number of automata kept by [`cached_automata`]
+const MAX_CACHED_AUTOMATA: usize = 16;
+
+/// What the automata of a thesaurus depend on: the fingerprint of the
+/// thesaurus and the normalization
+type AutomataKey = (u64, Option<Normalization>);
+
+/// Recently built automata, the most recent last
+static AUTOMATA: std::sync::Mutex<Vec<(AutomataKey, Automata)>> = std::sync::Mutex::new(Vec::new());
+
+/// The Aho-Corasick automata of a thesaurus, built unless they were built
+/// for a thesaurus with the same [`Thesaurus::fingerprint`] before
+///
+/// Rolegraphs of roles sharing a thesaurus, rebuilt when the config
+/// changes or reloading an unchanged thesaurus reuse the automata, which
+/// takes a pass over the thesaurus instead of building them.
+fn cached_automata(
+    thesaurus: &Thesaurus,
+    normalization: Option<Normalization>,
+) -> Result<Automata> {
+    let key = (thesaurus.fingerprint(), normalization);
+    {
+        let mut cached = AUTOMATA.lock().unwrap();
+        if let Some(position) = cached.iter().position(|(cached_key, _)| *cached_key == key) {
+            let entry = cached.remove(position);
+            let automata = entry.1.clone();
+            cached.push(entry);
+            return Ok(automata);
+        }
+    }
+    let automata = build_automata(thesaurus, normalization)?;
+    let mut cached = AUTOMATA.lock().unwrap();
+    if !cached.iter().any(|(cached_key, _)| *cached_key == key) {
+        if cached.len() >= MAX_CACHED_AUTOMATA {
+            cached.remove(0);
+        }
+        cached.push((key, automata.clone()));
+    }
+    Ok(automata)
+}
+
+/// Builds the Aho-Corasick automata of a thesaurus
+fn build_automata(thesaurus: &Thesaurus, normalization: Option<Normalization>) -> Result<Automata> {
+    // We need to iterate over keys and values at the same time
+    // because the order of entries is not guaranteed
+    // when using `.keys()` and `.values()`.
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    let mut weights = Vec::new();
+    let mut ac_reverse_nterm = AHashMap::new();
+
+    for (key, normalized_term) in thesaurus {
+        keys.push(key);
+        values.push(normalized_term.id);
+        weights.push(normalized_term.weight());
+        ac_reverse_nterm.insert(normalized_term.id, normalized_term.value.clone());
+    }
+
+    let ac = TermMatcher::new(keys.iter().map(|key| key.as_str()), normalization)?;
+
+    Ok(Automata {
+        ac,
+        values,
+        weights,
+        reverse_nterm: ac_reverse_nterm,
+    })
+}
+
 /// Wraps the `RoleGraph` for ingesting documents and is `Send` and `Sync`
 #[derive(Debug, Clone)]
 pub struct RoleGraphSync {
@@ -338,6 +566,7 @@ mod tests {
     use super::*;
 
     use terraphim_automata::{load_thesaurus, AutomataPath};
+    use terraphim_types::{NormalizedTerm, TermWeight};
     use tokio::test;
     use ulid::Ulid;
 
@@ -397,6 +626,138 @@ mod tests {
         );
     }
 
+    #[test]
+    async fn test_cached_automata() {
+        let mut thesaurus = load_sample_thesaurus().await;
+        thesaurus.insert(
+            NormalizedTermValue::new("cached automata".to_string()),
+            NormalizedTerm::new(u64::MAX, NormalizedTermValue::new("cached".to_string())),
+        );
+        let key = (thesaurus.fingerprint(), None);
+        let rolegraph = RoleGraph::new("system operator".into(), thesaurus.clone())
+            .await
+            .unwrap();
+        assert!(AUTOMATA
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(cached_key, _)| *cached_key == key));
+
+        // A rolegraph of the same thesaurus matches the same concepts
+        let cached = RoleGraph::new("engineer".into(), thesaurus).await.unwrap();
+        let text = "Trained operators and maintainers use cached automata";
+        assert_eq!(
+            cached.find_matching_node_ids(text),
+            rolegraph.find_matching_node_ids(text)
+        );
+        assert!(cached.find_matching_node_ids(text).contains(&u64::MAX));
+    }
+
+    #[test]
+    async fn test_find_query_spans() {
+        let role = "system operator".to_string();
+        let rolegraph = RoleGraph::new(role.into(), load_sample_thesaurus().await)
+            .await
+            .unwrap();
+        let text = "The Project Direction is set by trained operators and maintainers";
+        let spans = rolegraph.find_query_spans("project direction", text);
+        assert_eq!(spans.len(), 1);
+        let (start, end, _) = &spans[0];
+        assert_eq!(&text[*start..*end], "Project Direction");
+
+        assert!(rolegraph
+            .find_query_spans("project direction", "no concepts")
+            .is_empty());
+        assert!(rolegraph.find_query_spans("nothing known", text).is_empty());
+    }
+
+    #[test]
+    async fn test_replace_thesaurus() {
+        let thesaurus = load_sample_thesaurus().await;
+        let mut rolegraph = RoleGraph::new("system operator".into(), thesaurus.clone())
+            .await
+            .unwrap();
+        let document = Document {
+            id: "document".to_string(),
+            body: "I am a text with the word Life cycle concepts and bar and Trained operators and maintainers, project direction, some bingo words Paradigm Map and project planning".to_string(),
+            ..Default::default()
+        };
+        rolegraph.insert_document(&document.id, &document);
+        let query = "Life cycle concepts and project direction";
+        assert_eq!(rolegraph.query_graph(query, None, None).unwrap().len(), 1);
+
+        // Reloading the same thesaurus keeps the indexed documents
+        let version = rolegraph.thesaurus_version();
+        rolegraph.replace_thesaurus(thesaurus.clone()).unwrap();
+        assert_eq!(rolegraph.query_graph(query, None, None).unwrap().len(), 1);
+        assert!(rolegraph.thesaurus_version() > version);
+
+        // Concepts removed from the thesaurus are dropped from the graph
+        let removed = rolegraph.find_matching_node_ids(query)[0];
+        let mut reduced = Thesaurus::new(thesaurus.name().to_string());
+        for (key, normalized_term) in &thesaurus {
+            if normalized_term.id != removed {
+                reduced.insert(key.clone(), normalized_term.clone());
+            }
+        }
+        rolegraph.replace_thesaurus(reduced).unwrap();
+        assert!(!rolegraph.nodes.contains_key(&removed));
+        assert!(rolegraph.find_matching_node_ids(query).is_empty());
+        assert!(rolegraph.query_graph(query, None, None).unwrap().is_empty());
+    }
+
+    #[test]
+    async fn test_normalized_matching() {
+        let thesaurus = load_sample_thesaurus().await;
+        let text = "PROJECT CONSTRAINTS and a project constraint";
+        let rolegraph = RoleGraph::new("system operator".into(), thesaurus.clone())
+            .await
+            .unwrap();
+        assert_eq!(rolegraph.find_matching_node_ids(text).len(), 1);
+
+        let normalization = Normalization {
+            stem: Some(terraphim_automata::language::Language::English),
+            ..Default::default()
+        };
+        let mut rolegraph = rolegraph.with_normalization(Some(normalization)).unwrap();
+        let ids = rolegraph.find_matching_node_ids(text);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0], ids[1]);
+
+        // The normalization is kept with a new thesaurus
+        rolegraph.replace_thesaurus(thesaurus).unwrap();
+        assert_eq!(rolegraph.find_matching_node_ids(text).len(), 2);
+    }
+
+    #[test]
+    async fn test_weighted_synonyms() {
+        let mut thesaurus = Thesaurus::new("weights".to_string());
+        for (term, id, concept, weight) in [
+            ("rust", 1, "rust", None),
+            ("rustlang", 1, "rust", TermWeight::new(0.5)),
+            ("oxide", 1, "rust", TermWeight::new(0.0)),
+            ("cargo", 2, "cargo", None),
+        ] {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(id, NormalizedTermValue::new(concept.to_string()))
+                    .with_weight(weight),
+            );
+        }
+        let mut rolegraph = RoleGraph::new("weights".into(), thesaurus).await.unwrap();
+        let document = Document {
+            id: "document".to_string(),
+            body: "rust and cargo".to_string(),
+            ..Default::default()
+        };
+        rolegraph.insert_document(&document.id, &document);
+
+        let rank = |query: &str| rolegraph.query_graph(query, None, None).unwrap()[0].1.rank;
+        assert_eq!(rank("rust"), 3);
+        assert_eq!(rank("rustlang"), 2);
+        assert_eq!(rank("oxide"), 0);
+    }
+
     #[test]
     async fn test_terraphim_engineer() {
         let role_name = "Terraphim Engineer".to_string();
@@ -426,16 +787,13 @@ mod tests {
             rolegraph.add_or_update_document(&document_id, a, b);
         }
         let document = Document {
-            stub: None,
-            url: "/path/to/document".to_string(),
-            tags: None,
-            rank: None,
             id: document_id.clone(),
+            url: "/path/to/document".to_string(),
             title: "README".to_string(),
             body: test_document.to_string(),
-            description: None,
+            ..Default::default()
         };
-        rolegraph.insert_document(&document_id, document);
+        rolegraph.insert_document(&document_id, &document);
         println!("query with {}", "terraphim-graph and service".to_string());
         let results: Vec<(String, IndexedDocument)> =
             match rolegraph.query_graph("terraphim-graph and service", Some(0), Some(10)) {
@@ -461,16 +819,13 @@ mod tests {
         Now we will have a concept "Terrpahim Graph Scorer" with synonyms "graph embeddings" and "terraphim-graph". This provides service
         "#;
         let document2 = Document {
-            stub: None,
-            url: "/path/to/document2".to_string(),
-            tags: None,
-            rank: None,
             id: document_id2.clone(),
+            url: "/path/to/document2".to_string(),
             title: "terraphim-graph".to_string(),
             body: test_document2.to_string(),
-            description: None,
+            ..Default::default()
         };
-        rolegraph.insert_document(&document_id2, document2);
+        rolegraph.insert_document(&document_id2, &document2);
         log::debug!("Query graph");
         let results: Vec<(String, IndexedDocument)> = rolegraph
             .query_graph("terraphim-graph and service", Some(0), Some(10))
@@ -511,16 +866,13 @@ mod tests {
         let document_id4 = "DocumentID4".to_string();
         let query4 = "I am a text with the word Life cycle concepts and bar and maintainers, some bingo words, then again: some bingo words Paradigm Map and project planning, then repeats: Trained operators and maintainers, project direction";
         let document = Document {
-            stub: None,
-            url: "/path/to/document".to_string(),
-            tags: None,
-            rank: None,
             id: document_id4.clone(),
+            url: "/path/to/document".to_string(),
             title: "Life cycle concepts and project direction".to_string(),
             body: query4.to_string(),
-            description: None,
+            ..Default::default()
         };
-        rolegraph.insert_document(&document_id4, document);
+        rolegraph.insert_document(&document_id4, &document);
         log::debug!("Query graph");
         let results: Vec<(String, IndexedDocument)> = rolegraph
             .query_graph(
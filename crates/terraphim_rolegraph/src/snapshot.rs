@@ -0,0 +1,175 @@
+//! Snapshots of rolegraphs, so a restart doesn't start from an empty graph
+//!
+//! A [`RoleGraphSnapshot`] holds the nodes, edges and documents of a
+//! rolegraph, keyed by the role and tagged with the fingerprint of its
+//! thesaurus (see [`Thesaurus::fingerprint`]). The automata are not part of
+//! it, they are rebuilt from the thesaurus. A snapshot taken with another
+//! thesaurus is not restored, as its node IDs may refer to other concepts.
+
+use std::sync::OnceLock;
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use terraphim_persistence::Persistable;
+use terraphim_types::{Edge, IndexedDocument, Node, RoleName};
+
+#[cfg(doc)]
+use terraphim_types::Thesaurus;
+
+use crate::RoleGraph;
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// The graph of a rolegraph without its automata
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoleGraphSnapshot {
+    pub role: RoleName,
+    /// Fingerprint of the thesaurus of the rolegraph
+    pub thesaurus: u64,
+    #[serde(default)]
+    pub nodes: AHashMap<u64, Node>,
+    #[serde(default)]
+    pub edges: AHashMap<u64, Edge>,
+    #[serde(default)]
+    pub documents: AHashMap<String, IndexedDocument>,
+}
+
+#[async_trait]
+impl Persistable for RoleGraphSnapshot {
+    fn new(key: String) -> Self {
+        RoleGraphSnapshot {
+            role: RoleName::new(&key),
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    /// The role name is hex-encoded, so that distinct roles never share a key
+    fn get_key(&self) -> String {
+        let role: String = self
+            .role
+            .as_lowercase()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("rolegraph_{role}.json")
+    }
+}
+
+impl RoleGraph {
+    /// The nodes, edges and documents of the rolegraph
+    pub fn snapshot(&self) -> RoleGraphSnapshot {
+        RoleGraphSnapshot {
+            role: self.role.clone(),
+            thesaurus: self.thesaurus.fingerprint(),
+            nodes: self.nodes.clone(),
+            edges: self.edges.clone(),
+            documents: self.documents.clone(),
+        }
+    }
+
+    /// Replace the nodes, edges and documents of the rolegraph with those
+    /// of a snapshot of the same role and thesaurus
+    ///
+    /// Returns whether the snapshot was restored.
+    pub fn restore(&mut self, snapshot: RoleGraphSnapshot) -> bool {
+        if snapshot.role != self.role || snapshot.thesaurus != self.thesaurus.fingerprint() {
+            return false;
+        }
+        self.nodes = snapshot.nodes;
+        self.edges = snapshot.edges;
+        self.documents = snapshot.documents;
+        self.centrality = OnceLock::new();
+        true
+    }
+
+    /// Persist a snapshot of the rolegraph, see [`RoleGraph::snapshot`]
+    pub async fn save_snapshot(&self) -> PersistenceResult<()> {
+        self.snapshot().save().await
+    }
+
+    /// Restore the persisted snapshot of the rolegraph, if there is one of
+    /// the same thesaurus
+    ///
+    /// Returns whether a snapshot was restored.
+    pub async fn load_snapshot(&mut self) -> PersistenceResult<bool> {
+        let mut snapshot = <RoleGraphSnapshot as Persistable>::new(self.role.original.clone());
+        match snapshot.load().await {
+            Ok(snapshot) => Ok(self.restore(snapshot)),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::{Document, NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+    fn thesaurus(terms: &[(&str, u64)]) -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("snapshot".to_string());
+        for (term, id) in terms {
+            let value = NormalizedTermValue::from(*term);
+            thesaurus.insert(value.clone(), NormalizedTerm::new(*id, value));
+        }
+        thesaurus
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let terms = [("rust", 1), ("cargo", 2), ("crate", 3)];
+        let mut rolegraph = RoleGraph::new("Engineer".into(), thesaurus(&terms))
+            .await
+            .unwrap();
+        let document = Document {
+            id: "doc".to_string(),
+            body: "rust builds a crate with cargo".to_string(),
+            ..Default::default()
+        };
+        rolegraph.insert_document(&document.id, &document);
+        let snapshot = rolegraph.snapshot();
+        assert_eq!(snapshot.get_key(), "rolegraph_656e67696e656572.json");
+        assert_eq!(snapshot.nodes.len(), 3);
+
+        // The same thesaurus, in another order
+        let reversed: Vec<(&str, u64)> = terms.iter().rev().copied().collect();
+        let mut restored = RoleGraph::new("Engineer".into(), thesaurus(&reversed))
+            .await
+            .unwrap();
+        assert!(restored.restore(snapshot.clone()));
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(
+            restored.find_matching_node_ids("cargo"),
+            rolegraph.find_matching_node_ids("cargo")
+        );
+
+        let mut other = RoleGraph::new("Engineer".into(), thesaurus(&terms[..2]))
+            .await
+            .unwrap();
+        assert!(!other.restore(snapshot.clone()));
+        let mut other = RoleGraph::new("Operator".into(), thesaurus(&terms))
+            .await
+            .unwrap();
+        assert!(!other.restore(snapshot));
+    }
+}
@@ -0,0 +1,152 @@
+//! Benchmarks for the search pipeline against a synthetic corpus.
+//!
+//! Requires `rg` (ripgrep) to be installed. To run a single benchmark use:
+//!
+//! ```sh
+//! cargo bench --bench search -- "search/Profile Title Scorer"
+//! ```
+//!
+//! For a per-stage breakdown of a single run use the `--profile-search`
+//! mode of the server instead.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+
+use terraphim_service::profile::{
+    profile_config_state, CANNED_QUERIES, PROFILE_GRAPH_ROLE, PROFILE_TITLE_ROLE,
+};
+use terraphim_service::TerraphimService;
+use terraphim_types::{Document, Index, RoleName, SearchQuery};
+
+/// Corpus sizes to benchmark
+const SIZES: &[usize] = &[100, 1000];
+
+/// Allocator which counts the allocated bytes, to report how much memory
+/// copying documents costs
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Bytes allocated while running `f`
+fn allocated<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let result = f();
+    let bytes = ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(result);
+    bytes
+}
+
+fn search_query(search_term: &str, role: &str) -> SearchQuery {
+    SearchQuery {
+        search_term: search_term.into(),
+        role: Some(RoleName::new(role)),
+        ..Default::default()
+    }
+}
+
+fn bench_search(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("search");
+    for size in SIZES {
+        let dir = tempfile::tempdir().unwrap();
+        let config_state = runtime
+            .block_on(profile_config_state(dir.path(), *size))
+            .unwrap();
+        for role in [PROFILE_TITLE_ROLE, PROFILE_GRAPH_ROLE] {
+            let mut service = TerraphimService::new(config_state.clone());
+            group.bench_with_input(BenchmarkId::new(role, size), size, |b, _| {
+                b.iter(|| {
+                    for query in CANNED_QUERIES {
+                        runtime
+                            .block_on(service.search_profiled(&search_query(query, role)))
+                            .unwrap();
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_search_haystacks(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("search_haystacks");
+    for size in SIZES {
+        let dir = tempfile::tempdir().unwrap();
+        let config_state = runtime
+            .block_on(profile_config_state(dir.path(), *size))
+            .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, _| {
+            b.iter(|| {
+                runtime
+                    .block_on(terraphim_middleware::search_haystacks(
+                        config_state.clone(),
+                        search_query("knowledge graph", PROFILE_TITLE_ROLE),
+                    ))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Compare handing the documents of a search result with about 1000 documents to
+/// the scorer by copying them (as the pipeline used to) with sharing them
+fn bench_documents(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let config_state = runtime
+        .block_on(profile_config_state(dir.path(), 1000))
+        .unwrap();
+    // Nearly every document of the synthetic corpus contains "the"
+    let index: Index = runtime
+        .block_on(terraphim_middleware::search_haystacks(
+            config_state,
+            search_query("the", PROFILE_TITLE_ROLE),
+        ))
+        .unwrap();
+
+    let copy = |index: &Index| -> Vec<Document> {
+        index
+            .values()
+            .map(|document| Document::clone(document))
+            .collect()
+    };
+    println!(
+        "{} documents: copying allocates {} bytes, sharing {} bytes, cloning the index {} bytes",
+        index.len(),
+        allocated(|| copy(&index)),
+        allocated(|| index.get_all_documents()),
+        allocated(|| index.clone()),
+    );
+
+    let mut group = c.benchmark_group("documents");
+    group.bench_function("copy", |b| b.iter(|| copy(&index)));
+    group.bench_function("share", |b| b.iter(|| index.get_all_documents()));
+    group.bench_function("clone_index", |b| b.iter(|| index.clone()));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_search,
+    bench_search_haystacks,
+    bench_documents
+);
+criterion_main!(benches);
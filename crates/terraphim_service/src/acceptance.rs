@@ -0,0 +1,338 @@
+//! Learning from the suggestions users accept
+//!
+//! When a suggestion for the search box is accepted, the client reports it
+//! together with the suggestions shown alongside it. Each term counts how
+//! often it was shown and accepted, per role, and later suggestions are
+//! scored by their acceptance rate: the score of a suggestion is
+//! multiplied by `0.5 + (accepted + 1) / (shown + 2)`, so terms never shown
+//! keep their score, and a term is at most boosted by half or cut by half.
+//!
+//! Only terms of the role thesaurus are counted, and at most [`MAX_SHOWN`]
+//! shown terms per acceptance, so clients cannot grow the counters with
+//! arbitrary strings.
+//!
+//! Counters are persisted via `terraphim_persistence`, one key per role,
+//! after every [`PERSIST_EVERY`] acceptances of the role.
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use terraphim_persistence::Persistable;
+use terraphim_types::{NormalizedTermValue, RoleName, Thesaurus};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::suggest::Suggestion;
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+static ACCEPTANCE: OnceCell<AcceptanceStore> = OnceCell::const_new();
+
+/// Number of shown terms counted per acceptance
+pub const MAX_SHOWN: usize = 50;
+
+/// Number of acceptances of a role after which its counters are persisted
+pub const PERSIST_EVERY: usize = 10;
+
+/// A suggestion accepted in the search box
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuggestionAcceptance {
+    /// The term of the accepted suggestion
+    pub accepted: String,
+    /// The terms of all suggestions shown, including the accepted one
+    #[serde(default)]
+    pub shown: Vec<String>,
+}
+
+impl SuggestionAcceptance {
+    /// Keep the first [`MAX_SHOWN`] shown terms, and of them only those of
+    /// the thesaurus
+    ///
+    /// Returns whether the accepted term is a term of the thesaurus; if it
+    /// is not, the acceptance should not be counted.
+    pub fn restrict_to(&mut self, thesaurus: &Thesaurus) -> bool {
+        let known = |term: &str| {
+            thesaurus
+                .get(&NormalizedTermValue::new(term.to_string()))
+                .is_some()
+        };
+        self.shown.truncate(MAX_SHOWN);
+        self.shown.retain(|term| known(term));
+        known(&self.accepted)
+    }
+}
+
+/// How often a term was suggested and accepted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acceptance {
+    pub shown: u32,
+    pub accepted: u32,
+}
+
+impl Acceptance {
+    /// Factor of the score of the term, between 0.5 and 1.5
+    fn weight(&self) -> f64 {
+        0.5 + (f64::from(self.accepted) + 1.0) / (f64::from(self.shown) + 2.0)
+    }
+}
+
+/// The accepted suggestions of a role
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoleAcceptance {
+    pub role: RoleName,
+    /// Acceptance by lowercase term
+    #[serde(default)]
+    pub terms: AHashMap<String, Acceptance>,
+}
+
+#[async_trait]
+impl Persistable for RoleAcceptance {
+    fn new(key: String) -> Self {
+        RoleAcceptance {
+            role: RoleName::new(&key),
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    /// The role name is hex-encoded, so that distinct roles never share a key
+    fn get_key(&self) -> String {
+        let role: String = self
+            .role
+            .as_lowercase()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("acceptance_{role}.json")
+    }
+}
+
+impl RoleAcceptance {
+    /// Count an accepted suggestion and the suggestions shown with it
+    pub fn record(&mut self, acceptance: &SuggestionAcceptance) {
+        let accepted = acceptance.accepted.to_lowercase();
+        let mut shown: Vec<String> = acceptance
+            .shown
+            .iter()
+            .map(|term| term.to_lowercase())
+            .chain([accepted.clone()])
+            .collect();
+        shown.sort_unstable();
+        shown.dedup();
+        for term in shown {
+            let counts = self.terms.entry(term).or_default();
+            counts.shown = counts.shown.saturating_add(1);
+        }
+        let counts = self.terms.entry(accepted).or_default();
+        counts.accepted = counts.accepted.saturating_add(1);
+    }
+
+    /// Scale the scores of suggestions by the acceptance rate of their
+    /// terms and reorder them
+    pub fn boost(&self, suggestions: &mut [Suggestion]) {
+        if self.terms.is_empty() {
+            return;
+        }
+        for suggestion in suggestions.iter_mut() {
+            if let Some(acceptance) = self.terms.get(&suggestion.term.to_lowercase()) {
+                suggestion.score *= acceptance.weight();
+            }
+        }
+        suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    }
+}
+
+/// The counters of a role and the number of acceptances since they were
+/// persisted
+struct Entry {
+    counters: RoleAcceptance,
+    unsaved: usize,
+}
+
+/// The accepted suggestions of all roles of this process
+pub struct AcceptanceStore {
+    roles: Mutex<AHashMap<RoleName, Entry>>,
+}
+
+impl AcceptanceStore {
+    /// Get the store; the counters of a role are loaded when first used
+    pub async fn instance() -> &'static AcceptanceStore {
+        ACCEPTANCE
+            .get_or_init(|| async {
+                AcceptanceStore {
+                    roles: Mutex::new(AHashMap::new()),
+                }
+            })
+            .await
+    }
+
+    /// Count an accepted suggestion of a role, persisting the counters of
+    /// the role after every [`PERSIST_EVERY`] acceptances
+    ///
+    /// Callers restrict the acceptance to the terms of the role first, see
+    /// [`SuggestionAcceptance::restrict_to`].
+    pub async fn record(&self, role: &RoleName, acceptance: &SuggestionAcceptance) {
+        let unsaved = self
+            .with_role(role, |entry| {
+                entry.counters.record(acceptance);
+                entry.unsaved += 1;
+                if entry.unsaved < PERSIST_EVERY {
+                    return None;
+                }
+                entry.unsaved = 0;
+                Some(entry.counters.clone())
+            })
+            .await;
+        // Saved without holding the lock, so suggestions don't wait for it
+        if let Some(counters) = unsaved {
+            if let Err(e) = counters.save().await {
+                log::warn!(
+                    "Failed to persist accepted suggestions of role `{}`: {:?}",
+                    role,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Reorder suggestions of a role by their acceptance, see
+    /// [`RoleAcceptance::boost`]
+    pub async fn boost(&self, role: &RoleName, suggestions: &mut [Suggestion]) {
+        self.with_role(role, |entry| entry.counters.boost(suggestions))
+            .await
+    }
+
+    /// Run `f` on the counters of a role, loading them on first use
+    ///
+    /// The store is not locked while the counters are loaded.
+    async fn with_role<T>(&self, role: &RoleName, f: impl FnOnce(&mut Entry) -> T) -> T {
+        if let Some(entry) = self.roles.lock().await.get_mut(role) {
+            return f(entry);
+        }
+        let counters = load(role).await;
+        let mut roles = self.roles.lock().await;
+        let entry = roles.entry(role.clone()).or_insert(Entry {
+            counters,
+            unsaved: 0,
+        });
+        f(entry)
+    }
+}
+
+/// The persisted counters of a role, or new ones
+async fn load(role: &RoleName) -> RoleAcceptance {
+    let mut counters = RoleAcceptance {
+        role: role.clone(),
+        ..Default::default()
+    };
+    match counters.load().await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            log::debug!(
+                "Starting without accepted suggestions for role `{}`: {:?}",
+                role,
+                e
+            );
+            counters
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use terraphim_types::NormalizedTerm;
+
+    use crate::suggest::SuggestionSource;
+
+    fn suggestion(term: &str, score: f64) -> Suggestion {
+        Suggestion {
+            term: term.to_string(),
+            source: SuggestionSource::Autocomplete,
+            score,
+        }
+    }
+
+    fn terms(suggestions: &[Suggestion]) -> Vec<&str> {
+        suggestions.iter().map(|s| s.term.as_str()).collect()
+    }
+
+    #[test]
+    fn test_acceptance() {
+        let mut counters = <RoleAcceptance as Persistable>::new("Engineer".to_string());
+        assert_eq!(counters.get_key(), "acceptance_656e67696e656572.json");
+
+        let acceptance = SuggestionAcceptance {
+            accepted: "Rustc".to_string(),
+            shown: vec!["rust".to_string(), "rustc".to_string()],
+        };
+        counters.record(&acceptance);
+        counters.record(&acceptance);
+        assert_eq!(
+            counters.terms["rustc"],
+            Acceptance {
+                shown: 2,
+                accepted: 2
+            }
+        );
+        assert_eq!(
+            counters.terms["rust"],
+            Acceptance {
+                shown: 2,
+                accepted: 0
+            }
+        );
+
+        let mut suggestions = vec![
+            suggestion("rust", 0.9),
+            suggestion("rustc", 0.6),
+            suggestion("rustdoc", 0.5),
+        ];
+        counters.boost(&mut suggestions);
+        assert_eq!(terms(&suggestions), vec!["rustc", "rust", "rustdoc"]);
+        assert_eq!(suggestions[1].score, 0.9 * 0.75);
+        // Never shown, so neither boosted nor cut
+        assert_eq!(suggestions[2].score, 0.5);
+    }
+
+    #[test]
+    fn test_restrict_to() {
+        let mut thesaurus = Thesaurus::new("Engineer".to_string());
+        for (id, term) in [(1, "rust"), (2, "rustc")] {
+            let value = NormalizedTermValue::new(term.to_string());
+            thesaurus.insert(value.clone(), NormalizedTerm::new(id, value));
+        }
+
+        let mut acceptance = SuggestionAcceptance {
+            accepted: "Rustc".to_string(),
+            shown: vec!["rust".to_string(), "x".repeat(1000), "rustc".to_string()],
+        };
+        assert!(acceptance.restrict_to(&thesaurus));
+        assert_eq!(acceptance.shown, vec!["rust", "rustc"]);
+
+        let mut acceptance = SuggestionAcceptance {
+            accepted: "rustdoc".to_string(),
+            shown: vec!["rust".to_string(); MAX_SHOWN + 1],
+        };
+        assert!(!acceptance.restrict_to(&thesaurus));
+        assert_eq!(acceptance.shown.len(), MAX_SHOWN);
+    }
+}
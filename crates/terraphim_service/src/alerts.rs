@@ -0,0 +1,506 @@
+//! Saved searches and alerts
+//!
+//! A saved search is a search query of a role which the runner started with
+//! [`spawn_alerts`] re-runs on a schedule. The documents found are
+//! remembered by a fingerprint of their content, so every run after the
+//! first can tell which documents are new or changed since the previous run.
+//! If any are, an [`Alert`] is sent to the subscribers of the
+//! [`SavedSearchStore`] (the event stream of the server, the desktop app)
+//! and posted to the webhook of the saved search, if it has one.
+//!
+//! Saved searches are persisted via `terraphim_persistence`.
+
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use terraphim_config::ConfigState;
+use terraphim_persistence::Persistable;
+use terraphim_types::{Document, RoleName, SearchQuery};
+use tokio::sync::{broadcast, Mutex, OnceCell};
+use tokio::task::JoinHandle;
+
+use crate::analytics::now_millis;
+use crate::TerraphimService;
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Minimum interval between two runs of a saved search in seconds
+pub const MIN_INTERVAL: u64 = 60;
+
+/// How often the runner checks for saved searches which are due
+pub(crate) const TICK: Duration = Duration::from_secs(15);
+
+/// Number of alerts a slow subscriber can lag behind before missing alerts
+const ALERT_CAPACITY: usize = 64;
+
+static SAVED_SEARCHES: OnceCell<SavedSearchStore> = OnceCell::const_new();
+
+/// A search which is re-run on a schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSearch {
+    /// Unique ID, assigned when the search is saved
+    #[serde(default)]
+    pub id: String,
+    /// Name of the saved search, shown in notifications
+    pub name: String,
+    /// The search query; the role of the query is the role the search
+    /// runs as and defaults to the default role
+    pub query: SearchQuery,
+    /// How often the search is run in seconds, at least [`MIN_INTERVAL`]
+    pub interval: u64,
+    /// URL to which alerts of the saved search are posted as JSON
+    #[serde(default)]
+    pub webhook: Option<String>,
+    /// Time of the last run in milliseconds since the Unix epoch
+    #[serde(default)]
+    pub last_run: Option<u64>,
+}
+
+impl SavedSearch {
+    /// Role the search runs as
+    pub fn role(&self) -> Option<&RoleName> {
+        self.query.role.as_ref()
+    }
+
+    fn is_due(&self, now: u64) -> bool {
+        match self.last_run {
+            Some(last_run) => now >= last_run + self.interval * 1000,
+            None => true,
+        }
+    }
+}
+
+/// A document of an alert
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AlertDocument {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+}
+
+impl From<&Document> for AlertDocument {
+    fn from(document: &Document) -> Self {
+        Self {
+            id: document.id.clone(),
+            title: document.title.clone(),
+            url: document.url.clone(),
+        }
+    }
+}
+
+/// Documents which a saved search found since its previous run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Alert {
+    /// ID of the saved search
+    pub saved_search: String,
+    /// Name of the saved search
+    pub name: String,
+    /// Role the search ran as
+    pub role: Option<RoleName>,
+    /// Documents which weren't found by the previous run
+    pub new_documents: Vec<AlertDocument>,
+    /// Documents whose content changed since the previous run
+    pub changed_documents: Vec<AlertDocument>,
+    /// Time of the run in milliseconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Saved searches with the documents their last runs found
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedSearches {
+    searches: Vec<SavedSearch>,
+    /// Fingerprints of the documents found by the last run of each saved
+    /// search, by saved search ID and document ID
+    #[serde(default)]
+    seen: AHashMap<String, AHashMap<String, u64>>,
+}
+
+#[async_trait]
+impl Persistable for SavedSearches {
+    fn new(_key: String) -> Self {
+        SavedSearches::default()
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        "saved_searches.json".to_string()
+    }
+}
+
+/// Fingerprint of the content of a document
+///
+/// FNV is used because the fingerprints are persisted and have to stay the
+/// same across restarts.
+fn fingerprint(document: &Document) -> u64 {
+    let mut hasher = FnvHasher::default();
+    document.title.hash(&mut hasher);
+    document.body.hash(&mut hasher);
+    document.description.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What a run of a saved search found compared to the previous run
+#[derive(Debug, Default)]
+struct Changes {
+    /// Fingerprints of the documents found, by document ID
+    fingerprints: AHashMap<String, u64>,
+    new_documents: Vec<AlertDocument>,
+    changed_documents: Vec<AlertDocument>,
+}
+
+/// Compare the documents found by a run with the fingerprints of the
+/// documents found by the previous run
+fn diff(seen: &AHashMap<String, u64>, documents: &[Document]) -> Changes {
+    let mut changes = Changes::default();
+    for document in documents {
+        let current = fingerprint(document);
+        match seen.get(&document.id) {
+            None => changes.new_documents.push(document.into()),
+            Some(previous) if *previous != current => {
+                changes.changed_documents.push(document.into())
+            }
+            Some(_) => {}
+        }
+        changes.fingerprints.insert(document.id.clone(), current);
+    }
+    changes
+}
+
+/// The saved searches of this process
+pub struct SavedSearchStore {
+    searches: Mutex<SavedSearches>,
+    alerts: broadcast::Sender<Alert>,
+}
+
+impl SavedSearchStore {
+    /// Get the store, loading the persisted saved searches on first access
+    pub async fn instance() -> &'static SavedSearchStore {
+        SAVED_SEARCHES
+            .get_or_init(|| async {
+                let searches = match SavedSearches::default().load().await {
+                    Ok(searches) => searches,
+                    Err(e) => {
+                        log::debug!("Starting without saved searches: {:?}", e);
+                        SavedSearches::default()
+                    }
+                };
+                SavedSearchStore {
+                    searches: Mutex::new(searches),
+                    alerts: broadcast::channel(ALERT_CAPACITY).0,
+                }
+            })
+            .await
+    }
+
+    /// Receive the alerts of all saved searches from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Alert> {
+        self.alerts.subscribe()
+    }
+
+    /// Save a search, replacing the saved search with the same ID
+    ///
+    /// A search without ID gets a new one. Returns the saved search.
+    pub async fn save(&self, mut search: SavedSearch) -> SavedSearch {
+        if search.id.is_empty() {
+            search.id = ulid::Ulid::new().to_string();
+        }
+        search.interval = search.interval.max(MIN_INTERVAL);
+        let mut guard = self.searches.lock().await;
+        let searches = &mut *guard;
+        match searches.searches.iter_mut().find(|s| s.id == search.id) {
+            Some(existing) => {
+                // A changed query, e.g. with other filters, starts from a
+                // clean slate
+                if existing.query != search.query {
+                    searches.seen.remove(&search.id);
+                    search.last_run = None;
+                } else {
+                    search.last_run = existing.last_run;
+                }
+                *existing = search.clone();
+            }
+            None => {
+                search.last_run = None;
+                searches.searches.push(search.clone());
+            }
+        }
+        persist(searches).await;
+        search
+    }
+
+    /// Saved searches of a role, or of all roles
+    pub async fn list(&self, role: Option<&RoleName>) -> Vec<SavedSearch> {
+        self.searches
+            .lock()
+            .await
+            .searches
+            .iter()
+            .filter(|search| role.is_none() || search.role() == role)
+            .cloned()
+            .collect()
+    }
+
+    /// The saved search with the given ID
+    pub async fn get(&self, id: &str) -> Option<SavedSearch> {
+        self.searches
+            .lock()
+            .await
+            .searches
+            .iter()
+            .find(|search| search.id == id)
+            .cloned()
+    }
+
+    /// Delete a saved search
+    ///
+    /// Returns `false` if there is no saved search with the given ID.
+    pub async fn delete(&self, id: &str) -> bool {
+        let mut searches = self.searches.lock().await;
+        let count = searches.searches.len();
+        searches.searches.retain(|search| search.id != id);
+        if searches.searches.len() == count {
+            return false;
+        }
+        searches.seen.remove(id);
+        persist(&searches).await;
+        true
+    }
+
+    /// Saved searches which are due to run
+    async fn due(&self, now: u64) -> Vec<SavedSearch> {
+        self.searches
+            .lock()
+            .await
+            .searches
+            .iter()
+            .filter(|search| search.is_due(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Record a run of a saved search
+    ///
+    /// Returns the alert for the run, unless nothing changed, it was the
+    /// first run of the search or the search was deleted meanwhile.
+    async fn record_run(
+        &self,
+        search: &SavedSearch,
+        documents: &[Document],
+        now: u64,
+    ) -> Option<Alert> {
+        let mut searches = self.searches.lock().await;
+        let SavedSearches {
+            searches: saved,
+            seen,
+        } = &mut *searches;
+        let saved = saved.iter_mut().find(|s| s.id == search.id)?;
+        let is_first_run = saved.last_run.is_none();
+        saved.last_run = Some(now);
+
+        let previous = seen.remove(&search.id).unwrap_or_default();
+        let changes = diff(&previous, documents);
+        seen.insert(search.id.clone(), changes.fingerprints);
+        persist(&searches).await;
+
+        if is_first_run
+            || (changes.new_documents.is_empty() && changes.changed_documents.is_empty())
+        {
+            return None;
+        }
+        Some(Alert {
+            saved_search: search.id.clone(),
+            name: search.name.clone(),
+            role: search.role().cloned(),
+            new_documents: changes.new_documents,
+            changed_documents: changes.changed_documents,
+            timestamp: now,
+        })
+    }
+
+    /// Mark a saved search as run without recording what it found, e.g.
+    /// because the search failed
+    async fn skip_run(&self, id: &str, now: u64) {
+        let mut searches = self.searches.lock().await;
+        if let Some(saved) = searches.searches.iter_mut().find(|s| s.id == id) {
+            saved.last_run = Some(now);
+            persist(&searches).await;
+        }
+    }
+
+    /// Send an alert to the subscribers and the webhook of the saved search
+    fn notify(&self, alert: Alert, webhook: Option<String>) {
+        log::info!(
+            "Saved search `{}` found {} new and {} changed documents",
+            alert.name,
+            alert.new_documents.len(),
+            alert.changed_documents.len()
+        );
+        if let Some(url) = webhook {
+            let alert = alert.clone();
+            tokio::spawn(async move {
+                let response = reqwest::Client::new().post(&url).json(&alert).send().await;
+                match response.and_then(|response| response.error_for_status()) {
+                    Ok(_) => log::debug!("Posted alert to webhook {url}"),
+                    Err(e) => log::warn!("Failed to post alert to webhook {url}: {e}"),
+                }
+            });
+        }
+        // Sending only fails if nobody is subscribed
+        let _ = self.alerts.send(alert);
+    }
+}
+
+async fn persist(searches: &SavedSearches) {
+    if let Err(e) = searches.save().await {
+        log::warn!("Failed to persist saved searches: {:?}", e);
+    }
+}
+
+/// Run the saved searches which are due and send their alerts
+///
+/// Returns the number of saved searches run.
+pub(crate) async fn run_due(config_state: &ConfigState) -> usize {
+    let store = SavedSearchStore::instance().await;
+    let due = store.due(now_millis()).await;
+    for search in &due {
+        log::debug!("Running saved search `{}`", search.name);
+        let mut service = TerraphimService::new(config_state.clone());
+        let now = now_millis();
+        match service.search_profiled(&search.query).await {
+            Ok((documents, _record)) => {
+                if let Some(alert) = store.record_run(search, &documents, now).await {
+                    store.notify(alert, search.webhook.clone());
+                }
+            }
+            Err(e) => {
+                log::warn!("Saved search `{}` failed: {e}", search.name);
+                store.skip_run(&search.id, now).await;
+            }
+        }
+    }
+    due.len()
+}
+
+/// Start running saved searches when they are due
+///
+/// The runner runs until the returned task is aborted. The server runs
+/// saved searches as one of its maintenance jobs instead, see
+/// [`crate::jobs`].
+pub fn spawn_alerts(config_state: ConfigState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            run_due(&config_state).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, body: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            title: id.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let changes = diff(
+            &AHashMap::new(),
+            &[document("a", "first"), document("b", "second")],
+        );
+        assert_eq!(changes.new_documents.len(), 2);
+        assert!(changes.changed_documents.is_empty());
+
+        let changes = diff(
+            &changes.fingerprints,
+            &[
+                document("a", "first"),
+                document("b", "second, edited"),
+                document("c", "third"),
+            ],
+        );
+        assert_eq!(
+            changes.new_documents,
+            vec![(&document("c", "third")).into()]
+        );
+        assert_eq!(
+            changes.changed_documents,
+            vec![(&document("b", "second, edited")).into()]
+        );
+    }
+
+    #[test]
+    fn test_is_due() {
+        let mut search = SavedSearch {
+            id: "search".to_string(),
+            name: "Releases".to_string(),
+            query: SearchQuery::default(),
+            interval: 60,
+            webhook: None,
+            last_run: None,
+        };
+        assert!(search.is_due(0));
+        search.last_run = Some(1_000);
+        assert!(!search.is_due(60_999));
+        assert!(search.is_due(61_000));
+    }
+
+    #[tokio::test]
+    async fn test_save_changed_query() {
+        let store = SavedSearchStore {
+            searches: Mutex::new(SavedSearches::default()),
+            alerts: broadcast::channel(ALERT_CAPACITY).0,
+        };
+        let mut search = store
+            .save(SavedSearch {
+                id: String::new(),
+                name: "Releases".to_string(),
+                query: SearchQuery {
+                    search_term: "release".into(),
+                    ..Default::default()
+                },
+                interval: 60,
+                webhook: None,
+                last_run: None,
+            })
+            .await;
+        store.skip_run(&search.id, 1_000).await;
+
+        // Renaming keeps the last run
+        search.name = "Release notes".to_string();
+        assert_eq!(store.save(search.clone()).await.last_run, Some(1_000));
+
+        // Another filter is another query
+        search.query.language = Some("en".to_string());
+        assert_eq!(store.save(search).await.last_run, None);
+    }
+}
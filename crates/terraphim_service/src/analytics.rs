@@ -0,0 +1,679 @@
+//! Query analytics
+//!
+//! Every search run through `TerraphimService::search` is recorded in a
+//! process-wide, bounded log which is periodically persisted via
+//! `terraphim_persistence`. The log backs the reporting API (top queries,
+//! zero-result queries and latency percentiles per role).
+//!
+//! Interactions with the results (opening, copying or dismissing a
+//! document) are recorded alongside, together with the query they answered,
+//! so the report can include basic search quality metrics.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ahash::{AHashMap, AHashSet};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use terraphim_persistence::Persistable;
+use terraphim_types::{RelevanceFunction, RoleName, SearchQuery};
+use tokio::sync::{Mutex, OnceCell};
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Maximum number of queries kept in the log; older ones are dropped first
+pub const MAX_QUERY_RECORDS: usize = 10_000;
+
+/// Maximum number of result interactions kept in the log
+pub const MAX_INTERACTION_RECORDS: usize = 10_000;
+
+/// Persist a log after this many new records
+const PERSIST_EVERY: usize = 50;
+
+static ANALYTICS: OnceCell<Analytics> = OnceCell::const_new();
+
+/// Milliseconds since the Unix epoch
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Time spent in a single stage of the search pipeline
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StageLatency {
+    /// Name of the stage, e.g. `haystacks` or `scoring`
+    pub stage: String,
+    /// Time spent in the stage in microseconds
+    pub micros: u64,
+}
+
+/// Measures the latency of consecutive search stages
+#[derive(Debug)]
+pub struct StageTimer {
+    start: Instant,
+    last: Instant,
+    stages: Vec<StageLatency>,
+}
+
+impl StageTimer {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Mark the end of a stage, which started when the previous stage ended
+    pub fn stage(&mut self, stage: &str) {
+        let now = Instant::now();
+        self.stages.push(StageLatency {
+            stage: stage.to_string(),
+            micros: micros(now.duration_since(self.last)),
+        });
+        self.last = now;
+    }
+
+    /// Total time since the timer was created
+    pub fn total(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Default for StageTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// A single search as seen by the analytics log
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QueryRecord {
+    /// When the search was run (milliseconds since the Unix epoch)
+    pub timestamp: u64,
+    /// Role the search was run for
+    pub role: RoleName,
+    /// The (normalized) search term
+    pub search_term: String,
+    /// Number of whitespace separated terms in the search term
+    pub term_count: usize,
+    /// Relevance function of the role
+    pub relevance_function: RelevanceFunction,
+    /// Number of documents returned
+    pub result_count: usize,
+    /// Latency of each search stage
+    pub stages: Vec<StageLatency>,
+    /// Total latency in microseconds
+    pub total_micros: u64,
+}
+
+impl QueryRecord {
+    pub fn new(
+        search_query: &SearchQuery,
+        role: &RoleName,
+        relevance_function: RelevanceFunction,
+        result_count: usize,
+        timer: StageTimer,
+    ) -> Self {
+        let search_term = search_query.search_term.to_string();
+        Self {
+            timestamp: now_millis(),
+            role: role.clone(),
+            term_count: search_term.split_whitespace().count(),
+            search_term,
+            relevance_function,
+            result_count,
+            total_micros: micros(timer.total()),
+            stages: timer.stages,
+        }
+    }
+}
+
+/// How often a search term was used
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueryCount {
+    pub search_term: String,
+    pub count: usize,
+}
+
+/// Latency percentiles of all searches of a role
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RoleLatency {
+    pub role: RoleName,
+    /// Number of searches
+    pub count: usize,
+    /// Median latency in milliseconds
+    pub p50_ms: f64,
+    /// 95th percentile latency in milliseconds
+    pub p95_ms: f64,
+}
+
+/// Summary of the query log
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AnalyticsReport {
+    /// Number of searches in the log
+    pub total_queries: usize,
+    /// Most frequent search terms
+    pub top_queries: Vec<QueryCount>,
+    /// Most frequent search terms which returned no results
+    pub zero_result_queries: Vec<QueryCount>,
+    /// Latency percentiles per role
+    pub latency: Vec<RoleLatency>,
+    /// Search quality derived from result interactions
+    pub quality: SearchQuality,
+}
+
+/// Bounded log of the most recent searches
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueryLog {
+    records: VecDeque<QueryRecord>,
+}
+
+impl QueryLog {
+    /// Append a record, dropping the oldest one if the log is full
+    pub fn push(&mut self, record: QueryRecord) {
+        if self.records.len() >= MAX_QUERY_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// All records, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &QueryRecord> {
+        self.records.iter()
+    }
+
+    /// Number of records in the log
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Check if the log is empty
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Summarize the log, returning at most `limit` entries per query list
+    pub fn report(&self, limit: usize) -> AnalyticsReport {
+        let mut latencies: AHashMap<RoleName, Vec<u64>> = AHashMap::new();
+        for record in &self.records {
+            latencies
+                .entry(record.role.clone())
+                .or_default()
+                .push(record.total_micros);
+        }
+        let mut latency: Vec<RoleLatency> = latencies
+            .into_iter()
+            .map(|(role, mut values)| {
+                values.sort_unstable();
+                RoleLatency {
+                    role,
+                    count: values.len(),
+                    p50_ms: percentile(&values, 50.0) as f64 / 1000.0,
+                    p95_ms: percentile(&values, 95.0) as f64 / 1000.0,
+                }
+            })
+            .collect();
+        latency.sort_by(|a, b| a.role.original.cmp(&b.role.original));
+
+        AnalyticsReport {
+            total_queries: self.records.len(),
+            top_queries: count_terms(self.records.iter(), limit),
+            zero_result_queries: count_terms(
+                self.records.iter().filter(|r| r.result_count == 0),
+                limit,
+            ),
+            latency,
+            quality: SearchQuality::default(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+pub(crate) fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Count search terms, most frequent first (ties broken alphabetically)
+fn count_terms<'a>(
+    records: impl Iterator<Item = &'a QueryRecord>,
+    limit: usize,
+) -> Vec<QueryCount> {
+    let mut counts: AHashMap<&str, usize> = AHashMap::new();
+    for record in records {
+        *counts.entry(record.search_term.as_str()).or_default() += 1;
+    }
+    let mut counts: Vec<QueryCount> = counts
+        .into_iter()
+        .map(|(search_term, count)| QueryCount {
+            search_term: search_term.to_string(),
+            count,
+        })
+        .collect();
+    counts.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.search_term.cmp(&b.search_term))
+    });
+    counts.truncate(limit);
+    counts
+}
+
+#[async_trait]
+impl Persistable for QueryLog {
+    fn new(_key: String) -> Self {
+        QueryLog::default()
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        "analytics_query_log.json".to_string()
+    }
+}
+
+/// What a user did with a search result
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum InteractionKind {
+    /// The document was opened
+    Open,
+    /// The document (or a part of it) was copied
+    Copy,
+    /// The document was dismissed as irrelevant
+    Dismiss,
+}
+
+/// A single interaction with a search result
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Interaction {
+    /// When the interaction happened (milliseconds since the Unix epoch)
+    ///
+    /// Set to the current time when recorded if not given.
+    #[serde(default)]
+    pub timestamp: u64,
+    /// Role the search was run for
+    pub role: RoleName,
+    /// Search term which returned the document
+    pub search_term: String,
+    /// ID of the document
+    pub document_id: String,
+    /// What the user did with the document
+    pub kind: InteractionKind,
+    /// Position of the document in the results (1-based), if known
+    pub rank: Option<usize>,
+}
+
+impl Interaction {
+    /// Whether the interaction marks the document as relevant
+    pub fn is_positive(&self) -> bool {
+        matches!(self.kind, InteractionKind::Open | InteractionKind::Copy)
+    }
+}
+
+/// Search quality metrics derived from result interactions
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SearchQuality {
+    /// Number of opened results
+    pub opens: usize,
+    /// Number of copied results
+    pub copies: usize,
+    /// Number of dismissed results
+    pub dismissals: usize,
+    /// Share of searches (per role and search term) with at least one
+    /// opened or copied result
+    pub click_through_rate: f64,
+    /// Mean reciprocal rank of the best opened or copied result per search
+    /// (searches without one count as zero)
+    pub mean_reciprocal_rank: f64,
+}
+
+/// Bounded log of the most recent result interactions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InteractionLog {
+    records: VecDeque<Interaction>,
+}
+
+impl InteractionLog {
+    /// Append an interaction, dropping the oldest one if the log is full
+    pub fn push(&mut self, interaction: Interaction) {
+        if self.records.len() >= MAX_INTERACTION_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(interaction);
+    }
+
+    /// All interactions, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &Interaction> {
+        self.records.iter()
+    }
+
+    /// Number of interactions in the log
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Check if the log is empty
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Compute search quality metrics for the searches in `queries`
+    pub fn quality(&self, queries: &QueryLog) -> SearchQuality {
+        let mut quality = SearchQuality::default();
+        // Best (lowest) rank of a positive interaction per search; `None` if
+        // the rank is unknown
+        let mut best_rank: AHashMap<(&RoleName, &str), Option<usize>> = AHashMap::new();
+        for interaction in &self.records {
+            match interaction.kind {
+                InteractionKind::Open => quality.opens += 1,
+                InteractionKind::Copy => quality.copies += 1,
+                InteractionKind::Dismiss => quality.dismissals += 1,
+            }
+            if !interaction.is_positive() {
+                continue;
+            }
+            let best = best_rank
+                .entry((&interaction.role, interaction.search_term.as_str()))
+                .or_insert(interaction.rank);
+            if let (Some(current), Some(rank)) = (*best, interaction.rank) {
+                *best = Some(current.min(rank));
+            }
+        }
+
+        let searches: AHashSet<(&RoleName, &str)> = queries
+            .records()
+            .map(|record| (&record.role, record.search_term.as_str()))
+            .collect();
+        if searches.is_empty() {
+            return quality;
+        }
+        let mut clicked = 0;
+        let mut reciprocal_ranks = 0.0;
+        for search in &searches {
+            if let Some(rank) = best_rank.get(search) {
+                clicked += 1;
+                if let Some(rank) = rank {
+                    reciprocal_ranks += 1.0 / (*rank).max(1) as f64;
+                }
+            }
+        }
+        quality.click_through_rate = clicked as f64 / searches.len() as f64;
+        quality.mean_reciprocal_rank = reciprocal_ranks / searches.len() as f64;
+        quality
+    }
+}
+
+#[async_trait]
+impl Persistable for InteractionLog {
+    fn new(_key: String) -> Self {
+        InteractionLog::default()
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        "analytics_interaction_log.json".to_string()
+    }
+}
+
+/// Process-wide analytics store
+///
+/// Search requests create a new `TerraphimService` each time, so the
+/// analytics live in a lazily initialized global which is loaded from
+/// persistence on first use.
+#[derive(Debug)]
+pub struct Analytics {
+    queries: Mutex<QueryLog>,
+    unsaved: AtomicUsize,
+    interactions: Mutex<InteractionLog>,
+    unsaved_interactions: AtomicUsize,
+}
+
+impl Analytics {
+    /// Get the analytics store, loading the persisted logs on first access
+    pub async fn instance() -> &'static Analytics {
+        ANALYTICS
+            .get_or_init(|| async {
+                let queries = match QueryLog::default().load().await {
+                    Ok(queries) => queries,
+                    Err(e) => {
+                        log::debug!("Starting with an empty query log: {:?}", e);
+                        QueryLog::default()
+                    }
+                };
+                let interactions = match InteractionLog::default().load().await {
+                    Ok(interactions) => interactions,
+                    Err(e) => {
+                        log::debug!("Starting with an empty interaction log: {:?}", e);
+                        InteractionLog::default()
+                    }
+                };
+                Analytics {
+                    queries: Mutex::new(queries),
+                    unsaved: AtomicUsize::new(0),
+                    interactions: Mutex::new(interactions),
+                    unsaved_interactions: AtomicUsize::new(0),
+                }
+            })
+            .await
+    }
+
+    /// Record a search
+    pub async fn record_query(&self, record: QueryRecord) {
+        let snapshot = {
+            let mut queries = self.queries.lock().await;
+            queries.push(record);
+            if self.unsaved.fetch_add(1, Ordering::SeqCst) + 1 >= PERSIST_EVERY {
+                self.unsaved.store(0, Ordering::SeqCst);
+                Some(queries.clone())
+            } else {
+                None
+            }
+        };
+        // Save outside the lock so concurrent searches don't wait on storage
+        if let Some(queries) = snapshot {
+            if let Err(e) = queries.save().await {
+                log::warn!("Failed to persist query log: {:?}", e);
+            }
+        }
+    }
+
+    /// Record an interaction with a search result
+    pub async fn record_interaction(&self, mut interaction: Interaction) {
+        if interaction.timestamp == 0 {
+            interaction.timestamp = now_millis();
+        }
+        let snapshot = {
+            let mut interactions = self.interactions.lock().await;
+            interactions.push(interaction);
+            if self.unsaved_interactions.fetch_add(1, Ordering::SeqCst) + 1 >= PERSIST_EVERY {
+                self.unsaved_interactions.store(0, Ordering::SeqCst);
+                Some(interactions.clone())
+            } else {
+                None
+            }
+        };
+        if let Some(interactions) = snapshot {
+            if let Err(e) = interactions.save().await {
+                log::warn!("Failed to persist interaction log: {:?}", e);
+            }
+        }
+    }
+
+    /// All recorded interactions, oldest first
+    pub async fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().await.records().cloned().collect()
+    }
+
+    /// Summarize the query and interaction logs
+    pub async fn report(&self, limit: usize) -> AnalyticsReport {
+        let queries = self.queries.lock().await;
+        let mut report = queries.report(limit);
+        report.quality = self.interactions.lock().await.quality(&queries);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        role: &str,
+        search_term: &str,
+        result_count: usize,
+        total_micros: u64,
+    ) -> QueryRecord {
+        QueryRecord {
+            timestamp: 0,
+            role: role.into(),
+            search_term: search_term.to_string(),
+            term_count: search_term.split_whitespace().count(),
+            relevance_function: RelevanceFunction::TitleScorer,
+            result_count,
+            stages: vec![],
+            total_micros,
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&values, 50.0), 50);
+        assert_eq!(percentile(&values, 95.0), 95);
+        assert_eq!(percentile(&[7], 95.0), 7);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut log = QueryLog::default();
+        log.push(record("Engineer", "rust", 3, 1_000));
+        log.push(record("Engineer", "rust", 2, 3_000));
+        log.push(record("Engineer", "haskell", 0, 2_000));
+        log.push(record("Default", "haskell", 0, 10_000));
+        log.push(record("Default", "go", 1, 20_000));
+
+        let report = log.report(10);
+        assert_eq!(report.total_queries, 5);
+        assert_eq!(
+            report.top_queries[0],
+            QueryCount {
+                search_term: "haskell".to_string(),
+                count: 2
+            }
+        );
+        assert_eq!(report.top_queries.len(), 3);
+        assert_eq!(report.zero_result_queries.len(), 1);
+        assert_eq!(report.zero_result_queries[0].count, 2);
+
+        let engineer = report
+            .latency
+            .iter()
+            .find(|l| l.role == RoleName::new("Engineer"))
+            .unwrap();
+        assert_eq!(engineer.count, 3);
+        assert_eq!(engineer.p50_ms, 2.0);
+        assert_eq!(engineer.p95_ms, 3.0);
+
+        assert_eq!(log.report(1).top_queries.len(), 1);
+    }
+
+    fn interaction(search_term: &str, kind: InteractionKind, rank: usize) -> Interaction {
+        Interaction {
+            timestamp: 0,
+            role: "Engineer".into(),
+            search_term: search_term.to_string(),
+            document_id: "doc".to_string(),
+            kind,
+            rank: Some(rank),
+        }
+    }
+
+    #[test]
+    fn test_search_quality() {
+        let mut queries = QueryLog::default();
+        queries.push(record("Engineer", "rust", 3, 1_000));
+        queries.push(record("Engineer", "rust", 3, 1_000));
+        queries.push(record("Engineer", "haskell", 2, 1_000));
+        queries.push(record("Engineer", "go", 2, 1_000));
+        queries.push(record("Engineer", "zig", 0, 1_000));
+
+        let mut interactions = InteractionLog::default();
+        interactions.push(interaction("rust", InteractionKind::Open, 2));
+        interactions.push(interaction("rust", InteractionKind::Copy, 1));
+        interactions.push(interaction("haskell", InteractionKind::Open, 2));
+        interactions.push(interaction("go", InteractionKind::Dismiss, 1));
+
+        let quality = interactions.quality(&queries);
+        assert_eq!(quality.opens, 2);
+        assert_eq!(quality.copies, 1);
+        assert_eq!(quality.dismissals, 1);
+        // rust and haskell out of four distinct searches
+        assert_eq!(quality.click_through_rate, 0.5);
+        // (1/1 + 1/2 + 0 + 0) / 4
+        assert_eq!(quality.mean_reciprocal_rank, 0.375);
+
+        assert_eq!(
+            InteractionLog::default().quality(&QueryLog::default()),
+            SearchQuality::default()
+        );
+    }
+
+    #[test]
+    fn test_log_is_bounded() {
+        let mut log = QueryLog::default();
+        for i in 0..MAX_QUERY_RECORDS + 5 {
+            log.push(record("Default", &i.to_string(), 1, 1));
+        }
+        assert_eq!(log.len(), MAX_QUERY_RECORDS);
+        assert_eq!(log.records().next().unwrap().search_term, "5");
+    }
+}
@@ -0,0 +1,317 @@
+//! Backlinks between documents and concepts
+//!
+//! Every role keeps an index of the links of the documents in its
+//! haystacks: wikilinks, Markdown links and `kg:` links to concepts. Link
+//! targets are reduced to a key, so `[[Trained Operators]]`,
+//! `[guide](trained-operators.md#setup)` and `[operators](kg:trained_operators)`
+//! all link to `trained operators`, which is also the key of a document
+//! titled `trained-operators` and of a concept with that term.
+
+use std::cmp::Reverse;
+use std::sync::{Mutex, OnceLock};
+
+use ahash::{AHashMap, AHashSet};
+use serde::{Deserialize, Serialize};
+use terraphim_config::Access;
+use terraphim_types::{Document, RoleName};
+
+use crate::score;
+
+/// A document linking to a document or concept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backlink {
+    /// ID of the linking document
+    pub id: String,
+    /// Title of the linking document
+    pub title: String,
+    /// URL of the linking document
+    pub url: String,
+    /// The link as written in the linking document
+    pub link: String,
+    /// Visibility labels of the linking document
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visibility: Vec<String>,
+}
+
+/// Links of the documents of a role
+#[derive(Debug, Default)]
+struct BacklinkIndex {
+    /// URL and keys of every document by document ID
+    documents: AHashMap<String, (String, AHashSet<String>)>,
+    /// Links of every document with links by document ID
+    links: AHashMap<String, Vec<Backlink>>,
+    /// IDs of the documents linking to a key
+    linked_from: AHashMap<String, AHashSet<String>>,
+    /// Whether all documents of the haystacks of the role were added
+    complete: bool,
+}
+
+impl BacklinkIndex {
+    /// Add a document, replacing the links it had before
+    fn add(&mut self, document: &Document) {
+        if let Some(previous) = self.links.remove(&document.id) {
+            for backlink in previous {
+                if let Some(ids) = self.linked_from.get_mut(&link_key(&backlink.link)) {
+                    ids.remove(&document.id);
+                }
+            }
+        }
+        self.documents.insert(
+            document.id.clone(),
+            (document.url.clone(), document_keys(document)),
+        );
+        if document.links.is_empty() {
+            return;
+        }
+        let backlinks: Vec<Backlink> = document
+            .links
+            .iter()
+            .map(|link| Backlink {
+                id: document.id.clone(),
+                title: document.title.clone(),
+                url: document.url.clone(),
+                link: link.clone(),
+                visibility: document.visibility.clone(),
+            })
+            .collect();
+        for backlink in &backlinks {
+            self.linked_from
+                .entry(link_key(&backlink.link))
+                .or_default()
+                .insert(document.id.clone());
+        }
+        self.links.insert(document.id.clone(), backlinks);
+    }
+
+    /// The documents linking to any of the keys, except `exclude`, with
+    /// their first link to one of the keys
+    fn find(&self, keys: &AHashSet<String>, exclude: Option<&str>) -> Vec<Backlink> {
+        let ids: AHashSet<&String> = keys
+            .iter()
+            .filter_map(|key| self.linked_from.get(key))
+            .flatten()
+            .filter(|id| Some(id.as_str()) != exclude)
+            .collect();
+        let mut backlinks: Vec<Backlink> = ids
+            .into_iter()
+            .filter_map(|id| {
+                self.links
+                    .get(id)?
+                    .iter()
+                    .find(|backlink| keys.contains(&link_key(&backlink.link)))
+                    .cloned()
+            })
+            .collect();
+        backlinks.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.id.cmp(&b.id)));
+        backlinks
+    }
+}
+
+fn indexes() -> &'static Mutex<AHashMap<RoleName, BacklinkIndex>> {
+    static INDEXES: OnceLock<Mutex<AHashMap<RoleName, BacklinkIndex>>> = OnceLock::new();
+    INDEXES.get_or_init(Default::default)
+}
+
+/// Reduce a link target, document title or term to the key it links by
+///
+/// The `kg:` prefix, anchors, directories and the `.md` extension are
+/// dropped; dashes and underscores count as spaces and case is ignored.
+pub(crate) fn link_key(target: &str) -> String {
+    let target = target.strip_prefix("kg:").unwrap_or(target);
+    let target = target.split(['#', '?']).next().unwrap_or_default();
+    let name = target.rsplit('/').next().unwrap_or_default();
+    let name = name.strip_suffix(".md").unwrap_or(name);
+    name.replace("%20", " ")
+        .replace(['-', '_'], " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The keys other documents link to a document by: its title and the file
+/// name of its URL
+fn document_keys(document: &Document) -> AHashSet<String> {
+    [link_key(&document.title), link_key(&document.url)]
+        .into_iter()
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+/// Add the links of documents of a role, replacing the links they had
+/// before
+pub(crate) fn learn<'a>(role: &RoleName, documents: impl IntoIterator<Item = &'a Document>) {
+    let mut indexes = indexes().lock().unwrap();
+    let index = indexes.entry(role.clone()).or_default();
+    for document in documents {
+        index.add(document);
+    }
+}
+
+/// Whether the links of all documents in the haystacks of a role were added
+pub(crate) fn is_complete(role: &RoleName) -> bool {
+    match indexes().lock().unwrap().get(role) {
+        Some(index) => index.complete,
+        None => false,
+    }
+}
+
+/// Mark the links of all documents in the haystacks of a role as added
+pub(crate) fn set_complete(role: &RoleName) {
+    indexes()
+        .lock()
+        .unwrap()
+        .entry(role.clone())
+        .or_default()
+        .complete = true;
+}
+
+/// The keys of a target given by the ID or URL of a document, or by a title
+/// or term, and the ID of the document if it is one
+pub(crate) fn target_keys(role: &RoleName, target: &str) -> (AHashSet<String>, Option<String>) {
+    let indexes = indexes().lock().unwrap();
+    let document = indexes.get(role).and_then(|index| {
+        index
+            .documents
+            .iter()
+            .find(|(id, (url, _))| *id == target || url == target)
+    });
+    match document {
+        Some((id, (_, keys))) => (keys.clone(), Some(id.clone())),
+        None => (AHashSet::from([link_key(target)]), None),
+    }
+}
+
+/// The documents of a role linking to any of the keys, except `exclude`
+pub(crate) fn find(
+    role: &RoleName,
+    keys: &AHashSet<String>,
+    exclude: Option<&str>,
+) -> Vec<Backlink> {
+    match indexes().lock().unwrap().get(role) {
+        Some(index) => index.find(keys, exclude),
+        None => Vec::new(),
+    }
+}
+
+/// Set the number of documents linking to each document
+///
+/// Only linking documents visible with the given access count.
+pub(crate) fn count(role: &RoleName, documents: &mut [Document], access: &Access) {
+    let indexes = indexes().lock().unwrap();
+    for document in documents.iter_mut() {
+        let backlinks = match indexes.get(role) {
+            Some(index) => index
+                .find(&document_keys(document), Some(&document.id))
+                .iter()
+                .filter(|backlink| access.allows(&backlink.visibility))
+                .count(),
+            None => 0,
+        };
+        document.backlinks = Some(backlinks);
+    }
+}
+
+/// Rank documents which more documents link to higher
+///
+/// The rank of a document is multiplied by `1 + ln(1 + backlinks)`, so the
+/// first backlinks count most.
+pub(crate) fn boost(documents: &mut [Document]) {
+    for document in documents.iter_mut() {
+        let backlinks = document.backlinks.unwrap_or_default() as f64;
+        score::scale(document, 1.0 + backlinks.ln_1p());
+    }
+    documents.sort_by_key(|document| Reverse(document.rank));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, links: &[&str]) -> Document {
+        Document {
+            id: id.to_string(),
+            url: format!("/notes/{id}.md"),
+            title: id.to_string(),
+            links: links.iter().map(|link| link.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_link_key() {
+        assert_eq!(link_key("Trained Operators"), "trained operators");
+        assert_eq!(
+            link_key("../guides/trained-operators.md#setup"),
+            "trained operators"
+        );
+        assert_eq!(link_key("kg:trained_operators"), "trained operators");
+        assert_eq!(link_key("Trained%20Operators.md"), "trained operators");
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let role = RoleName::new("Backlinks test");
+        let documents = [
+            document("maintenance", &["Life-cycle", "kg:operator"]),
+            document("operations", &["maintenance.md", "[[nothing]]"]),
+            document("life-cycle", &["Maintenance"]),
+        ];
+        learn(&role, &documents);
+
+        let (keys, id) = target_keys(&role, "maintenance");
+        assert_eq!(id.as_deref(), Some("maintenance"));
+        let backlinks = find(&role, &keys, id.as_deref());
+        let linking: Vec<(&str, &str)> = backlinks
+            .iter()
+            .map(|backlink| (backlink.id.as_str(), backlink.link.as_str()))
+            .collect();
+        assert_eq!(
+            linking,
+            vec![
+                ("life-cycle", "Maintenance"),
+                ("operations", "maintenance.md")
+            ]
+        );
+
+        let (keys, id) = target_keys(&role, "Operator");
+        assert_eq!(id, None);
+        assert_eq!(find(&role, &keys, None).len(), 1);
+
+        // Links are replaced when a document is added again
+        learn(&role, [&document("operations", &[])]);
+        let mut results = documents.to_vec();
+        count(&role, &mut results, &Access::All);
+        let counts: Vec<Option<usize>> = results.iter().map(|d| d.backlinks).collect();
+        assert_eq!(counts, vec![Some(1), Some(0), Some(1)]);
+
+        // Linking documents which aren't visible don't count
+        let mut hidden = document("life-cycle", &["Maintenance"]);
+        hidden.visibility = vec!["finance".to_string()];
+        learn(&role, [&hidden]);
+        count(&role, &mut results, &Access::Labels(Vec::new()));
+        assert_eq!(results[0].backlinks, Some(0));
+    }
+
+    #[test]
+    fn test_boost() {
+        let mut documents = vec![
+            Document {
+                id: "a".to_string(),
+                rank: Some(10),
+                backlinks: Some(0),
+                ..Default::default()
+            },
+            Document {
+                id: "b".to_string(),
+                rank: Some(8),
+                backlinks: Some(3),
+                ..Default::default()
+            },
+        ];
+        boost(&mut documents);
+        assert_eq!(documents[0].id, "b");
+        assert_eq!(documents[0].rank, Some(19));
+        assert_eq!(documents[1].rank, Some(10));
+    }
+}
@@ -0,0 +1,347 @@
+//! Candidate concepts for the knowledge graph
+//!
+//! Named entities (people, products, projects and systems) are extracted
+//! from the documents of a role with
+//! [`terraphim_automata::entities::extract_entities`]. Entities which are
+//! not in the thesaurus of the role become candidates with citations of
+//! the documents they occur in. Curators review the candidates: promoting
+//! one writes a page for it into the local knowledge graph of the role,
+//! rejecting one keeps it from being suggested again.
+//!
+//! Candidates are persisted via `terraphim_persistence`.
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use terraphim_automata::entities::{extract_entities, EntityKind};
+use terraphim_persistence::Persistable;
+use terraphim_types::{Document, RoleName};
+use tokio::sync::{Mutex, OnceCell};
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Bytes of context on each side of an entity in a citation
+const EXCERPT_CONTEXT: usize = 60;
+
+static CANDIDATES: OnceCell<CandidateQueue> = OnceCell::const_new();
+
+/// Where a candidate occurs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Citation {
+    pub document_id: String,
+    pub title: String,
+    pub url: String,
+    /// The text around the first occurrence in the document
+    pub excerpt: String,
+}
+
+/// Review status of a candidate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateStatus {
+    #[default]
+    Pending,
+    /// Written into the knowledge graph of the role
+    Promoted,
+    /// Not to be suggested again
+    Rejected,
+}
+
+/// An entity which is not in the thesaurus of a role yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    /// Unique ID, assigned when the candidate is first found
+    pub id: String,
+    pub role: RoleName,
+    /// The name as first found
+    pub term: String,
+    pub kind: EntityKind,
+    #[serde(default)]
+    pub status: CandidateStatus,
+    /// One citation per document the candidate occurs in
+    pub citations: Vec<Citation>,
+}
+
+/// An entity found in a document
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Found {
+    pub(crate) term: String,
+    pub(crate) kind: EntityKind,
+    pub(crate) citation: Citation,
+}
+
+/// The text around `start..end`, widened to character boundaries
+fn excerpt(text: &str, start: usize, end: usize) -> String {
+    let mut from = start.saturating_sub(EXCERPT_CONTEXT);
+    while !text.is_char_boundary(from) {
+        from -= 1;
+    }
+    let mut to = (end + EXCERPT_CONTEXT).min(text.len());
+    while !text.is_char_boundary(to) {
+        to += 1;
+    }
+    text[from..to]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The entities of a document, once per name
+pub(crate) fn find_entities(document: &Document) -> Vec<Found> {
+    let mut found: Vec<Found> = Vec::new();
+    for entity in extract_entities(&document.body) {
+        if found
+            .iter()
+            .any(|f| f.term.eq_ignore_ascii_case(&entity.text))
+        {
+            continue;
+        }
+        found.push(Found {
+            citation: Citation {
+                document_id: document.id.clone(),
+                title: document.title.clone(),
+                url: document.url.clone(),
+                excerpt: excerpt(&document.body, entity.start, entity.end),
+            },
+            term: entity.text,
+            kind: entity.kind,
+        });
+    }
+    found
+}
+
+/// Page of a candidate in a Logseq knowledge graph
+pub(crate) fn kg_page(candidate: &Candidate) -> String {
+    let kind = serde_json::to_value(candidate.kind)
+        .ok()
+        .and_then(|kind| kind.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut page = format!("type:: {kind}\nsynonyms:: {}\n\n", candidate.term);
+    for citation in &candidate.citations {
+        page.push_str(&format!(
+            "- Mentioned in [{}]({}): {}\n",
+            citation.title, citation.url, citation.excerpt
+        ));
+    }
+    page
+}
+
+/// Write the page of a candidate into a knowledge graph directory
+///
+/// Fails if the knowledge graph already has a page of that name.
+pub(crate) async fn write_kg_page(dir: &Path, candidate: &Candidate) -> std::io::Result<PathBuf> {
+    let name = candidate.term.replace(['/', '\\', ':'], "-");
+    let path = dir.join(format!("{name}.md"));
+    if tokio::fs::try_exists(&path).await? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    tokio::fs::write(&path, kg_page(candidate)).await?;
+    Ok(path)
+}
+
+/// All candidates, whatever their status
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KgCandidates {
+    candidates: Vec<Candidate>,
+}
+
+#[async_trait]
+impl Persistable for KgCandidates {
+    fn new(_key: String) -> Self {
+        KgCandidates::default()
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        "kg_candidates.json".to_string()
+    }
+}
+
+impl KgCandidates {
+    /// Add entities found in the documents of a role
+    ///
+    /// Returns the number of new candidates.
+    fn add(&mut self, role: &RoleName, found: Vec<Found>) -> usize {
+        let mut added = 0;
+        for found in found {
+            let existing = self.candidates.iter_mut().find(|candidate| {
+                candidate.role == *role && candidate.term.eq_ignore_ascii_case(&found.term)
+            });
+            match existing {
+                Some(candidate) => {
+                    let is_cited = candidate
+                        .citations
+                        .iter()
+                        .any(|c| c.document_id == found.citation.document_id);
+                    if !is_cited {
+                        candidate.citations.push(found.citation);
+                    }
+                }
+                None => {
+                    self.candidates.push(Candidate {
+                        id: ulid::Ulid::new().to_string(),
+                        role: role.clone(),
+                        term: found.term,
+                        kind: found.kind,
+                        status: CandidateStatus::Pending,
+                        citations: vec![found.citation],
+                    });
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+}
+
+/// The review queue of candidates of this process
+pub struct CandidateQueue {
+    candidates: Mutex<KgCandidates>,
+}
+
+impl CandidateQueue {
+    /// Get the queue, loading the persisted candidates on first access
+    pub async fn instance() -> &'static CandidateQueue {
+        CANDIDATES
+            .get_or_init(|| async {
+                let candidates = match KgCandidates::default().load().await {
+                    Ok(candidates) => candidates,
+                    Err(e) => {
+                        log::debug!("Starting without KG candidates: {:?}", e);
+                        KgCandidates::default()
+                    }
+                };
+                CandidateQueue {
+                    candidates: Mutex::new(candidates),
+                }
+            })
+            .await
+    }
+
+    /// Add entities found in the documents of a role
+    ///
+    /// Returns the number of new candidates.
+    pub(crate) async fn add(&self, role: &RoleName, found: Vec<Found>) -> usize {
+        let mut candidates = self.candidates.lock().await;
+        let added = candidates.add(role, found);
+        persist(&candidates).await;
+        added
+    }
+
+    /// Pending candidates of a role, the most cited first
+    pub async fn pending(&self, role: &RoleName) -> Vec<Candidate> {
+        let mut pending: Vec<Candidate> = self
+            .candidates
+            .lock()
+            .await
+            .candidates
+            .iter()
+            .filter(|c| c.role == *role && c.status == CandidateStatus::Pending)
+            .cloned()
+            .collect();
+        pending.sort_by(|a, b| {
+            b.citations
+                .len()
+                .cmp(&a.citations.len())
+                .then_with(|| a.term.cmp(&b.term))
+        });
+        pending
+    }
+
+    /// Get a candidate
+    pub async fn get(&self, id: &str) -> Option<Candidate> {
+        self.candidates
+            .lock()
+            .await
+            .candidates
+            .iter()
+            .find(|candidate| candidate.id == id)
+            .cloned()
+    }
+
+    /// Set the status of a candidate
+    ///
+    /// Returns the updated candidate, or `None` if there is no candidate
+    /// with the given ID.
+    pub(crate) async fn set_status(&self, id: &str, status: CandidateStatus) -> Option<Candidate> {
+        let mut candidates = self.candidates.lock().await;
+        let candidate = candidates
+            .candidates
+            .iter_mut()
+            .find(|candidate| candidate.id == id)?;
+        candidate.status = status;
+        let candidate = candidate.clone();
+        persist(&candidates).await;
+        Some(candidate)
+    }
+}
+
+async fn persist(candidates: &KgCandidates) {
+    if let Err(e) = candidates.save().await {
+        log::warn!("Failed to persist KG candidates: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, body: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            url: format!("/docs/{id}.md"),
+            title: id.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_add_candidates() {
+        let role = RoleName::new("Candidates test");
+        let first = document("first", "The Apollo project was started by Grace Hopper.");
+        let second = document("second", "The APOLLO project, again by Grace Hopper.");
+        let mut candidates = KgCandidates::default();
+
+        assert_eq!(candidates.add(&role, find_entities(&first)), 2);
+        assert_eq!(candidates.add(&role, find_entities(&second)), 0);
+        // Documents are only cited once
+        assert_eq!(candidates.add(&role, find_entities(&second)), 0);
+
+        let apollo = &candidates.candidates[0];
+        assert_eq!(apollo.term, "Apollo");
+        assert_eq!(apollo.kind, EntityKind::Project);
+        assert_eq!(apollo.citations.len(), 2);
+        assert_eq!(
+            apollo.citations[0].excerpt,
+            "The Apollo project was started by Grace Hopper."
+        );
+        assert_eq!(candidates.candidates[1].term, "Grace Hopper");
+
+        let page = kg_page(apollo);
+        assert!(page.starts_with("type:: project\nsynonyms:: Apollo\n"));
+        assert!(page.contains("- Mentioned in [second](/docs/second.md): "));
+    }
+}
@@ -0,0 +1,211 @@
+//! Browsing documents by concept
+//!
+//! Documents are tagged with the top-level concepts of their role when they
+//! are indexed, see [`terraphim_rolegraph::ConceptClassifier`]. The concept
+//! tags work as facets: every concept is listed with the number of
+//! documents tagged with it, and the documents of a concept can be listed
+//! without a search term.
+//!
+//! The backlinks of a concept are the other way round: the documents
+//! linking to the concept or mentioning any of its synonyms, like the
+//! backlinks panel of a note.
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use terraphim_types::Document;
+
+use crate::backlinks::Backlink;
+
+/// A top-level concept and the number of documents tagged with it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConceptCount {
+    /// Normalized term of the concept
+    pub concept: String,
+    pub documents: usize,
+}
+
+/// Count the documents per tag, the most frequent tag first
+pub(crate) fn count<'a>(documents: impl IntoIterator<Item = &'a Document>) -> Vec<ConceptCount> {
+    let mut counts: AHashMap<&str, usize> = AHashMap::new();
+    for document in documents {
+        for tag in document.tags.iter().flatten() {
+            *counts.entry(tag.as_str()).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<ConceptCount> = counts
+        .into_iter()
+        .map(|(concept, documents)| ConceptCount {
+            concept: concept.to_string(),
+            documents,
+        })
+        .collect();
+    counts.sort_by(|a, b| {
+        b.documents
+            .cmp(&a.documents)
+            .then_with(|| a.concept.cmp(&b.concept))
+    });
+    counts
+}
+
+/// Keep the documents tagged with a concept
+///
+/// Documents for which the concept is the best matching one come first,
+/// then the documents are sorted by title.
+pub(crate) fn browse(documents: Vec<Document>, concept: &str) -> Vec<Document> {
+    let position = |document: &Document| {
+        document
+            .tags
+            .iter()
+            .flatten()
+            .position(|tag| tag.eq_ignore_ascii_case(concept))
+    };
+    let mut documents: Vec<(usize, Document)> = documents
+        .into_iter()
+        .filter_map(|document| Some((position(&document)?, document)))
+        .collect();
+    documents.sort_by(|(a_position, a), (b_position, b)| {
+        a_position
+            .cmp(b_position)
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    documents
+        .into_iter()
+        .map(|(_, document)| document)
+        .collect()
+}
+
+/// A document linking to or mentioning a concept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConceptBacklink {
+    /// ID of the document
+    pub id: String,
+    /// Title of the document
+    pub title: String,
+    /// URL of the document
+    pub url: String,
+    /// Number of matches of the synonyms of the concept in the document
+    pub mentions: usize,
+    /// Links of the document to the concept, as written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+}
+
+/// Merge the documents mentioning a concept with the documents linking to
+/// it, the documents with the most links and mentions first
+pub(crate) fn merge_backlinks<'a>(
+    mentions: impl IntoIterator<Item = (&'a Document, usize)>,
+    links: Vec<Backlink>,
+) -> Vec<ConceptBacklink> {
+    let mut backlinks: AHashMap<String, ConceptBacklink> = AHashMap::new();
+    for (document, count) in mentions {
+        if count == 0 {
+            continue;
+        }
+        backlinks.insert(
+            document.id.clone(),
+            ConceptBacklink {
+                id: document.id.clone(),
+                title: document.title.clone(),
+                url: document.url.clone(),
+                mentions: count,
+                links: Vec::new(),
+            },
+        );
+    }
+    for link in links {
+        backlinks
+            .entry(link.id.clone())
+            .or_insert_with(|| ConceptBacklink {
+                id: link.id,
+                title: link.title,
+                url: link.url,
+                mentions: 0,
+                links: Vec::new(),
+            })
+            .links
+            .push(link.link);
+    }
+    let mut backlinks: Vec<ConceptBacklink> = backlinks.into_values().collect();
+    backlinks.sort_by(|a, b| {
+        (b.links.len() + b.mentions)
+            .cmp(&(a.links.len() + a.mentions))
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    backlinks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(title: &str, tags: &[&str]) -> Document {
+        Document {
+            id: title.to_string(),
+            title: title.to_string(),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_count_and_browse() {
+        let documents = vec![
+            document("c", &["haskell", "rust"]),
+            document("b", &["rust"]),
+            document("a", &["rust", "haskell"]),
+            document("d", &[]),
+        ];
+        assert_eq!(
+            count(&documents),
+            vec![
+                ConceptCount {
+                    concept: "rust".to_string(),
+                    documents: 3,
+                },
+                ConceptCount {
+                    concept: "haskell".to_string(),
+                    documents: 2,
+                },
+            ]
+        );
+
+        let titles: Vec<String> = browse(documents, "Rust")
+            .into_iter()
+            .map(|document| document.title)
+            .collect();
+        assert_eq!(titles, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_merge_backlinks() {
+        let a = document("a", &[]);
+        let b = document("b", &[]);
+        let c = document("c", &[]);
+        let link = |id: &str, link: &str| Backlink {
+            id: id.to_string(),
+            title: id.to_string(),
+            url: String::new(),
+            link: link.to_string(),
+            visibility: Vec::new(),
+        };
+        let backlinks = merge_backlinks(
+            [(&a, 1), (&b, 3), (&c, 0)],
+            vec![
+                link("a", "kg:rust"),
+                link("a", "[[Rust]]"),
+                link("d", "kg:rust"),
+            ],
+        );
+        let counts: Vec<(&str, usize, usize)> = backlinks
+            .iter()
+            .map(|backlink| {
+                (
+                    backlink.id.as_str(),
+                    backlink.mentions,
+                    backlink.links.len(),
+                )
+            })
+            .collect();
+        assert_eq!(counts, vec![("a", 1, 2), ("b", 3, 0), ("d", 0, 1)]);
+    }
+}
@@ -0,0 +1,89 @@
+//! Merging duplicate search results
+//!
+//! The same file reachable through two haystacks, or a document created
+//! through the API which a haystack finds again under another ID, would
+//! show up several times in the results. Documents with the same
+//! [`Document::content_hash`] are merged into the best ranked of them.
+
+use ahash::AHashMap;
+use terraphim_types::Document;
+
+/// Merge the documents with the same content hash, in ranked order
+///
+/// The first of the duplicates stays in place with the best rank of them,
+/// the sources of all of them, and is visible to anyone who may see any of
+/// them. Documents without a content hash are never duplicates.
+pub fn dedup(documents: &mut Vec<Document>) {
+    let mut firsts: AHashMap<String, usize> = AHashMap::new();
+    let mut kept: Vec<Document> = Vec::with_capacity(documents.len());
+    for document in documents.drain(..) {
+        let Some(hash) = document.content_hash.clone() else {
+            kept.push(document);
+            continue;
+        };
+        let Some(&first) = firsts.get(&hash) else {
+            firsts.insert(hash, kept.len());
+            kept.push(document);
+            continue;
+        };
+        log::debug!(
+            "Merging duplicate `{}` ({}) into `{}`",
+            document.id,
+            document.url,
+            kept[first].id
+        );
+        let first = &mut kept[first];
+        first.rank = first.rank.max(document.rank);
+        first.score = match (first.score, document.score) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        for source in document.sources {
+            first.add_source(source);
+        }
+        if first.visibility != document.visibility {
+            first.merge_visibility(&document.visibility);
+        }
+    }
+    *documents = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::DocumentSource;
+
+    fn document(id: &str, hash: Option<&str>, rank: u64, haystack: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            content_hash: hash.map(str::to_string),
+            rank: Some(rank),
+            sources: vec![DocumentSource {
+                haystack: haystack.to_string(),
+                service: "ripgrep".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut documents = vec![
+            document("a", Some("1"), 3, "docs"),
+            document("b", None, 3, "docs"),
+            document("c", None, 2, "docs"),
+            document("d", Some("1"), 5, "mirror"),
+            document("e", Some("2"), 1, "docs"),
+        ];
+        dedup(&mut documents);
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "c", "e"]);
+        assert_eq!(documents[0].rank, Some(5));
+        let haystacks: Vec<&str> = documents[0]
+            .sources
+            .iter()
+            .map(|source| source.haystack.as_str())
+            .collect();
+        assert_eq!(haystacks, vec!["docs", "mirror"]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Definitions of concepts, the titles of KG links
+//!
+//! The page of a concept in a Logseq knowledge graph is named after it,
+//! e.g. `path/to/concept.md` (see `terraphim_middleware::thesaurus`). Its
+//! definition is the first sentence of its `definition::` or
+//! `documentation::` property if it has one, or else of its first line of
+//! prose, skipping headings and the other properties.
+
+use std::path::Path;
+
+use ahash::AHashMap;
+use terraphim_types::{NormalizedTermValue, Thesaurus};
+
+/// Length of a definition in characters, beyond which it is cut at a word
+/// boundary and ends with an ellipsis
+const MAX_DEFINITION_LENGTH: usize = 160;
+
+/// Properties which hold the definition of a concept, in order of preference
+const DEFINITION_PROPERTIES: [&str; 2] = ["definition", "documentation"];
+
+/// The definitions of the concepts of a thesaurus with a page in a
+/// directory, by concept ID
+///
+/// Pages which can't be read, and pages of concepts not in the thesaurus,
+/// are skipped.
+pub(crate) async fn load(dir: &Path, thesaurus: &Thesaurus) -> AHashMap<u64, String> {
+    let mut definitions = AHashMap::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Failed to read the concept pages in {}: {e}", dir.display());
+            return definitions;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("md") {
+            continue;
+        }
+        let Some(stem) = path.file_stem() else {
+            continue;
+        };
+        let value = NormalizedTermValue::new(stem.to_string_lossy().to_string());
+        let Some(concept) = thesaurus.get(&value) else {
+            continue;
+        };
+        let page = match tokio::fs::read_to_string(&path).await {
+            Ok(page) => page,
+            Err(e) => {
+                log::debug!("Failed to read concept page {}: {e}", path.display());
+                continue;
+            }
+        };
+        if let Some(definition) = definition(&page) {
+            definitions.insert(concept.id, definition);
+        }
+    }
+    definitions
+}
+
+/// The definition of a concept from its page, see the module documentation
+fn definition(page: &str) -> Option<String> {
+    let mut properties = AHashMap::new();
+    let mut prose = None;
+    for line in page.lines() {
+        let line = line.trim().trim_start_matches("- ").trim();
+        if line.is_empty() || line.starts_with('#') || line == "---" {
+            continue;
+        }
+        match property(line) {
+            Some((key, value)) => {
+                properties.entry(key.to_lowercase()).or_insert(value);
+            }
+            None => {
+                prose.get_or_insert(line);
+            }
+        }
+    }
+    let text = DEFINITION_PROPERTIES
+        .iter()
+        .find_map(|key| properties.get(*key).copied())
+        .or(prose)?;
+    let sentence = first_sentence(text);
+    (!sentence.is_empty()).then_some(sentence)
+}
+
+/// The key and value of a Logseq property line, `key:: value`
+fn property(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once("::")?;
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    is_key.then(|| (key, value.trim()))
+}
+
+/// The first sentence of a text, with its whitespace collapsed and Logseq
+/// page references written as plain text
+fn first_sentence(text: &str) -> String {
+    let text = text.replace("[[", "").replace("]]", "");
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut sentence = String::new();
+    for (i, word) in words.iter().enumerate() {
+        if !sentence.is_empty() {
+            sentence.push(' ');
+        }
+        if sentence.chars().count() + word.chars().count() > MAX_DEFINITION_LENGTH {
+            let cut = sentence.trim_end();
+            return format!("{}…", cut.trim_end_matches([',', ';', ':']));
+        }
+        sentence.push_str(word);
+        let ends_sentence = word.ends_with(['.', '!', '?'])
+            && words
+                .get(i + 1)
+                .is_none_or(|next| next.starts_with(char::is_uppercase));
+        if ends_sentence {
+            break;
+        }
+    }
+    sentence
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_definition() {
+        let page = "type:: [[Business function]]\n\
+                    documentation:: As stated in [[ISO]] 15288, the purpose of the \
+                    Operation process is to use the system. See the handbook.\n\
+                    synonyms:: operate the system\n";
+        assert_eq!(
+            definition(page).as_deref(),
+            Some("As stated in ISO 15288, the purpose of the Operation process is to use the system.")
+        );
+
+        let page = "# Terraphim-graph\n\n## Scorer\n\n\
+                    Terraphim Graph is using e.g. graph embeddings. It ranks terms.\n\n\
+                    synonyms:: graph embeddings\n";
+        assert_eq!(
+            definition(page).as_deref(),
+            Some("Terraphim Graph is using e.g. graph embeddings.")
+        );
+
+        assert_eq!(definition("# Service\nsynonyms:: provider\n"), None);
+
+        let long = format!("synonyms:: long\n\n{}", "word ".repeat(100));
+        let definition = definition(&long).unwrap();
+        assert!(definition.ends_with("word…"));
+        assert!(definition.chars().count() <= MAX_DEFINITION_LENGTH + 1);
+    }
+}
@@ -0,0 +1,368 @@
+//! Enrichment of search results with persisted documents
+//!
+//! Documents created through the API are persisted. When a search result
+//! has a persisted copy, the copy fills in what the haystack doesn't provide,
+//! e.g. a description, attachments or custom metadata.
+//!
+//! Most results have no persisted copy, so lookups are batched through
+//! [`load_many`], running [`Config::enrichment_concurrency`] of them at a
+//! time, and misses are remembered for a short time to avoid asking the
+//! storage backends for the same missing documents on every search.
+//!
+//! Copies are kept in memory as long as the [`Freshness`] of the haystacks
+//! a document was found in allows. The refresher started with
+//! [`spawn_refresher`] reloads them periodically, so copies edited elsewhere
+//! don't go stale.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use terraphim_config::{Config, Freshness, Haystack};
+use terraphim_persistence::{load_many, LOAD_MANY_CONCURRENCY};
+use terraphim_types::Document;
+use tokio::task::JoinHandle;
+
+/// How long a document without persisted copy isn't looked up again
+const MISS_TTL: Duration = Duration::from_secs(30);
+
+/// IDs of documents without persisted copy, with the time of the lookup
+fn misses() -> &'static Mutex<AHashMap<String, Instant>> {
+    static MISSES: OnceLock<Mutex<AHashMap<String, Instant>>> = OnceLock::new();
+    MISSES.get_or_init(Default::default)
+}
+
+/// A persisted copy kept in memory
+#[derive(Debug, Clone)]
+struct CachedCopy {
+    document: Document,
+    loaded_at: Instant,
+    freshness: Freshness,
+}
+
+impl CachedCopy {
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.freshness
+            .max_age()
+            .is_some_and(|max_age| now.duration_since(self.loaded_at) < max_age)
+    }
+}
+
+/// Persisted copies kept in memory, by document ID
+fn copies() -> &'static Mutex<AHashMap<String, CachedCopy>> {
+    static COPIES: OnceLock<Mutex<AHashMap<String, CachedCopy>>> = OnceLock::new();
+    COPIES.get_or_init(Default::default)
+}
+
+/// Forget that a document had no persisted copy, e.g. because it was just
+/// persisted, and drop its cached copy
+pub(crate) fn forget_miss(id: &str) {
+    misses().lock().unwrap().remove(id);
+    copies().lock().unwrap().remove(id);
+}
+
+/// The strictest freshness of the haystacks a document was found in
+fn freshness_of(document: &Document, haystacks: &[Haystack]) -> Freshness {
+    let mut freshness: Option<Freshness> = None;
+    for haystack in haystacks {
+        let path = haystack.path.display().to_string();
+        let is_source = document
+            .sources
+            .iter()
+            .any(|source| source.haystack == path);
+        if !is_source {
+            continue;
+        }
+        freshness = Some(match freshness {
+            None => haystack.freshness,
+            Some(current) => Freshness {
+                // A copy which isn't cached is the freshest
+                max_age: current
+                    .max_age
+                    .zip(haystack.freshness.max_age)
+                    .map(|(a, b)| a.min(b)),
+                refresh_on_access: current.refresh_on_access
+                    && haystack.freshness.refresh_on_access,
+                refresh_interval: match (
+                    current.refresh_interval,
+                    haystack.freshness.refresh_interval,
+                ) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                },
+            },
+        });
+    }
+    freshness.unwrap_or_default()
+}
+
+/// Number of persisted copies looked up at a time
+pub(crate) fn concurrency(config: &Config) -> usize {
+    config
+        .enrichment_concurrency
+        .unwrap_or(LOAD_MANY_CONCURRENCY)
+}
+
+/// Load the persisted copies of the documents with the given IDs, with at
+/// most `concurrency` lookups at a time
+///
+/// Copies are cached with the given freshness if it allows caching.
+async fn load_copies(
+    requests: Vec<(String, Freshness)>,
+    concurrency: usize,
+) -> AHashMap<String, Document> {
+    let now = Instant::now();
+    let ids: Vec<String> = requests.iter().map(|(id, _)| id.clone()).collect();
+    let loaded = load_many::<Document>(ids, concurrency).await;
+
+    let mut persisted = AHashMap::new();
+    let mut new_misses = Vec::new();
+    let mut new_copies = Vec::new();
+    for ((id, freshness), result) in requests.into_iter().zip(loaded) {
+        match result {
+            Ok(document) => {
+                if freshness.max_age.is_some() {
+                    new_copies.push((
+                        id.clone(),
+                        CachedCopy {
+                            document: document.clone(),
+                            loaded_at: now,
+                            freshness,
+                        },
+                    ));
+                }
+                persisted.insert(id, document);
+            }
+            Err(e) if e.is_not_found() => new_misses.push(id),
+            Err(e) => log::warn!("Failed to load persisted document `{id}`: {e}"),
+        }
+    }
+    {
+        let mut copies = copies().lock().unwrap();
+        for id in &new_misses {
+            copies.remove(id);
+        }
+        copies.extend(new_copies);
+    }
+    misses()
+        .lock()
+        .unwrap()
+        .extend(new_misses.into_iter().map(|id| (id, now)));
+    persisted
+}
+
+/// Complete the documents with their persisted copies
+///
+/// `haystacks` are the haystacks of the role searched, whose freshness
+/// settings decide how long copies are served from memory. At most
+/// `concurrency` copies are looked up at a time.
+#[tracing::instrument(skip_all, fields(documents = documents.len()))]
+pub(crate) async fn enrich_documents(
+    documents: &mut [Document],
+    haystacks: &[Haystack],
+    concurrency: usize,
+) {
+    let now = Instant::now();
+    let mut persisted = AHashMap::new();
+    let mut requests = Vec::new();
+    let mut background = Vec::new();
+    {
+        let mut misses = misses().lock().unwrap();
+        misses.retain(|_id, time| now.duration_since(*time) < MISS_TTL);
+        let copies = copies().lock().unwrap();
+        for document in documents.iter() {
+            if misses.contains_key(&document.id) {
+                continue;
+            }
+            let freshness = freshness_of(document, haystacks);
+            match copies.get(&document.id) {
+                Some(copy) if copy.is_fresh(now) => {
+                    persisted.insert(document.id.clone(), copy.document.clone());
+                }
+                Some(copy) if freshness.refresh_on_access && freshness.max_age.is_some() => {
+                    persisted.insert(document.id.clone(), copy.document.clone());
+                    background.push((document.id.clone(), freshness));
+                }
+                _ => requests.push((document.id.clone(), freshness)),
+            }
+        }
+    }
+    if !background.is_empty() {
+        log::debug!("Refreshing {} stale document copies", background.len());
+        tokio::spawn(load_copies(background, concurrency));
+    }
+    if !requests.is_empty() {
+        persisted.extend(load_copies(requests, concurrency).await);
+    }
+
+    for document in documents.iter_mut() {
+        if let Some(copy) = persisted.remove(&document.id) {
+            merge(document, copy);
+        }
+    }
+}
+
+/// Start reloading the cached copies of documents in haystacks with a
+/// refresh interval in the background
+///
+/// Returns `None` if no haystack has a refresh interval. The refresher
+/// runs until the returned task is aborted.
+pub fn spawn_refresher(config: &Config) -> Option<JoinHandle<()>> {
+    let period = config
+        .roles
+        .values()
+        .flat_map(|role| &role.haystacks)
+        .filter_map(|haystack| haystack.freshness.refresh_interval())
+        .min()?;
+    let concurrency = concurrency(config);
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let due: Vec<(String, Freshness)> = copies()
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_id, copy)| {
+                    copy.freshness
+                        .refresh_interval()
+                        .is_some_and(|every| now.duration_since(copy.loaded_at) >= every)
+                })
+                .map(|(id, copy)| (id.clone(), copy.freshness))
+                .collect();
+            if !due.is_empty() {
+                log::debug!("Refreshing {} cached document copies", due.len());
+                load_copies(due, concurrency).await;
+            }
+        }
+    }))
+}
+
+/// Fill in the fields of `document` which are missing with those of the
+/// persisted copy
+fn merge(document: &mut Document, copy: Document) {
+    if document.description.is_none() {
+        document.description = copy.description;
+    }
+    if document.stub.is_none() {
+        document.stub = copy.stub;
+    }
+    if document.language.is_none() {
+        document.language = copy.language;
+    }
+    if document.attachments.is_empty() {
+        document.attachments = copy.attachments;
+    }
+    if document.visibility.is_empty() {
+        document.visibility = copy.visibility;
+    }
+    if document.created.is_none() {
+        document.created = copy.created;
+    }
+    if document.modified.is_none() {
+        document.modified = copy.modified;
+    }
+    for (key, value) in copy.extra {
+        document.extra.entry(key).or_insert(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_config::ServiceType;
+    use terraphim_types::DocumentSource;
+
+    #[test]
+    fn test_freshness_of() {
+        let haystack = |path: &str, freshness| Haystack {
+            path: path.into(),
+            service: ServiceType::Ripgrep,
+            freshness,
+            visibility: Vec::new(),
+        };
+        let haystacks = vec![
+            haystack(
+                "docs",
+                Freshness {
+                    max_age: Some(600),
+                    refresh_on_access: true,
+                    refresh_interval: Some(300),
+                },
+            ),
+            haystack(
+                "notes",
+                Freshness {
+                    max_age: Some(60),
+                    refresh_on_access: true,
+                    refresh_interval: None,
+                },
+            ),
+            haystack("tickets", Freshness::default()),
+        ];
+        let mut document = Document::default();
+        assert_eq!(freshness_of(&document, &haystacks), Freshness::default());
+
+        for path in ["docs", "notes"] {
+            document.add_source(DocumentSource {
+                haystack: path.to_string(),
+                service: "ripgrep".to_string(),
+            });
+        }
+        assert_eq!(
+            freshness_of(&document, &haystacks),
+            Freshness {
+                max_age: Some(60),
+                refresh_on_access: true,
+                refresh_interval: Some(300),
+            }
+        );
+
+        // Copies of documents in a haystack without max age aren't cached
+        document.add_source(DocumentSource {
+            haystack: "tickets".to_string(),
+            service: "ripgrep".to_string(),
+        });
+        let freshness = freshness_of(&document, &haystacks);
+        assert_eq!(freshness.max_age, None);
+        assert!(!freshness.refresh_on_access);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut document = Document {
+            id: "doc".to_string(),
+            body: "Indexed body".to_string(),
+            description: Some("Indexed description".to_string()),
+            modified: Some(2_000),
+            extra: serde_json::json!({ "status": "Open" })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ..Default::default()
+        };
+        let copy = Document {
+            id: "doc".to_string(),
+            body: "Persisted body".to_string(),
+            description: Some("Persisted description".to_string()),
+            stub: Some("Persisted stub".to_string()),
+            created: Some(1_000),
+            modified: Some(1_500),
+            extra: serde_json::json!({ "status": "Done", "commit": "8d5f2a1" })
+                .as_object()
+                .unwrap()
+                .clone(),
+            ..Default::default()
+        };
+        merge(&mut document, copy);
+
+        assert_eq!(document.body, "Indexed body");
+        assert_eq!(document.description.as_deref(), Some("Indexed description"));
+        assert_eq!(document.stub.as_deref(), Some("Persisted stub"));
+        assert_eq!(document.extra["status"], "Open");
+        assert_eq!(document.extra["commit"], "8d5f2a1");
+        assert_eq!(document.created, Some(1_000));
+        assert_eq!(document.modified, Some(2_000));
+    }
+}
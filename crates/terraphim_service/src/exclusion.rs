@@ -0,0 +1,97 @@
+//! Leaving documents with unwanted terms out of search results
+//!
+//! A search query may name terms to exclude (see
+//! [`terraphim_types::SearchQuery::exclude_terms`]). Documents whose title or
+//! body contains an excluded term as a whole word, ignoring case, are left
+//! out. With a thesaurus, each excluded term stands for its concept, so
+//! documents containing any synonym of it are left out, too.
+
+use terraphim_types::{Document, NormalizedTermValue, Thesaurus};
+
+/// The terms to look for in documents to exclude them
+///
+/// Excluded terms found in the thesaurus are expanded to all terms of their
+/// concept.
+pub fn expand(exclude_terms: &[NormalizedTermValue], thesaurus: Option<&Thesaurus>) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    let mut add = |term: &str| {
+        let term = term.trim().to_lowercase();
+        if !term.is_empty() && !terms.contains(&term) {
+            terms.push(term);
+        }
+    };
+    for exclude_term in exclude_terms {
+        add(exclude_term.as_str());
+        // Terms of deserialized queries aren't normalized yet
+        let key = NormalizedTermValue::new(exclude_term.to_string());
+        let Some(thesaurus) = thesaurus else {
+            continue;
+        };
+        let Some(concept) = thesaurus
+            .get(&key)
+            .map(|normalized_term| normalized_term.id)
+        else {
+            continue;
+        };
+        for (synonym, normalized_term) in thesaurus {
+            if normalized_term.id == concept {
+                add(synonym.as_str());
+            }
+        }
+    }
+    terms
+}
+
+/// Whether the title or body of a document contains any of the terms
+///
+/// `terms` are expected in lowercase, as returned by [`expand`].
+pub fn contains_any(document: &Document, terms: &[String]) -> bool {
+    let title = document.title.to_lowercase();
+    let body = document.body.to_lowercase();
+    terms
+        .iter()
+        .any(|term| contains_word(&title, term) || contains_word(&body, term))
+}
+
+/// Whether `term` occurs in `text` on word boundaries
+fn contains_word(text: &str, term: &str) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(term).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + term.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terraphim_types::NormalizedTerm;
+
+    #[test]
+    fn test_exclusion() {
+        let mut thesaurus = Thesaurus::new("test".to_string());
+        for (term, id) in [("tokio", 1), ("async runtime", 1), ("rust", 2)] {
+            thesaurus.insert(
+                NormalizedTermValue::from(term),
+                NormalizedTerm::new(id, NormalizedTermValue::from("tokio")),
+            );
+        }
+        let mut terms = expand(&[NormalizedTermValue::from("Tokio")], Some(&thesaurus));
+        terms.sort();
+        assert_eq!(terms, vec!["async runtime", "tokio"]);
+        assert_eq!(
+            expand(&[NormalizedTermValue::from("Tokio")], None),
+            vec!["tokio"]
+        );
+
+        let document = Document {
+            title: "Choosing an Async Runtime".to_string(),
+            body: "Tokiorama is not a word".to_string(),
+            ..Default::default()
+        };
+        assert!(contains_any(&document, &terms));
+        assert!(!contains_any(&document, &["tokio".to_string()]));
+        assert!(!contains_any(&document, &["runtim".to_string()]));
+    }
+}
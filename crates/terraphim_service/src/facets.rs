@@ -0,0 +1,124 @@
+//! Facet counts of search results
+//!
+//! Search queries narrow their results down by facets (see
+//! [`terraphim_types::FacetFilters`]); the counts tell clients which values
+//! of each facet the results have, so they can offer them for narrowing
+//! down further. Counts are of the results of a search, after its facets
+//! are applied.
+
+use ahash::AHashMap;
+use terraphim_types::{Document, FacetCount, FacetCounts};
+
+/// Count the values of the facets of documents
+///
+/// Tags are counted ignoring case, under the spelling seen first. Values
+/// are sorted by their count, the most frequent first, then by value.
+pub fn count(documents: &[Document]) -> FacetCounts {
+    let mut tags = Counter::default();
+    let mut haystacks = Counter::default();
+    for document in documents {
+        let mut seen_tags: Vec<String> = Vec::new();
+        for tag in document.tags.iter().flatten() {
+            let key = tag.to_lowercase();
+            if !seen_tags.contains(&key) {
+                tags.add(key.clone(), tag);
+                seen_tags.push(key);
+            }
+        }
+        let mut seen_haystacks: Vec<&str> = Vec::new();
+        for source in &document.sources {
+            if !seen_haystacks.contains(&source.haystack.as_str()) {
+                haystacks.add(source.haystack.clone(), &source.haystack);
+                seen_haystacks.push(&source.haystack);
+            }
+        }
+    }
+    FacetCounts {
+        tags: tags.into_counts(),
+        source_haystack: haystacks.into_counts(),
+    }
+}
+
+/// Number of documents per value of a facet
+#[derive(Default)]
+struct Counter {
+    /// Key of the value -> the value as first seen and its count
+    counts: AHashMap<String, FacetCount>,
+}
+
+impl Counter {
+    fn add(&mut self, key: String, value: &str) {
+        self.counts
+            .entry(key)
+            .or_insert_with(|| FacetCount {
+                value: value.to_string(),
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    fn into_counts(self) -> Vec<FacetCount> {
+        let mut counts: Vec<FacetCount> = self.counts.into_values().collect();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use terraphim_types::{DocumentSource, FacetFilters};
+
+    fn document(url: &str, tags: &[&str], haystacks: &[&str]) -> Document {
+        Document {
+            id: url.to_string(),
+            url: url.to_string(),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            sources: haystacks
+                .iter()
+                .map(|haystack| DocumentSource {
+                    haystack: haystack.to_string(),
+                    service: "ripgrep".to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_facets() {
+        let documents = vec![
+            document("docs/a.md", &["Rust", "rust"], &["docs"]),
+            document("docs/b.md", &["rust", "tokio"], &["docs", "wiki"]),
+            document("wiki/c.md", &["tokio"], &["wiki"]),
+        ];
+
+        let counts = count(&documents);
+        let tags: Vec<(&str, usize)> = counts
+            .tags
+            .iter()
+            .map(|count| (count.value.as_str(), count.count))
+            .collect();
+        assert_eq!(tags, vec![("Rust", 2), ("tokio", 2)]);
+        let haystacks: Vec<(&str, usize)> = counts
+            .source_haystack
+            .iter()
+            .map(|count| (count.value.as_str(), count.count))
+            .collect();
+        assert_eq!(haystacks, vec![("docs", 2), ("wiki", 2)]);
+
+        let filters = FacetFilters {
+            tags: vec!["RUST".to_string()],
+            source_haystack: vec!["wiki".to_string()],
+            url_prefix: Some("docs/".to_string()),
+        };
+        let matching: Vec<&str> = documents
+            .iter()
+            .filter(|document| filters.matches(document))
+            .map(|document| document.url.as_str())
+            .collect();
+        assert_eq!(matching, vec!["docs/b.md"]);
+        assert!(FacetFilters::default().matches(&documents[2]));
+    }
+}
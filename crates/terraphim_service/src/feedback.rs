@@ -0,0 +1,472 @@
+//! Learning from the results users engage with
+//!
+//! Opening or copying a search result counts for it, dismissing it counts
+//! against it. Feedback is kept per role for each document, and for each
+//! concept of the search term which returned it, so a document clicked for
+//! searches about a concept ranks higher in later searches about that
+//! concept, whatever the exact search term.
+//!
+//! The `feedback` stage of the search pipeline multiplies the rank of each
+//! document by `1 + engagement`, where the engagement of a document is
+//! `ln(1 + positive) - ln(1 + negative)` summed over the document and the
+//! concepts of the query, so the first clicks count most. A rank is never
+//! cut by more than half.
+//!
+//! Feedback is only counted for documents recently returned by the
+//! `feedback` stage of a search of the role, or with feedback already, and
+//! for at most [`MAX_DOCUMENTS`] documents per role; the least engaged
+//! ones are dropped first.
+//!
+//! Feedback is persisted via `terraphim_persistence`, one key per role,
+//! after every [`PERSIST_EVERY`] feedbacks of the role. The cached results
+//! of a role are dropped on feedback at most once per [`RESULT_TTL`], so
+//! later feedback shows when they expire.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use ahash::{AHashMap, AHashSet};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use terraphim_config::ConfigState;
+use terraphim_persistence::Persistable;
+use terraphim_types::{Document, RoleName};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::analytics::Interaction;
+use crate::result_cache::{ResultCache, RESULT_TTL};
+use crate::{score, ServiceError};
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Smallest factor a rank is multiplied by
+const MIN_BOOST: f64 = 0.5;
+
+/// Number of documents with feedback kept per role
+pub const MAX_DOCUMENTS: usize = 10_000;
+
+/// Number of recently returned documents per role which feedback is
+/// accepted for
+pub const MAX_RETURNED: usize = 10_000;
+
+/// Number of feedbacks of a role after which its feedback is persisted
+pub const PERSIST_EVERY: usize = 10;
+
+static FEEDBACK: OnceCell<FeedbackStore> = OnceCell::const_new();
+
+/// How often a document was engaged with or dismissed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Engagement {
+    pub positive: u32,
+    pub negative: u32,
+}
+
+impl Engagement {
+    fn record(&mut self, positive: bool) {
+        if positive {
+            self.positive = self.positive.saturating_add(1);
+        } else {
+            self.negative = self.negative.saturating_add(1);
+        }
+    }
+
+    fn weight(&self) -> f64 {
+        f64::from(self.positive).ln_1p() - f64::from(self.negative).ln_1p()
+    }
+
+    fn count(&self) -> u64 {
+        u64::from(self.positive) + u64::from(self.negative)
+    }
+}
+
+/// The feedback recorded for the search results of a role
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoleFeedback {
+    pub role: RoleName,
+    /// Engagement by document ID
+    #[serde(default)]
+    pub documents: AHashMap<String, Engagement>,
+    /// Engagement by concept ID of the search term, then by document ID
+    #[serde(default)]
+    pub concepts: AHashMap<u64, AHashMap<String, Engagement>>,
+}
+
+#[async_trait]
+impl Persistable for RoleFeedback {
+    fn new(key: String) -> Self {
+        RoleFeedback {
+            role: RoleName::new(&key),
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    /// The role name is hex-encoded, so that distinct roles never share a key
+    fn get_key(&self) -> String {
+        let role: String = self
+            .role
+            .as_lowercase()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("feedback_{role}.json")
+    }
+}
+
+impl RoleFeedback {
+    /// Count an interaction with a document returned for a search term with
+    /// the given concepts
+    ///
+    /// Keeps at most [`MAX_DOCUMENTS`] documents, dropping the least
+    /// engaged other ones.
+    pub fn record(&mut self, interaction: &Interaction, concepts: &[u64]) {
+        let positive = interaction.is_positive();
+        let document_id = &interaction.document_id;
+        if !self.documents.contains_key(document_id) {
+            self.make_room();
+        }
+        self.documents
+            .entry(document_id.clone())
+            .or_default()
+            .record(positive);
+        for concept in concepts {
+            self.concepts
+                .entry(*concept)
+                .or_default()
+                .entry(document_id.clone())
+                .or_default()
+                .record(positive);
+        }
+    }
+
+    /// Drop the least engaged documents, so that another one can be kept
+    fn make_room(&mut self) {
+        while self.documents.len() >= MAX_DOCUMENTS {
+            let Some(least) = self
+                .documents
+                .iter()
+                .min_by_key(|(_, engagement)| engagement.count())
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            self.documents.remove(&least);
+            self.concepts.retain(|_, documents| {
+                documents.remove(&least);
+                !documents.is_empty()
+            });
+        }
+    }
+
+    /// Engagement of a document for a query with the given concepts
+    pub fn engagement(&self, document_id: &str, concepts: &[u64]) -> f64 {
+        let document = self
+            .documents
+            .get(document_id)
+            .map(Engagement::weight)
+            .unwrap_or_default();
+        let concepts: f64 = concepts
+            .iter()
+            .filter_map(|concept| self.concepts.get(concept)?.get(document_id))
+            .map(Engagement::weight)
+            .sum();
+        document + concepts
+    }
+
+    /// Boost the ranks of documents by their engagement and reorder them
+    ///
+    /// Documents nobody engaged with keep their rank.
+    pub fn boost(&self, documents: &mut [Document], concepts: &[u64]) {
+        let mut boosted = false;
+        for document in documents.iter_mut() {
+            let engagement = self.engagement(&document.id, concepts);
+            if engagement == 0.0 {
+                continue;
+            }
+            score::scale(document, (1.0 + engagement).max(MIN_BOOST));
+            boosted = true;
+        }
+        if boosted {
+            documents.sort_by_key(|document| Reverse(document.rank));
+        }
+    }
+}
+
+/// IDs of the documents recently returned for a role, the oldest dropped
+/// first
+#[derive(Default)]
+struct Returned {
+    order: VecDeque<String>,
+    ids: AHashSet<String>,
+}
+
+impl Returned {
+    fn insert(&mut self, id: &str) {
+        if self.ids.contains(id) {
+            return;
+        }
+        if self.order.len() >= MAX_RETURNED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(id.to_string());
+        self.ids.insert(id.to_string());
+    }
+}
+
+/// The feedback of a role with what is needed to accept, persist and
+/// apply more of it
+struct Entry {
+    feedback: RoleFeedback,
+    returned: Returned,
+    /// Number of feedbacks since the feedback was persisted
+    unsaved: usize,
+    /// When the cached results of the role were last dropped
+    invalidated: Option<Instant>,
+}
+
+/// The feedback of all roles of this process
+pub struct FeedbackStore {
+    roles: Mutex<AHashMap<RoleName, Entry>>,
+}
+
+impl FeedbackStore {
+    /// Get the store; the feedback of a role is loaded when first used
+    pub async fn instance() -> &'static FeedbackStore {
+        FEEDBACK
+            .get_or_init(|| async {
+                FeedbackStore {
+                    roles: Mutex::new(AHashMap::new()),
+                }
+            })
+            .await
+    }
+
+    /// Count an interaction for its role
+    ///
+    /// Fails if the document of the interaction was not returned for the
+    /// role recently and has no feedback yet. The feedback of the role is
+    /// persisted after every [`PERSIST_EVERY`] feedbacks, and its cached
+    /// results are dropped at most once per [`RESULT_TTL`].
+    pub async fn record(
+        &self,
+        interaction: &Interaction,
+        concepts: &[u64],
+    ) -> Result<(), ServiceError> {
+        let role = &interaction.role;
+        let document_id = &interaction.document_id;
+        let recorded = self
+            .with_role(role, |entry| {
+                if !entry.returned.ids.contains(document_id)
+                    && !entry.feedback.documents.contains_key(document_id)
+                {
+                    return None;
+                }
+                entry.feedback.record(interaction, concepts);
+                entry.unsaved += 1;
+                let unsaved = (entry.unsaved >= PERSIST_EVERY).then(|| {
+                    entry.unsaved = 0;
+                    entry.feedback.clone()
+                });
+                let invalidate = entry
+                    .invalidated
+                    .is_none_or(|invalidated| invalidated.elapsed() >= RESULT_TTL);
+                if invalidate {
+                    entry.invalidated = Some(Instant::now());
+                }
+                Some((unsaved, invalidate))
+            })
+            .await;
+        let Some((unsaved, invalidate)) = recorded else {
+            return Err(ServiceError::UnknownDocument(document_id.clone()));
+        };
+        if invalidate {
+            // Cached results of the role are ranked without the feedback
+            ResultCache::instance().invalidate(role);
+        }
+        // Saved without holding the lock, so searches don't wait for it
+        if let Some(feedback) = unsaved {
+            if let Err(e) = feedback.save().await {
+                log::warn!("Failed to persist feedback of role `{}`: {:?}", role, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Boost documents of a role by their engagement, see
+    /// [`RoleFeedback::boost`]
+    ///
+    /// The documents are remembered as returned for the role, so feedback
+    /// on them is accepted.
+    pub async fn boost(&self, role: &RoleName, documents: &mut [Document], concepts: &[u64]) {
+        self.with_role(role, |entry| {
+            for document in documents.iter() {
+                entry.returned.insert(&document.id);
+            }
+            entry.feedback.boost(documents, concepts)
+        })
+        .await
+    }
+
+    /// Run `f` on the feedback of a role, loading it on first use
+    ///
+    /// The store is not locked while the feedback is loaded.
+    async fn with_role<T>(&self, role: &RoleName, f: impl FnOnce(&mut Entry) -> T) -> T {
+        if let Some(entry) = self.roles.lock().await.get_mut(role) {
+            return f(entry);
+        }
+        let feedback = load(role).await;
+        let mut roles = self.roles.lock().await;
+        let entry = roles.entry(role.clone()).or_insert(Entry {
+            feedback,
+            returned: Returned::default(),
+            unsaved: 0,
+            invalidated: None,
+        });
+        f(entry)
+    }
+}
+
+/// IDs of the concepts of a search term in the knowledge graph of a role
+///
+/// Empty for roles without a knowledge graph.
+pub(crate) async fn query_concepts(
+    config_state: &ConfigState,
+    role: &RoleName,
+    search_term: &str,
+) -> Vec<u64> {
+    let Some(rolegraph) = config_state.roles.get(role) else {
+        return Vec::new();
+    };
+    let mut concepts = rolegraph.lock().await.find_matching_node_ids(search_term);
+    concepts.sort_unstable();
+    concepts.dedup();
+    concepts
+}
+
+/// The persisted feedback of a role, or no feedback
+async fn load(role: &RoleName) -> RoleFeedback {
+    let mut feedback = RoleFeedback {
+        role: role.clone(),
+        ..Default::default()
+    };
+    match feedback.load().await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            log::debug!("Starting without feedback for role `{}`: {:?}", role, e);
+            feedback
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::analytics::InteractionKind;
+
+    fn interaction(document_id: &str, kind: InteractionKind) -> Interaction {
+        Interaction {
+            timestamp: 0,
+            role: RoleName::new("Engineer"),
+            search_term: "rust".to_string(),
+            document_id: document_id.to_string(),
+            kind,
+            rank: None,
+        }
+    }
+
+    fn document(id: &str, rank: u64) -> Document {
+        Document {
+            id: id.to_string(),
+            title: id.to_string(),
+            rank: Some(rank),
+            ..Default::default()
+        }
+    }
+
+    fn ids(documents: &[Document]) -> Vec<&str> {
+        documents.iter().map(|d| d.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_feedback() {
+        let mut feedback = <RoleFeedback as Persistable>::new("Engineer".to_string());
+        assert_eq!(feedback.get_key(), "feedback_656e67696e656572.json");
+
+        let mut documents = vec![document("a", 100), document("b", 80), document("c", 60)];
+        feedback.boost(&mut documents, &[1]);
+        assert_eq!(ids(&documents), vec!["a", "b", "c"]);
+        assert_eq!(documents[0].rank, Some(100));
+
+        // Clicked for concept 1 only
+        feedback.record(&interaction("c", InteractionKind::Open), &[1]);
+        feedback.record(&interaction("c", InteractionKind::Copy), &[1]);
+        feedback.record(&interaction("a", InteractionKind::Dismiss), &[]);
+        assert_eq!(
+            feedback.documents["c"],
+            Engagement {
+                positive: 2,
+                negative: 0
+            }
+        );
+
+        let mut documents = vec![document("a", 100), document("b", 80), document("c", 60)];
+        feedback.boost(&mut documents, &[1]);
+        assert_eq!(ids(&documents), vec!["c", "b", "a"]);
+        // A dismissed document loses at most half its rank
+        assert_eq!(documents[2].rank, Some(50));
+
+        // Without the concept only the document feedback counts
+        let mut documents = vec![document("a", 100), document("b", 80), document("c", 60)];
+        feedback.boost(&mut documents, &[2]);
+        assert_eq!(ids(&documents), vec!["c", "b", "a"]);
+        assert!(documents[0].rank < Some(200));
+    }
+
+    #[test]
+    fn test_max_documents() {
+        let mut feedback = <RoleFeedback as Persistable>::new("Engineer".to_string());
+        feedback.record(&interaction("clicked", InteractionKind::Open), &[1]);
+        feedback.record(&interaction("clicked", InteractionKind::Open), &[1]);
+        for i in 0..MAX_DOCUMENTS {
+            feedback.record(&interaction(&i.to_string(), InteractionKind::Open), &[1]);
+        }
+        assert_eq!(feedback.documents.len(), MAX_DOCUMENTS);
+        assert_eq!(feedback.concepts[&1].len(), MAX_DOCUMENTS);
+        // The most engaged document is kept
+        assert!(feedback.documents.contains_key("clicked"));
+    }
+
+    #[test]
+    fn test_returned() {
+        let mut returned = Returned::default();
+        for i in 0..=MAX_RETURNED {
+            returned.insert(&i.to_string());
+        }
+        returned.insert("1");
+        assert_eq!(returned.order.len(), MAX_RETURNED);
+        assert!(!returned.ids.contains("0"));
+        assert!(returned.ids.contains("1"));
+    }
+}
@@ -0,0 +1,129 @@
+//! Highlight spans of search results
+//!
+//! Instead of marking matches up in the document, search results carry the
+//! offsets of the query matches in their title and body, so any client can
+//! render them. For roles with a knowledge graph, every synonym of a concept
+//! in the query is a match; otherwise the words of the query are. The
+//! matches of regex queries are those of their pattern.
+
+use regex::Regex;
+use terraphim_rolegraph::RoleGraph;
+use terraphim_types::{Document, HighlightField, HighlightSpan};
+
+/// Byte offsets of the words of `query` in `text`, ignoring ASCII case
+///
+/// Overlapping matches are dropped, the longest match at a position wins.
+fn find_query_words(query: &str, text: &str) -> Vec<(usize, usize, String)> {
+    let text = text.to_ascii_lowercase();
+    let mut words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| word.to_ascii_lowercase())
+        .collect();
+    words.sort();
+    words.dedup();
+
+    let mut spans = Vec::new();
+    for word in words {
+        for (start, _) in text.match_indices(word.as_str()) {
+            spans.push((start, start + word.len(), word.clone()));
+        }
+    }
+    spans.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    let mut end = 0;
+    spans.retain(|(start, span_end, _)| {
+        if *start < end {
+            return false;
+        }
+        end = *span_end;
+        true
+    });
+    spans
+}
+
+/// Set the highlight spans of the documents found for `query`
+pub(crate) fn highlight_documents(
+    documents: &mut [Document],
+    query: &str,
+    rolegraph: Option<&RoleGraph>,
+) {
+    for document in documents.iter_mut() {
+        let mut highlights = Vec::new();
+        for (field, text) in [
+            (HighlightField::Title, &document.title),
+            (HighlightField::Body, &document.body),
+        ] {
+            let spans = match rolegraph {
+                Some(rolegraph) => rolegraph.find_query_spans(query, text),
+                None => find_query_words(query, text),
+            };
+            highlights.extend(spans.into_iter().map(|(start, end, term)| HighlightSpan {
+                term,
+                start,
+                end,
+                field,
+            }));
+        }
+        document.highlights = highlights;
+    }
+}
+
+/// Set the highlight spans of the documents to the matches of a regex
+pub(crate) fn highlight_regex(documents: &mut [Document], regex: &Regex) {
+    for document in documents.iter_mut() {
+        let mut highlights = Vec::new();
+        for (field, text) in [
+            (HighlightField::Title, &document.title),
+            (HighlightField::Body, &document.body),
+        ] {
+            highlights.extend(regex.find_iter(text).map(|found| HighlightSpan {
+                term: found.as_str().to_string(),
+                start: found.start(),
+                end: found.end(),
+                field,
+            }));
+        }
+        document.highlights = highlights;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_query_words() {
+        let mut documents = vec![Document {
+            title: "Release notes".to_string(),
+            body: "Notes on the release of Terraphim. RELEASED today.".to_string(),
+            ..Default::default()
+        }];
+        highlight_documents(&mut documents, "release notes", None);
+
+        let spans: Vec<(HighlightField, &str)> = documents[0]
+            .highlights
+            .iter()
+            .map(|span| {
+                let text = match span.field {
+                    HighlightField::Title => &documents[0].title,
+                    HighlightField::Body => &documents[0].body,
+                };
+                (span.field, &text[span.start..span.end])
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (HighlightField::Title, "Release"),
+                (HighlightField::Title, "notes"),
+                (HighlightField::Body, "Notes"),
+                (HighlightField::Body, "release"),
+                (HighlightField::Body, "RELEASE"),
+            ]
+        );
+        // The body is left as it is
+        assert_eq!(
+            documents[0].body,
+            "Notes on the release of Terraphim. RELEASED today."
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Recurring maintenance jobs
+//!
+//! The jobs of the config (see [`terraphim_config::JobSchedule`]) are run
+//! by the scheduler started with [`spawn_jobs`] whenever their interval has
+//! passed, starting with a run of every job when the scheduler starts.
+//! Saved searches are checked every 15 seconds unless the config schedules
+//! them differently, so alerts work without any jobs in the config.
+//!
+//! A job never runs twice at the same time: a run which is due or triggered
+//! while the job is running is skipped. Every run is recorded with its
+//! outcome; the last [`HISTORY_LENGTH`] runs of each job are kept.
+//!
+//! The history is persisted via `terraphim_persistence`.
+
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use terraphim_config::{Config, ConfigState, JobKind, JobSchedule};
+use terraphim_persistence::{blob, Persistable};
+use tokio::sync::{Mutex, OnceCell};
+use tokio::task::JoinHandle;
+
+use crate::alerts;
+use crate::analytics::now_millis;
+use crate::TerraphimService;
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Number of runs kept per job
+pub const HISTORY_LENGTH: usize = 50;
+
+/// Attachments younger than this are not collected as garbage, so that
+/// documents can still be created for them after the upload
+const GARBAGE_MIN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const JOBS: [JobKind; 5] = [
+    JobKind::Reindex,
+    JobKind::RebuildThesaurus,
+    JobKind::CollectGarbage,
+    JobKind::SavedSearches,
+    JobKind::DecayEdges,
+];
+
+static RUNNER: OnceCell<JobRunner> = OnceCell::const_new();
+
+/// What started a run of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    Schedule,
+    /// Triggered by an admin
+    Manual,
+}
+
+/// A finished run of a job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobRun {
+    pub job: JobKind,
+    pub trigger: Trigger,
+    /// Start of the run in milliseconds since the Unix epoch
+    pub started: u64,
+    pub duration_ms: u64,
+    /// What the job did, if it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Why the job failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A job with its schedule and last run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub job: JobKind,
+    /// Seconds between scheduled runs; `None` if the job only runs when
+    /// triggered
+    pub interval: Option<u64>,
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+}
+
+/// The runs of all jobs, the most recent first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct JobHistory {
+    runs: Vec<JobRun>,
+}
+
+#[async_trait]
+impl Persistable for JobHistory {
+    fn new(_key: String) -> Self {
+        JobHistory::default()
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        "job_runs.json".to_string()
+    }
+}
+
+impl JobHistory {
+    /// Add a run, dropping the oldest run of its job if it has too many
+    fn add(&mut self, run: JobRun) {
+        let job = run.job;
+        self.runs.insert(0, run);
+        let mut kept = 0;
+        self.runs.retain(|run| {
+            if run.job != job {
+                return true;
+            }
+            kept += 1;
+            kept <= HISTORY_LENGTH
+        });
+    }
+
+    fn runs(&self, job: JobKind) -> impl Iterator<Item = &JobRun> {
+        self.runs.iter().filter(move |run| run.job == job)
+    }
+}
+
+/// The schedule of the jobs of a config
+///
+/// Saved searches are checked as often as the alerts runner checks them,
+/// unless the config schedules them.
+pub fn schedule(config: &Config) -> Vec<JobSchedule> {
+    let mut schedule = config.jobs.clone();
+    if !schedule
+        .iter()
+        .any(|entry| entry.job == JobKind::SavedSearches)
+    {
+        schedule.push(JobSchedule {
+            job: JobKind::SavedSearches,
+            interval: alerts::TICK.as_secs(),
+        });
+    }
+    schedule
+}
+
+/// Runs jobs, one run of each job at a time, and records their runs
+pub struct JobRunner {
+    history: Mutex<JobHistory>,
+    locks: AHashMap<JobKind, Mutex<()>>,
+}
+
+impl JobRunner {
+    /// Get the runner, loading the persisted history on first access
+    pub async fn instance() -> &'static JobRunner {
+        RUNNER
+            .get_or_init(|| async {
+                let history = match JobHistory::default().load().await {
+                    Ok(history) => history,
+                    Err(e) => {
+                        log::debug!("Starting without job history: {:?}", e);
+                        JobHistory::default()
+                    }
+                };
+                JobRunner {
+                    history: Mutex::new(history),
+                    locks: JOBS.into_iter().map(|job| (job, Mutex::new(()))).collect(),
+                }
+            })
+            .await
+    }
+
+    /// Run a job and record the run
+    ///
+    /// Returns `None` without running the job if it is running already.
+    pub async fn run(
+        &self,
+        config_state: &ConfigState,
+        job: JobKind,
+        trigger: Trigger,
+    ) -> Option<JobRun> {
+        let Ok(_running) = self.locks[&job].try_lock() else {
+            log::debug!("Skipping job {:?}, it is still running", job);
+            return None;
+        };
+        log::debug!("Running job {:?}", job);
+        let started = now_millis();
+        let timer = Instant::now();
+        let outcome = execute(config_state, job).await;
+        let mut run = JobRun {
+            job,
+            trigger,
+            started,
+            duration_ms: timer.elapsed().as_millis() as u64,
+            message: None,
+            error: None,
+        };
+        match outcome {
+            Ok(message) => run.message = Some(message),
+            Err(e) => {
+                log::warn!("Job {:?} failed: {e}", job);
+                run.error = Some(e.to_string());
+            }
+        }
+
+        let mut history = self.history.lock().await;
+        history.add(run.clone());
+        if let Err(e) = history.save().await {
+            log::warn!("Failed to persist job history: {:?}", e);
+        }
+        Some(run)
+    }
+
+    /// Runs of a job, the most recent first
+    pub async fn runs(&self, job: JobKind) -> Vec<JobRun> {
+        self.history.lock().await.runs(job).cloned().collect()
+    }
+
+    /// All jobs with their schedule and last run
+    pub async fn status(&self, schedule: &[JobSchedule]) -> Vec<JobStatus> {
+        let history = self.history.lock().await;
+        JOBS.into_iter()
+            .map(|job| JobStatus {
+                job,
+                interval: schedule
+                    .iter()
+                    .find(|entry| entry.job == job)
+                    .map(|entry| entry.interval),
+                running: self.locks[&job].try_lock().is_err(),
+                last_run: history.runs(job).next().cloned(),
+            })
+            .collect()
+    }
+}
+
+/// Do the work of a job
+///
+/// Returns what the job did.
+async fn execute(config_state: &ConfigState, job: JobKind) -> crate::Result<String> {
+    let mut service = TerraphimService::new(config_state.clone());
+    match job {
+        JobKind::Reindex => {
+            let (roles, documents) = service.reindex().await?;
+            Ok(format!("Indexed {documents} documents of {roles} roles"))
+        }
+        JobKind::RebuildThesaurus => {
+            let roles = service.rebuild_thesauri().await?;
+            Ok(format!("Rebuilt the thesauri of {roles} roles"))
+        }
+        JobKind::CollectGarbage => {
+            let deleted = blob::collect_garbage(GARBAGE_MIN_AGE).await?;
+            Ok(format!("Deleted {deleted} unreferenced attachments"))
+        }
+        JobKind::SavedSearches => {
+            let searches = alerts::run_due(config_state).await;
+            Ok(format!("Ran {searches} saved searches"))
+        }
+        JobKind::DecayEdges => {
+            let dropped = service.decay_edges().await;
+            Ok(format!(
+                "Decayed co-occurrences, dropping {dropped} stale ones"
+            ))
+        }
+    }
+}
+
+/// Start running the jobs of the config when they are due
+///
+/// The schedule is read from the config on every check, so changes of the
+/// config take effect without a restart. The scheduler runs until the
+/// returned task is aborted.
+pub fn spawn_jobs(config_state: ConfigState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let runner = JobRunner::instance().await;
+        // Start of the last scheduled run of each job
+        let mut last_runs: AHashMap<JobKind, Instant> = AHashMap::new();
+        let mut interval = tokio::time::interval(alerts::TICK);
+        loop {
+            interval.tick().await;
+            let schedule = schedule(&*config_state.config.lock().await);
+            for entry in schedule {
+                let interval = Duration::from_secs(entry.interval);
+                let is_due = last_runs
+                    .get(&entry.job)
+                    .is_none_or(|last_run| last_run.elapsed() >= interval);
+                if !is_due {
+                    continue;
+                }
+                last_runs.insert(entry.job, Instant::now());
+                let config_state = config_state.clone();
+                // Jobs run concurrently, so a long job doesn't hold up others
+                tokio::spawn(async move {
+                    runner
+                        .run(&config_state, entry.job, Trigger::Schedule)
+                        .await;
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(job: JobKind, started: u64) -> JobRun {
+        JobRun {
+            job,
+            trigger: Trigger::Schedule,
+            started,
+            duration_ms: 0,
+            message: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_history_and_schedule() {
+        let mut history = JobHistory::default();
+        for started in 0..HISTORY_LENGTH as u64 + 5 {
+            history.add(run(JobKind::SavedSearches, started));
+        }
+        history.add(run(JobKind::Reindex, 1));
+        let runs: Vec<u64> = history
+            .runs(JobKind::SavedSearches)
+            .map(|run| run.started)
+            .collect();
+        assert_eq!(runs.len(), HISTORY_LENGTH);
+        assert_eq!(runs[0], HISTORY_LENGTH as u64 + 4);
+        assert_eq!(history.runs(JobKind::Reindex).count(), 1);
+
+        let mut config = Config::default();
+        assert_eq!(
+            schedule(&config),
+            vec![JobSchedule {
+                job: JobKind::SavedSearches,
+                interval: 15,
+            }]
+        );
+        config.jobs = vec![JobSchedule {
+            job: JobKind::SavedSearches,
+            interval: 300,
+        }];
+        assert_eq!(schedule(&config), config.jobs);
+    }
+}
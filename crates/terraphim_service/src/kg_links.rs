@@ -0,0 +1,360 @@
+//! Caching the KG links of search results
+//!
+//! Roles with a link mode link the terms of their knowledge graph in every
+//! search result (see [`Linker`]), so the same documents were linked again
+//! on every search. The links of a document are kept by the hash of its
+//! body, the fingerprint of the thesaurus, the link mode and the link
+//! options instead: in memory per role, and persisted via
+//! `terraphim_persistence`, one key per document, so they survive restarts.
+//!
+//! The linker and the documents kept in memory for a role are dropped when
+//! the thesaurus of its rolegraph is replaced. Persisted links of an older
+//! thesaurus are never hit again, since the fingerprint is part of their key,
+//! unless the rebuilt thesaurus has the same terms.
+//!
+//! Roles which link definitions read them from the pages of their concepts
+//! (see [`definitions`]) when the linker is built, so once per thesaurus.
+//! The definitions are part of the fingerprint too.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use terraphim_automata::markdown::{LinkOptions, Linker};
+use terraphim_config::KgLinkMode;
+use terraphim_persistence::{load_many, Persistable};
+use terraphim_rolegraph::RoleGraph;
+use terraphim_types::{Document, KgConceptRef, RoleName, Thesaurus};
+
+use crate::definitions;
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Number of linked documents kept in memory per role
+pub const MAX_DOCUMENTS: usize = 10_000;
+
+/// The links of a document
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkedDocument {
+    pub key: String,
+    /// The linked body, in the `inline` mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// The terms of the body, in the `concepts` mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kg_concepts: Vec<KgConceptRef>,
+}
+
+#[async_trait]
+impl Persistable for LinkedDocument {
+    fn new(key: String) -> Self {
+        LinkedDocument {
+            key,
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    fn get_key(&self) -> String {
+        format!("kg_links_{}.json", self.key)
+    }
+}
+
+impl LinkedDocument {
+    fn apply(&self, document: &mut Document) {
+        if let Some(body) = &self.body {
+            document.body.clone_from(body);
+        }
+        document.kg_concepts.clone_from(&self.kg_concepts);
+    }
+}
+
+/// The linker of a role and the documents it linked
+struct RoleLinks {
+    thesaurus_version: u64,
+    mode: KgLinkMode,
+    options: LinkOptions,
+    /// The directory of the concept pages the definitions were read from
+    definitions: Option<PathBuf>,
+    fingerprint: String,
+    linker: Arc<Linker>,
+    documents: AHashMap<String, LinkedDocument>,
+}
+
+fn roles() -> &'static Mutex<AHashMap<RoleName, RoleLinks>> {
+    static ROLES: OnceLock<Mutex<AHashMap<RoleName, RoleLinks>>> = OnceLock::new();
+    ROLES.get_or_init(Default::default)
+}
+
+/// Hash of the terms of a thesaurus and their concepts, the same for the
+/// same terms in any order and in any process
+pub fn fingerprint(thesaurus: &Thesaurus) -> String {
+    let mut entries: Vec<(&str, u64, &str)> = thesaurus
+        .into_iter()
+        .map(|(term, normalized_term)| {
+            (
+                term.as_str(),
+                normalized_term.id,
+                normalized_term.value.as_str(),
+            )
+        })
+        .collect();
+    entries.sort_unstable();
+    let mut hasher = Sha256::new();
+    for (term, id, value) in entries {
+        hasher.update(format!("{term}\t{id}\t{value}\n").as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Fingerprint of a thesaurus and the definitions of its concepts
+fn fingerprint_with_definitions(
+    fingerprint: String,
+    definitions: &AHashMap<u64, String>,
+) -> String {
+    if definitions.is_empty() {
+        return fingerprint;
+    }
+    let mut definitions: Vec<_> = definitions.iter().collect();
+    definitions.sort_unstable();
+    let mut hasher = Sha256::new();
+    hasher.update(fingerprint.as_bytes());
+    for (id, definition) in definitions {
+        hasher.update(format!("\n{id}\t{definition}").as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Key of the links of a body
+///
+/// The exact body is hashed, not its [`Document::content_hash`], since the
+/// offsets of the links depend on every byte of it.
+fn cache_key(fingerprint: &str, mode: KgLinkMode, options: &LinkOptions, body: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(
+        format!(
+            "{fingerprint}\n{mode:?}\n{:?}\n{:?}\n{:?}\n{:?}\n",
+            options.max_links_per_concept,
+            options.max_links,
+            options.template,
+            options.normalization
+        )
+        .as_bytes(),
+    );
+    hasher.update(body.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// The fingerprint of the thesaurus and the linker of a role, built again
+/// if the thesaurus, the mode, the options or the directory of the
+/// definitions changed
+async fn linker(
+    rolegraph: &RoleGraph,
+    mode: KgLinkMode,
+    options: &LinkOptions,
+    definitions: Option<&Path>,
+) -> terraphim_automata::Result<(String, Arc<Linker>)> {
+    if let Some(links) = roles().lock().unwrap().get(&rolegraph.role) {
+        if links.thesaurus_version == rolegraph.thesaurus_version()
+            && links.mode == mode
+            && links.options == *options
+            && links.definitions.as_deref() == definitions
+        {
+            return Ok((links.fingerprint.clone(), links.linker.clone()));
+        }
+    }
+    let concept_definitions = match definitions {
+        Some(dir) => definitions::load(dir, &rolegraph.thesaurus).await,
+        None => Default::default(),
+    };
+    let fingerprint =
+        fingerprint_with_definitions(fingerprint(&rolegraph.thesaurus), &concept_definitions);
+    let linker =
+        Linker::new(&rolegraph.thesaurus, options.clone())?.with_definitions(concept_definitions);
+    let linker = Arc::new(linker);
+    roles().lock().unwrap().insert(
+        rolegraph.role.clone(),
+        RoleLinks {
+            thesaurus_version: rolegraph.thesaurus_version(),
+            mode,
+            options: options.clone(),
+            definitions: definitions.map(Path::to_path_buf),
+            fingerprint: fingerprint.clone(),
+            linker: linker.clone(),
+            documents: AHashMap::new(),
+        },
+    );
+    Ok((fingerprint, linker))
+}
+
+fn cached(role: &RoleName, key: &str) -> Option<LinkedDocument> {
+    roles()
+        .lock()
+        .unwrap()
+        .get(role)?
+        .documents
+        .get(key)
+        .cloned()
+}
+
+fn remember(role: &RoleName, linked: LinkedDocument) {
+    if let Some(links) = roles().lock().unwrap().get_mut(role) {
+        if links.documents.len() >= MAX_DOCUMENTS {
+            links.documents.clear();
+        }
+        links.documents.insert(linked.key.clone(), linked);
+    }
+}
+
+/// Link the terms of the thesaurus of a rolegraph in a batch of documents,
+/// reusing the links of documents linked before
+///
+/// One linker, so one automaton, is used for the whole batch. Persisted
+/// links are looked up, and new links persisted, with at most `concurrency`
+/// requests at a time. The definitions of the concepts with a page in the
+/// `definitions` directory are the titles of their links.
+pub(crate) async fn link_documents(
+    rolegraph: &RoleGraph,
+    mode: KgLinkMode,
+    options: LinkOptions,
+    definitions: Option<&Path>,
+    documents: &mut [Document],
+    concurrency: usize,
+) -> terraphim_automata::Result<()> {
+    let role = &rolegraph.role;
+    let (fingerprint, linker) = linker(rolegraph, mode, &options, definitions).await?;
+
+    // Positions and keys of the documents not linked in memory
+    let mut misses = Vec::new();
+    for (position, document) in documents.iter_mut().enumerate() {
+        let key = cache_key(&fingerprint, mode, &options, &document.body);
+        match cached(role, &key) {
+            Some(linked) => linked.apply(document),
+            None => misses.push((position, key)),
+        }
+    }
+    if misses.is_empty() {
+        return Ok(());
+    }
+
+    let keys = misses.iter().map(|(_, key)| key.clone()).collect();
+    let loaded = load_many::<LinkedDocument>(keys, concurrency).await;
+    let mut new_links = Vec::new();
+    for ((position, key), result) in misses.into_iter().zip(loaded) {
+        let document = &mut documents[position];
+        let linked = match result {
+            Ok(linked) => linked,
+            Err(_) => {
+                let mut linked = <LinkedDocument as Persistable>::new(key);
+                match mode {
+                    KgLinkMode::Inline => linked.body = Some(linker.link(&document.body)),
+                    KgLinkMode::Concepts => linked.kg_concepts = linker.concepts(&document.body),
+                }
+                new_links.push(linked.clone());
+                linked
+            }
+        };
+        linked.apply(document);
+        remember(role, linked);
+    }
+
+    stream::iter(new_links)
+        .for_each_concurrent(concurrency.max(1), |linked| async move {
+            if let Err(e) = linked.save().await {
+                log::warn!("Failed to persist KG links `{}`: {:?}", linked.key, e);
+            }
+        })
+        .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use terraphim_types::{NormalizedTerm, NormalizedTermValue};
+
+    fn thesaurus(entries: &[(&str, u64, &str)]) -> Thesaurus {
+        let mut thesaurus = Thesaurus::new("links".to_string());
+        for (term, id, concept) in entries {
+            thesaurus.insert(
+                NormalizedTermValue::new(term.to_string()),
+                NormalizedTerm::new(*id, NormalizedTermValue::new(concept.to_string())),
+            );
+        }
+        thesaurus
+    }
+
+    #[test]
+    fn test_kg_link_keys() {
+        let rust = thesaurus(&[("rust", 1, "rust"), ("cargo", 2, "cargo")]);
+        let reordered = thesaurus(&[("cargo", 2, "cargo"), ("rust", 1, "rust")]);
+        let renamed = thesaurus(&[("rust", 1, "rust lang"), ("cargo", 2, "cargo")]);
+        assert_eq!(fingerprint(&rust), fingerprint(&reordered));
+        assert_ne!(fingerprint(&rust), fingerprint(&renamed));
+
+        let fingerprint = fingerprint(&rust);
+        let options = LinkOptions::default();
+        let key = cache_key(&fingerprint, KgLinkMode::Inline, &options, "rust\n");
+        assert_eq!(
+            key,
+            cache_key(&fingerprint, KgLinkMode::Inline, &options, "rust\n")
+        );
+        assert_ne!(
+            key,
+            cache_key(&fingerprint, KgLinkMode::Inline, &options, "rust\r\n")
+        );
+        assert_ne!(
+            key,
+            cache_key(&fingerprint, KgLinkMode::Concepts, &options, "rust\n")
+        );
+        let first = LinkOptions {
+            max_links_per_concept: Some(1),
+            ..Default::default()
+        };
+        assert_ne!(
+            key,
+            cache_key(&fingerprint, KgLinkMode::Inline, &first, "rust\n")
+        );
+        let wiki = LinkOptions {
+            template: Some("https://wiki.internal/{concept}".to_string()),
+            ..Default::default()
+        };
+        assert_ne!(
+            key,
+            cache_key(&fingerprint, KgLinkMode::Inline, &wiki, "rust\n")
+        );
+
+        let definitions = AHashMap::from([(1, "A language.".to_string())]);
+        assert_eq!(
+            fingerprint_with_definitions(fingerprint.clone(), &Default::default()),
+            fingerprint
+        );
+        assert_ne!(
+            fingerprint_with_definitions(fingerprint.clone(), &definitions),
+            fingerprint
+        );
+    }
+}
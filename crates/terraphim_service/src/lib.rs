@@ -1,14 +1,69 @@
-use ahash::AHashMap;
-use terraphim_automata::{load_thesaurus, AutomataPath};
-use terraphim_config::{ConfigState, Role};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use terraphim_automata::language::detect_language;
+use terraphim_automata::{
+    export_thesaurus, load_thesaurus, validate_thesaurus, AutomataPath, ExportFormat, LintIssue,
+};
+use terraphim_config::{
+    Access, ConfigState, JobKind, KgLinkMode, Role, SearchStageKind, TerraphimConfigError,
+};
+use terraphim_middleware::indexer::content_hash;
 use terraphim_middleware::thesaurus::{self, build_thesaurus_from_haystack};
+use terraphim_persistence::blob;
 use terraphim_persistence::error;
 use terraphim_persistence::Persistable;
-use terraphim_rolegraph::{RoleGraph, RoleGraphSync};
+use terraphim_rolegraph::{
+    Community, CoverageReport, GraphData, GraphFormat, GraphNode, RoleGraph, RoleGraphSync,
+};
 use terraphim_types::{
-    Document, Index, IndexedDocument, RelevanceFunction, RoleName, SearchQuery, Thesaurus,
+    Attachment, ConflictPolicy, Document, Index, IndexedDocument, NormalizedTermValue, QueryType,
+    RelevanceFunction, RoleName, SearchQuery, Thesaurus, ThesaurusConflict,
 };
-mod score;
+pub mod acceptance;
+pub mod alerts;
+pub mod analytics;
+pub mod backlinks;
+pub mod candidates;
+pub mod concepts;
+pub mod dedup;
+mod definitions;
+pub mod enrichment;
+pub mod exclusion;
+pub mod facets;
+pub mod feedback;
+mod highlight;
+pub mod jobs;
+pub mod kg_links;
+pub mod pages;
+pub mod pipeline;
+pub mod profile;
+pub mod query;
+pub mod result_cache;
+pub mod score;
+pub mod sessions;
+mod snippet;
+pub mod spelling;
+pub mod suggest;
+pub mod thesaurus_cache;
+
+use acceptance::{AcceptanceStore, SuggestionAcceptance};
+use ahash::{AHashMap, AHashSet};
+use alerts::{SavedSearch, SavedSearchStore};
+use analytics::{Analytics, AnalyticsReport, Interaction, QueryRecord, StageTimer};
+use backlinks::Backlink;
+use candidates::{Candidate, CandidateQueue, CandidateStatus};
+use concepts::{ConceptBacklink, ConceptCount};
+use feedback::FeedbackStore;
+use futures::future;
+use futures::stream::{self, FuturesUnordered, Stream, StreamExt};
+use jobs::{JobRun, JobRunner, JobStatus, Trigger};
+use pages::{Cursor, SearchPage, Snapshots};
+use pipeline::{SearchContext, SearchPipeline};
+use result_cache::{CacheKey, ResultCache};
+use sessions::{SessionStore, UserSession};
+use spelling::DidYouMean;
+use suggest::Suggestion;
+use thesaurus_cache::ThesaurusCache;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ServiceError {
@@ -23,41 +78,128 @@ pub enum ServiceError {
 
     #[error("Config error: {0}")]
     Config(String),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid metadata: {0}")]
+    InvalidMetadata(String),
+
+    #[error("Access denied: {0}")]
+    Forbidden(String),
+
+    #[error("No user session")]
+    NoSession,
+
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
+    #[error("Invalid query: {0}")]
+    InvalidQuery(#[from] query::ParseError),
+
+    #[error("Unknown haystack: {0}")]
+    UnknownHaystack(String),
+
+    #[error("Invalid regex: {0}")]
+    InvalidRegex(String),
+
+    #[error("Unknown document: {0}")]
+    UnknownDocument(String),
+
+    #[error("Automata error: {0}")]
+    Automata(#[from] terraphim_automata::TerraphimAutomataError),
+
+    #[error("Rolegraph error: {0}")]
+    RoleGraph(#[from] terraphim_rolegraph::Error),
 }
 
 pub type Result<T> = std::result::Result<T, ServiceError>;
 
 pub struct TerraphimService {
     config_state: ConfigState,
+    access: Access,
+    session: Option<String>,
 }
 
 impl<'a> TerraphimService {
     /// Create a new TerraphimService
+    ///
+    /// The service sees everything, see [`TerraphimService::with_access`].
     pub fn new(config_state: ConfigState) -> Self {
-        Self { config_state }
+        Self {
+            config_state,
+            access: Access::All,
+            session: None,
+        }
+    }
+
+    /// Restrict the documents and knowledge graphs the service sees, see
+    /// [`terraphim_config::access`]
+    pub fn with_access(mut self, access: Access) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Act in a user session, see [`sessions`]
+    pub fn with_session(mut self, session_id: String) -> Self {
+        self.session = Some(session_id);
+        self
+    }
+
+    /// Fail unless the knowledge graph of a role is visible
+    ///
+    /// Unknown roles pass, so callers report them as usual.
+    async fn check_role_access(&self, role_name: &RoleName) -> Result<()> {
+        if self.access.is_all() {
+            return Ok(());
+        }
+        match self.config_state.get_role(role_name).await {
+            Some(role) if !self.access.allows_role(&role) => Err(ServiceError::Forbidden(format!(
+                "knowledge graph of role `{role_name}`"
+            ))),
+            _ => Ok(()),
+        }
     }
 
     /// Build a thesaurus from the haystack and update the knowledge graph automata URL
+    #[tracing::instrument(skip_all)]
     async fn build_thesaurus(&mut self, search_query: &SearchQuery) -> Result<()> {
         Ok(build_thesaurus_from_haystack(&mut self.config_state, search_query).await?)
     }
+    /// Get the thesaurus of a role from the process-wide [`ThesaurusCache`]
+    ///
+    /// On a cache miss the thesaurus is loaded with
+    /// [`TerraphimService::load_thesaurus`] and cached, so all consumers
+    /// share the same instance until its sources change.
+    #[tracing::instrument(skip_all, fields(role = %role_name))]
+    pub async fn ensure_thesaurus_loaded(
+        &mut self,
+        role_name: &RoleName,
+    ) -> Result<Arc<Thesaurus>> {
+        let cached = ThesaurusCache::instance()
+            .get_or_load(role_name, || self.load_thesaurus(role_name))
+            .await?;
+        Ok(cached.thesaurus)
+    }
+
     /// load thesaurus from config object and if absent make sure it's loaded from automata_url
-    pub async fn ensure_thesaurus_loaded(&mut self, role_name: &RoleName) -> Result<Thesaurus> {
+    ///
+    /// This bypasses the [`ThesaurusCache`].
+    #[tracing::instrument(skip_all, fields(role = %role_name))]
+    pub async fn load_thesaurus(&self, role_name: &RoleName) -> Result<Thesaurus> {
         async fn load_thesaurus_from_automata_path(
             config_state: &ConfigState,
             role_name: &RoleName,
-            rolegraphs: &mut AHashMap<RoleName, RoleGraphSync>,
         ) -> Result<Thesaurus> {
             let role = config_state.get_role(role_name).await.unwrap();
             if let Some(automata_path) = role.kg.unwrap().automata_path {
                 let thesaurus = load_thesaurus(&automata_path).await.unwrap();
-                let rolegraph = RoleGraph::new(role_name.clone(), thesaurus.clone()).await;
-                match rolegraph {
-                    Ok(rolegraph) => {
-                        let rolegraph_value = RoleGraphSync::from(rolegraph);
-                        rolegraphs.insert(role_name.clone(), rolegraph_value);
+                // Swap the thesaurus of the warm rolegraph instead of
+                // creating a new one, which would lose all indexed documents
+                if let Some(rolegraph) = config_state.roles.get(role_name) {
+                    if let Err(e) = rolegraph.lock().await.replace_thesaurus(thesaurus.clone()) {
+                        log::error!("Failed to update role and thesaurus: {:?}", e);
                     }
-                    Err(e) => log::error!("Failed to update role and thesaurus: {:?}", e),
                 }
                 Ok(thesaurus)
             } else {
@@ -66,8 +208,7 @@ impl<'a> TerraphimService {
         }
         println!("Loading thesaurus for role: {}", role_name);
         println!("Role keys {:?}", self.config_state.roles.keys());
-        let mut rolegraphs = self.config_state.roles.clone();
-        if let Some(rolegraph_value) = rolegraphs.get(role_name) {
+        if let Some(rolegraph_value) = self.config_state.roles.get(role_name) {
             let mut thesaurus_result = rolegraph_value.lock().await.thesaurus.clone().load().await;
             match thesaurus_result {
                 Ok(thesaurus) => {
@@ -77,35 +218,122 @@ impl<'a> TerraphimService {
                 }
                 Err(e) => {
                     log::error!("Failed to load thesaurus: {:?}", e);
-                    load_thesaurus_from_automata_path(
-                        &self.config_state,
-                        role_name,
-                        &mut rolegraphs,
-                    )
-                    .await
+                    load_thesaurus_from_automata_path(&self.config_state, role_name).await
                 }
             }
         } else {
-            load_thesaurus_from_automata_path(
-                &self.config_state,
-                role_name,
-                &mut rolegraphs,
-            )
-            .await
+            load_thesaurus_from_automata_path(&self.config_state, role_name).await
         }
     }
 
     /// Create document
-    pub async fn create_document(&mut self, document: Document) -> Result<Document> {
+    ///
+    /// The document is persisted, so that search results with the same ID
+    /// are completed with its description, attachments and metadata.
+    ///
+    /// Attachments must have been stored with
+    /// [`TerraphimService::add_attachment`] before; their metadata is
+    /// completed from the stored attachment.
+    #[tracing::instrument(skip_all, fields(document_id = %document.id))]
+    pub async fn create_document(&mut self, mut document: Document) -> Result<Document> {
+        if !self.access.grants(&document.visibility) {
+            return Err(ServiceError::Forbidden(format!(
+                "visibility labels {:?}",
+                document.visibility
+            )));
+        }
+        for attachment in &mut document.attachments {
+            let stored = blob::load_attachment(&attachment.blob_id, &attachment.id).await?;
+            if attachment.name.is_empty() {
+                attachment.name = stored.name;
+            }
+            if attachment.mime_type.is_empty() {
+                attachment.mime_type = stored.mime_type;
+            }
+            attachment.size = stored.size;
+        }
+        if document.language.is_none() {
+            document.language = detect_language(&document.body).map(|l| l.code().to_string());
+        }
+        let now = analytics::now_millis();
+        document.created.get_or_insert(now);
+        document.modified = Some(now);
+        document.content_hash = Some(content_hash(&document.body));
+        // The document is added to all roles, so it has to match the
+        // metadata schema of every role
+        let config = self.fetch_config().await;
+        for role in config.roles.values() {
+            role.validate_metadata(&document.extra)
+                .map_err(|e| match e {
+                    TerraphimConfigError::InvalidMetadata(message) => {
+                        ServiceError::InvalidMetadata(message)
+                    }
+                    e => ServiceError::Config(e.to_string()),
+                })?;
+        }
         self.config_state.add_to_roles(&document).await?;
+        for role_name in config.roles.keys() {
+            backlinks::learn(role_name, [&document]);
+        }
+        document.save().await?;
+        blob::add_references(&document).await?;
+        enrichment::forget_miss(&document.id);
+        ResultCache::instance().invalidate_all();
         Ok(document)
     }
 
+    /// Store the content of an attachment
+    ///
+    /// The returned attachment can be added to a document passed to
+    /// [`TerraphimService::create_document`].
+    pub async fn add_attachment(
+        &self,
+        name: &str,
+        mime_type: &str,
+        content: &[u8],
+    ) -> Result<Attachment> {
+        Ok(blob::save_attachment(name, mime_type, content).await?)
+    }
+
+    /// Load the upload `id` of an attachment and its content
+    ///
+    /// Unless access is unrestricted, the attachment must belong to a
+    /// visible document.
+    pub async fn get_attachment(&self, blob_id: &str, id: &str) -> Result<(Attachment, Vec<u8>)> {
+        if !self.access.is_all() {
+            let documents = blob::referencing_documents(blob_id, id).await?;
+            if !documents
+                .iter()
+                .any(|document| self.access.allows_document(document))
+            {
+                return Err(ServiceError::Forbidden(format!("attachment {blob_id}")));
+            }
+        }
+        let attachment = blob::load_attachment(blob_id, id).await?;
+        let content = blob::load_blob(blob_id).await?;
+        Ok((attachment, content))
+    }
+
+    /// The role of requests without a role: the selected role of the
+    /// session, if any, or the default role of the config
+    pub async fn default_role(&self) -> RoleName {
+        if let Some(session_id) = &self.session {
+            let session = SessionStore::instance().await.get(session_id).await;
+            if let Some(role) = session.selected_role {
+                return role;
+            }
+        }
+        self.config_state.get_default_role().await
+    }
+
     /// Get the role for the given search query
+    ///
+    /// Fails if the query is restricted to haystacks the role doesn't have,
+    /// or if it is a regex query with an invalid pattern.
     async fn get_search_role(&self, search_query: &SearchQuery) -> Result<Role> {
         let search_role = match &search_query.role {
             Some(role) => role.clone(),
-            None => self.config_state.get_default_role().await,
+            None => self.default_role().await,
         };
 
         log::debug!("Searching for role: {:?}", search_role);
@@ -115,11 +343,284 @@ impl<'a> TerraphimService {
                 search_role
             )));
         };
+        for location in &search_query.haystacks {
+            if !role
+                .haystacks
+                .iter()
+                .any(|haystack| Path::new(location) == haystack.path)
+            {
+                return Err(ServiceError::UnknownHaystack(format!(
+                    "Role `{}` has no haystack `{location}`",
+                    role.name
+                )));
+            }
+        }
+        query::check(search_query)?;
         Ok(role)
     }
 
     /// Search for documents in the haystacks
+    ///
+    /// In a session, a query without role runs as the selected role of the
+    /// session and the search term is added to its recent queries.
     pub async fn search(&mut self, search_query: &SearchQuery) -> Result<Vec<Document>> {
+        let Some(session_id) = self.session.clone() else {
+            let (documents, record) = self.search_cached(search_query).await?;
+            Analytics::instance().await.record_query(record).await;
+            return Ok(documents);
+        };
+        let mut search_query = search_query.clone();
+        if search_query.role.is_none() {
+            search_query.role = Some(self.default_role().await);
+        }
+        let (documents, record) = self.search_cached(&search_query).await?;
+        Analytics::instance().await.record_query(record).await;
+        SessionStore::instance()
+            .await
+            .update(&session_id, |session| {
+                session.record_query(search_query.search_term.as_str())
+            })
+            .await;
+        Ok(documents)
+    }
+
+    /// Search for a page of documents, see [`pages`]
+    ///
+    /// Without a cursor, the haystacks are searched and the first page is
+    /// returned. With the cursor of a previous page, the next page is taken
+    /// from the results of that search, without searching again; the
+    /// search term and role of the query must be those of the first page.
+    /// Pages hold `limit` documents, which must be at least one; `skip` is
+    /// ignored.
+    pub async fn search_page(
+        &mut self,
+        search_query: &SearchQuery,
+        cursor: Option<Cursor>,
+    ) -> Result<SearchPage> {
+        let page_size = search_query.limit.unwrap_or(pages::DEFAULT_PAGE_SIZE);
+        if page_size == 0 {
+            // An empty page would point its next cursor at itself
+            return Err(ServiceError::InvalidCursor(
+                "Pages need a limit of at least one document".to_string(),
+            ));
+        }
+        let role = self.get_search_role(search_query).await?;
+        let search_query = SearchQuery {
+            role: Some(role.name.clone()),
+            skip: None,
+            limit: None,
+            ..search_query.clone()
+        };
+        let (documents, cursor) = match cursor {
+            Some(cursor) => {
+                let documents = Snapshots::instance()
+                    .get(&cursor, &search_query)
+                    .ok_or_else(|| {
+                        ServiceError::InvalidCursor(format!(
+                            "The results of cursor `{cursor}` have expired or are of another search"
+                        ))
+                    })?;
+                (documents, cursor)
+            }
+            None => {
+                let documents = self.search(&search_query).await?;
+                let cursor = Snapshots::instance().insert(&search_query, documents);
+                let documents = Snapshots::instance()
+                    .get(&cursor, &search_query)
+                    .unwrap_or_default();
+                (documents, cursor)
+            }
+        };
+        // The snapshot may be of another principal with the same cursor
+        let documents: Vec<Document> = documents
+            .iter()
+            .filter(|document| self.access.allows_document(document))
+            .cloned()
+            .collect();
+        Ok(pages::page(&documents, cursor, page_size))
+    }
+
+    /// Search for documents, yielding them as each haystack is searched
+    ///
+    /// The haystacks are searched concurrently. The documents of each are
+    /// ranked, filtered and highlighted like those of
+    /// [`TerraphimService::search`] as soon as it is searched, so they can
+    /// be shown before slower haystacks are done. The documents of a
+    /// haystack come best first, but a later haystack may yield better
+    /// ones; ranks are comparable across haystacks, while scores are
+    /// relative to the best document of a haystack. A document found in
+    /// several haystacks is yielded once, and haystacks which fail are
+    /// logged and skipped.
+    ///
+    /// `skip` and `limit` are ignored, and the search is not recorded in the
+    /// analytics log.
+    pub async fn search_stream(
+        mut self,
+        search_query: &SearchQuery,
+    ) -> Result<impl Stream<Item = Document> + Send + 'static> {
+        let role = self.get_search_role(search_query).await?;
+        let search_query = SearchQuery {
+            role: Some(role.name.clone()),
+            skip: None,
+            limit: None,
+            ..search_query.clone()
+        };
+        if let Some(session_id) = &self.session {
+            SessionStore::instance()
+                .await
+                .update(session_id, |session| {
+                    session.record_query(search_query.search_term.as_str())
+                })
+                .await;
+        }
+        self.index_backlinks(&role.name).await?;
+        if role.relevance_function.uses_graph() {
+            self.load_search_thesaurus(&role, &search_query).await?;
+        }
+
+        let searches: FuturesUnordered<_> = role
+            .haystacks
+            .iter()
+            .filter(|haystack| search_query.searches_haystack(&haystack.path))
+            .cloned()
+            .map(|haystack| {
+                let config_state = self.config_state.clone();
+                let search_query = search_query.clone();
+                async move {
+                    let index = terraphim_middleware::search_haystack(
+                        config_state,
+                        &search_query,
+                        &haystack,
+                    )
+                    .await;
+                    (haystack, index)
+                }
+            })
+            .collect();
+        let service = Arc::new(self);
+        let role = Arc::new(role);
+        let search_query = Arc::new(search_query);
+        let mut seen = AHashSet::new();
+        let documents = searches
+            .then(move |(haystack, index)| {
+                let service = service.clone();
+                let role = role.clone();
+                let search_query = search_query.clone();
+                async move {
+                    let index = match index {
+                        Ok(index) => index,
+                        Err(e) => {
+                            log::warn!("Failed to search haystack {:?}: {e}", haystack.path);
+                            return Vec::new();
+                        }
+                    };
+                    spelling::learn(&role.name, &index.get_all_documents());
+                    backlinks::learn(&role.name, index.values().map(Arc::as_ref));
+                    let mut timer = StageTimer::new();
+                    let mut documents = service
+                        .rank_documents(&role, &search_query, &index, &mut timer)
+                        .await;
+                    service
+                        .finish_documents(&role, &search_query, &mut documents, &mut timer)
+                        .await;
+                    documents
+                }
+            })
+            .flat_map(stream::iter)
+            // Duplicates in later haystacks are left out, as the first copy
+            // is out already
+            .filter(move |document| {
+                let key = document.content_hash.as_ref().unwrap_or(&document.id);
+                future::ready(seen.insert(key.clone()))
+            });
+        Ok(documents)
+    }
+
+    /// Search for documents in the haystacks and suggest a correction of
+    /// the search term if nothing is found
+    ///
+    /// If the search query asks for auto-correction and the correction is
+    /// confident enough, the corrected search term is searched for instead
+    /// and the results are those of the corrected search. Corrections come
+    /// from the vocabulary of the role, so they are only suggested to those
+    /// who may see its knowledge graph.
+    pub async fn search_with_correction(
+        &mut self,
+        search_query: &SearchQuery,
+    ) -> Result<(Vec<Document>, Option<DidYouMean>)> {
+        let documents = self.search(search_query).await?;
+        if !documents.is_empty() {
+            return Ok((documents, None));
+        }
+        let role = self.get_search_role(search_query).await?;
+        if self.check_role_access(&role.name).await.is_err() {
+            return Ok((documents, None));
+        }
+        let Some(mut did_you_mean) =
+            spelling::correct(&role.name, search_query.search_term.as_str())
+        else {
+            return Ok((documents, None));
+        };
+        log::debug!("Did you mean: {:?}", did_you_mean);
+        if !search_query.auto_correct || did_you_mean.confidence < spelling::AUTO_CORRECT_CONFIDENCE
+        {
+            return Ok((documents, Some(did_you_mean)));
+        }
+        let corrected_query = SearchQuery {
+            search_term: NormalizedTermValue::new(did_you_mean.suggestion.clone()),
+            auto_correct: false,
+            ..search_query.clone()
+        };
+        let documents = self.search(&corrected_query).await?;
+        did_you_mean.applied = true;
+        Ok((documents, Some(did_you_mean)))
+    }
+
+    /// Like [`TerraphimService::search_profiled`], but with the results of
+    /// the same search from the [`ResultCache`] if there are any
+    ///
+    /// The timings of a cached search only have the `cache` stage.
+    async fn search_cached(
+        &mut self,
+        search_query: &SearchQuery,
+    ) -> Result<(Vec<Document>, QueryRecord)> {
+        let role = self.get_search_role(search_query).await?;
+        let key = CacheKey::new(&role.name, search_query, &self.access);
+        if let Some(documents) = ResultCache::instance().get(&key) {
+            log::debug!(
+                "Search results of {:?} are cached",
+                search_query.search_term
+            );
+            let mut timer = StageTimer::new();
+            timer.stage("cache");
+            let record = QueryRecord::new(
+                search_query,
+                &role.name,
+                role.relevance_function,
+                documents.len(),
+                timer,
+            );
+            return Ok((documents, record));
+        }
+        let (documents, record) = self.search_profiled(search_query).await?;
+        ResultCache::instance().insert(key, documents.clone());
+        Ok((documents, record))
+    }
+
+    /// Search for documents in the haystacks and return the timings of
+    /// every search stage alongside the documents
+    ///
+    /// Unlike [`TerraphimService::search`], the search is not recorded in
+    /// the analytics log.
+    #[tracing::instrument(
+        skip_all,
+        fields(role = ?search_query.role, search_term = %search_query.search_term)
+    )]
+    pub async fn search_profiled(
+        &mut self,
+        search_query: &SearchQuery,
+    ) -> Result<(Vec<Document>, QueryRecord)> {
+        let mut timer = StageTimer::new();
         // Get the role from the config
         log::debug!("Role for searching: {:?}", search_query.role);
         let role = self.get_search_role(search_query).await?;
@@ -128,8 +629,77 @@ impl<'a> TerraphimService {
         let index: Index =
             terraphim_middleware::search_haystacks(self.config_state.clone(), search_query.clone())
                 .await?;
+        timer.stage("haystacks");
+        spelling::learn(&role.name, &index.get_all_documents());
+        self.index_backlinks(&role.name).await?;
+        backlinks::learn(&role.name, index.values().map(Arc::as_ref));
+        timer.stage("backlinks");
 
-        match role.relevance_function {
+        if role.relevance_function.uses_graph() {
+            self.load_search_thesaurus(&role, search_query).await?;
+            timer.stage("thesaurus");
+        }
+        let mut documents = self
+            .rank_documents(&role, search_query, &index, &mut timer)
+            .await;
+        self.finish_documents(&role, search_query, &mut documents, &mut timer)
+            .await;
+
+        let record = QueryRecord::new(
+            search_query,
+            &role.name,
+            role.relevance_function,
+            documents.len(),
+            timer,
+        );
+        Ok((documents, record))
+    }
+
+    /// Load the thesaurus of a role ranked by the knowledge graph
+    ///
+    /// The thesaurus is only rebuilt from the haystacks when it isn't
+    /// cached or its sources changed.
+    async fn load_search_thesaurus(
+        &mut self,
+        role: &Role,
+        search_query: &SearchQuery,
+    ) -> Result<()> {
+        let service = &mut *self;
+        let role_name = &role.name;
+        ThesaurusCache::instance()
+            .get_or_load(role_name, move || async move {
+                service.build_thesaurus(search_query).await?;
+                service.load_thesaurus(role_name).await
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Rank the documents of an index with the relevance function of the
+    /// role
+    ///
+    /// For roles ranked by the knowledge graph, the thesaurus must be
+    /// loaded, see [`TerraphimService::load_search_thesaurus`].
+    async fn rank_documents(
+        &self,
+        role: &Role,
+        search_query: &SearchQuery,
+        index: &Index,
+        timer: &mut StageTimer,
+    ) -> Vec<Document> {
+        if let Some(scorer) = score::scorer_of(role) {
+            log::debug!("Ranking documents with scorer `{}`", scorer.name());
+            let documents = scorer.sort(search_query, index.get_all_documents());
+            timer.stage("scoring");
+            return score::rank_in_order(documents);
+        }
+        // Patterns aren't concepts of the knowledge graph, so the documents
+        // found by regex queries are ranked by their titles
+        let relevance_function = match search_query.query_type {
+            QueryType::Term => role.relevance_function,
+            QueryType::Regex => RelevanceFunction::TitleScorer,
+        };
+        match relevance_function {
             RelevanceFunction::TitleScorer => {
                 log::debug!("Searching haystack with title scorer");
 
@@ -137,34 +707,1077 @@ impl<'a> TerraphimService {
 
                 log::debug!("Sorting documents by relevance");
                 // Sort the documents by relevance
-                let documents = score::sort_documents(search_query, documents);
-                let total_length = documents.len();
-                let mut docs_ranked = Vec::new();
-                for (idx, doc) in documents.iter().enumerate() {
-                    let document: &mut terraphim_types::Document = &mut doc.clone();
-                    let rank = (total_length - idx).try_into().unwrap();
-                    document.rank = Some(rank);
-                    docs_ranked.push(document.clone());
-                }
-                Ok(docs_ranked)
+                let documents = score::score_documents(search_query, documents);
+                let docs_ranked = score::rank_scored(documents);
+                timer.stage("scoring");
+                docs_ranked
+            }
+            RelevanceFunction::BM25 => {
+                let params = role.scoring.unwrap_or_default();
+                let documents =
+                    score::bm25::score_documents(search_query, index.get_all_documents(), params);
+                timer.stage("scoring");
+                score::rank_scored(documents)
             }
             RelevanceFunction::TerraphimGraph => {
-                self.build_thesaurus(search_query).await?;
-                let thesaurus = self.ensure_thesaurus_loaded(&role.name).await?;
                 let scored_index_docs: Vec<IndexedDocument> = self
                     .config_state
-                    .search_indexed_documents(search_query, &role)
+                    .search_indexed_documents(search_query, role)
                     .await;
+                timer.stage("graph_query");
 
                 // Apply to ripgrep vector of document output
                 // I.e. use the ranking of thesaurus to rank the documents here
                 log::debug!("Ranking documents with thesaurus");
                 println!("Ranking documents with thesaurus");
-                let documents = index.get_documents(scored_index_docs);
+                let mut documents = index.get_documents(scored_index_docs);
+                score::score_by_rank(&mut documents);
+                timer.stage("ranking");
+
+                documents
+            }
+            RelevanceFunction::Hybrid => {
+                let weights = role.hybrid_weights.unwrap_or_default();
+                let params = role.scoring.unwrap_or_default();
+                let documents = index.get_all_documents();
+                // The scorers of documents run while the graph is queried
+                let sorted = {
+                    let search_query = search_query.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let by_bm25 =
+                            score::bm25::sort_documents(&search_query, documents.clone(), params);
+                        (score::sort_documents(&search_query, documents), by_bm25)
+                    })
+                };
+                let by_graph = self
+                    .config_state
+                    .search_indexed_documents(search_query, role)
+                    .await;
+                let (by_title, by_bm25) = match sorted.await {
+                    Ok(sorted) => sorted,
+                    Err(e) => {
+                        log::error!("Failed to score documents: {e}");
+                        (Vec::new(), Vec::new())
+                    }
+                };
+                timer.stage("scoring");
+
+                let fused = score::hybrid::fuse_scored(&[
+                    (
+                        weights.title,
+                        by_title.iter().map(|doc| doc.id.as_str()).collect(),
+                    ),
+                    (
+                        weights.bm25,
+                        by_bm25.iter().map(|doc| doc.id.as_str()).collect(),
+                    ),
+                    (
+                        weights.graph,
+                        by_graph.iter().map(|doc| doc.id.as_str()).collect(),
+                    ),
+                ]);
+                let documents = fused
+                    .into_iter()
+                    .filter_map(|(id, score)| Some((index.get(id)?.clone(), score)))
+                    .collect();
+                timer.stage("ranking");
+                score::rank_scored(documents)
+            }
+        }
+    }
+
+    /// Run the search pipeline of the role on ranked documents, see
+    /// [`SearchPipeline`]
+    async fn finish_documents(
+        &self,
+        role: &Role,
+        search_query: &SearchQuery,
+        documents: &mut Vec<Document>,
+        timer: &mut StageTimer,
+    ) {
+        let context = SearchContext {
+            role,
+            query: search_query,
+            access: &self.access,
+            config_state: &self.config_state,
+        };
+        SearchPipeline::for_role(role)
+            .run(&context, documents, timer)
+            .await;
+        // Stages may have boosted the scores of some documents
+        score::normalize(documents);
+    }
+
+    /// Record what the user did with a search result
+    ///
+    /// Interactions are the ground truth for search quality metrics in the
+    /// analytics report.
+    pub async fn record_interaction(&self, interaction: Interaction) -> Result<()> {
+        self.check_role_access(&interaction.role).await?;
+        log::debug!("Recording interaction: {:?}", interaction);
+        Analytics::instance()
+            .await
+            .record_interaction(interaction)
+            .await;
+        Ok(())
+    }
+
+    /// Record feedback on a search result, which re-ranks later searches
+    /// of its role
+    ///
+    /// The interaction is recorded in the analytics log, too. The concepts
+    /// of its search term in the knowledge graph of the role carry the
+    /// feedback over to other search terms with the same concepts, see
+    /// [`feedback`]. Fails if the document was not returned for the role
+    /// recently. Roles without the `feedback` stage only record the
+    /// interaction.
+    pub async fn record_feedback(&self, interaction: Interaction) -> Result<()> {
+        self.check_role_access(&interaction.role).await?;
+        let ranks_by_feedback = self
+            .config_state
+            .get_role(&interaction.role)
+            .await
+            .is_some_and(|role| !role.skip_stages.contains(&SearchStageKind::Feedback));
+        if ranks_by_feedback {
+            let concepts = feedback::query_concepts(
+                &self.config_state,
+                &interaction.role,
+                &interaction.search_term,
+            )
+            .await;
+            FeedbackStore::instance()
+                .await
+                .record(&interaction, &concepts)
+                .await?;
+        }
+        self.record_interaction(interaction).await
+    }
+
+    /// Report on all searches recorded in the analytics log
+    ///
+    /// `limit` caps the number of entries in the top and zero-result query
+    /// lists.
+    pub async fn query_analytics(&self, limit: usize) -> AnalyticsReport {
+        Analytics::instance().await.report(limit).await
+    }
+
+    /// Get the rolegraph of a role as layout-ready graph data
+    ///
+    /// If `center` is given, only the concepts matched in `center` and their
+    /// neighbours up to `depth` hops away are returned.
+    pub async fn get_graph_data(
+        &self,
+        role_name: &RoleName,
+        center: Option<&str>,
+        depth: usize,
+    ) -> Result<GraphData> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let graph_data = rolegraph.lock().await.graph_data(center, depth);
+        Ok(graph_data)
+    }
+
+    /// Concepts of a role which co-occur with the concepts matched in
+    /// `term`, e.g. for a "related topics" panel next to search results
+    ///
+    /// Returns at most `limit` concepts, the most strongly connected first,
+    /// see [`RoleGraph::related_concepts`].
+    pub async fn related_concepts(
+        &self,
+        role_name: &RoleName,
+        term: &str,
+        limit: usize,
+    ) -> Result<Vec<GraphNode>> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let related = rolegraph.lock().await.related_concepts(term, limit);
+        Ok(related)
+    }
+
+    /// Get the communities of the concepts of a role, the largest first
+    ///
+    /// Their numbers are the `community` of the nodes of
+    /// [`TerraphimService::get_graph_data`].
+    pub async fn communities(&self, role_name: &RoleName) -> Result<Vec<Community>> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let communities = rolegraph.lock().await.communities();
+        Ok(communities)
+    }
+
+    /// Export the knowledge graph of a role for ontology tools, see
+    /// [`export_thesaurus`]
+    ///
+    /// With `relations`, concepts which co-occur in the documents indexed so
+    /// far are related to each other.
+    pub async fn export_kg(
+        &self,
+        role_name: &RoleName,
+        format: ExportFormat,
+        relations: bool,
+    ) -> Result<String> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let rolegraph = rolegraph.lock().await;
+        let relations = if relations {
+            rolegraph.concept_relations()
+        } else {
+            Vec::new()
+        };
+        Ok(export_thesaurus(&rolegraph.thesaurus, &relations, format))
+    }
+
+    /// Export the concepts of a role and their co-occurrences for graph
+    /// tools, see [`RoleGraph::export`]
+    pub async fn export_graph(&self, role_name: &RoleName, format: GraphFormat) -> Result<String> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let export = rolegraph.lock().await.export(format);
+        Ok(export)
+    }
+
+    /// Validate the knowledge graph of a role, see
+    /// [`terraphim_automata::lint`]
+    ///
+    /// With a local knowledge graph, concepts without a page with a
+    /// definition in it are reported too.
+    pub async fn validate_kg(&self, role_name: &RoleName) -> Result<Vec<LintIssue>> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let thesaurus = rolegraph.lock().await.thesaurus.clone();
+        let mut issues = validate_thesaurus(&thesaurus);
+
+        let kg_path = self
+            .config_state
+            .get_role(role_name)
+            .await
+            .and_then(|role| role.kg)
+            .and_then(|kg| kg.knowledge_graph_local)
+            .map(|local| local.path);
+        if let Some(kg_path) = kg_path {
+            let defined = definitions::load(&kg_path, &thesaurus).await;
+            let mut missing: Vec<(String, u64)> = thesaurus
+                .into_iter()
+                .filter(|(_, concept)| !defined.contains_key(&concept.id))
+                .map(|(_, concept)| (concept.value.to_string(), concept.id))
+                .collect();
+            missing.sort_unstable();
+            missing.dedup();
+            issues.extend(
+                missing
+                    .into_iter()
+                    .map(|(concept, id)| LintIssue::MissingDefinition { concept, id }),
+            );
+        }
+        Ok(issues)
+    }
+
+    /// Add the links of all documents in the haystacks of a role to the
+    /// backlinks index, unless they were added before
+    ///
+    /// Later searches keep the links of the documents they find up to date.
+    async fn index_backlinks(&self, role_name: &RoleName) -> Result<()> {
+        if backlinks::is_complete(role_name) {
+            return Ok(());
+        }
+        // An empty needle matches every document of the haystacks
+        let search_query = SearchQuery {
+            role: Some(role_name.clone()),
+            ..Default::default()
+        };
+        let index =
+            terraphim_middleware::search_haystacks(self.config_state.clone(), search_query).await?;
+        backlinks::learn(role_name, index.values().map(Arc::as_ref));
+        backlinks::set_complete(role_name);
+        Ok(())
+    }
 
-                Ok(documents)
+    /// Documents of a role linking to a document or concept, see
+    /// [`backlinks`]
+    ///
+    /// `target` is the ID or URL of a document, or a title or term. If the
+    /// term is in the thesaurus of the role, links to any of the synonyms of
+    /// its concept count.
+    pub async fn backlinks(&self, role_name: &RoleName, target: &str) -> Result<Vec<Backlink>> {
+        if self.config_state.get_role(role_name).await.is_none() {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                role_name
+            )));
+        }
+        self.check_role_access(role_name).await?;
+        self.index_backlinks(role_name).await?;
+        let (mut keys, document_id) = backlinks::target_keys(role_name, target);
+        if let Some(rolegraph) = self.config_state.roles.get(role_name) {
+            let rolegraph = rolegraph.lock().await;
+            let concepts: AHashSet<u64> = (&rolegraph.thesaurus)
+                .into_iter()
+                .filter(|(term, _)| keys.contains(&backlinks::link_key(term.as_str())))
+                .map(|(_, normalized_term)| normalized_term.id)
+                .collect();
+            for (term, normalized_term) in &rolegraph.thesaurus {
+                if concepts.contains(&normalized_term.id) {
+                    keys.insert(backlinks::link_key(term.as_str()));
+                }
+            }
+        }
+        let mut found = backlinks::find(role_name, &keys, document_id.as_deref());
+        found.retain(|backlink| self.access.allows(&backlink.visibility));
+        Ok(found)
+    }
+
+    /// Documents of a role linking to or mentioning a concept, with the
+    /// number of mentions and the links of each, see [`concepts`]
+    ///
+    /// `concept` is any synonym of the concept in the thesaurus of the role.
+    /// Mentions are the matches of the synonyms of the concept found by the
+    /// rolegraph of the role in the documents of its haystacks.
+    pub async fn get_concept_backlinks(
+        &self,
+        role_name: &RoleName,
+        concept: &str,
+    ) -> Result<Vec<ConceptBacklink>> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let index = self.index_role(role_name).await?;
+        let term = NormalizedTermValue::new(concept.to_string());
+        let mentions: Vec<(&Document, usize)> = {
+            let rolegraph = rolegraph.lock().await;
+            let Some(concept_id) = rolegraph.thesaurus.get(&term).map(|term| term.id) else {
+                return Ok(Vec::new());
+            };
+            index
+                .values()
+                .map(Arc::as_ref)
+                .filter(|document| self.access.allows_document(document))
+                .map(|document| {
+                    let mentions = rolegraph.count_matches(concept_id, &document.to_string());
+                    (document, mentions)
+                })
+                .collect()
+        };
+        let links = self.backlinks(role_name, concept).await?;
+        Ok(concepts::merge_backlinks(mentions, links))
+    }
+
+    /// Link the terms of the knowledge graph of a role in a batch of
+    /// documents, see [`kg_links`]
+    ///
+    /// Uses the link options of the role, whatever its link mode. Documents
+    /// linked before, with the same thesaurus, aren't linked again.
+    pub async fn link_documents(
+        &self,
+        role_name: &RoleName,
+        mode: KgLinkMode,
+        documents: &mut [Document],
+    ) -> Result<()> {
+        self.check_role_access(role_name).await?;
+        let Some(role) = self.config_state.get_role(role_name).await else {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                role_name
+            )));
+        };
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        let options = role
+            .kg
+            .as_ref()
+            .map(|kg| kg.link_options())
+            .unwrap_or_default();
+        let definitions = role.kg.as_ref().and_then(|kg| kg.definitions_path());
+        let concurrency = enrichment::concurrency(&*self.config_state.config.lock().await);
+        let rolegraph = rolegraph.lock().await;
+        kg_links::link_documents(
+            &rolegraph,
+            mode,
+            options,
+            definitions,
+            documents,
+            concurrency,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Extract named entities from all documents in the haystacks of a role
+    /// as candidate concepts, see [`candidates`]
+    ///
+    /// Entities which are in the thesaurus of the role are left out.
+    /// Returns the pending candidates of the role, the most cited first.
+    pub async fn extract_kg_candidates(&self, role_name: &RoleName) -> Result<Vec<Candidate>> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        // An empty needle matches every document of the haystacks
+        let search_query = SearchQuery {
+            role: Some(role_name.clone()),
+            ..Default::default()
+        };
+        let index =
+            terraphim_middleware::search_haystacks(self.config_state.clone(), search_query).await?;
+        let found = {
+            let rolegraph = rolegraph.lock().await;
+            index
+                .values()
+                .flat_map(|document| candidates::find_entities(document))
+                .filter(|found| {
+                    let term = NormalizedTermValue::new(found.term.clone());
+                    rolegraph.thesaurus.get(&term).is_none()
+                })
+                .collect()
+        };
+        let queue = CandidateQueue::instance().await;
+        let added = queue.add(role_name, found).await;
+        log::info!("Found {added} new KG candidates for role `{role_name}`");
+        Ok(queue.pending(role_name).await)
+    }
+
+    /// Pending candidate concepts of a role, the most cited first
+    pub async fn kg_candidates(&self, role_name: &RoleName) -> Result<Vec<Candidate>> {
+        self.check_role_access(role_name).await?;
+        Ok(CandidateQueue::instance().await.pending(role_name).await)
+    }
+
+    /// Write a candidate concept into the local knowledge graph of its role
+    ///
+    /// Returns the promoted candidate and the path of its page, or `None`
+    /// if there is no candidate with the given ID. The cached thesaurus of
+    /// the role is dropped, so the next search picks up the new concept.
+    pub async fn promote_kg_candidate(&self, id: &str) -> Result<Option<(Candidate, PathBuf)>> {
+        let queue = CandidateQueue::instance().await;
+        let Some(candidate) = queue.get(id).await else {
+            return Ok(None);
+        };
+        self.check_role_access(&candidate.role).await?;
+        let kg_path = self
+            .config_state
+            .get_role(&candidate.role)
+            .await
+            .and_then(|role| role.kg)
+            .and_then(|kg| kg.knowledge_graph_local)
+            .map(|local| local.path);
+        let Some(kg_path) = kg_path else {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` has no local knowledge graph",
+                candidate.role
+            )));
+        };
+        let path = candidates::write_kg_page(&kg_path, &candidate).await?;
+        ThesaurusCache::instance().invalidate(&candidate.role);
+        let candidate = queue
+            .set_status(id, CandidateStatus::Promoted)
+            .await
+            .unwrap_or(candidate);
+        Ok(Some((candidate, path)))
+    }
+
+    /// Reject a candidate concept, so it isn't suggested again
+    ///
+    /// Returns the rejected candidate, or `None` if there is no candidate
+    /// with the given ID.
+    pub async fn reject_kg_candidate(&self, id: &str) -> Result<Option<Candidate>> {
+        let queue = CandidateQueue::instance().await;
+        let Some(candidate) = queue.get(id).await else {
+            return Ok(None);
+        };
+        self.check_role_access(&candidate.role).await?;
+        Ok(queue.set_status(id, CandidateStatus::Rejected).await)
+    }
+
+    /// Top-level concepts of a role with the number of documents in the
+    /// haystacks of the role tagged with each, see [`concepts`]
+    pub async fn concept_counts(&self, role_name: &RoleName) -> Result<Vec<ConceptCount>> {
+        let index = self.index_role(role_name).await?;
+        Ok(concepts::count(
+            index
+                .values()
+                .map(Arc::as_ref)
+                .filter(|document| self.access.allows_document(document)),
+        ))
+    }
+
+    /// Documents in the haystacks of a role tagged with a top-level concept,
+    /// to browse the concept without a search term
+    pub async fn browse_concept(
+        &self,
+        role_name: &RoleName,
+        concept: &str,
+    ) -> Result<Vec<Document>> {
+        let index = self.index_role(role_name).await?;
+        let documents = index
+            .into_iter()
+            .map(|(_, document)| Arc::unwrap_or_clone(document))
+            .collect();
+        let mut documents = concepts::browse(documents, concept);
+        if let Some(role) = self.config_state.get_role(role_name).await {
+            let concurrency = enrichment::concurrency(&*self.config_state.config.lock().await);
+            enrichment::enrich_documents(&mut documents, &role.haystacks, concurrency).await;
+        }
+        documents.retain(|document| self.access.allows_document(document));
+        Ok(documents)
+    }
+
+    /// Index all documents in the haystacks of a role
+    async fn index_role(&self, role_name: &RoleName) -> Result<Index> {
+        if self.config_state.get_role(role_name).await.is_none() {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                role_name
+            )));
+        }
+        // An empty needle matches every document of the haystacks
+        let search_query = SearchQuery {
+            role: Some(role_name.clone()),
+            ..Default::default()
+        };
+        Ok(terraphim_middleware::search_haystacks(self.config_state.clone(), search_query).await?)
+    }
+
+    /// Index the haystacks of all roles, for spelling corrections and
+    /// backlinks
+    ///
+    /// Returns the number of roles and documents indexed.
+    pub async fn reindex(&self) -> Result<(usize, usize)> {
+        let role_names: Vec<RoleName> = self
+            .config_state
+            .config
+            .lock()
+            .await
+            .roles
+            .keys()
+            .cloned()
+            .collect();
+        let mut documents = 0;
+        for role_name in &role_names {
+            let index = self.index_role(role_name).await?;
+            spelling::learn(role_name, &index.get_all_documents());
+            backlinks::learn(role_name, index.values().map(Arc::as_ref));
+            backlinks::set_complete(role_name);
+            documents += index.len();
+        }
+        // Backlink counts and documents found may have changed
+        ResultCache::instance().invalidate_all();
+        // The next start restores the graphs instead of indexing again
+        self.config_state.save_rolegraphs().await;
+        Ok((role_names.len(), documents))
+    }
+
+    /// Decay the co-occurrences of the rolegraphs of all roles, see
+    /// [`RoleGraph::rebuild_with_decay`], and persist the rolegraphs
+    ///
+    /// Returns the number of edges dropped.
+    pub async fn decay_edges(&self) -> usize {
+        let mut dropped = 0;
+        for rolegraph in self.config_state.roles.values() {
+            dropped += rolegraph.lock().await.rebuild_with_decay();
+        }
+        // Rankings follow the weakened co-occurrences
+        ResultCache::instance().invalidate_all();
+        self.config_state.save_rolegraphs().await;
+        dropped
+    }
+
+    /// Rebuild the thesauri of the roles ranked by their knowledge graph
+    /// and replace them in the [`ThesaurusCache`]
+    ///
+    /// Returns the number of roles.
+    pub async fn rebuild_thesauri(&mut self) -> Result<usize> {
+        let roles: Vec<Role> = self
+            .config_state
+            .config
+            .lock()
+            .await
+            .roles
+            .values()
+            .filter(|role| role.relevance_function.uses_graph())
+            .cloned()
+            .collect();
+        for role in &roles {
+            let search_query = SearchQuery {
+                role: Some(role.name.clone()),
+                ..Default::default()
+            };
+            self.build_thesaurus(&search_query).await?;
+            let thesaurus = self.load_thesaurus(&role.name).await?;
+            ThesaurusCache::instance().insert(role.name.clone(), thesaurus);
+        }
+        Ok(roles.len())
+    }
+
+    /// Create a role whose knowledge graph combines those of other roles
+    ///
+    /// The thesauri of the roles are merged in order with
+    /// [`Thesaurus::merge`], so with [`ConflictPolicy::KeepExisting`] the
+    /// first role mapping a term to a concept wins. The composite role
+    /// searches the haystacks of all roles, ranked by its knowledge graph,
+    /// and has the other settings of the first role. Its thesaurus is
+    /// persisted under its name.
+    ///
+    /// Returns the terms the roles map to different concepts.
+    pub async fn create_composite_role(
+        &mut self,
+        name: RoleName,
+        roles: &[RoleName],
+        policy: ConflictPolicy,
+    ) -> Result<Vec<ThesaurusConflict>> {
+        let Some(first) = roles.first() else {
+            return Err(ServiceError::Config(
+                "A composite role needs at least one role".into(),
+            ));
+        };
+        if self.config_state.get_role(&name).await.is_some() {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` already exists",
+                name
+            )));
+        }
+        let Some(mut composite) = self.config_state.get_role(first).await else {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                first
+            )));
+        };
+        composite.name = name.clone();
+        composite.shortname = None;
+        composite.relevance_function = RelevanceFunction::TerraphimGraph;
+
+        let mut thesaurus = Thesaurus::new(name.as_lowercase().to_string());
+        let mut conflicts = Vec::new();
+        for role_name in roles {
+            self.check_role_access(role_name).await?;
+            let role = match self.config_state.get_role(role_name).await {
+                Some(role) if role.kg.is_some() => role,
+                Some(_) => {
+                    return Err(ServiceError::Config(format!(
+                        "Role `{}` has no knowledge graph",
+                        role_name
+                    )))
+                }
+                None => {
+                    return Err(ServiceError::Config(format!(
+                        "Role `{}` not found in config",
+                        role_name
+                    )))
+                }
+            };
+            for haystack in role.haystacks {
+                if !composite.haystacks.contains(&haystack) {
+                    composite.haystacks.push(haystack);
+                }
+            }
+            let role_thesaurus = self.ensure_thesaurus_loaded(role_name).await?;
+            conflicts.extend(thesaurus.merge(&role_thesaurus, policy));
+        }
+        // The merged thesaurus is the knowledge graph of the role, not a
+        // copy of that of the first role
+        if let Some(kg) = composite.kg.as_mut() {
+            kg.automata_path = None;
+            kg.knowledge_graph_local = None;
+        }
+        log::info!(
+            "Created composite role `{}` from {} roles with {} conflicting terms",
+            name,
+            roles.len(),
+            conflicts.len()
+        );
+
+        if let Err(e) = thesaurus.save().await {
+            log::error!("Failed to save thesaurus of role `{}`: {:?}", name, e);
+        }
+        let normalization = composite.kg.as_ref().and_then(|kg| kg.normalization);
+        let centrality = composite.kg.as_ref().and_then(|kg| kg.centrality);
+        let edge_decay = composite.kg.as_ref().and_then(|kg| kg.edge_decay);
+        let rolegraph = RoleGraph::new(name.clone(), thesaurus.clone())
+            .await?
+            .with_normalization(normalization)?
+            .with_centrality_bias(centrality)
+            .with_edge_decay(edge_decay);
+        self.config_state
+            .roles
+            .insert(name.clone(), RoleGraphSync::from(rolegraph));
+        ThesaurusCache::instance().insert(name.clone(), thesaurus);
+        self.config_state
+            .config
+            .lock()
+            .await
+            .roles
+            .insert(name, composite);
+        Ok(conflicts)
+    }
+
+    /// Report on how the knowledge graph of a role covers all documents in
+    /// the haystacks of the role: how often each concept occurs, which
+    /// documents have no concept and which terms never occur, see
+    /// [`CoverageReport`]
+    pub async fn kg_coverage(&self, role_name: &RoleName) -> Result<CoverageReport> {
+        self.check_role_access(role_name).await?;
+        let Some(rolegraph) = self.config_state.roles.get(role_name) else {
+            return Err(ServiceError::Config(format!(
+                "No rolegraph found for role `{}`",
+                role_name
+            )));
+        };
+        // An empty needle matches every document of the haystacks
+        let search_query = SearchQuery {
+            role: Some(role_name.clone()),
+            ..Default::default()
+        };
+        let index =
+            terraphim_middleware::search_haystacks(self.config_state.clone(), search_query).await?;
+        let documents = index.get_all_documents();
+        let report = rolegraph
+            .lock()
+            .await
+            .coverage_report(documents.iter().map(|document| document.as_ref()));
+        Ok(report)
+    }
+
+    /// Suggestions for the search box of a role, see [`suggest`]
+    ///
+    /// Returns at most `limit` suggestions, the best first.
+    pub async fn suggest(
+        &self,
+        role_name: &RoleName,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<Suggestion>> {
+        self.suggest_in_context(role_name, query, "", limit).await
+    }
+
+    /// Suggestions for what is typed after `context`, e.g. the sentence
+    /// before the cursor
+    ///
+    /// Like [`TerraphimService::suggest`], but thesaurus terms of concepts
+    /// which co-occur in the rolegraph with the concepts of the context
+    /// are suggested first. Recent searches are only suggested in a
+    /// session, from the searches of the session.
+    pub async fn suggest_in_context(
+        &self,
+        role_name: &RoleName,
+        query: &str,
+        context: &str,
+        limit: usize,
+    ) -> Result<Vec<Suggestion>> {
+        self.check_role_access(role_name).await?;
+        let Some(role) = self.config_state.get_role(role_name).await else {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                role_name
+            )));
+        };
+        // Only the searches of the session are suggested, never those of
+        // other users
+        let history = match &self.session {
+            Some(session_id) => SessionStore::instance()
+                .await
+                .get(session_id)
+                .await
+                .queries_starting_with(query, limit),
+            None => Vec::new(),
+        };
+        if query.trim().is_empty() {
+            let suggestions = suggest::rank(history, Vec::new(), Vec::new(), usize::MAX);
+            return Ok(self.boost_accepted(role_name, suggestions, limit).await);
+        }
+
+        let related: AHashMap<u64, u64> = match self.config_state.roles.get(role_name) {
+            Some(rolegraph) if !context.trim().is_empty() => rolegraph
+                .lock()
+                .await
+                .related_concepts(context, usize::MAX)
+                .into_iter()
+                .map(|node| (node.id, node.rank))
+                .collect(),
+            _ => AHashMap::new(),
+        };
+        let candidates = if related.is_empty() {
+            limit
+        } else {
+            limit.saturating_mul(suggest::CONTEXT_CANDIDATES)
+        };
+
+        let mut autocomplete = Vec::new();
+        if role.kg.is_some() {
+            let cached = ThesaurusCache::instance()
+                .get_or_load(role_name, || self.load_thesaurus(role_name))
+                .await;
+            let compact = match cached {
+                Ok(cached) => Ok(suggest::compact_thesaurus(role_name, &cached).await),
+                Err(e) => Err(e),
+            };
+            match compact {
+                Ok(Ok(compact)) => {
+                    let mut terms: Vec<(String, u64)> = compact
+                        .autocomplete(query, candidates)
+                        .into_iter()
+                        .map(|(term, concept)| (term, concept.id))
+                        .collect();
+                    // Phrases typed in another word order than that of the term
+                    for (term, concept) in compact.phrase_autocomplete(query, candidates) {
+                        if terms.len() >= candidates {
+                            break;
+                        }
+                        if !terms.iter().any(|(known, _)| *known == term) {
+                            terms.push((term, concept.id));
+                        }
+                    }
+                    if terms.is_empty() {
+                        // Probably a typo early on, which prefix search can't get past
+                        terms = compact
+                            .fuzzy_autocomplete(query, candidates)
+                            .into_iter()
+                            .map(|(term, concept, _)| (term, concept.id))
+                            .collect();
+                    }
+                    autocomplete = suggest::boost_related(terms, &related, limit);
+                }
+                Ok(Err(e)) => log::warn!("Failed to build compact thesaurus: {e}"),
+                Err(e) => log::warn!("Failed to load thesaurus for suggestions: {e}"),
             }
         }
+        let concepts = match self.config_state.roles.get(role_name) {
+            Some(rolegraph) => rolegraph.lock().await.related_concepts(query, limit),
+            None => Vec::new(),
+        };
+
+        let suggestions = suggest::rank(history, autocomplete, concepts, usize::MAX);
+        Ok(self.boost_accepted(role_name, suggestions, limit).await)
+    }
+
+    /// The best `limit` suggestions after scaling them by how often they
+    /// were accepted, see [`acceptance`]
+    async fn boost_accepted(
+        &self,
+        role_name: &RoleName,
+        mut suggestions: Vec<Suggestion>,
+        limit: usize,
+    ) -> Vec<Suggestion> {
+        AcceptanceStore::instance()
+            .await
+            .boost(role_name, &mut suggestions)
+            .await;
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    /// Record that a suggestion for the search box of a role was accepted,
+    /// which re-ranks later suggestions of the role, see [`acceptance`]
+    ///
+    /// Only terms of the role thesaurus are counted; the acceptance is
+    /// ignored if the accepted term is not one of them.
+    pub async fn accept_suggestion(
+        &self,
+        role_name: &RoleName,
+        mut acceptance: SuggestionAcceptance,
+    ) -> Result<()> {
+        self.check_role_access(role_name).await?;
+        if acceptance.accepted.trim().is_empty() {
+            return Err(ServiceError::Config(
+                "No accepted suggestion given".to_string(),
+            ));
+        }
+        let Some(role) = self.config_state.get_role(role_name).await else {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                role_name
+            )));
+        };
+        if role.kg.is_none() {
+            return Ok(());
+        }
+        let cached = ThesaurusCache::instance()
+            .get_or_load(role_name, || self.load_thesaurus(role_name))
+            .await?;
+        if !acceptance.restrict_to(&cached.thesaurus) {
+            log::debug!(
+                "Ignoring accepted suggestion `{}` of role `{}`, not in its thesaurus",
+                acceptance.accepted,
+                role_name
+            );
+            return Ok(());
+        }
+        AcceptanceStore::instance()
+            .await
+            .record(role_name, &acceptance)
+            .await;
+        Ok(())
+    }
+
+    /// The thesaurus of a role as a serialized compact thesaurus, for
+    /// autocomplete and matching in clients
+    ///
+    /// See [`terraphim_automata::CompactThesaurus::to_bytes`] for the format.
+    pub async fn automata(&self, role_name: &RoleName) -> Result<Vec<u8>> {
+        self.check_role_access(role_name).await?;
+        let Some(role) = self.config_state.get_role(role_name).await else {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` not found in config",
+                role_name
+            )));
+        };
+        if role.kg.is_none() {
+            return Err(ServiceError::Config(format!(
+                "Role `{}` has no knowledge graph",
+                role_name
+            )));
+        }
+        let cached = ThesaurusCache::instance()
+            .get_or_load(role_name, || self.load_thesaurus(role_name))
+            .await?;
+        suggest::compact_thesaurus(role_name, &cached)
+            .await
+            .and_then(|compact| compact.to_bytes())
+            .map_err(|e| ServiceError::Config(format!("Failed to serialize thesaurus: {e}")))
+    }
+
+    /// Save a search to be re-run on a schedule, see [`alerts`]
+    ///
+    /// A query without role runs as the default role. Returns the saved
+    /// search with its ID.
+    pub async fn save_search(&self, mut search: SavedSearch) -> Result<SavedSearch> {
+        let role = self.get_search_role(&search.query).await?;
+        search.query.role = Some(role.name);
+        Ok(SavedSearchStore::instance().await.save(search).await)
+    }
+
+    /// Saved searches of a role, or of all roles
+    pub async fn saved_searches(&self, role_name: Option<&RoleName>) -> Vec<SavedSearch> {
+        SavedSearchStore::instance().await.list(role_name).await
+    }
+
+    /// Run a saved search now
+    ///
+    /// Returns `None` if there is no saved search with the given ID. The
+    /// results are those of a search with the query of the saved search;
+    /// the run doesn't count as a scheduled run, so it sends no alert.
+    pub async fn run_saved_search(&mut self, id: &str) -> Result<Option<Vec<Document>>> {
+        let Some(search) = SavedSearchStore::instance().await.get(id).await else {
+            return Ok(None);
+        };
+        self.search(&search.query).await.map(Some)
+    }
+
+    /// Delete a saved search
+    ///
+    /// Returns `false` if there is no saved search with the given ID.
+    pub async fn delete_saved_search(&self, id: &str) -> bool {
+        SavedSearchStore::instance().await.delete(id).await
+    }
+
+    /// The session of the service
+    ///
+    /// Fails if the service has no session.
+    pub async fn session(&self) -> Result<UserSession> {
+        let session_id = self.session_id()?;
+        Ok(SessionStore::instance().await.get(session_id).await)
+    }
+
+    /// Select the role of searches without a role in the session of the
+    /// service
+    ///
+    /// `None` goes back to the default role of the config. Returns the
+    /// updated session.
+    pub async fn select_role(&self, role_name: Option<RoleName>) -> Result<UserSession> {
+        let session_id = self.session_id()?;
+        if let Some(role_name) = &role_name {
+            if self.config_state.get_role(role_name).await.is_none() {
+                return Err(ServiceError::Config(format!(
+                    "Role `{}` not found in config",
+                    role_name
+                )));
+            }
+            self.check_role_access(role_name).await?;
+        }
+        Ok(SessionStore::instance()
+            .await
+            .update(session_id, |session| session.selected_role = role_name)
+            .await)
+    }
+
+    /// Merge preferences into those of the session of the service, see
+    /// [`UserSession::update_preferences`]
+    ///
+    /// Returns the updated session.
+    pub async fn update_preferences(
+        &self,
+        preferences: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<UserSession> {
+        let session_id = self.session_id()?;
+        Ok(SessionStore::instance()
+            .await
+            .update(session_id, |session| {
+                session.update_preferences(preferences)
+            })
+            .await)
+    }
+
+    fn session_id(&self) -> Result<&str> {
+        self.session.as_deref().ok_or(ServiceError::NoSession)
+    }
+
+    /// Maintenance jobs with their schedule and last run, see [`jobs`]
+    pub async fn jobs(&self) -> Vec<JobStatus> {
+        let schedule = jobs::schedule(&*self.config_state.config.lock().await);
+        JobRunner::instance().await.status(&schedule).await
+    }
+
+    /// Runs of a maintenance job, the most recent first
+    pub async fn job_runs(&self, job: JobKind) -> Vec<JobRun> {
+        JobRunner::instance().await.runs(job).await
+    }
+
+    /// Run a maintenance job now
+    ///
+    /// Returns `None` if the job is running already.
+    pub async fn run_job(&self, job: JobKind) -> Option<JobRun> {
+        JobRunner::instance()
+            .await
+            .run(&self.config_state, job, Trigger::Manual)
+            .await
     }
 
     /// Fetch the current config
@@ -176,13 +1789,18 @@ impl<'a> TerraphimService {
     /// Update the config
     ///
     /// Overwrites the config in the config state and returns the updated
-    /// config.
+    /// config. Cached thesauri and search results are dropped, and the
+    /// thesaurus sources of the new config are watched instead.
     pub async fn update_config(
         &self,
         config: terraphim_config::Config,
     ) -> Result<terraphim_config::Config> {
         let mut current_config = self.config_state.config.lock().await;
         *current_config = config.clone();
+        // Knowledge graphs and haystacks of roles may have changed
+        ThesaurusCache::instance().invalidate_all();
+        ResultCache::instance().invalidate_all();
+        thesaurus_cache::rewatch_sources(&config);
         Ok(config)
     }
 }
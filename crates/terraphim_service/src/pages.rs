@@ -0,0 +1,204 @@
+//! Paging through search results with cursors
+//!
+//! `skip` and `limit` of a [`SearchQuery`] page by searching all haystacks
+//! and ranking all documents again for every page, so pages shift when the
+//! haystacks change in between. A search by pages instead keeps the ranked
+//! results of its first page as a snapshot; a [`Cursor`] points into the
+//! snapshot, so the following pages are sliced from it without searching
+//! again.
+//!
+//! Snapshots expire [`SNAPSHOT_TTL`] after the first page, and at most
+//! [`MAX_SNAPSHOTS`] are kept, dropping the oldest first. A cursor into an
+//! expired snapshot is rejected; the client starts over from the first page.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use terraphim_types::{Document, FacetCounts, SearchQuery};
+use ulid::Ulid;
+
+use crate::facets;
+
+/// How long the results of a search stay available for its next pages
+pub const SNAPSHOT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Number of snapshots kept at most
+pub const MAX_SNAPSHOTS: usize = 100;
+
+/// Number of documents per page if the query has no limit
+pub const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// Position in the results of a search by pages
+///
+/// Serialized as an opaque string token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    snapshot: Ulid,
+    offset: usize,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.snapshot, self.offset)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid cursor `{s}`");
+        let (snapshot, offset) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Cursor {
+            snapshot: Ulid::from_string(snapshot).map_err(|_| invalid())?,
+            offset: offset.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        cursor.to_string()
+    }
+}
+
+/// A page of the results of a search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub documents: Vec<Document>,
+    /// Number of documents found by the search, on all pages
+    pub total: usize,
+    /// Cursor of the next page; `None` on the last page
+    pub next_cursor: Option<Cursor>,
+    /// Facet counts of the documents on all pages
+    pub facets: FacetCounts,
+}
+
+/// The ranked results of the first page of a search
+struct Snapshot {
+    query: SearchQuery,
+    documents: Arc<Vec<Document>>,
+    created: Instant,
+}
+
+/// The snapshots of the searches by pages of this process
+#[derive(Default)]
+pub struct Snapshots {
+    snapshots: Mutex<AHashMap<Ulid, Snapshot>>,
+}
+
+impl Snapshots {
+    /// The snapshots of this process
+    pub fn instance() -> &'static Snapshots {
+        static SNAPSHOTS: OnceLock<Snapshots> = OnceLock::new();
+        SNAPSHOTS.get_or_init(Snapshots::default)
+    }
+
+    /// Keep the results of a search; returns the cursor of their start
+    pub(crate) fn insert(&self, query: &SearchQuery, documents: Vec<Document>) -> Cursor {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.retain(|_, snapshot| snapshot.created.elapsed() < SNAPSHOT_TTL);
+        while snapshots.len() >= MAX_SNAPSHOTS {
+            let Some(oldest) = snapshots
+                .iter()
+                .min_by_key(|(_, snapshot)| snapshot.created)
+                .map(|(id, _)| *id)
+            else {
+                break;
+            };
+            snapshots.remove(&oldest);
+        }
+        let id = Ulid::new();
+        snapshots.insert(
+            id,
+            Snapshot {
+                query: query.clone(),
+                documents: Arc::new(documents),
+                created: Instant::now(),
+            },
+        );
+        Cursor {
+            snapshot: id,
+            offset: 0,
+        }
+    }
+
+    /// The results a cursor points into, if they haven't expired and are
+    /// the results of the same search
+    pub(crate) fn get(&self, cursor: &Cursor, query: &SearchQuery) -> Option<Arc<Vec<Document>>> {
+        let snapshots = self.snapshots.lock().unwrap();
+        let snapshot = snapshots.get(&cursor.snapshot)?;
+        (snapshot.query == *query && snapshot.created.elapsed() < SNAPSHOT_TTL)
+            .then(|| snapshot.documents.clone())
+    }
+}
+
+/// The page of the results starting at the cursor
+pub(crate) fn page(documents: &[Document], cursor: Cursor, page_size: usize) -> SearchPage {
+    let start = cursor.offset.min(documents.len());
+    let end = start.saturating_add(page_size).min(documents.len());
+    SearchPage {
+        documents: documents[start..end].to_vec(),
+        total: documents.len(),
+        facets: facets::count(documents),
+        next_cursor: (end < documents.len()).then_some(Cursor {
+            offset: end,
+            ..cursor
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: usize) -> Document {
+        Document {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pages() {
+        let query = SearchQuery {
+            search_term: "rust".into(),
+            ..Default::default()
+        };
+        let documents: Vec<Document> = (0..5).map(document).collect();
+        let cursor = Snapshots::instance().insert(&query, documents);
+        let token = cursor.to_string();
+        assert_eq!(token.parse::<Cursor>(), Ok(cursor));
+        assert!("nonsense".parse::<Cursor>().is_err());
+
+        let mut ids = Vec::new();
+        let mut next = Some(cursor);
+        while let Some(cursor) = next {
+            let documents = Snapshots::instance().get(&cursor, &query).unwrap();
+            let page = page(&documents, cursor, 2);
+            assert_eq!(page.total, 5);
+            ids.extend(page.documents.into_iter().map(|document| document.id));
+            next = page.next_cursor;
+        }
+        assert_eq!(ids, vec!["0", "1", "2", "3", "4"]);
+
+        let other_query = SearchQuery {
+            search_term: "tokio".into(),
+            ..Default::default()
+        };
+        assert!(Snapshots::instance().get(&cursor, &other_query).is_none());
+    }
+}
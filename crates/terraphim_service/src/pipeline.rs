@@ -0,0 +1,616 @@
+//! The stages a search runs on the documents it ranked
+//!
+//! Ranking turns the index of the documents found in the haystacks into
+//! documents ordered by the relevance function of the role. A
+//! [`SearchPipeline`] takes it from there: each [`SearchStage`] filters,
+//! completes, reorders or annotates the ranked documents in turn, and is
+//! timed as a stage of the search.
+//!
+//! [`SearchPipeline::for_role`] is the pipeline of every search, without the
+//! optional stages the role leaves out (see [`Role::skip_stages`]).
+
+use std::cmp::Reverse;
+
+use async_trait::async_trait;
+use terraphim_config::{Access, ConfigState, Role, SearchStageKind};
+use terraphim_types::{Document, QueryType, RelevanceFunction, SearchQuery, SortBy};
+
+use crate::analytics::{now_millis, StageTimer};
+use crate::feedback::{self, FeedbackStore};
+use crate::score::{mmr, proximity, recency};
+use crate::thesaurus_cache::ThesaurusCache;
+use crate::{backlinks, dedup, enrichment, exclusion, highlight, kg_links, query, snippet};
+
+/// What the stages of a search see besides its documents
+pub struct SearchContext<'a> {
+    pub role: &'a Role,
+    pub query: &'a SearchQuery,
+    /// What the searcher may see
+    pub access: &'a Access,
+    pub config_state: &'a ConfigState,
+}
+
+/// A step of a search on its ranked documents
+#[async_trait]
+pub trait SearchStage: Send + Sync {
+    /// Name of the stage in the timings of a search
+    fn name(&self) -> &'static str;
+
+    /// The kind of the stage if roles can leave it out, `None` if the stage
+    /// always runs
+    fn kind(&self) -> Option<SearchStageKind> {
+        None
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>);
+}
+
+/// Stages run one after the other on the ranked documents of a search
+#[derive(Default)]
+pub struct SearchPipeline {
+    stages: Vec<Box<dyn SearchStage>>,
+}
+
+impl SearchPipeline {
+    /// A pipeline without stages
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a stage after the stages added so far
+    pub fn stage(mut self, stage: impl SearchStage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// All stages of a search
+    ///
+    /// Documents are filtered by their metadata before they are completed
+    /// with their persisted copies, which is the expensive part, and by
+    /// everything else once they are complete.
+    pub fn standard() -> Self {
+        Self::new()
+            .stage(Dedup)
+            .stage(MetadataFilter)
+            .stage(Enrichment)
+            .stage(AccessFilter)
+            .stage(QueryFilter)
+            .stage(Proximity)
+            .stage(Backlinks)
+            .stage(Feedback)
+            .stage(Recency)
+            .stage(Diversity)
+            .stage(Order)
+            .stage(KgLinks)
+            .stage(Highlighting)
+            .stage(Snippets)
+            .stage(OmitBody)
+    }
+
+    /// The stages of a search with a role
+    pub fn for_role(role: &Role) -> Self {
+        Self::standard().without(&role.skip_stages)
+    }
+
+    /// Leave out the stages of the given kinds
+    pub fn without(mut self, kinds: &[SearchStageKind]) -> Self {
+        self.stages
+            .retain(|stage| stage.kind().is_none_or(|kind| !kinds.contains(&kind)));
+        self
+    }
+
+    /// Names of the stages, in order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// Run all stages on the documents, timing each of them
+    pub async fn run(
+        &self,
+        context: &SearchContext<'_>,
+        documents: &mut Vec<Document>,
+        timer: &mut StageTimer,
+    ) {
+        for stage in &self.stages {
+            stage.run(context, documents).await;
+            timer.stage(stage.name());
+        }
+    }
+}
+
+/// Merge documents with the same content, see [`dedup`]
+pub struct Dedup;
+
+#[async_trait]
+impl SearchStage for Dedup {
+    fn name(&self) -> &'static str {
+        "dedup"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Dedup)
+    }
+
+    async fn run(&self, _context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        dedup::dedup(documents);
+    }
+}
+
+/// Keep the documents in the language and with the metadata of the query
+pub struct MetadataFilter;
+
+#[async_trait]
+impl SearchStage for MetadataFilter {
+    fn name(&self) -> &'static str {
+        "metadata_filter"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        let search_query = context.query;
+        if let Some(language) = &search_query.language {
+            documents.retain(|document| {
+                document
+                    .language
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(language))
+            });
+        }
+        if !search_query.metadata.is_empty() {
+            documents.retain(|document| {
+                search_query
+                    .metadata
+                    .iter()
+                    .all(|filter| filter.matches(&document.extra))
+            });
+        }
+    }
+}
+
+/// Complete documents with their persisted copies, see [`enrichment`]
+pub struct Enrichment;
+
+#[async_trait]
+impl SearchStage for Enrichment {
+    fn name(&self) -> &'static str {
+        "enrichment"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Enrichment)
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        let concurrency = enrichment::concurrency(&*context.config_state.config.lock().await);
+        enrichment::enrich_documents(documents, &context.role.haystacks, concurrency).await;
+    }
+}
+
+/// Keep the documents the searcher may see
+///
+/// Persisted copies may carry visibility labels, so documents are filtered
+/// once they are complete.
+pub struct AccessFilter;
+
+#[async_trait]
+impl SearchStage for AccessFilter {
+    fn name(&self) -> &'static str {
+        "access"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        documents.retain(|document| context.access.allows_document(document));
+    }
+}
+
+/// Keep the documents in the facets and time range of the query, which
+/// match its term expression or regex and have none of its excluded terms
+///
+/// Persisted copies may carry more tags, so documents are filtered once
+/// they are complete.
+pub struct QueryFilter;
+
+#[async_trait]
+impl SearchStage for QueryFilter {
+    fn name(&self) -> &'static str {
+        "query_filter"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        let search_query = context.query;
+        if !search_query.facets.is_empty() {
+            documents.retain(|document| search_query.facets.matches(document));
+        }
+        documents.retain(|document| search_query.in_time_range(document));
+        if let Some(terms) = &search_query.terms {
+            documents.retain(|document| query::matches(terms, document));
+        }
+        if search_query.query_type == QueryType::Regex {
+            // The pattern was checked before searching
+            if let Ok(regex) = query::regex(search_query.search_term.as_str()) {
+                documents.retain(|document| {
+                    regex.is_match(&document.title) || regex.is_match(&document.body)
+                });
+            }
+        }
+        if !search_query.exclude_terms.is_empty() {
+            // Only roles ranked by the knowledge graph have a thesaurus to
+            // expand excluded terms to their synonyms
+            let thesaurus = match context.role.relevance_function {
+                RelevanceFunction::TerraphimGraph | RelevanceFunction::Hybrid => {
+                    ThesaurusCache::instance().get(&context.role.name)
+                }
+                RelevanceFunction::TitleScorer | RelevanceFunction::BM25 => None,
+            };
+            let terms = exclusion::expand(
+                &search_query.exclude_terms,
+                thesaurus.as_ref().map(|cached| cached.thesaurus.as_ref()),
+            );
+            documents.retain(|document| !exclusion::contains_any(document, &terms));
+        }
+    }
+}
+
+/// Boost the ranks of documents with the terms of an `AND` query close
+/// together, see [`proximity`]
+pub struct Proximity;
+
+#[async_trait]
+impl SearchStage for Proximity {
+    fn name(&self) -> &'static str {
+        "proximity"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Proximity)
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        if let Some(terms) = &context.query.terms {
+            proximity::boost(documents, terms);
+        }
+    }
+}
+
+/// Count the backlinks of documents and boost the ranks of documents with
+/// many of them if the query asks for it, see [`backlinks`]
+pub struct Backlinks;
+
+#[async_trait]
+impl SearchStage for Backlinks {
+    fn name(&self) -> &'static str {
+        "backlinks"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Backlinks)
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        backlinks::count(&context.role.name, documents, context.access);
+        if context.query.boost_backlinks {
+            backlinks::boost(documents);
+        }
+    }
+}
+
+/// Boost the ranks of documents users engaged with, see [`feedback`]
+pub struct Feedback;
+
+#[async_trait]
+impl SearchStage for Feedback {
+    fn name(&self) -> &'static str {
+        "feedback"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Feedback)
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        let concepts = feedback::query_concepts(
+            context.config_state,
+            &context.role.name,
+            context.query.search_term.as_str(),
+        )
+        .await;
+        FeedbackStore::instance()
+            .await
+            .boost(&context.role.name, documents, &concepts)
+            .await;
+    }
+}
+
+/// Decay the ranks of documents by their age if the role sets a half-life,
+/// see [`recency`]
+pub struct Recency;
+
+#[async_trait]
+impl SearchStage for Recency {
+    fn name(&self) -> &'static str {
+        "recency"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        let half_life_days = context
+            .role
+            .scoring
+            .and_then(|scoring| scoring.recency_half_life_days);
+        if let Some(half_life_days) = half_life_days {
+            recency::boost(documents, half_life_days, now_millis());
+        }
+    }
+}
+
+/// Diversify the top results if the query sets a trade-off between
+/// relevance and diversity, see [`mmr`]
+///
+/// Results ordered by time aren't diversified.
+pub struct Diversity;
+
+#[async_trait]
+impl SearchStage for Diversity {
+    fn name(&self) -> &'static str {
+        "diversity"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        if context.query.sort_by != SortBy::Relevance {
+            return;
+        }
+        if let Some(lambda) = context.query.mmr_lambda {
+            mmr::diversify(documents, lambda);
+        }
+    }
+}
+
+/// Order documents as the query asks for
+///
+/// Documents without timestamps go last, in order of relevance.
+pub struct Order;
+
+#[async_trait]
+impl SearchStage for Order {
+    fn name(&self) -> &'static str {
+        "order"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        match context.query.sort_by {
+            SortBy::Relevance => {}
+            SortBy::Modified => documents.sort_by_key(|document| Reverse(document.last_changed())),
+            SortBy::Created => documents.sort_by_key(|document| Reverse(document.created)),
+        }
+    }
+}
+
+/// Link the terms of the knowledge graph of the role in the bodies of
+/// documents if the role sets a link mode, see
+/// [`terraphim_config::KgLinkMode`] and [`kg_links`]
+///
+/// Runs before highlighting, so the highlights and snippets of inline links
+/// are those of the linked body.
+pub struct KgLinks;
+
+#[async_trait]
+impl SearchStage for KgLinks {
+    fn name(&self) -> &'static str {
+        "kg_links"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        let Some(kg) = context.role.kg.as_ref() else {
+            return;
+        };
+        let Some(mode) = kg.link_mode else {
+            return;
+        };
+        let Some(rolegraph) = context.config_state.roles.get(&context.role.name) else {
+            return;
+        };
+        let concurrency = enrichment::concurrency(&*context.config_state.config.lock().await);
+        let rolegraph = rolegraph.lock().await;
+        let linked = kg_links::link_documents(
+            &rolegraph,
+            mode,
+            kg.link_options(),
+            kg.definitions_path(),
+            documents,
+            concurrency,
+        )
+        .await;
+        if let Err(e) = linked {
+            log::warn!(
+                "Failed to link the concepts of role `{}`: {:?}",
+                context.role.name,
+                e
+            );
+        }
+    }
+}
+
+/// Add the matches of the search term to documents
+///
+/// Only roles ranked by the knowledge graph highlight concepts. The matches
+/// of regex queries are those of their pattern.
+pub struct Highlighting;
+
+#[async_trait]
+impl SearchStage for Highlighting {
+    fn name(&self) -> &'static str {
+        "highlighting"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Highlighting)
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        if context.query.query_type == QueryType::Regex {
+            if let Ok(regex) = query::regex(context.query.search_term.as_str()) {
+                highlight::highlight_regex(documents, &regex);
+            }
+            return;
+        }
+        let rolegraph = match context.role.relevance_function {
+            RelevanceFunction::TerraphimGraph | RelevanceFunction::Hybrid => {
+                context.config_state.roles.get(&context.role.name)
+            }
+            RelevanceFunction::TitleScorer | RelevanceFunction::BM25 => None,
+        };
+        let rolegraph = match rolegraph {
+            Some(rolegraph) => Some(rolegraph.lock().await),
+            None => None,
+        };
+        highlight::highlight_documents(
+            documents,
+            context.query.search_term.as_str(),
+            rolegraph.as_deref(),
+        );
+    }
+}
+
+/// Add snippets around the highlights of documents
+pub struct Snippets;
+
+#[async_trait]
+impl SearchStage for Snippets {
+    fn name(&self) -> &'static str {
+        "snippets"
+    }
+
+    fn kind(&self) -> Option<SearchStageKind> {
+        Some(SearchStageKind::Snippets)
+    }
+
+    async fn run(&self, _context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        snippet::add_snippets(documents);
+    }
+}
+
+/// Clear the bodies of documents if the query asks for it
+pub struct OmitBody;
+
+#[async_trait]
+impl SearchStage for OmitBody {
+    fn name(&self) -> &'static str {
+        "omit_body"
+    }
+
+    async fn run(&self, context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+        if context.query.omit_body {
+            for document in documents.iter_mut() {
+                document.body.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ahash::AHashMap;
+    use terraphim_config::Config;
+    use terraphim_types::RoleName;
+    use tokio::sync::Mutex;
+
+    use super::*;
+
+    /// Keeps the first document
+    struct First;
+
+    #[async_trait]
+    impl SearchStage for First {
+        fn name(&self) -> &'static str {
+            "first"
+        }
+
+        async fn run(&self, _context: &SearchContext<'_>, documents: &mut Vec<Document>) {
+            documents.truncate(1);
+        }
+    }
+
+    fn document(id: &str, body: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            title: id.to_string(),
+            body: body.to_string(),
+            content_hash: Some(body.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let role = Role {
+            shortname: None,
+            name: RoleName::new("Engineer"),
+            relevance_function: RelevanceFunction::TitleScorer,
+            theme: "lumen".to_string(),
+            kg: None,
+            haystacks: Vec::new(),
+            metadata_schema: Vec::new(),
+            skip_stages: vec![
+                SearchStageKind::Enrichment,
+                SearchStageKind::Backlinks,
+                SearchStageKind::Feedback,
+            ],
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
+            extra: AHashMap::new(),
+        };
+        let pipeline = SearchPipeline::for_role(&role);
+        assert_eq!(
+            pipeline.names(),
+            vec![
+                "dedup",
+                "metadata_filter",
+                "access",
+                "query_filter",
+                "proximity",
+                "recency",
+                "diversity",
+                "order",
+                "kg_links",
+                "highlighting",
+                "snippets",
+                "omit_body"
+            ]
+        );
+
+        let config_state = ConfigState {
+            config: Arc::new(Mutex::new(Config::default())),
+            roles: AHashMap::new(),
+        };
+        let search_query = SearchQuery {
+            search_term: "rust".into(),
+            omit_body: true,
+            ..Default::default()
+        };
+        let context = SearchContext {
+            role: &role,
+            query: &search_query,
+            access: &Access::All,
+            config_state: &config_state,
+        };
+        let mut documents = vec![
+            document("a", "Rust and Tokio"),
+            document("b", "Rust and Tokio"),
+            document("c", "Rust without Tokio"),
+        ];
+        let mut timer = StageTimer::new();
+        pipeline.run(&context, &mut documents, &mut timer).await;
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "c"]);
+        assert!(documents.iter().all(|d| d.body.is_empty()));
+        assert!(!documents[0].snippets.is_empty());
+
+        let mut documents = vec![document("a", "Rust"), document("b", "Tokio")];
+        SearchPipeline::new()
+            .stage(First)
+            .run(&context, &mut documents, &mut timer)
+            .await;
+        assert_eq!(documents.len(), 1);
+    }
+}
@@ -0,0 +1,353 @@
+//! Search profiling against a synthetic corpus
+//!
+//! Generates a deterministic corpus of Markdown documents together with a
+//! matching thesaurus, runs a canned set of queries against it for every
+//! relevance function and aggregates the per-stage timings which
+//! `TerraphimService` records for each search. This is used by the
+//! `search` benchmark and the `--profile-search` mode of the server.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use terraphim_automata::AutomataPath;
+use terraphim_config::{
+    Config, ConfigBuilder, ConfigState, Freshness, Haystack, KnowledgeGraph, Role, ServiceType,
+};
+use terraphim_types::{
+    NormalizedTerm, NormalizedTermValue, RelevanceFunction, RoleName, SearchQuery, Thesaurus,
+};
+
+use crate::analytics::percentile;
+use crate::{Result, ServiceError, TerraphimService};
+
+/// Role which ranks the synthetic corpus with the title scorer
+pub const PROFILE_TITLE_ROLE: &str = "Profile Title Scorer";
+
+/// Role which ranks the synthetic corpus with the knowledge graph
+pub const PROFILE_GRAPH_ROLE: &str = "Profile Terraphim Graph";
+
+/// Concepts of the synthetic thesaurus with one synonym each
+const CONCEPTS: &[(&str, &str)] = &[
+    ("knowledge graph", "kg"),
+    ("search engine", "retrieval system"),
+    ("life cycle", "lifecycle"),
+    ("system operator", "sysop"),
+    ("project planning", "project plan"),
+    ("requirements engineering", "requirements analysis"),
+    ("risk management", "risk assessment"),
+    ("configuration management", "config management"),
+    ("quality assurance", "qa"),
+    ("trained operators", "operator training"),
+    ("maintenance", "maintainers"),
+    ("acceptance testing", "acceptance test"),
+    ("interface control", "interface management"),
+    ("verification", "verification plan"),
+    ("validation", "validation plan"),
+    ("stakeholder needs", "stakeholder requirements"),
+    ("system architecture", "architecture design"),
+    ("trade study", "trade-off analysis"),
+    ("technical review", "design review"),
+    ("supply chain", "procurement"),
+];
+
+/// Words used to pad the documents between concepts
+const FILLER: &[&str] = &[
+    "the", "a", "of", "and", "to", "in", "for", "with", "on", "by", "process", "team", "report",
+    "data", "model", "phase", "review", "input", "output", "result", "change", "plan", "work",
+    "time", "cost", "scope", "level", "support", "activity", "control",
+];
+
+/// Queries run by the profiler; the last one intentionally matches nothing
+pub const CANNED_QUERIES: &[&str] = &[
+    "knowledge graph",
+    "life cycle",
+    "trained operators",
+    "risk management",
+    "system architecture trade study",
+    "quality assurance acceptance testing",
+    "maintenance",
+    "nonexistentterm",
+];
+
+/// Deterministic pseudo-random numbers (xorshift), so corpora are
+/// reproducible between runs
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[(self.next() % items.len() as u64) as usize]
+    }
+}
+
+/// Build the thesaurus matching the synthetic corpus
+pub fn synthetic_thesaurus() -> Thesaurus {
+    let mut thesaurus = Thesaurus::new("profile".to_string());
+    for (id, (concept, synonym)) in CONCEPTS.iter().enumerate() {
+        let nterm = NormalizedTerm::new(id as u64 + 1, NormalizedTermValue::from(*concept));
+        thesaurus.insert(NormalizedTermValue::from(*concept), nterm.clone());
+        thesaurus.insert(NormalizedTermValue::from(*synonym), nterm);
+    }
+    thesaurus
+}
+
+/// Write `documents` Markdown documents and the thesaurus into `dir`
+///
+/// Documents are written to `dir/haystack`; the path of the thesaurus is
+/// returned.
+pub async fn write_synthetic_corpus(dir: &Path, documents: usize) -> Result<PathBuf> {
+    let haystack = dir.join("haystack");
+    tokio::fs::create_dir_all(&haystack).await?;
+
+    let thesaurus_path = dir.join("thesaurus.json");
+    let thesaurus = serde_json::to_string(&synthetic_thesaurus())
+        .map_err(|e| ServiceError::Config(e.to_string()))?;
+    tokio::fs::write(&thesaurus_path, thesaurus).await?;
+
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let terms: Vec<&str> = CONCEPTS
+        .iter()
+        .flat_map(|(concept, synonym)| [*concept, *synonym])
+        .collect();
+    for i in 0..documents {
+        let mut body = format!("# Document {i}: {}\n\n", rng.pick(&terms));
+        for _paragraph in 0..3 {
+            for word in 0..60 {
+                if word > 0 {
+                    body.push(' ');
+                }
+                // Roughly every tenth word is a concept
+                if rng.next().is_multiple_of(10) {
+                    body.push_str(rng.pick(&terms));
+                } else {
+                    body.push_str(rng.pick(FILLER));
+                }
+            }
+            body.push_str(".\n\n");
+        }
+        tokio::fs::write(haystack.join(format!("document_{i}.md")), body).await?;
+    }
+    Ok(thesaurus_path)
+}
+
+/// Config with one role per relevance function, searching the synthetic
+/// corpus in `dir`
+pub fn profile_config(dir: &Path, thesaurus_path: &Path) -> Config {
+    let haystacks = vec![Haystack {
+        path: dir.join("haystack"),
+        service: ServiceType::Ripgrep,
+        freshness: Freshness::default(),
+        visibility: Vec::new(),
+    }];
+    let role =
+        |name: &str, relevance_function: RelevanceFunction, kg: Option<KnowledgeGraph>| Role {
+            shortname: None,
+            name: name.into(),
+            relevance_function,
+            theme: "spacelab".to_string(),
+            kg,
+            haystacks: haystacks.clone(),
+            metadata_schema: Vec::new(),
+            skip_stages: Vec::new(),
+            scorer: None,
+            hybrid_weights: None,
+            scoring: None,
+            extra: AHashMap::new(),
+        };
+    ConfigBuilder::new()
+        .add_role(
+            PROFILE_TITLE_ROLE,
+            role(PROFILE_TITLE_ROLE, RelevanceFunction::TitleScorer, None),
+        )
+        .add_role(
+            PROFILE_GRAPH_ROLE,
+            role(
+                PROFILE_GRAPH_ROLE,
+                RelevanceFunction::TerraphimGraph,
+                Some(KnowledgeGraph {
+                    automata_path: Some(AutomataPath::from_local(thesaurus_path)),
+                    knowledge_graph_local: None,
+                    public: false,
+                    publish: false,
+                    include_terms: Vec::new(),
+                    exclude_terms: Vec::new(),
+                    min_term_length: 0,
+                    max_kg_terms: None,
+                    max_links_per_concept: None,
+                    max_links: None,
+                    link_mode: None,
+                    link_template: None,
+                    link_definitions: false,
+                    normalization: None,
+                    centrality: None,
+                    edge_decay: None,
+                }),
+            ),
+        )
+        .build()
+        .expect("Profile config is valid")
+}
+
+/// Write a synthetic corpus into `dir` and create the config state for it
+pub async fn profile_config_state(dir: &Path, documents: usize) -> Result<ConfigState> {
+    let thesaurus_path = write_synthetic_corpus(dir, documents).await?;
+    let mut config = profile_config(dir, &thesaurus_path);
+    ConfigState::new(&mut config)
+        .await
+        .map_err(|e| ServiceError::Config(e.to_string()))
+}
+
+/// Timing summary of one search stage
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StageProfile {
+    pub role: RoleName,
+    /// Name of the stage, or `total` for the whole search
+    pub stage: String,
+    /// Number of measurements
+    pub count: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Result of a profiling run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProfileReport {
+    /// Number of documents in the corpus
+    pub documents: usize,
+    /// Number of searches run
+    pub searches: usize,
+    /// Timings per role and stage, in pipeline order
+    pub stages: Vec<StageProfile>,
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} searches against {} documents",
+            self.searches, self.documents
+        )?;
+        writeln!(
+            f,
+            "{:<24} {:<12} {:>6} {:>10} {:>10} {:>10} {:>10}",
+            "role", "stage", "count", "mean ms", "p50 ms", "p95 ms", "max ms"
+        )?;
+        for stage in &self.stages {
+            writeln!(
+                f,
+                "{:<24} {:<12} {:>6} {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+                stage.role.original,
+                stage.stage,
+                stage.count,
+                stage.mean_ms,
+                stage.p50_ms,
+                stage.p95_ms,
+                stage.max_ms
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a synthetic corpus of `documents` documents into `dir`, run every
+/// canned query `iterations` times for every role and summarize the
+/// per-stage timings
+///
+/// The searches are not recorded in the query analytics log.
+pub async fn profile_search(
+    dir: &Path,
+    documents: usize,
+    iterations: usize,
+) -> Result<ProfileReport> {
+    let config_state = profile_config_state(dir, documents).await?;
+    let roles = [PROFILE_TITLE_ROLE, PROFILE_GRAPH_ROLE].map(RoleName::new);
+
+    let mut service = TerraphimService::new(config_state);
+    let mut searches = 0;
+    let mut stages = Vec::new();
+    for role in roles {
+        // Stage name -> latencies in microseconds, kept in pipeline order
+        let mut timings: Vec<(String, Vec<u64>)> = Vec::new();
+        for _ in 0..iterations {
+            for query in CANNED_QUERIES {
+                let search_query = SearchQuery {
+                    search_term: NormalizedTermValue::from(*query),
+                    role: Some(role.clone()),
+                    ..Default::default()
+                };
+                let (_documents, record) = service.search_profiled(&search_query).await?;
+                searches += 1;
+                let measurements = record
+                    .stages
+                    .iter()
+                    .map(|stage| (stage.stage.as_str(), stage.micros))
+                    .chain(std::iter::once(("total", record.total_micros)));
+                for (stage, micros) in measurements {
+                    match timings.iter_mut().find(|(name, _)| name == stage) {
+                        Some((_, values)) => values.push(micros),
+                        None => timings.push((stage.to_string(), vec![micros])),
+                    }
+                }
+            }
+        }
+        for (stage, mut values) in timings {
+            values.sort_unstable();
+            let ms = |micros: u64| micros as f64 / 1000.0;
+            stages.push(StageProfile {
+                role: role.clone(),
+                stage,
+                count: values.len(),
+                mean_ms: ms(values.iter().sum::<u64>()) / values.len() as f64,
+                p50_ms: ms(percentile(&values, 50.0)),
+                p95_ms: ms(percentile(&values, 95.0)),
+                max_ms: ms(*values.last().unwrap_or(&0)),
+            });
+        }
+    }
+
+    Ok(ProfileReport {
+        documents,
+        searches,
+        stages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_synthetic_corpus() {
+        let dir = tempfile::tempdir().unwrap();
+        let thesaurus_path = write_synthetic_corpus(dir.path(), 10).await.unwrap();
+
+        let thesaurus =
+            terraphim_automata::load_thesaurus(&AutomataPath::from_local(&thesaurus_path))
+                .await
+                .unwrap();
+        assert_eq!(thesaurus, synthetic_thesaurus());
+        assert_eq!(thesaurus.len(), CONCEPTS.len() * 2);
+
+        let documents = std::fs::read_dir(dir.path().join("haystack"))
+            .unwrap()
+            .count();
+        assert_eq!(documents, 10);
+
+        // The corpus is deterministic
+        let other = tempfile::tempdir().unwrap();
+        write_synthetic_corpus(other.path(), 10).await.unwrap();
+        let read =
+            |dir: &Path| std::fs::read_to_string(dir.join("haystack/document_3.md")).unwrap();
+        assert_eq!(read(dir.path()), read(other.path()));
+    }
+}
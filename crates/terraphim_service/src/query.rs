@@ -0,0 +1,497 @@
+//! A query language for search
+//!
+//! Instead of building a [`SearchQuery`] field by field, clients can write
+//! queries like
+//!
+//! ```text
+//! rust AND (tokio OR async-std) -blocking tag:networking haystack:docs "event loop"
+//! ```
+//!
+//! - words and `"quoted phrases"` are terms; terms next to each other must
+//!   all match, as with `AND`;
+//! - `OR` matches either side and binds weaker than `AND`; parentheses
+//!   group;
+//! - `-term` or `-"phrase"` excludes documents with the term (see
+//!   [`SearchQuery::exclude_terms`]);
+//! - `tag:`, `haystack:` and `url:` select facets (see
+//!   [`terraphim_types::FacetFilters`]); values may be quoted.
+//!
+//! Operators are only recognized in upper case. Exclusions and facets apply
+//! to the whole query, so they can't be inside parentheses or `OR`.
+//!
+//! Search queries of [`QueryType::Regex`] aren't written in the query
+//! language: their search term is a regular expression, see [`regex`].
+
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use regex::{Regex, RegexBuilder};
+use terraphim_types::{Document, NormalizedTermValue, QueryType, SearchQuery, TermExpr};
+
+use crate::ServiceError;
+
+/// Longest regular expression accepted as search term, in bytes
+pub const MAX_PATTERN_LEN: usize = 256;
+
+/// Limit of the size of a compiled regular expression, in bytes
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// Limit of the nesting of groups and repetitions of a regular expression
+const PATTERN_NEST_LIMIT: u32 = 16;
+
+/// A query which can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct ParseError {
+    /// Byte offset of the error in the query
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parse a query into a search query
+///
+/// The search term of the result is its terms, for ranking; a query of
+/// more than one term also gets the expression of its terms as
+/// [`SearchQuery::terms`]. Everything else, like the role, is left to the
+/// caller.
+pub fn parse(input: &str) -> Result<SearchQuery, ParseError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        end: input.len(),
+    };
+    let node = match parser.peek() {
+        Some(_) => Some(parser.or_expr()?),
+        None => None,
+    };
+    if let Some((position, _)) = parser.tokens.get(parser.next) {
+        return Err(ParseError::new(*position, "Unexpected `)`"));
+    }
+
+    let mut search_query = SearchQuery::default();
+    let mut exprs = Vec::new();
+    let top_level = match node {
+        Some(Node::And(nodes)) => nodes,
+        Some(node) => vec![node],
+        None => Vec::new(),
+    };
+    for node in top_level {
+        match node {
+            Node::Not(term, _) => search_query
+                .exclude_terms
+                .push(NormalizedTermValue::new(term)),
+            Node::Field(Field::Tag, value, _) => search_query.facets.tags.push(value),
+            Node::Field(Field::Haystack, value, _) => {
+                search_query.facets.source_haystack.push(value)
+            }
+            Node::Field(Field::Url, value, _) => search_query.facets.url_prefix = Some(value),
+            node => exprs.push(node.into_expr()?),
+        }
+    }
+    let expr = match exprs.len() {
+        0 => return Ok(search_query),
+        1 => exprs.remove(0),
+        _ => TermExpr::And(exprs),
+    };
+    search_query.search_term = NormalizedTermValue::new(expr.terms().join(" "));
+    if !matches!(expr, TermExpr::Term(_)) {
+        search_query.terms = Some(expr);
+    }
+    Ok(search_query)
+}
+
+/// Compile the search term of a regex query
+///
+/// Patterns ignore case, like the search of haystacks. Patterns longer than
+/// [`MAX_PATTERN_LEN`], nested too deeply or compiling too large are
+/// rejected, as are patterns which match the empty string and would find
+/// every document. Patterns starting with `-` are rejected too, as they
+/// could be taken for flags of ripgrep.
+pub fn regex(pattern: &str) -> Result<Regex, ServiceError> {
+    if pattern.starts_with('-') {
+        return Err(ServiceError::InvalidRegex(
+            "Pattern starts with `-`".to_string(),
+        ));
+    }
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(ServiceError::InvalidRegex(format!(
+            "Pattern is longer than {MAX_PATTERN_LEN} bytes"
+        )));
+    }
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .nest_limit(PATTERN_NEST_LIMIT)
+        .build()
+        .map_err(|e| ServiceError::InvalidRegex(e.to_string()))?;
+    if regex.is_match("") {
+        return Err(ServiceError::InvalidRegex(
+            "Pattern matches the empty string".to_string(),
+        ));
+    }
+    Ok(regex)
+}
+
+/// Check the search term of a query, if it is a regex query
+pub(crate) fn check(search_query: &SearchQuery) -> Result<(), ServiceError> {
+    if search_query.query_type == QueryType::Regex {
+        regex(search_query.search_term.as_str())?;
+    }
+    Ok(())
+}
+
+/// Whether the title or body of a document matches an expression
+pub fn matches(expr: &TermExpr, document: &Document) -> bool {
+    let title = document.title.to_lowercase();
+    let body = document.body.to_lowercase();
+    matches_text(expr, &title, &body)
+}
+
+/// Whether a lower case text matches an expression
+pub(crate) fn matches_lowercase(expr: &TermExpr, text: &str) -> bool {
+    matches_text(expr, text, "")
+}
+
+fn matches_text(expr: &TermExpr, title: &str, body: &str) -> bool {
+    match expr {
+        TermExpr::Term(term) => {
+            let term = term.to_lowercase();
+            title.contains(&term) || body.contains(&term)
+        }
+        TermExpr::And(exprs) => exprs.iter().all(|expr| matches_text(expr, title, body)),
+        TermExpr::Or(exprs) => exprs.iter().any(|expr| matches_text(expr, title, body)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Tag,
+    Haystack,
+    Url,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tag" => Some(Field::Tag),
+            "haystack" => Some(Field::Haystack),
+            "url" => Some(Field::Url),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Term(String),
+    Field(Field, String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+/// Split a query into tokens with their byte offsets
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push((start, Token::Open));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((start, Token::Close));
+            }
+            '"' => tokens.push((start, Token::Term(phrase(&mut chars)?))),
+            '-' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, c)) if !c.is_whitespace() && c != ')' => {
+                        tokens.push((start, Token::Not))
+                    }
+                    _ => return Err(ParseError::new(start, "Expected a term after `-`")),
+                }
+            }
+            _ => {
+                let word = word(&mut chars);
+                let field = word.split_once(':').and_then(|(name, value)| {
+                    Field::from_name(name).map(|field| (field, name, value))
+                });
+                let token = match (word.as_str(), field) {
+                    ("AND", _) => Token::And,
+                    ("OR", _) => Token::Or,
+                    (_, Some((field, name, ""))) => match chars.peek() {
+                        Some((_, '"')) => Token::Field(field, phrase(&mut chars)?),
+                        _ => {
+                            return Err(ParseError::new(
+                                start,
+                                format!("Expected a value after `{name}:`"),
+                            ))
+                        }
+                    },
+                    (_, Some((field, _, value))) => Token::Field(field, value.to_string()),
+                    _ => Token::Term(word),
+                };
+                tokens.push((start, token));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Read a quoted phrase, starting at its opening quote
+fn phrase(chars: &mut Peekable<CharIndices>) -> Result<String, ParseError> {
+    let (start, _) = chars.next().expect("phrase starts with a quote");
+    let mut phrase = String::new();
+    for (_, c) in chars.by_ref() {
+        if c == '"' {
+            return Ok(phrase);
+        }
+        phrase.push(c);
+    }
+    Err(ParseError::new(start, "Unterminated phrase"))
+}
+
+/// Read a word up to the next space, parenthesis or quote
+fn word(chars: &mut Peekable<CharIndices>) -> String {
+    let mut word = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+    word
+}
+
+/// A parsed query, before exclusions and facets are taken out
+#[derive(Debug)]
+enum Node {
+    Term(String),
+    Field(Field, String, usize),
+    Not(String, usize),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    /// A node in parentheses
+    Group(Box<Node>),
+}
+
+impl Node {
+    /// The expression of the terms of a node, which must have no exclusions
+    /// or facets
+    fn into_expr(self) -> Result<TermExpr, ParseError> {
+        let collect = |nodes: Vec<Node>| -> Result<Vec<TermExpr>, ParseError> {
+            nodes.into_iter().map(Node::into_expr).collect()
+        };
+        match self {
+            Node::Term(term) => Ok(TermExpr::Term(term)),
+            Node::And(nodes) => Ok(TermExpr::And(collect(nodes)?)),
+            Node::Or(nodes) => Ok(TermExpr::Or(collect(nodes)?)),
+            Node::Group(node) => node.into_expr(),
+            Node::Field(_, _, position) | Node::Not(_, position) => Err(ParseError::new(
+                position,
+                "Exclusions and facets can't be inside parentheses or `OR`",
+            )),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Length of the query, the position of errors at its end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.end, |(position, _)| *position)
+    }
+
+    fn advance(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or_expr(&mut self) -> Result<Node, ParseError> {
+        let mut nodes = vec![self.and_expr()?];
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            nodes.push(self.and_expr()?);
+        }
+        Ok(if nodes.len() == 1 {
+            nodes.remove(0)
+        } else {
+            Node::Or(nodes)
+        })
+    }
+
+    fn and_expr(&mut self) -> Result<Node, ParseError> {
+        let mut nodes = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Or) | Some(Token::Close) => break,
+                Some(Token::And) => {
+                    self.advance();
+                }
+                Some(_) => {}
+            }
+            nodes.push(self.unary()?);
+        }
+        Ok(if nodes.len() == 1 {
+            nodes.remove(0)
+        } else {
+            Node::And(nodes)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some((_, Token::Not)) => match self.advance() {
+                Some((_, Token::Term(term))) => Ok(Node::Not(term, position)),
+                _ => Err(ParseError::new(position, "Only terms can be excluded")),
+            },
+            Some((_, Token::Term(term))) => Ok(Node::Term(term)),
+            Some((_, Token::Field(field, value))) => Ok(Node::Field(field, value, position)),
+            Some((_, Token::Open)) => {
+                if self.peek() == Some(&Token::Close) {
+                    return Err(ParseError::new(position, "Empty parentheses"));
+                }
+                let node = self.or_expr()?;
+                match self.advance() {
+                    Some((_, Token::Close)) => Ok(Node::Group(Box::new(node))),
+                    _ => Err(ParseError::new(position, "Unclosed `(`")),
+                }
+            }
+            Some((_, Token::And | Token::Or)) => Err(ParseError::new(
+                position,
+                "Expected a term before the operator",
+            )),
+            Some((_, Token::Close)) => Err(ParseError::new(position, "Unexpected `)`")),
+            None => Err(ParseError::new(position, "Expected a term")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: &str) -> TermExpr {
+        TermExpr::Term(term.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        let query =
+            parse(r#"term AND (foo OR bar) -baz tag:rust haystack:docs "exact phrase""#).unwrap();
+        assert_eq!(
+            query.terms,
+            Some(TermExpr::And(vec![
+                term("term"),
+                TermExpr::Or(vec![term("foo"), term("bar")]),
+                term("exact phrase"),
+            ]))
+        );
+        assert_eq!(query.search_term.as_str(), "term foo bar exact phrase");
+        assert_eq!(query.exclude_terms, vec![NormalizedTermValue::from("baz")]);
+        assert_eq!(query.facets.tags, vec!["rust"]);
+        assert_eq!(query.facets.source_haystack, vec!["docs"]);
+
+        let query = parse("Tokio").unwrap();
+        assert_eq!(query.search_term.as_str(), "tokio");
+        assert_eq!(query.terms, None);
+
+        let query = parse("a b OR c").unwrap();
+        assert_eq!(
+            query.terms,
+            Some(TermExpr::Or(vec![
+                TermExpr::And(vec![term("a"), term("b")]),
+                term("c"),
+            ]))
+        );
+
+        let query = parse(r#"a tag:"machine learning""#).unwrap();
+        assert_eq!(query.search_term.as_str(), "a");
+        assert_eq!(query.facets.tags, vec!["machine learning"]);
+
+        for (input, position) in [
+            ("(a OR b", 0),
+            ("a)", 1),
+            ("\"open", 0),
+            ("a OR", 4),
+            ("()", 0),
+            ("a OR -b", 5),
+            ("(a tag:x)", 3),
+            ("- a", 0),
+            ("a OR c tag:x", 7),
+            ("AND a", 0),
+        ] {
+            assert_eq!(parse(input).unwrap_err().position, position, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let document = Document {
+            title: "Async Rust".to_string(),
+            body: "Tokio is an event loop".to_string(),
+            ..Default::default()
+        };
+        let expr = parse("rust (tokio OR async-std)").unwrap().terms.unwrap();
+        assert!(matches(&expr, &document));
+        let expr = parse(r#"rust "event queue""#).unwrap().terms.unwrap();
+        assert!(!matches(&expr, &document));
+    }
+
+    #[test]
+    fn test_regex() {
+        let pattern = regex(r"tokio::(spawn|select)").unwrap();
+        assert!(pattern.is_match("Calling TOKIO::SPAWN"));
+        assert!(!pattern.is_match("tokio::join"));
+
+        assert!(matches!(
+            regex("(unclosed"),
+            Err(ServiceError::InvalidRegex(_))
+        ));
+        assert!(matches!(regex("a*"), Err(ServiceError::InvalidRegex(_))));
+        assert!(matches!(
+            regex("--pre=/bin/sh"),
+            Err(ServiceError::InvalidRegex(_))
+        ));
+        let long = "a".repeat(MAX_PATTERN_LEN + 1);
+        assert!(matches!(regex(&long), Err(ServiceError::InvalidRegex(_))));
+        let nested = format!("{}a{}", "(".repeat(20), ")".repeat(20));
+        assert!(matches!(regex(&nested), Err(ServiceError::InvalidRegex(_))));
+        assert!(matches!(
+            regex(r"\w{1000}{1000}"),
+            Err(ServiceError::InvalidRegex(_))
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+//! Cache of search results
+//!
+//! Every search runs ripgrep over all haystacks of its role and ranks the
+//! documents found, even if the same query was run a moment ago. The
+//! results of a search are cached by role, query and the access of the
+//! searcher for [`RESULT_TTL`], and at most [`MAX_RESULTS`] searches are
+//! kept, dropping the oldest first.
+//!
+//! Cached results are dropped when they may be stale:
+//! - of all roles when a document is created, the config changes or the
+//!   haystacks are reindexed;
+//! - of a role when its thesaurus is replaced or invalidated in the
+//!   [`ThesaurusCache`](crate::thesaurus_cache::ThesaurusCache), as its
+//!   documents were ranked with the old one;
+//! - of a role on click feedback, at most once per [`RESULT_TTL`], see
+//!   [`feedback`](crate::feedback).
+//!
+//! Files changed in haystacks aren't watched for, so results reflect them
+//! after [`RESULT_TTL`] at the latest.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use terraphim_config::Access;
+use terraphim_types::{Document, NormalizedTermValue, RoleName, SearchQuery};
+
+/// How long the results of a search are cached
+pub const RESULT_TTL: Duration = Duration::from_secs(60);
+
+/// Number of searches whose results are cached at most
+pub const MAX_RESULTS: usize = 1000;
+
+/// What the results of a search depend on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    role: RoleName,
+    /// The query without its role, as JSON
+    query: String,
+    access: Access,
+}
+
+impl CacheKey {
+    /// The key of a search of a role
+    ///
+    /// The search term of the query is normalized, so searches differing
+    /// only in case or surrounding whitespace share their results.
+    pub fn new(role: &RoleName, search_query: &SearchQuery, access: &Access) -> Self {
+        let search_query = SearchQuery {
+            search_term: NormalizedTermValue::new(search_query.search_term.to_string()),
+            role: None,
+            ..search_query.clone()
+        };
+        CacheKey {
+            role: role.clone(),
+            query: serde_json::to_string(&search_query).unwrap_or_default(),
+            access: access.clone(),
+        }
+    }
+}
+
+struct Entry {
+    documents: Vec<Document>,
+    created: Instant,
+}
+
+/// The cached search results of this process
+#[derive(Default)]
+pub struct ResultCache {
+    entries: Mutex<AHashMap<CacheKey, Entry>>,
+}
+
+impl ResultCache {
+    /// The cache of this process
+    pub fn instance() -> &'static ResultCache {
+        static CACHE: OnceLock<ResultCache> = OnceLock::new();
+        CACHE.get_or_init(ResultCache::default)
+    }
+
+    /// The cached results of a search, unless they expired
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<Document>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        (entry.created.elapsed() < RESULT_TTL).then(|| entry.documents.clone())
+    }
+
+    /// Cache the results of a search
+    pub fn insert(&self, key: CacheKey, documents: Vec<Document>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.created.elapsed() < RESULT_TTL);
+        while entries.len() >= MAX_RESULTS {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            Entry {
+                documents,
+                created: Instant::now(),
+            },
+        );
+    }
+
+    /// Drop the cached results of the searches of a role
+    pub fn invalidate(&self, role: &RoleName) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.role != *role);
+    }
+
+    /// Drop all cached results
+    pub fn invalidate_all(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_cache() {
+        let cache = ResultCache::default();
+        let engineer = RoleName::new("Engineer");
+        let operator = RoleName::new("System Operator");
+        let query = |term: &str| SearchQuery {
+            search_term: term.into(),
+            ..Default::default()
+        };
+        let documents = vec![Document {
+            id: "1".to_string(),
+            ..Default::default()
+        }];
+
+        let key = CacheKey::new(&engineer, &query("rust"), &Access::All);
+        cache.insert(key.clone(), documents);
+        // Search terms of deserialized queries aren't normalized
+        let unnormalized: SearchQuery =
+            serde_json::from_str(r#"{"search_term": " Rust"}"#).unwrap();
+        let same = CacheKey::new(&engineer, &unnormalized, &Access::All);
+        assert_eq!(cache.get(&same).unwrap().len(), 1);
+        let restricted = CacheKey::new(&engineer, &query("rust"), &Access::Labels(Vec::new()));
+        assert!(cache.get(&restricted).is_none());
+        assert!(cache
+            .get(&CacheKey::new(&engineer, &query("tokio"), &Access::All))
+            .is_none());
+
+        let other_role = CacheKey::new(&operator, &query("rust"), &Access::All);
+        cache.insert(other_role.clone(), Vec::new());
+        cache.invalidate(&engineer);
+        assert!(cache.get(&key).is_none());
+        assert!(cache.get(&other_role).is_some());
+        cache.invalidate_all();
+        assert!(cache.get(&other_role).is_none());
+    }
+}
@@ -0,0 +1,236 @@
+//! Okapi BM25F ranking of documents
+//!
+//! Documents are ranked by how often their fields contain the terms of the
+//! search term, weighing rare terms higher. The fields are the title, body,
+//! description and tags of documents; occurrences in each field are
+//! normalized by the length of the field, relative to the same field of the
+//! other documents found, and weighted by the boost of the field. Term
+//! frequencies are counted among the documents found, and both the
+//! documents and the search term are analyzed with the analyzer of the
+//! document language, like for the title scorer.
+//!
+//! Roles ranked with the `bm25` or `hybrid` relevance function tune the
+//! saturation `k1`, the length normalization `b` and the boosts of the
+//! fields with their [`ScoringParams`].
+
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use terraphim_automata::language::Analyzer;
+use terraphim_config::{FieldBoosts, ScoringParams};
+use terraphim_types::{Document, SearchQuery};
+
+/// Number of fields of documents which are scored
+const FIELDS: usize = 4;
+
+/// The terms of a field of a document
+#[derive(Default)]
+struct Field {
+    terms: AHashMap<String, usize>,
+    length: usize,
+}
+
+impl Field {
+    fn new(tokens: impl IntoIterator<Item = String>) -> Self {
+        let mut field = Field::default();
+        for token in tokens {
+            *field.terms.entry(token).or_insert(0) += 1;
+            field.length += 1;
+        }
+        field
+    }
+}
+
+/// The fields of a document and the search term in its language
+struct Analyzed {
+    fields: [Field; FIELDS],
+    query: Vec<String>,
+}
+
+impl Analyzed {
+    fn new(document: &Document, search_term: &str) -> Self {
+        let analyzer = Analyzer::for_code(document.language.as_deref());
+        let description = document.description.as_deref().unwrap_or_default();
+        let tags = document.tags.iter().flatten();
+        let fields = [
+            Field::new(analyzer.tokens(&document.title)),
+            Field::new(analyzer.tokens(&document.body)),
+            Field::new(analyzer.tokens(description)),
+            Field::new(tags.flat_map(|tag| analyzer.tokens(tag))),
+        ];
+        let mut query = analyzer.tokens(search_term);
+        query.sort();
+        query.dedup();
+        Analyzed { fields, query }
+    }
+
+    fn contains(&self, term: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|field| field.terms.contains_key(term))
+    }
+}
+
+/// Boosts of the fields, in the order of [`Analyzed::fields`]
+fn boosts(boosts: FieldBoosts) -> [f64; FIELDS] {
+    [boosts.title, boosts.body, boosts.description, boosts.tags].map(|boost| boost.max(0.0))
+}
+
+/// Sort documents by their BM25F score for the search term, the best first
+///
+/// Documents with the same score, e.g. without any term of the search
+/// term, keep their order. Parameters out of range are clamped into it.
+pub fn sort_documents(
+    search_query: &SearchQuery,
+    documents: Vec<Arc<Document>>,
+    params: ScoringParams,
+) -> Vec<Arc<Document>> {
+    score_documents(search_query, documents, params)
+        .into_iter()
+        .map(|(document, _)| document)
+        .collect()
+}
+
+/// Sort documents by their BM25F score for the search term, the best
+/// first, with their scores
+pub fn score_documents(
+    search_query: &SearchQuery,
+    documents: Vec<Arc<Document>>,
+    params: ScoringParams,
+) -> Vec<(Arc<Document>, f64)> {
+    let analyzed: Vec<Analyzed> = documents
+        .iter()
+        .map(|document| Analyzed::new(document, search_query.search_term.as_str()))
+        .collect();
+    let mut scored: Vec<(Arc<Document>, f64)> = documents
+        .into_iter()
+        .zip(scores(&analyzed, params))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored
+}
+
+fn scores(documents: &[Analyzed], params: ScoringParams) -> Vec<f64> {
+    if documents.is_empty() {
+        return Vec::new();
+    }
+    let k1 = params.k1.max(0.0);
+    let b = params.b.clamp(0.0, 1.0);
+    let boosts = boosts(params.boosts);
+    let count = documents.len() as f64;
+    let mut average_lengths = [0.0; FIELDS];
+    for (i, average_length) in average_lengths.iter_mut().enumerate() {
+        let total: usize = documents
+            .iter()
+            .map(|document| document.fields[i].length)
+            .sum();
+        *average_length = (total as f64 / count).max(1.0);
+    }
+    let mut frequencies: AHashMap<&str, usize> = AHashMap::new();
+    for document in documents {
+        for term in &document.query {
+            if document.contains(term) {
+                *frequencies.entry(term).or_insert(0) += 1;
+            }
+        }
+    }
+    documents
+        .iter()
+        .map(|document| {
+            document
+                .query
+                .iter()
+                .filter_map(|term| {
+                    let df = *frequencies.get(term.as_str())? as f64;
+                    // Frequency of the term over all fields, each
+                    // normalized by its length and boosted
+                    let tf: f64 = (0..FIELDS)
+                        .map(|i| {
+                            let field = &document.fields[i];
+                            let tf = field.terms.get(term).copied().unwrap_or(0) as f64;
+                            let norm = 1.0 - b + b * field.length as f64 / average_lengths[i];
+                            boosts[i] * tf / norm
+                        })
+                        .sum();
+                    let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    (tf > 0.0).then(|| idf * tf * (k1 + 1.0) / (tf + k1))
+                })
+                .sum()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, title: &str, body: &str) -> Arc<Document> {
+        Arc::new(Document {
+            id: id.to_string(),
+            title: title.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_bm25() {
+        let documents = vec![
+            document("none", "Release notes", "Nothing to see"),
+            document(
+                "once",
+                "Tokio",
+                "A long body about many other things and more",
+            ),
+            document("twice", "Runtimes", "tokio tokio"),
+            document("rare", "Scheduler", "tokio work stealing"),
+        ];
+        let query = SearchQuery {
+            search_term: "tokio stealing".into(),
+            ..Default::default()
+        };
+        let sorted = sort_documents(&query, documents.clone(), ScoringParams::default());
+        let ids: Vec<&str> = sorted.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["rare", "twice", "once", "none"]);
+        // The scores are those the documents are sorted by
+        let scored = score_documents(&query, documents.clone(), ScoringParams::default());
+        assert!(scored.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert_eq!(scored[3].1, 0.0);
+
+        // Without length normalization, long and short documents with a
+        // term once score the same and keep their order
+        let query = SearchQuery {
+            search_term: "tokio".into(),
+            ..Default::default()
+        };
+        let ids = |params| -> Vec<String> {
+            sort_documents(&query, documents.clone(), params)
+                .iter()
+                .map(|d| d.id.clone())
+                .collect()
+        };
+        assert_eq!(
+            ids(ScoringParams::default()),
+            vec!["twice", "rare", "once", "none"]
+        );
+        assert_eq!(
+            ids(ScoringParams {
+                b: 0.0,
+                ..Default::default()
+            }),
+            vec!["twice", "once", "rare", "none"]
+        );
+        // Boosted titles outweigh bodies
+        let boosts = FieldBoosts {
+            title: 10.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(ScoringParams {
+                boosts,
+                ..Default::default()
+            })[0],
+            "once"
+        );
+    }
+}
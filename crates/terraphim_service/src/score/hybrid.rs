@@ -0,0 +1,64 @@
+//! Reciprocal Rank Fusion of rankings
+//!
+//! The hybrid relevance function ranks the documents found with several
+//! scorers and fuses their rankings: each ranking adds
+//! `weight / (RRF_K + position)` to the score of a document, so documents
+//! ranked well by several scorers come first, without comparing the scores
+//! of different scorers.
+
+use ahash::AHashMap;
+
+/// Damping of the difference between the top positions of a ranking
+pub const RRF_K: f64 = 60.0;
+
+/// Fuse weighted rankings of document IDs, each the best first
+///
+/// Returns the IDs in all rankings by fused score, the best first. IDs
+/// with the same score are in the order they first appear in the rankings.
+pub fn fuse<'a>(rankings: &[(u32, Vec<&'a str>)]) -> Vec<&'a str> {
+    fuse_scored(rankings)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Fuse weighted rankings of document IDs like [`fuse`], with the fused
+/// scores
+pub fn fuse_scored<'a>(rankings: &[(u32, Vec<&'a str>)]) -> Vec<(&'a str, f64)> {
+    let mut scores: AHashMap<&str, (f64, usize)> = AHashMap::new();
+    for (weight, ranking) in rankings {
+        for (position, id) in ranking.iter().enumerate() {
+            let first_seen = scores.len();
+            let entry = scores.entry(id).or_insert((0.0, first_seen));
+            entry.0 += f64::from(*weight) / (RRF_K + (position + 1) as f64);
+        }
+    }
+    let mut fused: Vec<(&str, (f64, usize))> = scores.into_iter().collect();
+    fused.sort_by(|a, b| b.1 .0.total_cmp(&a.1 .0).then(a.1 .1.cmp(&b.1 .1)));
+    fused
+        .into_iter()
+        .map(|(id, (score, _))| (id, score))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuse() {
+        let title = vec!["a", "b", "c"];
+        let bm25 = vec!["b", "c", "a"];
+        let graph = vec!["b"];
+        assert_eq!(
+            fuse(&[(1, title.clone()), (1, bm25.clone()), (1, graph.clone())]),
+            vec!["b", "a", "c"]
+        );
+        // Scorers with weight 0 are ignored, but their IDs are kept
+        assert_eq!(
+            fuse(&[(1, title.clone()), (0, bm25), (0, graph.clone())]),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(fuse(&[(0, title), (1, graph)]), vec!["b", "a", "c"]);
+    }
+}
@@ -0,0 +1,142 @@
+//! Maximal Marginal Relevance diversification of search results
+//!
+//! Near-duplicates, like the copies of a note in a family of files, crowd
+//! the top of the results. MMR reorders the top results one at a time: the
+//! next result is the document with the best
+//! `lambda * relevance - (1 - lambda) * similarity`, where the relevance is
+//! the score of the document relative to the best one, and the similarity
+//! is its greatest similarity to the results before it. A `lambda` of 1
+//! keeps the order of relevance; the lower it is, the more documents unlike
+//! those above them move up.
+//!
+//! Documents are compared by the Jaccard similarity of the terms of their
+//! titles and bodies, analyzed like for BM25. Only the first
+//! [`MAX_CANDIDATES`] results are reordered.
+
+use ahash::AHashSet;
+use terraphim_automata::language::Analyzer;
+use terraphim_types::Document;
+
+/// Number of top results which are reordered
+pub const MAX_CANDIDATES: usize = 100;
+
+/// The terms of the title and body of a document
+fn terms(document: &Document) -> AHashSet<String> {
+    let analyzer = Analyzer::for_code(document.language.as_deref());
+    analyzer
+        .tokens(&document.title)
+        .into_iter()
+        .chain(analyzer.tokens(&document.body))
+        .collect()
+}
+
+/// Share of the terms of either set which are in both, from 0 to 1
+fn jaccard(a: &AHashSet<String>, b: &AHashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// Reorder the top results for a trade-off between relevance and
+/// diversity, from 1 (relevance only) to 0 (diversity only)
+///
+/// Documents keep their ranks and scores.
+pub fn diversify(documents: &mut Vec<Document>, lambda: f64) {
+    let lambda = if lambda.is_nan() {
+        1.0
+    } else {
+        lambda.clamp(0.0, 1.0)
+    };
+    let count = documents.len().min(MAX_CANDIDATES);
+    if count < 3 || lambda == 1.0 {
+        return;
+    }
+    let rest = documents.split_off(count);
+    let best = documents
+        .iter()
+        .filter_map(|document| document.score)
+        .fold(0.0, f64::max);
+    // Documents without scores are as relevant as their position
+    let relevance: Vec<f64> = documents
+        .iter()
+        .enumerate()
+        .map(|(position, document)| match document.score {
+            Some(score) if best > 0.0 => score / best,
+            _ => (count - position) as f64 / count as f64,
+        })
+        .collect();
+    let terms: Vec<AHashSet<String>> = documents.iter().map(terms).collect();
+
+    let mut similarity = vec![0.0_f64; count];
+    let mut picked = vec![false; count];
+    let mut order = Vec::with_capacity(count);
+    for _ in 0..count {
+        let marginal = |i: usize| lambda * relevance[i] - (1.0 - lambda) * similarity[i];
+        // Of equally good documents, the more relevant one comes first
+        let Some(next) = (0..count)
+            .filter(|&i| !picked[i])
+            .max_by(|&a, &b| marginal(a).total_cmp(&marginal(b)).then(b.cmp(&a)))
+        else {
+            break;
+        };
+        picked[next] = true;
+        order.push(next);
+        for i in 0..count {
+            if !picked[i] {
+                similarity[i] = similarity[i].max(jaccard(&terms[i], &terms[next]));
+            }
+        }
+    }
+
+    let mut candidates: Vec<Option<Document>> = documents.drain(..).map(Some).collect();
+    documents.extend(order.into_iter().filter_map(|i| candidates[i].take()));
+    documents.extend(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, body: &str, score: f64) -> Document {
+        Document {
+            id: id.to_string(),
+            title: "Notes".to_string(),
+            body: body.to_string(),
+            score: Some(score),
+            ..Default::default()
+        }
+    }
+
+    fn ids(documents: &[Document]) -> Vec<&str> {
+        documents.iter().map(|d| d.id.as_str()).collect()
+    }
+
+    fn results() -> Vec<Document> {
+        vec![
+            document("a", "tokio runtime spawns async tasks", 1.0),
+            document("a-copy", "tokio runtime spawns async tasks", 0.95),
+            document("a-draft", "tokio runtime spawns many async tasks", 0.9),
+            document("b", "rayon splits work across threads", 0.8),
+        ]
+    }
+
+    #[test]
+    fn test_mmr() {
+        let mut documents = results();
+        diversify(&mut documents, 1.0);
+        assert_eq!(ids(&documents), vec!["a", "a-copy", "a-draft", "b"]);
+
+        let mut documents = results();
+        diversify(&mut documents, 0.5);
+        assert_eq!(ids(&documents), vec!["a", "b", "a-draft", "a-copy"]);
+        assert_eq!(documents[1].score, Some(0.8));
+
+        // Pure diversity still starts with a most relevant document
+        let mut documents = results();
+        diversify(&mut documents, 0.0);
+        assert_eq!(documents[0].id, "a");
+        assert_eq!(documents[1].id, "b");
+    }
+}
@@ -1,15 +1,26 @@
+use std::cmp;
 use std::f64;
 use std::fmt;
 use std::result;
+use std::sync::Arc;
 
+pub mod bm25;
+pub mod hybrid;
+pub mod mmr;
 mod names;
+pub mod proximity;
+pub mod recency;
+mod registry;
 mod scored;
 
 use crate::error::Result;
 use names::NameScorer;
+pub(crate) use registry::scorer_of;
+pub use registry::{Scorer, ScorerRegistry};
 use scored::{Scored, SearchResults};
 use serde::{Serialize, Serializer};
 
+use terraphim_automata::language::Analyzer;
 use terraphim_types::Document;
 use terraphim_types::SearchQuery;
 
@@ -17,32 +28,162 @@ use terraphim_types::SearchQuery;
 ///
 /// The `relevance_function` parameter is used to determine how the documents
 /// should be sorted.
-pub fn sort_documents(search_query: &SearchQuery, documents: Vec<Document>) -> Vec<Document> {
+///
+/// Documents are shared with the index, so sorting never copies them.
+pub fn sort_documents(
+    search_query: &SearchQuery,
+    documents: Vec<Arc<Document>>,
+) -> Vec<Arc<Document>> {
+    score_documents(search_query, documents)
+        .into_iter()
+        .map(|(document, _)| document)
+        .collect()
+}
+
+/// Sort the documents by relevance, with the similarity of their titles to
+/// the search term
+pub fn score_documents(
+    search_query: &SearchQuery,
+    documents: Vec<Arc<Document>>,
+) -> Vec<(Arc<Document>, f64)> {
     log::debug!("Sorting documents by relevance");
 
     // Create a new scorer
-    let mut scorer = Scorer::new();
+    let mut scorer = TitleScorer::new();
 
     // Create a new query
     let query = Query::new(&search_query.search_term.as_str()).similarity(Similarity::Levenshtein);
 
     // Score the documents
     let mut results = scorer.score(&query, documents).unwrap();
-    results.rescore(|doc| query.similarity.similarity(&query.name, &doc.title));
+    results.rescore(|doc| scorer.similarity(&query, doc));
     log::debug!("Rescore results {:#?}", results);
     results
         .into_vec()
+        .into_iter()
+        .map(|scored| {
+            let (score, document) = scored.into_pair();
+            (document, score)
+        })
+        .collect()
+}
+
+/// Copy sorted documents out of the index, ranking them in their order
+///
+/// Without scores of their own, documents are scored by their position.
+pub(crate) fn rank_in_order(documents: Vec<Arc<Document>>) -> Vec<Document> {
+    let total_length = documents.len();
+    documents
+        .into_iter()
+        .enumerate()
+        .map(|(idx, document)| {
+            // Only the ranked results are copied out of the index
+            let mut document = Arc::unwrap_or_clone(document);
+            let rank = total_length - idx;
+            document.rank = Some(rank as u64);
+            document.score = Some(rank as f64 / total_length as f64);
+            document
+        })
+        .collect()
+}
+
+/// Copy sorted documents out of the index with the scores they were sorted
+/// by, ranking them in their order
+///
+/// Scores are normalized to the best score.
+pub(crate) fn rank_scored(documents: Vec<(Arc<Document>, f64)>) -> Vec<Document> {
+    let total_length = documents.len();
+    let best = documents
         .iter()
-        .map(|s| s.clone().into_value())
+        .map(|(_, score)| *score)
+        .fold(0.0, f64::max);
+    documents
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (document, score))| {
+            let mut document = Arc::unwrap_or_clone(document);
+            document.rank = Some((total_length - idx) as u64);
+            document.score = Some(normalized(score, best));
+            document
+        })
         .collect()
 }
 
-#[derive(Debug)]
-pub struct Scorer {}
+/// Score documents by their ranks, relative to the best rank
+///
+/// For ranks which carry the relevance of documents, like those of the
+/// knowledge graph.
+pub(crate) fn score_by_rank(documents: &mut [Document]) {
+    let best = documents
+        .iter()
+        .filter_map(|document| document.rank)
+        .max()
+        .unwrap_or_default() as f64;
+    for document in documents.iter_mut() {
+        document.score = document.rank.map(|rank| normalized(rank as f64, best));
+    }
+}
 
-impl Scorer {
-    pub fn new() -> Scorer {
-        Scorer {}
+/// Scale the scores of documents so that the best one is 1
+pub(crate) fn normalize(documents: &mut [Document]) {
+    let best = documents
+        .iter()
+        .filter_map(|document| document.score)
+        .fold(0.0, f64::max);
+    for document in documents.iter_mut() {
+        document.score = document.score.map(|score| normalized(score, best));
+    }
+}
+
+fn normalized(score: f64, best: f64) -> f64 {
+    if best > 0.0 {
+        (score / best).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Multiply the rank and score of a document by a factor
+pub(crate) fn scale(document: &mut Document, factor: f64) {
+    document.rank = document
+        .rank
+        .map(|rank| (rank as f64 * factor).round() as u64);
+    document.score = document.score.map(|score| score * factor);
+}
+
+/// Multiply the ranks and scores of documents by a factor each and reorder
+/// them
+///
+/// Ranks are rounded, so documents are ordered by their exact products.
+/// Documents without a rank go last.
+pub(crate) fn rescale(documents: &mut Vec<Document>, factor: impl Fn(&Document) -> f64) {
+    let mut scored: Vec<(Option<f64>, f64, Document)> = documents
+        .drain(..)
+        .map(|document| {
+            let factor = factor(&document);
+            let product = document.rank.map(|rank| rank as f64 * factor);
+            (product, factor, document)
+        })
+        .collect();
+    scored.sort_by(|(a, _, _), (b, _, _)| match (a, b) {
+        (Some(a), Some(b)) => b.total_cmp(a),
+        (Some(_), None) => cmp::Ordering::Less,
+        (None, Some(_)) => cmp::Ordering::Greater,
+        (None, None) => cmp::Ordering::Equal,
+    });
+    documents.extend(scored.into_iter().map(|(_, factor, mut document)| {
+        scale(&mut document, factor);
+        document
+    }));
+}
+
+/// Ranks documents by the similarity of their titles to the search term
+#[derive(Debug, Default)]
+pub struct TitleScorer {}
+
+impl TitleScorer {
+    pub fn new() -> TitleScorer {
+        TitleScorer {}
     }
 
     /// Execute a search with the given `Query`.
@@ -72,8 +213,8 @@ impl Scorer {
     pub fn score(
         &mut self,
         query: &Query,
-        documents: Vec<Document>,
-    ) -> Result<SearchResults<Document>> {
+        documents: Vec<Arc<Document>>,
+    ) -> Result<SearchResults<Arc<Document>>> {
         if query.is_empty() {
             return Ok(SearchResults::new());
         }
@@ -86,24 +227,31 @@ impl Scorer {
     fn score_documents(
         &mut self,
         query: &Query,
-        documents: Vec<Document>,
-    ) -> Result<SearchResults<Document>> {
+        documents: Vec<Arc<Document>>,
+    ) -> Result<SearchResults<Arc<Document>>> {
         let mut results = SearchResults::new();
         for document in documents {
             results.push(Scored::new(document));
         }
         log::debug!("Similarity {:?}", query.similarity);
         log::debug!("Query {:?}", query);
-        results.rescore(|document| self.similarity(query, &document.title));
+        results.rescore(|document| self.similarity(query, document));
         log::debug!("results after rescoring: {:#?}", results);
         Ok(results)
     }
 
-    fn similarity(&self, query: &Query, name: &str) -> f64 {
+    /// Similarity of the query and the title of the document
+    ///
+    /// Both are analyzed with the analyzer of the document language first,
+    /// so that inflected forms and stopwords don't affect the score.
+    fn similarity(&self, query: &Query, document: &Document) -> f64 {
+        let analyzer = Analyzer::for_code(document.language.as_deref());
+        let name = analyzer.analyze(&document.title);
+        let query_name = analyzer.analyze(&query.name);
         log::debug!("Similarity {:?}", query.similarity);
         log::debug!("Query {:?}", query);
         log::debug!("Name {:?}", name);
-        let result = query.similarity.similarity(&query.name, name);
+        let result = query.similarity.similarity(&query_name, &name);
         log::debug!("Similarity calculation {:?}", result);
         result
     }
@@ -266,3 +414,50 @@ impl fmt::Display for Similarity {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str) -> Arc<Document> {
+        Arc::new(Document {
+            id: id.to_string(),
+            title: id.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_scores() {
+        let mut documents = rank_scored(vec![
+            (document("a"), 4.0),
+            (document("b"), 2.0),
+            (document("c"), 0.0),
+        ]);
+        let scores: Vec<Option<f64>> = documents.iter().map(|d| d.score).collect();
+        assert_eq!(scores, vec![Some(1.0), Some(0.5), Some(0.0)]);
+        assert_eq!(documents[0].rank, Some(3));
+
+        // Boosts scale ranks and scores alike, then scores are normalized
+        rescale(&mut documents, |d| if d.id == "b" { 4.0 } else { 1.0 });
+        normalize(&mut documents);
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a", "c"]);
+        assert_eq!(documents[0].rank, Some(8));
+        assert_eq!(documents[0].score, Some(1.0));
+        assert_eq!(documents[1].score, Some(0.5));
+
+        let documents = rank_in_order(vec![document("a"), document("b")]);
+        assert_eq!(documents[1].score, Some(0.5));
+
+        let mut documents: Vec<Document> = [10, 5]
+            .into_iter()
+            .map(|rank| Document {
+                rank: Some(rank),
+                ..Default::default()
+            })
+            .collect();
+        score_by_rank(&mut documents);
+        assert_eq!(documents[1].score, Some(0.5));
+    }
+}
@@ -0,0 +1,149 @@
+//! Proximity of the terms of `AND` queries
+//!
+//! A document matches a query of terms joined by `AND` (or written next to
+//! each other) if it has every term anywhere in its title or body. A
+//! document with the terms in the same paragraph is more likely about what
+//! was searched for than one with the terms pages apart, so the rank of a
+//! document is multiplied by `1 + 1 / span`, where the span is the smallest
+//! number of consecutive paragraphs with every operand of the `AND`: twice
+//! the rank with all terms in one paragraph, 1.5 times with the terms in
+//! adjacent paragraphs, barely more with the terms far apart.
+//!
+//! The title counts as the first paragraph; the paragraphs of the body are
+//! separated by blank lines.
+
+use terraphim_types::{Document, TermExpr};
+
+use crate::query;
+
+/// The paragraphs of a text, separated by blank lines
+pub fn paragraphs(text: &str) -> Vec<&str> {
+    let mut paragraphs = Vec::new();
+    let mut start = None;
+    let mut end = 0;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            if let Some(start) = start.take() {
+                paragraphs.push(text[start..end].trim_end());
+            }
+        } else {
+            start.get_or_insert(offset);
+            end = offset + line.len();
+        }
+        offset += line.len();
+    }
+    if let Some(start) = start {
+        paragraphs.push(text[start..end].trim_end());
+    }
+    paragraphs
+}
+
+/// Smallest number of consecutive paragraphs of a document with every
+/// operand of an `AND` expression
+///
+/// `None` if the expression isn't an `AND` of several operands, or the
+/// document doesn't have all of them.
+pub fn span(expr: &TermExpr, document: &Document) -> Option<usize> {
+    let TermExpr::And(operands) = expr else {
+        return None;
+    };
+    if operands.len() < 2 {
+        return None;
+    }
+    let mut texts = vec![document.title.to_lowercase()];
+    texts.extend(
+        paragraphs(&document.body)
+            .into_iter()
+            .map(str::to_lowercase),
+    );
+    // Operands found in each paragraph, in order of paragraphs
+    let mut hits = Vec::new();
+    for (paragraph, text) in texts.iter().enumerate() {
+        for (operand, expr) in operands.iter().enumerate() {
+            if query::matches_lowercase(expr, text) {
+                hits.push((paragraph, operand));
+            }
+        }
+    }
+
+    let mut counts = vec![0usize; operands.len()];
+    let mut missing = operands.len();
+    let mut best: Option<usize> = None;
+    let mut left = 0;
+    for &(paragraph, operand) in &hits {
+        if counts[operand] == 0 {
+            missing -= 1;
+        }
+        counts[operand] += 1;
+        while missing == 0 {
+            let (first, dropped) = hits[left];
+            let span = paragraph - first + 1;
+            best = Some(best.map_or(span, |best| best.min(span)));
+            counts[dropped] -= 1;
+            if counts[dropped] == 0 {
+                missing += 1;
+            }
+            left += 1;
+        }
+    }
+    best
+}
+
+/// Boost the ranks of documents by the proximity of the operands of an
+/// `AND` expression and reorder them
+///
+/// Other expressions leave documents as they are.
+pub fn boost(documents: &mut Vec<Document>, expr: &TermExpr) {
+    if !matches!(expr, TermExpr::And(operands) if operands.len() > 1) {
+        return;
+    }
+    super::rescale(documents, |document| {
+        span(expr, document).map_or(1.0, |span| 1.0 + 1.0 / span as f64)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, title: &str, body: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            title: title.to_string(),
+            body: body.to_string(),
+            rank: Some(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_proximity() {
+        assert_eq!(
+            paragraphs("First line\nsecond line\n\n  \nSecond\r\n\nThird\n"),
+            vec!["First line\nsecond line", "Second", "Third"]
+        );
+
+        let expr = query::parse("rust tokio").unwrap().terms.unwrap();
+        let far = document(
+            "far",
+            "Notes",
+            "Rust is a language.\n\nSomething else.\n\nMore.\n\nTokio is a runtime.",
+        );
+        let near = document("near", "Notes", "Intro.\n\nRust with Tokio.");
+        let adjacent = document("adjacent", "Rust", "Tokio is a runtime.");
+        assert_eq!(span(&expr, &far), Some(4));
+        assert_eq!(span(&expr, &near), Some(1));
+        assert_eq!(span(&expr, &adjacent), Some(2));
+        assert_eq!(span(&expr, &document("none", "Rust", "")), None);
+        let or = query::parse("rust OR tokio").unwrap().terms.unwrap();
+        assert_eq!(span(&or, &near), None);
+
+        let mut documents = vec![far, adjacent, near];
+        boost(&mut documents, &expr);
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["near", "adjacent", "far"]);
+        assert_eq!(documents[0].rank, Some(20));
+        assert_eq!(documents[2].rank, Some(13));
+    }
+}
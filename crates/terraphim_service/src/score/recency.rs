@@ -0,0 +1,70 @@
+//! Exponential decay of ranks by the age of documents
+//!
+//! With a half-life, the rank of a document is multiplied by
+//! `0.5 ^ (age / half_life)`, where the age is the time since the document
+//! was last changed. Of two documents ranked the same, the newer one comes
+//! first; a much newer document also outranks a somewhat more relevant
+//! stale one.
+
+use terraphim_types::Document;
+
+/// Milliseconds in a day
+const DAY_MILLIS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// Factor the rank of a document of the given age is multiplied by
+pub fn decay(age_millis: u64, half_life_days: f64) -> f64 {
+    if half_life_days <= 0.0 || !half_life_days.is_finite() {
+        return 1.0;
+    }
+    0.5_f64.powf(age_millis as f64 / DAY_MILLIS / half_life_days)
+}
+
+/// Decay the ranks of documents by their age at `now` (milliseconds since
+/// the Unix epoch) and reorder them
+///
+/// Documents without a timestamp keep their rank, and documents changed
+/// after `now` count as new.
+pub fn boost(documents: &mut Vec<Document>, half_life_days: f64, now: u64) {
+    super::rescale(documents, |document| {
+        document
+            .last_changed()
+            .map(|changed| decay(now.saturating_sub(changed), half_life_days))
+            .unwrap_or(1.0)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000 * DAY_MILLIS as u64;
+
+    fn document(id: &str, rank: u64, age_days: Option<u64>) -> Document {
+        Document {
+            id: id.to_string(),
+            title: id.to_string(),
+            rank: Some(rank),
+            modified: age_days.map(|days| NOW - days * DAY_MILLIS as u64),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_recency() {
+        assert_eq!(decay(0, 30.0), 1.0);
+        assert_eq!(decay(30 * DAY_MILLIS as u64, 30.0), 0.5);
+        assert_eq!(decay(30 * DAY_MILLIS as u64, 0.0), 1.0);
+
+        let mut documents = vec![
+            document("stale", 10, Some(60)),
+            document("new", 10, Some(1)),
+            document("undated", 8, None),
+            document("relevant", 40, Some(30)),
+        ];
+        boost(&mut documents, 30.0, NOW);
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["relevant", "new", "undated", "stale"]);
+        assert_eq!(documents[0].rank, Some(20));
+        assert_eq!(documents[3].rank, Some(3));
+    }
+}
@@ -0,0 +1,128 @@
+//! Scorers added by applications
+//!
+//! The relevance functions of [`RelevanceFunction`] are built in. An
+//! application embedding Terraphim can rank search results its own way by
+//! implementing [`Scorer`] and registering it in the [`ScorerRegistry`]
+//! under a name. Roles with `"scorer": "<name>"` in their config rank the
+//! documents they find with the scorer of that name.
+//!
+//! [`RelevanceFunction`]: terraphim_types::RelevanceFunction
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use ahash::AHashMap;
+use terraphim_config::Role;
+use terraphim_types::{Document, SearchQuery};
+
+/// A ranking of the documents found by a search
+pub trait Scorer: Send + Sync {
+    /// Name of the scorer, as used in the `scorer` of roles
+    fn name(&self) -> &str;
+
+    /// Sort the documents found for the query, the most relevant first
+    ///
+    /// Documents left out aren't returned by the search.
+    fn sort(&self, search_query: &SearchQuery, documents: Vec<Arc<Document>>)
+        -> Vec<Arc<Document>>;
+}
+
+/// The scorers of this process, by name
+#[derive(Default)]
+pub struct ScorerRegistry {
+    scorers: RwLock<AHashMap<String, Arc<dyn Scorer>>>,
+}
+
+impl ScorerRegistry {
+    /// The registry of this process
+    pub fn instance() -> &'static ScorerRegistry {
+        static REGISTRY: OnceLock<ScorerRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(ScorerRegistry::default)
+    }
+
+    /// Register a scorer under its name
+    ///
+    /// Returns the scorer it replaces, if one was registered under the same
+    /// name.
+    pub fn register(&self, scorer: Arc<dyn Scorer>) -> Option<Arc<dyn Scorer>> {
+        let name = scorer.name().to_string();
+        log::info!("Registering scorer `{name}`");
+        self.scorers.write().unwrap().insert(name, scorer)
+    }
+
+    /// Get the scorer registered under a name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Scorer>> {
+        self.scorers.read().unwrap().get(name).cloned()
+    }
+
+    /// Names of the registered scorers, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.scorers.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// The registered scorer a role ranks with, if it names one
+///
+/// A role naming a scorer which isn't registered ranks with its relevance
+/// function, as the scorer may not be registered yet.
+pub(crate) fn scorer_of(role: &Role) -> Option<Arc<dyn Scorer>> {
+    let name = role.scorer.as_deref()?;
+    let scorer = ScorerRegistry::instance().get(name);
+    if scorer.is_none() {
+        log::warn!(
+            "Role `{}` ranks with unknown scorer `{name}`, using {:?} instead",
+            role.name,
+            role.relevance_function
+        );
+    }
+    scorer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ranks the shortest documents first
+    struct Shortest;
+
+    impl Scorer for Shortest {
+        fn name(&self) -> &str {
+            "shortest"
+        }
+
+        fn sort(
+            &self,
+            _search_query: &SearchQuery,
+            mut documents: Vec<Arc<Document>>,
+        ) -> Vec<Arc<Document>> {
+            documents.sort_by_key(|document| document.body.len());
+            documents
+        }
+    }
+
+    #[test]
+    fn test_scorer_registry() {
+        let registry = ScorerRegistry::default();
+        assert!(registry.register(Arc::new(Shortest)).is_none());
+        assert!(registry.register(Arc::new(Shortest)).is_some());
+        assert_eq!(registry.names(), vec!["shortest"]);
+        assert!(registry.get("longest").is_none());
+
+        let documents = ["a long body", "short", "a body"]
+            .into_iter()
+            .map(|body| {
+                Arc::new(Document {
+                    body: body.to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sorted = registry
+            .get("shortest")
+            .unwrap()
+            .sort(&SearchQuery::default(), documents);
+        let bodies: Vec<&str> = sorted.iter().map(|d| d.body.as_str()).collect();
+        assert_eq!(bodies, vec!["short", "a body", "a long body"]);
+    }
+}
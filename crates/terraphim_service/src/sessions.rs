@@ -0,0 +1,323 @@
+//! Sessions of the users of a shared server
+//!
+//! The selected role of the config is shared by everyone using the server.
+//! A session keeps what is personal instead: the role the user selected,
+//! their recent search terms and the preferences of their UI. Searches of a
+//! session without a role run as the selected role of the session, falling
+//! back to the default role of the config.
+//!
+//! The server identifies the session of a request by the principal of its
+//! API key or by a session ID sent by the client. Without a session, like
+//! in the single-user desktop app, nothing changes.
+//!
+//! Sessions of principals are persisted via `terraphim_persistence`, one
+//! key per session. Sessions named by clients, see [`anonymous_session`],
+//! are only kept in memory, so that clients cannot fill the persistence
+//! with sessions. At most [`MAX_SESSIONS`] are kept in memory; sessions
+//! unused for [`SESSION_IDLE_TTL`] are dropped first, then the least
+//! recently used. Dropped sessions of principals are loaded again when
+//! they are next used.
+
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use terraphim_persistence::Persistable;
+use terraphim_types::RoleName;
+use tokio::sync::{Mutex, OnceCell};
+
+type PersistenceResult<T> = std::result::Result<T, terraphim_persistence::Error>;
+
+/// Number of recent search terms kept per session
+pub const MAX_RECENT_QUERIES: usize = 20;
+
+/// Number of sessions kept in memory at most
+pub const MAX_SESSIONS: usize = 10_000;
+
+/// How long an unused session is kept in memory
+pub const SESSION_IDLE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Prefix of the IDs of sessions named by clients
+const ANONYMOUS_PREFIX: &str = "session:";
+
+/// ID of the session of a principal
+pub fn principal_session(name: &str) -> String {
+    format!("user:{name}")
+}
+
+/// ID of a session named by a client
+///
+/// These sessions are not persisted. The prefix tells them apart from the
+/// sessions of principals, so a client cannot pick the session of a
+/// principal.
+pub fn anonymous_session(id: &str) -> String {
+    format!("{ANONYMOUS_PREFIX}{id}")
+}
+
+/// Whether the session with the given ID is persisted
+fn is_persisted(id: &str) -> bool {
+    !id.starts_with(ANONYMOUS_PREFIX)
+}
+
+static SESSIONS: OnceCell<SessionStore> = OnceCell::const_new();
+
+/// What a user selected and searched for
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserSession {
+    pub id: String,
+    /// Role of searches without a role; the default role of the config if
+    /// not set
+    #[serde(default)]
+    pub selected_role: Option<RoleName>,
+    /// Search terms, the most recent first
+    #[serde(default)]
+    pub recent_queries: Vec<String>,
+    /// Preferences of the UI, e.g. the theme, as set by the client
+    #[serde(default)]
+    pub preferences: Map<String, Value>,
+}
+
+#[async_trait]
+impl Persistable for UserSession {
+    fn new(key: String) -> Self {
+        UserSession {
+            id: key,
+            ..Default::default()
+        }
+    }
+
+    /// Save to a single profile
+    async fn save_to_one(&self, profile_name: &str) -> PersistenceResult<()> {
+        self.save_to_profile(profile_name).await?;
+        Ok(())
+    }
+
+    // Saves to all profiles
+    async fn save(&self) -> PersistenceResult<()> {
+        self.save_to_all().await
+    }
+
+    /// Load key from the fastest operator
+    async fn load(&mut self) -> PersistenceResult<Self> {
+        let op = &self.load_config().await?.1;
+        let key = self.get_key();
+        let obj = self.load_from_operator(&key, op).await?;
+        Ok(obj)
+    }
+
+    /// The session ID is hashed, so that keys are safe file names of the
+    /// same length whatever the ID
+    fn get_key(&self) -> String {
+        format!("session_{:x}.json", Sha256::digest(self.id.as_bytes()))
+    }
+}
+
+impl UserSession {
+    /// Remember a search term as the most recent one
+    pub fn record_query(&mut self, search_term: &str) {
+        let search_term = search_term.trim();
+        if search_term.is_empty() {
+            return;
+        }
+        self.recent_queries
+            .retain(|query| !query.eq_ignore_ascii_case(search_term));
+        self.recent_queries.insert(0, search_term.to_string());
+        self.recent_queries.truncate(MAX_RECENT_QUERIES);
+    }
+
+    /// The recent search terms starting with `prefix` (ignoring case),
+    /// the most recent first
+    pub fn queries_starting_with(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let prefix = prefix.trim().to_lowercase();
+        self.recent_queries
+            .iter()
+            .filter(|query| query.to_lowercase().starts_with(&prefix))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Merge preferences into those of the session
+    ///
+    /// A `null` value removes a preference.
+    pub fn update_preferences(&mut self, preferences: Map<String, Value>) {
+        for (key, value) in preferences {
+            if value.is_null() {
+                self.preferences.remove(&key);
+            } else {
+                self.preferences.insert(key, value);
+            }
+        }
+    }
+}
+
+struct Entry {
+    session: UserSession,
+    used: Instant,
+}
+
+/// The sessions of this process
+pub struct SessionStore {
+    sessions: Mutex<AHashMap<String, Entry>>,
+}
+
+impl SessionStore {
+    /// Get the store; sessions are loaded when first used
+    pub async fn instance() -> &'static SessionStore {
+        SESSIONS
+            .get_or_init(|| async {
+                SessionStore {
+                    sessions: Mutex::new(AHashMap::new()),
+                }
+            })
+            .await
+    }
+
+    /// Get a session, a new one if the ID is unknown
+    pub async fn get(&self, id: &str) -> UserSession {
+        self.with_session(id, |session| session.clone()).await
+    }
+
+    /// Change a session and persist it
+    ///
+    /// Returns the changed session.
+    pub async fn update(&self, id: &str, change: impl FnOnce(&mut UserSession)) -> UserSession {
+        let session = self
+            .with_session(id, |session| {
+                change(session);
+                session.clone()
+            })
+            .await;
+        if is_persisted(id) {
+            if let Err(e) = session.save().await {
+                log::warn!("Failed to persist session `{}`: {:?}", id, e);
+            }
+        }
+        session
+    }
+
+    /// Run `f` on the session with the given ID, loading it on first use
+    ///
+    /// The store is not locked while the session is loaded, so that other
+    /// sessions don't wait for persistence.
+    async fn with_session<T>(&self, id: &str, f: impl FnOnce(&mut UserSession) -> T) -> T {
+        if let Some(entry) = self.sessions.lock().await.get_mut(id) {
+            entry.used = Instant::now();
+            return f(&mut entry.session);
+        }
+        let session = load(id).await;
+        let mut sessions = self.sessions.lock().await;
+        let now = Instant::now();
+        if !sessions.contains_key(id) {
+            make_room(&mut sessions, now);
+        }
+        let entry = sessions
+            .entry(id.to_string())
+            .or_insert(Entry { session, used: now });
+        entry.used = now;
+        f(&mut entry.session)
+    }
+}
+
+/// The persisted session with the given ID, or a new one
+async fn load(id: &str) -> UserSession {
+    let mut session = <UserSession as Persistable>::new(id.to_string());
+    if !is_persisted(id) {
+        return session;
+    }
+    match session.load().await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            log::debug!("Starting new session `{}`: {:?}", id, e);
+            session
+        }
+    }
+}
+
+/// Drop idle sessions, then the least recently used ones, so that another
+/// session can be kept
+fn make_room(sessions: &mut AHashMap<String, Entry>, now: Instant) {
+    sessions.retain(|_, entry| now.saturating_duration_since(entry.used) < SESSION_IDLE_TTL);
+    while sessions.len() >= MAX_SESSIONS {
+        let Some(oldest) = sessions
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(id, _)| id.clone())
+        else {
+            break;
+        };
+        sessions.remove(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_session() {
+        let mut session = <UserSession as Persistable>::new("alice".to_string());
+        assert_eq!(
+            session.get_key(),
+            "session_2bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90.json"
+        );
+
+        for query in ["rust", "tokio", "Rust", " "] {
+            session.record_query(query);
+        }
+        assert_eq!(session.recent_queries, vec!["Rust", "tokio"]);
+        assert_eq!(session.queries_starting_with("RU", 10), vec!["Rust"]);
+        assert_eq!(session.queries_starting_with("", 1), vec!["Rust"]);
+        assert!(session.queries_starting_with("go", 10).is_empty());
+        for i in 0..MAX_RECENT_QUERIES {
+            session.record_query(&format!("query {i}"));
+        }
+        assert_eq!(session.recent_queries.len(), MAX_RECENT_QUERIES);
+        assert_eq!(session.recent_queries[0], "query 19");
+
+        let preferences = |value: Value| value.as_object().unwrap().clone();
+        session.update_preferences(preferences(json!({"theme": "dark", "page_size": 20})));
+        session.update_preferences(preferences(json!({"theme": null, "page_size": 50})));
+        assert_eq!(Value::Object(session.preferences), json!({"page_size": 50}));
+    }
+
+    #[test]
+    fn test_session_ids() {
+        assert!(is_persisted(&principal_session("alice")));
+        assert!(!is_persisted(&anonymous_session("alice")));
+        assert!(!is_persisted(&anonymous_session("user:alice")));
+    }
+
+    #[test]
+    fn test_make_room() {
+        let start = Instant::now();
+        let entry = |id: &str, used: Instant| Entry {
+            session: <UserSession as Persistable>::new(id.to_string()),
+            used,
+        };
+        let mut sessions: AHashMap<String, Entry> = (0..MAX_SESSIONS)
+            .map(|i| {
+                let used = start + SESSION_IDLE_TTL + Duration::from_millis(i as u64);
+                (i.to_string(), entry(&i.to_string(), used))
+            })
+            .collect();
+        sessions.get_mut("1").unwrap().used = start;
+        let now = start + SESSION_IDLE_TTL + Duration::from_millis(MAX_SESSIONS as u64);
+
+        make_room(&mut sessions, now);
+        assert_eq!(sessions.len(), MAX_SESSIONS - 1);
+        assert!(!sessions.contains_key("1"));
+
+        // Without idle sessions, the least recently used one is dropped
+        sessions.insert("new".to_string(), entry("new", now));
+        make_room(&mut sessions, now);
+        assert_eq!(sessions.len(), MAX_SESSIONS - 1);
+        assert!(!sessions.contains_key("0"));
+        assert!(sessions.contains_key("new"));
+    }
+}
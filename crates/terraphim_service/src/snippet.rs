@@ -0,0 +1,177 @@
+//! Snippets of search results
+//!
+//! A snippet is a short window of the body centered on a cluster of
+//! matches of the search query. Clusters are found from the highlight spans
+//! of the body: the window with the most distinct query terms, then the
+//! most matches, wins.
+
+use ahash::AHashSet;
+use terraphim_types::{Document, HighlightField, HighlightSpan, Snippet};
+
+/// Maximum number of snippets per document
+const MAX_SNIPPETS: usize = 3;
+
+/// Length of a snippet in bytes, before it is widened to word boundaries
+const SNIPPET_LENGTH: usize = 200;
+
+/// How far a snippet is widened to reach a word boundary, in bytes
+const MAX_WIDENING: usize = 20;
+
+/// Move `start` back to the start of its word
+fn word_start(body: &str, start: usize) -> usize {
+    let mut position = start;
+    while !body.is_char_boundary(position) {
+        position -= 1;
+    }
+    match body[..position].rfind(char::is_whitespace) {
+        Some(whitespace) if position - whitespace <= MAX_WIDENING => {
+            whitespace + body[whitespace..].chars().next().map_or(0, char::len_utf8)
+        }
+        Some(_) => position,
+        None if position <= MAX_WIDENING => 0,
+        None => position,
+    }
+}
+
+/// Move `end` forward to the end of its word
+fn word_end(body: &str, end: usize) -> usize {
+    let mut position = end;
+    while !body.is_char_boundary(position) {
+        position += 1;
+    }
+    match body[position..].find(char::is_whitespace) {
+        Some(offset) if offset <= MAX_WIDENING => position + offset,
+        Some(_) => position,
+        None if body.len() - position <= MAX_WIDENING => body.len(),
+        None => position,
+    }
+}
+
+/// The best cluster of spans which fits in a snippet, as the indices of the
+/// first and last span of the cluster
+fn best_cluster(spans: &[&HighlightSpan]) -> Option<(usize, usize)> {
+    let mut best: Option<((usize, usize), (usize, usize))> = None;
+    for first in 0..spans.len() {
+        let mut terms = AHashSet::new();
+        let mut last = first;
+        for (index, span) in spans.iter().enumerate().skip(first) {
+            if span.end - spans[first].start > SNIPPET_LENGTH {
+                break;
+            }
+            terms.insert(span.term.as_str());
+            last = index;
+        }
+        let score = (terms.len(), last - first + 1);
+        let is_better = match best {
+            Some((best_score, _)) => score > best_score,
+            None => true,
+        };
+        if is_better {
+            best = Some((score, (first, last)));
+        }
+    }
+    best.map(|(_, cluster)| cluster)
+}
+
+/// Build the snippets of a body from the highlight spans of the body,
+/// sorted by start
+fn build_snippets(body: &str, mut spans: Vec<&HighlightSpan>) -> Vec<Snippet> {
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    while windows.len() < MAX_SNIPPETS {
+        let Some((first, last)) = best_cluster(&spans) else {
+            break;
+        };
+        let (cluster_start, cluster_end) = (spans[first].start, spans[last].end);
+        let margin = SNIPPET_LENGTH.saturating_sub(cluster_end - cluster_start) / 2;
+        let start = cluster_start.saturating_sub(margin);
+        let end = (cluster_end + margin).min(body.len());
+        windows.push((word_start(body, start), word_end(body, end)));
+        // Matches in the window don't make another snippet
+        spans.retain(|span| span.end <= start || span.start >= end);
+    }
+    if windows.is_empty() && !body.is_empty() {
+        // Without matches, the start of the body is the snippet
+        windows.push((0, word_end(body, SNIPPET_LENGTH.min(body.len()))));
+    }
+
+    windows.sort();
+    let mut snippets: Vec<Snippet> = Vec::new();
+    for (start, end) in windows {
+        match snippets.last_mut() {
+            // Windows widened to word boundaries may overlap
+            Some(previous) if start <= previous.end => {
+                previous.end = previous.end.max(end);
+                previous.text = body[previous.start..previous.end].to_string();
+            }
+            _ => snippets.push(Snippet {
+                text: body[start..end].to_string(),
+                start,
+                end,
+            }),
+        }
+    }
+    snippets
+}
+
+/// Set the snippets of the documents from their highlight spans
+pub(crate) fn add_snippets(documents: &mut [Document]) {
+    for document in documents.iter_mut() {
+        let mut spans: Vec<&HighlightSpan> = document
+            .highlights
+            .iter()
+            .filter(|span| span.field == HighlightField::Body)
+            .collect();
+        spans.sort_by_key(|span| span.start);
+        document.snippets = build_snippets(&document.body, spans);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(body: &str, term: &str, from: usize) -> HighlightSpan {
+        let start = from + body[from..].find(term).unwrap();
+        HighlightSpan {
+            term: term.to_string(),
+            start,
+            end: start + term.len(),
+            field: HighlightField::Body,
+        }
+    }
+
+    #[test]
+    fn test_snippets_centered_on_best_cluster() {
+        let filler = "lorem ipsum dolor sit amet ".repeat(20);
+        let body = format!(
+            "{filler}rust appears alone here. {filler}rust and cargo build together. {filler}"
+        );
+        let lone = span(&body, "rust", 0);
+        let cluster = span(&body, "rust", lone.end);
+        let cargo = span(&body, "cargo", cluster.end);
+        let spans = vec![&lone, &cluster, &cargo];
+
+        let snippets = build_snippets(&body, spans);
+        assert_eq!(snippets.len(), 2);
+        assert!(snippets[0].text.contains("rust appears alone"));
+        assert!(snippets[1].text.contains("rust and cargo build"));
+        for snippet in &snippets {
+            assert_eq!(snippet.text, body[snippet.start..snippet.end]);
+            assert!(snippet.text.len() <= SNIPPET_LENGTH + 2 * MAX_WIDENING);
+            // Snippets start and end on word boundaries
+            assert!(!snippet.text.starts_with(char::is_whitespace));
+            assert!(!snippet.text.ends_with(char::is_whitespace));
+        }
+        // The best cluster is in the middle of its snippet
+        let middle = (snippets[1].start + snippets[1].end) / 2;
+        assert!(middle > cluster.start && middle < cargo.end);
+    }
+
+    #[test]
+    fn test_snippet_without_matches() {
+        let snippets = build_snippets("A short body", vec![]);
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].text, "A short body");
+        assert!(build_snippets("", vec![]).is_empty());
+    }
+}
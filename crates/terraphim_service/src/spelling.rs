@@ -0,0 +1,135 @@
+//! "Did you mean" corrections for searches without results
+//!
+//! Query words are corrected against two dictionaries per role: the words of
+//! the role thesaurus and the words of the documents found in the haystacks
+//! of the role so far.
+
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ahash::{AHashMap, AHashSet};
+use serde::{Deserialize, Serialize};
+use terraphim_automata::spelling::{self, TermDictionary};
+use terraphim_types::{Document, RoleName};
+
+use crate::thesaurus_cache::{Derived, ThesaurusCache};
+
+/// Minimum confidence of a correction to search for it automatically
+pub const AUTO_CORRECT_CONFIDENCE: f64 = 0.8;
+
+/// A correction suggested for a search without results
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DidYouMean {
+    /// The search term as given
+    pub original: String,
+    /// The corrected search term
+    pub suggestion: String,
+    /// How likely the correction is right, from 0 to 1
+    pub confidence: f64,
+    /// Whether the results are those of the corrected search term
+    pub applied: bool,
+}
+
+/// Words of the documents found for a role
+#[derive(Debug, Default)]
+struct Corpus {
+    dictionary: TermDictionary,
+    /// IDs of the documents already counted
+    seen: AHashSet<String>,
+}
+
+fn corpora() -> &'static Mutex<AHashMap<RoleName, Corpus>> {
+    static CORPORA: OnceLock<Mutex<AHashMap<RoleName, Corpus>>> = OnceLock::new();
+    CORPORA.get_or_init(Default::default)
+}
+
+/// Thesaurus dictionaries, with the version of the cached thesaurus they
+/// were built from
+fn thesaurus_dictionaries() -> &'static Mutex<AHashMap<RoleName, Derived<TermDictionary>>> {
+    static DICTIONARIES: OnceLock<Mutex<AHashMap<RoleName, Derived<TermDictionary>>>> =
+        OnceLock::new();
+    DICTIONARIES.get_or_init(Default::default)
+}
+
+/// Count the words of the documents found for a role which weren't counted
+/// before
+///
+/// Documents with visibility labels are left out, so that corrections
+/// don't reveal their words to anyone.
+pub(crate) fn learn(role: &RoleName, documents: &[Arc<Document>]) {
+    let mut corpora = corpora().lock().unwrap();
+    let corpus = corpora.entry(role.clone()).or_default();
+    for document in documents {
+        if !document.visibility.is_empty() {
+            continue;
+        }
+        if corpus.seen.insert(document.id.clone()) {
+            corpus.dictionary.add_text(&document.title);
+            corpus.dictionary.add_text(&document.body);
+        }
+    }
+}
+
+/// The dictionary of the cached thesaurus of a role, if the thesaurus is
+/// cached
+fn thesaurus_dictionary(role: &RoleName) -> Option<Arc<TermDictionary>> {
+    let cached = ThesaurusCache::instance().get(role)?;
+    if let Some((version, dictionary)) = thesaurus_dictionaries().lock().unwrap().get(role) {
+        if *version == cached.version {
+            return Some(dictionary.clone());
+        }
+    }
+    let dictionary = Arc::new(TermDictionary::from_thesaurus(&cached.thesaurus));
+    thesaurus_dictionaries()
+        .lock()
+        .unwrap()
+        .insert(role.clone(), (cached.version, dictionary.clone()));
+    Some(dictionary)
+}
+
+/// Correct the search term of a role
+///
+/// Returns `None` if all words are known or no known word is close enough.
+pub(crate) fn correct(role: &RoleName, search_term: &str) -> Option<DidYouMean> {
+    let thesaurus = thesaurus_dictionary(role);
+    let corpora = corpora().lock().unwrap();
+    let mut dictionaries: Vec<&TermDictionary> = Vec::new();
+    if let Some(thesaurus) = &thesaurus {
+        dictionaries.push(thesaurus);
+    }
+    if let Some(corpus) = corpora.get(role) {
+        dictionaries.push(&corpus.dictionary);
+    }
+    let correction = spelling::correct(search_term, &dictionaries)?;
+    Some(DidYouMean {
+        original: correction.original,
+        suggestion: correction.corrected,
+        confidence: correction.confidence,
+        applied: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correct_from_documents() {
+        let role = RoleName::new("Spelling test");
+        let document = Arc::new(Document {
+            id: "doc".to_string(),
+            title: "Release checklist".to_string(),
+            body: "Steps before every release".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(correct(&role, "relese"), None);
+
+        learn(&role, std::slice::from_ref(&document));
+        // Documents are only counted once
+        learn(&role, &[document]);
+        let did_you_mean = correct(&role, "relese checklst").unwrap();
+        assert_eq!(did_you_mean.suggestion, "release checklist");
+        assert!(did_you_mean.confidence >= AUTO_CORRECT_CONFIDENCE);
+        assert!(!did_you_mean.applied);
+        assert_eq!(correct(&role, "release"), None);
+    }
+}
@@ -0,0 +1,363 @@
+//! Suggestions for the search box
+//!
+//! Suggestions come from three sources: terms of the role thesaurus
+//! starting with what was typed, recent searches of the user session, and
+//! concepts which co-occur in the rolegraph with the concepts typed. They
+//! are merged into one ranked list.
+//!
+//! While writing, the text before the cursor is context: thesaurus terms of
+//! concepts which co-occur in the rolegraph with the concepts of the
+//! context are suggested first, so writing about a topic completes its
+//! vocabulary.
+//!
+//! The compact thesaurus used for autocompletion is persisted once built,
+//! keyed by the role and a fingerprint of its thesaurus, so it is loaded
+//! instead of rebuilt after a restart. [`spawn_warmup`] prepares the ones
+//! of all roles when the server starts.
+
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+use terraphim_automata::CompactThesaurus;
+use terraphim_config::ConfigState;
+use terraphim_persistence::autocomplete::{load_autocomplete_index, save_autocomplete_index};
+use terraphim_rolegraph::GraphNode;
+use terraphim_types::RoleName;
+use tokio::task::JoinHandle;
+
+use crate::thesaurus_cache::{CachedThesaurus, Derived, ThesaurusCache};
+use crate::TerraphimService;
+
+/// Where a suggestion comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionSource {
+    /// A recent search of the user session
+    History,
+    /// A thesaurus term starting with the query or with its words in any
+    /// order, or similar to it if no term does
+    Autocomplete,
+    /// A concept related to the concepts in the query
+    Concept,
+}
+
+impl SuggestionSource {
+    /// Score of the first suggestion of the source
+    fn weight(&self) -> f64 {
+        match self {
+            SuggestionSource::History => 1.0,
+            SuggestionSource::Autocomplete => 0.9,
+            SuggestionSource::Concept => 0.6,
+        }
+    }
+}
+
+/// A suggestion for the search box
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suggestion {
+    /// The suggested search term
+    pub term: String,
+    /// Where the suggestion comes from
+    pub source: SuggestionSource,
+    /// Higher is better
+    pub score: f64,
+}
+
+/// Compact thesauri for autocompletion, with the version of the cached
+/// thesaurus they were built from
+fn compact_thesauri() -> &'static Mutex<AHashMap<RoleName, Derived<CompactThesaurus>>> {
+    static COMPACT: OnceLock<Mutex<AHashMap<RoleName, Derived<CompactThesaurus>>>> =
+        OnceLock::new();
+    COMPACT.get_or_init(Default::default)
+}
+
+/// The persisted compact thesaurus of a role, if it was built from the
+/// same terms
+async fn load_compact_thesaurus(role: &RoleName, fingerprint: u64) -> Option<CompactThesaurus> {
+    let bytes = match load_autocomplete_index(role.original.as_str(), fingerprint).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.is_not_found() => return None,
+        Err(e) => {
+            log::debug!("Failed to load autocomplete index of role `{role}`: {e}");
+            return None;
+        }
+    };
+    CompactThesaurus::from_bytes(&bytes)
+        .map_err(|e| log::warn!("Ignoring invalid autocomplete index of role `{role}`: {e}"))
+        .ok()
+}
+
+/// The compact thesaurus of a role, rebuilt whenever the cached thesaurus
+/// of the role is replaced
+///
+/// A compact thesaurus is loaded from persistence if one was built from the
+/// same terms before, and persisted otherwise.
+pub(crate) async fn compact_thesaurus(
+    role: &RoleName,
+    cached: &CachedThesaurus,
+) -> terraphim_automata::Result<Arc<CompactThesaurus>> {
+    if let Some((version, compact)) = compact_thesauri().lock().unwrap().get(role) {
+        if *version == cached.version {
+            return Ok(compact.clone());
+        }
+    }
+    let fingerprint = cached.thesaurus.fingerprint();
+    let compact = match load_compact_thesaurus(role, fingerprint).await {
+        Some(compact) => Arc::new(compact),
+        None => {
+            let compact = CompactThesaurus::from_thesaurus(&cached.thesaurus)?;
+            if let Err(e) =
+                save_autocomplete_index(role.original.as_str(), fingerprint, &compact.to_bytes()?)
+                    .await
+            {
+                log::warn!("Failed to persist autocomplete index of role `{role}`: {e}");
+            }
+            Arc::new(compact)
+        }
+    };
+    compact_thesauri()
+        .lock()
+        .unwrap()
+        .insert(role.clone(), (cached.version, compact.clone()));
+    Ok(compact)
+}
+
+/// Prepare the compact thesauri of all roles with a knowledge graph in the
+/// background, so the first suggestions needn't wait for them
+pub fn spawn_warmup(config_state: ConfigState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let service = TerraphimService::new(config_state);
+        let roles: Vec<RoleName> = service
+            .config_state
+            .config
+            .lock()
+            .await
+            .roles
+            .values()
+            .filter(|role| role.kg.is_some())
+            .map(|role| role.name.clone())
+            .collect();
+        for role in roles {
+            let cached = ThesaurusCache::instance()
+                .get_or_load(&role, || service.load_thesaurus(&role))
+                .await;
+            let prepared = match cached {
+                Ok(cached) => compact_thesaurus(&role, &cached)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = prepared {
+                log::warn!("Failed to prepare autocompletion of role `{role}`: {e}");
+            }
+        }
+    })
+}
+
+/// Number of thesaurus terms considered for every suggestion when terms
+/// related to the context are moved first
+pub(crate) const CONTEXT_CANDIDATES: usize = 5;
+
+/// Autocompletions with those of concepts related to the context first, the
+/// most strongly related first, otherwise in their order
+///
+/// `autocomplete` holds terms with the ID of their concept, `related` the
+/// weight of every concept related to the context, see
+/// [`terraphim_rolegraph::RoleGraph::related_concepts`]. Returns at most
+/// `limit` terms.
+pub(crate) fn boost_related(
+    mut autocomplete: Vec<(String, u64)>,
+    related: &AHashMap<u64, u64>,
+    limit: usize,
+) -> Vec<String> {
+    autocomplete.sort_by_key(|(_, id)| std::cmp::Reverse(related.get(id).copied().unwrap_or(0)));
+    autocomplete
+        .into_iter()
+        .take(limit)
+        .map(|(term, _)| term)
+        .collect()
+}
+
+/// Merge the suggestions of all sources into one list of at most `limit`
+/// suggestions, the best first
+///
+/// Each list is expected best first. A term suggested by several sources
+/// (ignoring case) is listed once, with the summed score and the source of
+/// the first list in the order history, autocomplete, concepts.
+pub(crate) fn rank(
+    history: Vec<String>,
+    autocomplete: Vec<String>,
+    concepts: Vec<GraphNode>,
+    limit: usize,
+) -> Vec<Suggestion> {
+    let sources = [
+        (SuggestionSource::History, history),
+        (SuggestionSource::Autocomplete, autocomplete),
+        (
+            SuggestionSource::Concept,
+            concepts.into_iter().map(|node| node.label).collect(),
+        ),
+    ];
+
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+    let mut positions: AHashMap<String, usize> = AHashMap::new();
+    for (source, terms) in sources {
+        for (position, term) in terms.into_iter().enumerate() {
+            let score = source.weight() / (1 + position) as f64;
+            match positions.get(&term.to_lowercase()) {
+                Some(&index) => suggestions[index].score += score,
+                None => {
+                    positions.insert(term.to_lowercase(), suggestions.len());
+                    suggestions.push(Suggestion {
+                        term,
+                        source,
+                        score,
+                    });
+                }
+            }
+        }
+    }
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(limit);
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concept(label: &str) -> GraphNode {
+        GraphNode {
+            id: 0,
+            label: label.to_string(),
+            rank: 1,
+            community: 0,
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let suggestions = rank(
+            vec!["rust async".to_string(), "rust".to_string()],
+            vec!["rust".to_string(), "rustdoc".to_string()],
+            vec![concept("cargo")],
+            10,
+        );
+        let terms: Vec<(&str, SuggestionSource)> = suggestions
+            .iter()
+            .map(|suggestion| (suggestion.term.as_str(), suggestion.source))
+            .collect();
+        assert_eq!(
+            terms,
+            vec![
+                // 0.5 from the history and 0.9 from autocompletion
+                ("rust", SuggestionSource::History),
+                ("rust async", SuggestionSource::History),
+                ("cargo", SuggestionSource::Concept),
+                ("rustdoc", SuggestionSource::Autocomplete),
+            ]
+        );
+        assert_eq!(rank(vec![], vec![], vec![], 10), vec![]);
+        assert_eq!(
+            rank(vec!["a".to_string(), "b".to_string()], vec![], vec![], 1).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_boost_related() {
+        let autocomplete = vec![
+            ("rust".to_string(), 1),
+            ("rustdoc".to_string(), 2),
+            ("rustc".to_string(), 3),
+            ("rustls".to_string(), 4),
+        ];
+        let related = AHashMap::from([(3, 2), (4, 5)]);
+        assert_eq!(
+            boost_related(autocomplete.clone(), &related, 3),
+            vec!["rustls", "rustc", "rust"]
+        );
+        assert_eq!(
+            boost_related(autocomplete, &AHashMap::new(), 2),
+            vec!["rust", "rustdoc"]
+        );
+    }
+
+    #[test]
+    fn test_thesaurus_fingerprint() {
+        use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+        let thesaurus = |terms: &[(&str, u64)]| {
+            let mut thesaurus = Thesaurus::new("fingerprint".to_string());
+            for (term, id) in terms {
+                let value = NormalizedTermValue::from(*term);
+                thesaurus.insert(value.clone(), NormalizedTerm::new(*id, value));
+            }
+            thesaurus
+        };
+        let fingerprint = thesaurus(&[("rust", 1), ("cargo", 2)]).fingerprint();
+        assert_eq!(
+            fingerprint,
+            thesaurus(&[("cargo", 2), ("rust", 1)]).fingerprint()
+        );
+        assert_ne!(
+            fingerprint,
+            thesaurus(&[("rust", 1), ("cargo", 3)]).fingerprint()
+        );
+        assert_ne!(fingerprint, thesaurus(&[("rust", 1)]).fingerprint());
+    }
+
+    #[tokio::test]
+    async fn test_compact_thesaurus_per_role() {
+        use terraphim_types::{NormalizedTerm, NormalizedTermValue, Thesaurus};
+
+        let cached = |terms: &[&str], version| {
+            let mut thesaurus = Thesaurus::new("suggest".to_string());
+            for (id, term) in terms.iter().enumerate() {
+                let value = NormalizedTermValue::from(*term);
+                thesaurus.insert(value.clone(), NormalizedTerm::new(id as u64 + 1, value));
+            }
+            CachedThesaurus {
+                thesaurus: Arc::new(thesaurus),
+                version,
+            }
+        };
+        async fn terms(role: &RoleName, cached: &CachedThesaurus) -> Vec<String> {
+            compact_thesaurus(role, cached)
+                .await
+                .unwrap()
+                .autocomplete("r", 10)
+                .into_iter()
+                .map(|(term, _)| term)
+                .collect()
+        }
+        let engineer = RoleName::new("suggest engineer");
+        let operator = RoleName::new("suggest operator");
+
+        let engineer_v1 = cached(&["rust", "rustdoc"], 1);
+        let operator_v1 = cached(&["rotation", "repair"], 1);
+        assert_eq!(
+            terms(&engineer, &engineer_v1).await,
+            vec!["rust", "rustdoc"]
+        );
+        assert_eq!(
+            terms(&operator, &operator_v1).await,
+            vec!["repair", "rotation"]
+        );
+        // Reused while the thesaurus of the role is the same version
+        assert!(Arc::ptr_eq(
+            &compact_thesaurus(&engineer, &engineer_v1).await.unwrap(),
+            &compact_thesaurus(&engineer, &engineer_v1).await.unwrap()
+        ));
+
+        // Rebuilt once the thesaurus of the role is replaced
+        let engineer_v2 = cached(&["rust", "rustc"], 2);
+        assert_eq!(terms(&engineer, &engineer_v2).await, vec!["rust", "rustc"]);
+        assert_eq!(
+            terms(&operator, &operator_v1).await,
+            vec!["repair", "rotation"]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Process-wide cache of the thesauri of all roles
+//!
+//! Loading a thesaurus means reading (and for local knowledge graphs,
+//! rebuilding) it from its source, which is far too slow to do on every
+//! search. The cache keeps one shared instance per role, so search, the
+//! desktop commands and any other consumer see the same thesaurus.
+//!
+//! Every cached thesaurus has a version, which is increased whenever the
+//! thesaurus of a role is replaced. Entries are dropped by the watcher
+//! started with [`watch_sources`] when the files they were built from
+//! change, and reloaded by the next consumer. When the config is updated,
+//! all entries are dropped and the sources of the new config are watched
+//! instead.
+//!
+//! Search results ranked with a thesaurus are dropped from the
+//! [`ResultCache`] whenever the thesaurus is replaced or dropped.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ahash::AHashMap;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use terraphim_config::Config;
+use terraphim_types::{RoleName, Thesaurus};
+
+use crate::result_cache::ResultCache;
+
+/// A thesaurus shared through the [`ThesaurusCache`]
+#[derive(Debug, Clone)]
+pub struct CachedThesaurus {
+    pub thesaurus: Arc<Thesaurus>,
+    /// Increases whenever the thesaurus of the role is replaced
+    pub version: u64,
+}
+
+/// A value derived from a cached thesaurus, with the
+/// [`CachedThesaurus::version`] it was built from
+pub(crate) type Derived<T> = (u64, Arc<T>);
+
+#[derive(Debug, Default)]
+pub struct ThesaurusCache {
+    entries: Mutex<AHashMap<RoleName, CachedThesaurus>>,
+    version: AtomicU64,
+}
+
+impl ThesaurusCache {
+    /// The cache of this process
+    pub fn instance() -> &'static ThesaurusCache {
+        static CACHE: OnceLock<ThesaurusCache> = OnceLock::new();
+        CACHE.get_or_init(ThesaurusCache::default)
+    }
+
+    /// Get the cached thesaurus of a role
+    pub fn get(&self, role: &RoleName) -> Option<CachedThesaurus> {
+        self.entries.lock().unwrap().get(role).cloned()
+    }
+
+    /// Cache the thesaurus of a role, replacing the cached one
+    pub fn insert(&self, role: RoleName, thesaurus: Thesaurus) -> CachedThesaurus {
+        let cached = CachedThesaurus {
+            thesaurus: Arc::new(thesaurus),
+            version: self.version.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+        ResultCache::instance().invalidate(&role);
+        self.entries.lock().unwrap().insert(role, cached.clone());
+        cached
+    }
+
+    /// Get the cached thesaurus of a role, or load and cache it with `load`
+    ///
+    /// Concurrent consumers which miss the cache may load the thesaurus at
+    /// the same time; the last one to finish wins.
+    pub async fn get_or_load<F, Fut, E>(
+        &self,
+        role: &RoleName,
+        load: F,
+    ) -> Result<CachedThesaurus, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Thesaurus, E>>,
+    {
+        if let Some(cached) = self.get(role) {
+            return Ok(cached);
+        }
+        let thesaurus = load().await?;
+        Ok(self.insert(role.clone(), thesaurus))
+    }
+
+    /// Drop the cached thesaurus of a role
+    pub fn invalidate(&self, role: &RoleName) {
+        ResultCache::instance().invalidate(role);
+        if self.entries.lock().unwrap().remove(role).is_some() {
+            log::info!("Invalidated cached thesaurus of role `{role}`");
+        }
+    }
+
+    /// Drop all cached thesauri, e.g. because the config changed
+    pub fn invalidate_all(&self) {
+        ResultCache::instance().invalidate_all();
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Directories of Markdown files the thesaurus of each role is built from
+///
+/// Automata files aren't watched: they are generated, often by building
+/// the thesaurus itself, which would invalidate it right away.
+fn sources(config: &Config) -> Vec<(RoleName, PathBuf)> {
+    let mut sources = Vec::new();
+    for (role_name, role) in &config.roles {
+        let Some(kg) = &role.kg else {
+            continue;
+        };
+        if let Some(local) = &kg.knowledge_graph_local {
+            sources.push((role_name.clone(), local.path.clone()));
+        }
+        // Thesauri of knowledge graph roles are also built from the haystacks
+        for haystack in &role.haystacks {
+            sources.push((role_name.clone(), haystack.path.clone()));
+        }
+    }
+    sources
+}
+
+/// The watcher started by [`watch_sources`], if any
+static WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+
+/// Watch the sources of the thesauri of all roles and invalidate the cached
+/// thesaurus of a role when one of its Markdown files changes
+///
+/// Watching lasts until the process exits; a config update replaces the
+/// watched sources, see [`rewatch_sources`]. Sources which don't exist are
+/// skipped.
+pub fn watch_sources(config: &Config) -> notify::Result<()> {
+    let watcher = source_watcher(config)?;
+    *WATCHER.lock().unwrap() = Some(watcher);
+    Ok(())
+}
+
+/// Watch the sources of an updated config instead, if sources are watched
+///
+/// If the new sources cannot be watched, the old ones still are.
+pub(crate) fn rewatch_sources(config: &Config) {
+    let mut watcher = WATCHER.lock().unwrap();
+    if watcher.is_none() {
+        return;
+    }
+    match source_watcher(config) {
+        Ok(new_watcher) => *watcher = Some(new_watcher),
+        Err(e) => log::warn!("Failed to watch thesaurus sources of the updated config: {e}"),
+    }
+}
+
+fn source_watcher(config: &Config) -> notify::Result<RecommendedWatcher> {
+    let sources = sources(config);
+    let watched = sources.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) if !event.kind.is_access() => event,
+            Ok(_) => return,
+            Err(e) => {
+                log::warn!("Thesaurus source watcher error: {e}");
+                return;
+            }
+        };
+        let cache = ThesaurusCache::instance();
+        for (role, source) in &watched {
+            let is_source = |path: &PathBuf| {
+                path.starts_with(source) && path.extension().is_some_and(|ext| ext == "md")
+            };
+            if event.paths.iter().any(is_source) {
+                cache.invalidate(role);
+            }
+        }
+    })?;
+    for (role, source) in &sources {
+        if !source.exists() {
+            log::debug!("Not watching missing thesaurus source {source:?} of role `{role}`");
+            continue;
+        }
+        watcher.watch(source, RecursiveMode::Recursive)?;
+    }
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_or_load() {
+        let cache = ThesaurusCache::default();
+        let role = RoleName::new("Engineer");
+
+        let first = cache
+            .get_or_load(&role, || async {
+                Ok::<_, ()>(Thesaurus::new("first".to_string()))
+            })
+            .await
+            .unwrap();
+        let second = cache
+            .get_or_load(&role, || async { Err(()) })
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&first.thesaurus, &second.thesaurus));
+        assert_eq!(first.version, second.version);
+
+        cache.invalidate(&role);
+        assert!(cache.get(&role).is_none());
+        let reloaded = cache.insert(role.clone(), Thesaurus::new("reloaded".to_string()));
+        assert!(reloaded.version > first.version);
+        assert_eq!(cache.get(&role).unwrap().thesaurus.name(), "reloaded");
+    }
+}
@@ -1,12 +1,16 @@
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::hash_map::Iter;
+use serde_json::{Map, Value};
+use std::cmp;
+use std::collections::hash_map::{Entry, Iter};
 use std::collections::HashSet;
 use std::fmt::{self, Display, Formatter};
 use std::iter::IntoIterator;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub struct RoleName {
@@ -130,11 +134,75 @@ pub struct NormalizedTerm {
     // This field is currently called `nterm` in the JSON
     #[serde(rename = "nterm")]
     pub value: NormalizedTermValue,
+    /// How much the term counts compared to the normalized value, if it is a
+    /// loose synonym
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<TermWeight>,
 }
 
 impl NormalizedTerm {
     pub fn new(id: u64, value: NormalizedTermValue) -> Self {
-        Self { id, value }
+        Self {
+            id,
+            value,
+            weight: None,
+        }
+    }
+
+    /// Set the weight of the term
+    pub fn with_weight(mut self, weight: Option<TermWeight>) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// The weight of the term, 1.0 unless it is a loose synonym
+    pub fn weight(&self) -> f64 {
+        self.weight.map_or(1.0, TermWeight::get)
+    }
+}
+
+/// The weight of a synonym between 0.0 and 1.0
+///
+/// A synonym with a weight of 0.5 contributes half as much to the rank of a
+/// document as the normalized value of its concept.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TermWeight(f64);
+
+impl TermWeight {
+    /// A weight, clamped to 0.0 to 1.0, unless it is not a number
+    pub fn new(weight: f64) -> Option<Self> {
+        (!weight.is_nan()).then(|| Self(weight.clamp(0.0, 1.0)))
+    }
+
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl PartialEq for TermWeight {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for TermWeight {}
+
+impl PartialOrd for TermWeight {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TermWeight {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl std::hash::Hash for TermWeight {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
     }
 }
 
@@ -174,6 +242,97 @@ impl Display for Concept {
     }
 }
 
+/// A binary file attached to a document, e.g. the PDF or image the text of
+/// the document was extracted from
+///
+/// Only the metadata is part of the document; the content is stored as a
+/// blob through `terraphim_persistence` and referenced by `blob_id`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attachment {
+    /// File name of the attachment
+    pub name: String,
+    /// MIME type of the content, e.g. `application/pdf`
+    pub mime_type: String,
+    /// Size of the content in bytes
+    pub size: u64,
+    /// ID of the blob holding the content (a hash of the content)
+    pub blob_id: String,
+    /// ID of the upload, so that uploads of the same content keep their own
+    /// name and MIME type. Empty for attachments uploaded before uploads had
+    /// IDs.
+    #[serde(default)]
+    pub id: String,
+}
+
+/// Provenance of a document: a haystack in which the document was found
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct DocumentSource {
+    /// Location of the haystack, e.g. the path of a directory
+    pub haystack: String,
+    /// Name of the service which indexed the haystack, e.g. `ripgrep`
+    pub service: String,
+}
+
+/// Field of a document a highlight span is in
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightField {
+    Title,
+    Body,
+}
+
+/// A match of the search query in a document, for clients to highlight
+///
+/// Offsets are byte offsets into the field, `end` exclusive.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HighlightSpan {
+    /// The query term matched, i.e. the normalized term of the concept if
+    /// the role has a knowledge graph
+    pub term: String,
+    /// Offset of the first byte of the match
+    pub start: usize,
+    /// Offset after the last byte of the match
+    pub end: usize,
+    /// Field the match is in
+    pub field: HighlightField,
+}
+
+/// A term of the knowledge graph in the body of a document, for clients to
+/// link to its concept
+///
+/// Offsets are byte offsets into the body, `end` exclusive.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct KgConceptRef {
+    /// The term as written in the body
+    pub term: String,
+    /// The concept of the term
+    pub concept: NormalizedTerm,
+    /// Offset of the first byte of the term
+    pub start: usize,
+    /// Offset after the last byte of the term
+    pub end: usize,
+    /// The `kg:` URL of the concept, e.g. `kg:trained_operators`
+    pub url: String,
+    /// The first sentence of the page of the concept in the knowledge
+    /// graph, if the role links definitions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition: Option<String>,
+}
+
+/// A short extract of a document body around matches of the search query
+///
+/// Offsets are byte offsets into the body, `end` exclusive, so the
+/// highlight spans of the body within the snippet can be mapped onto it.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// The text of the body from `start` to `end`
+    pub text: String,
+    /// Offset of the snippet in the body
+    pub start: usize,
+    /// Offset after the end of the snippet in the body
+    pub end: usize,
+}
+
 /// A document is the central a piece of content that gets indexed and searched.
 ///
 /// It holds the title, body, description, tags, and rank.
@@ -197,6 +356,127 @@ pub struct Document {
     pub tags: Option<Vec<String>>,
     /// Rank of the document in the search results
     pub rank: Option<u64>,
+    /// Relevance of the document relative to the best search result, from
+    /// 0 to 1
+    ///
+    /// Only set on search results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Binary attachments of the document
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Language of the document as ISO 639-1 code (e.g. `en`), detected
+    /// when the document is indexed
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Custom metadata of the document, e.g. the status of a Jira issue or
+    /// the commit SHA a document was generated from
+    ///
+    /// Roles can define a schema for the metadata, which is validated when
+    /// documents are created or indexed.
+    #[serde(default)]
+    pub extra: Map<String, Value>,
+    /// Haystacks the document was found in
+    ///
+    /// A document reachable via several haystacks keeps all of them.
+    /// Documents created through the API have no sources.
+    #[serde(default)]
+    pub sources: Vec<DocumentSource>,
+    /// Visibility labels of the document
+    ///
+    /// A document without labels is visible to everyone, a document with
+    /// labels only to API principals granted one of them. Documents found
+    /// in haystacks carry the labels of their haystacks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visibility: Vec<String>,
+    /// Matches of the search query in the document
+    ///
+    /// Only set on search results.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub highlights: Vec<HighlightSpan>,
+    /// Extracts of the body around the best matches of the search query,
+    /// in the order they appear in the body
+    ///
+    /// Only set on search results.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snippets: Vec<Snippet>,
+    /// Terms of the knowledge graph of the role in the body, in order
+    ///
+    /// Only set on search results of roles which leave linking the terms
+    /// to clients.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kg_concepts: Vec<KgConceptRef>,
+    /// Targets of the links in the body as written, e.g. the page of a
+    /// `[[wikilink]]` or the path of a Markdown link
+    ///
+    /// Links to external URLs are left out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    /// Number of documents linking to the document
+    ///
+    /// Only set on search results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backlinks: Option<usize>,
+    /// When the document was created, in milliseconds since the Unix epoch
+    ///
+    /// For files, the creation time of the file, where the file system
+    /// records it.
+    #[serde(default)]
+    pub created: Option<u64>,
+    /// When the document was last modified, in milliseconds since the Unix
+    /// epoch
+    #[serde(default)]
+    pub modified: Option<u64>,
+    /// Hash of the body, set when the document is indexed or created
+    ///
+    /// Documents with the same content hash are duplicates of each other,
+    /// e.g. the same file found through two haystacks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+impl Document {
+    /// When the document was last changed: its modification time, or its
+    /// creation time if it has none
+    pub fn last_changed(&self) -> Option<u64> {
+        self.modified.or(self.created)
+    }
+
+    /// Add tags to the document, leaving out those it already has
+    pub fn add_tags(&mut self, tags: impl IntoIterator<Item = String>) {
+        let existing = self.tags.get_or_insert_with(Vec::new);
+        for tag in tags {
+            if !existing.contains(&tag) {
+                existing.push(tag);
+            }
+        }
+    }
+
+    /// Merge the visibility labels of the same document found elsewhere
+    ///
+    /// A document without labels stays visible to everyone, so labels are
+    /// only kept if both have some.
+    pub fn merge_visibility(&mut self, labels: &[String]) {
+        if labels.is_empty() {
+            self.visibility.clear();
+            return;
+        }
+        if self.visibility.is_empty() {
+            return;
+        }
+        for label in labels {
+            if !self.visibility.contains(label) {
+                self.visibility.push(label.clone());
+            }
+        }
+    }
+
+    /// Add a source to the document unless it is already known
+    pub fn add_source(&mut self, source: DocumentSource) {
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+    }
 }
 
 impl fmt::Display for Document {
@@ -221,6 +501,10 @@ pub struct Edge {
     pub rank: u64,
     /// A hashmap of `document_id` to `rank`
     pub doc_hash: AHashMap<String, u64>,
+    /// When the edge was last created, strengthened or decayed, in seconds
+    /// since the Unix epoch; 0 if unknown
+    #[serde(default)]
+    pub updated: u64,
 }
 
 impl Edge {
@@ -231,10 +515,19 @@ impl Edge {
             id,
             rank: 1,
             doc_hash,
+            updated: unix_now(),
         }
     }
 }
 
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
 /// A `Node` represents single concept and its connections to other concepts.
 ///
 /// Each node can have multiple edges to other nodes
@@ -281,6 +574,9 @@ pub struct Thesaurus {
     name: String,
     /// The inner hashmap of normalized terms
     data: AHashMap<NormalizedTermValue, NormalizedTerm>,
+    /// Regular expressions which match terms of concepts, e.g. ticket IDs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    patterns: Vec<TermPattern>,
 }
 
 impl Thesaurus {
@@ -289,6 +585,7 @@ impl Thesaurus {
         Self {
             name,
             data: AHashMap::new(),
+            patterns: Vec::new(),
         }
     }
 
@@ -302,6 +599,70 @@ impl Thesaurus {
         self.data.insert(key, value);
     }
 
+    /// Inserts a regular expression which matches terms of a concept,
+    /// replacing the concept of the same pattern
+    ///
+    /// Patterns are matched as written, in the syntax of the `regex` crate,
+    /// so `(?i)` makes them case insensitive.
+    pub fn insert_pattern(&mut self, pattern: String, value: NormalizedTerm) {
+        match self
+            .patterns
+            .iter_mut()
+            .find(|entry| entry.pattern == pattern)
+        {
+            Some(entry) => entry.normalized_term = value,
+            None => self.patterns.push(TermPattern {
+                pattern,
+                normalized_term: value,
+            }),
+        }
+    }
+
+    /// The regular expressions of the thesaurus, in the order they were
+    /// inserted
+    pub fn patterns(&self) -> &[TermPattern] {
+        &self.patterns
+    }
+
+    /// A hash of the terms and patterns with their concepts, which is the
+    /// same in every process and build, and independent of the order of the
+    /// terms
+    ///
+    /// Identifies the thesaurus that something persisted was built from,
+    /// e.g. an autocomplete index or a snapshot of a rolegraph.
+    pub fn fingerprint(&self) -> u64 {
+        use fnv::FnvHasher;
+        use std::hash::{Hash, Hasher};
+
+        // Unlike `DefaultHasher`, FNV is specified, so it won't change
+        // between Rust releases
+        let hash = |term: &str, concept: &NormalizedTerm| {
+            let mut hasher = FnvHasher::default();
+            term.hash(&mut hasher);
+            concept.id.hash(&mut hasher);
+            concept.value.hash(&mut hasher);
+            concept.weight.hash(&mut hasher);
+            hasher.finish()
+        };
+        let terms = self.data.iter().fold(0u64, |sum, (term, concept)| {
+            sum.wrapping_add(hash(term.as_str(), concept))
+        });
+        let mut hasher = FnvHasher::default();
+        self.name.hash(&mut hasher);
+        terms.hash(&mut hasher);
+        for entry in &self.patterns {
+            hash(&entry.pattern, &entry.normalized_term).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// The concepts of all terms and patterns, with duplicates
+    fn concepts(&self) -> impl Iterator<Item = &NormalizedTerm> {
+        self.data
+            .values()
+            .chain(self.patterns.iter().map(|entry| &entry.normalized_term))
+    }
+
     /// Get the length of the thesaurus
     pub fn len(&self) -> usize {
         self.data.len()
@@ -322,6 +683,132 @@ impl Thesaurus {
     pub fn keys(&self) -> std::collections::hash_map::Keys<NormalizedTermValue, NormalizedTerm> {
         self.data.keys()
     }
+
+    /// Merge the terms of another thesaurus into this one
+    ///
+    /// Concepts are identified by their normalized term: a concept of both
+    /// thesauri keeps its ID in this one, and a concept only in the other
+    /// thesaurus gets a new ID if its ID is taken by another concept here.
+    /// A term which maps to different concepts in both thesauri is resolved
+    /// by `policy`. Returns these conflicts, sorted by term. Patterns are
+    /// merged the same way, without reporting their conflicts.
+    pub fn merge(&mut self, other: &Thesaurus, policy: ConflictPolicy) -> Vec<ThesaurusConflict> {
+        let mut ids: AHashMap<NormalizedTermValue, u64> = self
+            .concepts()
+            .map(|concept| (concept.value.clone(), concept.id))
+            .collect();
+        let mut taken: AHashSet<u64> = ids.values().copied().collect();
+        let mut next_id = self
+            .concepts()
+            .chain(other.concepts())
+            .map(|concept| concept.id)
+            .max()
+            .map_or(1, |id| id + 1);
+        // The concept of the other thesaurus with the ID it gets in this one
+        let mut merged = |concept: &NormalizedTerm| {
+            let id = match ids.get(&concept.value) {
+                Some(id) => *id,
+                None => {
+                    let id = if taken.contains(&concept.id) {
+                        next_id += 1;
+                        next_id - 1
+                    } else {
+                        concept.id
+                    };
+                    taken.insert(id);
+                    ids.insert(concept.value.clone(), id);
+                    id
+                }
+            };
+            NormalizedTerm::new(id, concept.value.clone()).with_weight(concept.weight)
+        };
+
+        // In order, so merging the same thesauri always assigns the same IDs
+        let mut entries: Vec<_> = other.data.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let mut conflicts = Vec::new();
+        for (term, concept) in entries {
+            let concept = merged(concept);
+            match self.data.get(term) {
+                Some(existing) if existing.value != concept.value => {
+                    conflicts.push(ThesaurusConflict {
+                        term: term.clone(),
+                        existing: existing.clone(),
+                        other: concept.clone(),
+                    });
+                    match policy {
+                        ConflictPolicy::KeepExisting => {}
+                        ConflictPolicy::Replace => {
+                            self.data.insert(term.clone(), concept);
+                        }
+                        ConflictPolicy::Drop => {
+                            self.data.remove(term);
+                        }
+                    }
+                }
+                _ => {
+                    self.data.insert(term.clone(), concept);
+                }
+            }
+        }
+        for other_pattern in &other.patterns {
+            let concept = merged(&other_pattern.normalized_term);
+            let existing = self
+                .patterns
+                .iter()
+                .position(|entry| entry.pattern == other_pattern.pattern);
+            match existing {
+                Some(index) if self.patterns[index].normalized_term.value != concept.value => {
+                    match policy {
+                        ConflictPolicy::KeepExisting => {}
+                        ConflictPolicy::Replace => self.patterns[index].normalized_term = concept,
+                        ConflictPolicy::Drop => {
+                            self.patterns.remove(index);
+                        }
+                    }
+                }
+                _ => self.insert_pattern(other_pattern.pattern.clone(), concept),
+            }
+        }
+        conflicts
+    }
+}
+
+/// A regular expression of a thesaurus and the concept of the terms it
+/// matches
+///
+/// In JSON, the fields of the concept are next to the pattern, e.g.
+/// `{"pattern": "PROJ-\\d+", "id": 7, "nterm": "ticket"}`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TermPattern {
+    pub pattern: String,
+    #[serde(flatten)]
+    pub normalized_term: NormalizedTerm,
+}
+
+/// How [`Thesaurus::merge`] resolves a term which maps to different
+/// concepts in the two thesauri
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Keep the concept of the thesaurus merged into
+    #[default]
+    KeepExisting,
+    /// Take the concept of the other thesaurus
+    Replace,
+    /// Leave the term out, since it's ambiguous
+    Drop,
+}
+
+/// A term which maps to different concepts in two merged thesauri
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ThesaurusConflict {
+    pub term: NormalizedTermValue,
+    /// The concept of the term in the thesaurus merged into
+    pub existing: NormalizedTerm,
+    /// The concept of the term in the other thesaurus, with the ID it has
+    /// in the merged thesaurus
+    pub other: NormalizedTerm,
 }
 
 // Implement `IntoIterator` for a reference to `Thesaurus`
@@ -338,9 +825,14 @@ impl<'a> IntoIterator for &'a Thesaurus {
 ///
 /// It holds the documents that have been indexed
 /// and can be searched through using the `RoleGraph`.
+///
+/// Documents are reference counted, so cloning an index or handing its
+/// documents to the scorer doesn't copy document bodies. A document is only
+/// copied when it is changed (see [`Arc::make_mut`]) while it is shared, or
+/// when it is turned into a search result.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Index {
-    inner: AHashMap<String, Document>,
+    inner: AHashMap<String, Arc<Document>>,
 }
 
 impl Default for Index {
@@ -357,6 +849,34 @@ impl Index {
         }
     }
 
+    /// Add the documents of another index
+    ///
+    /// Documents which are in both indexes keep the sources of both and
+    /// are visible to anyone who may see either of them.
+    pub fn merge(&mut self, other: Index) {
+        for (id, document) in other {
+            match self.inner.entry(id) {
+                Entry::Occupied(mut entry) => {
+                    let new_sources: Vec<DocumentSource> = document
+                        .sources
+                        .iter()
+                        .filter(|source| !entry.get().sources.contains(source))
+                        .cloned()
+                        .collect();
+                    if !new_sources.is_empty() {
+                        Arc::make_mut(entry.get_mut()).sources.extend(new_sources);
+                    }
+                    if entry.get().visibility != document.visibility {
+                        Arc::make_mut(entry.get_mut()).merge_visibility(&document.visibility);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(document);
+                }
+            }
+        }
+    }
+
     /// Converts all given indexed documents to documents
     ///
     /// Returns the all converted documents
@@ -364,44 +884,35 @@ impl Index {
         let mut documents: Vec<Document> = Vec::new();
         for doc in docs {
             log::trace!("doc: {:#?}", doc);
-            if let Some(document) = self.get_document(&doc) {
-                // Document found in cache
-                let mut document = document;
-                document.tags = Some(doc.tags.clone());
-                // rank only available for terraphim graph
-                // use scorer to populate the rank for all cases
-                document.rank = Some(doc.rank);
-                documents.push(document.clone());
-            } else {
-                log::warn!("Document not found in cache. Cannot convert.");
+            match self.get_document(&doc) {
+                Some(document) => documents.push(document),
+                None => log::warn!("Document not found in cache. Cannot convert."),
             }
         }
         documents
     }
+
     /// Returns all documents from the index for scorer without graph embeddings
-    pub fn get_all_documents(&self) -> Vec<Document> {
-        let documents: Vec<Document> = self.values().cloned().collect::<Vec<Document>>();
-        documents
+    pub fn get_all_documents(&self) -> Vec<Arc<Document>> {
+        self.values().cloned().collect()
     }
 
     /// Get a document from the index (if it exists in the index)
+    ///
+    /// The document is copied out of the index, with the rank of the indexed
+    /// document and its tags added to those of the document.
     pub fn get_document(&self, doc: &IndexedDocument) -> Option<Document> {
-        if let Some(document) = self.inner.get(&doc.id).cloned() {
-            // Document found in cache
-            let mut document = document;
-            document.tags = Some(doc.tags.clone());
-            // Rank only available for terraphim graph
-            // use scorer to populate the rank for all cases
-            document.rank = Some(doc.rank);
-            Some(document)
-        } else {
-            None
-        }
+        let mut document = Document::clone(self.inner.get(&doc.id)?);
+        document.add_tags(doc.tags.iter().cloned());
+        // Rank only available for terraphim graph
+        // use scorer to populate the rank for all cases
+        document.rank = Some(doc.rank);
+        Some(document)
     }
 }
 
 impl Deref for Index {
-    type Target = AHashMap<String, Document>;
+    type Target = AHashMap<String, Arc<Document>>;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
@@ -415,8 +926,8 @@ impl DerefMut for Index {
 }
 
 impl IntoIterator for Index {
-    type Item = (String, Document);
-    type IntoIter = std::collections::hash_map::IntoIter<String, Document>;
+    type Item = (String, Arc<Document>);
+    type IntoIter = std::collections::hash_map::IntoIter<String, Arc<Document>>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.inner.into_iter()
@@ -447,12 +958,282 @@ impl IndexedDocument {
 
 /// Query type for searching documents in the `RoleGraph`.
 /// It contains the search term, skip and limit parameters.
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct SearchQuery {
     pub search_term: NormalizedTermValue,
     pub skip: Option<usize>,
     pub limit: Option<usize>,
     pub role: Option<RoleName>,
+    /// Only return documents in this language (ISO 639-1 code, e.g. `en`)
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Only return documents whose custom metadata matches all filters
+    #[serde(default)]
+    pub metadata: Vec<MetadataFilter>,
+    /// If nothing is found, search for the spelling correction of the
+    /// search term instead, if the correction is confident enough
+    #[serde(default)]
+    pub auto_correct: bool,
+    /// Return the snippets of the documents found without their bodies
+    #[serde(default)]
+    pub omit_body: bool,
+    /// Rank documents which more documents link to higher
+    #[serde(default)]
+    pub boost_backlinks: bool,
+    /// Only return documents in the selected facets
+    #[serde(default)]
+    pub facets: FacetFilters,
+    /// Only return documents last changed at or after this time, in
+    /// milliseconds since the Unix epoch
+    #[serde(default)]
+    pub after: Option<u64>,
+    /// Only return documents last changed before this time, in
+    /// milliseconds since the Unix epoch
+    #[serde(default)]
+    pub before: Option<u64>,
+    /// Order of the results
+    #[serde(default)]
+    pub sort_by: SortBy,
+    /// Leave out documents containing any of these terms, ignoring case
+    ///
+    /// For roles ranked by the knowledge graph, documents containing a
+    /// synonym of an excluded term are left out, too.
+    #[serde(default)]
+    pub exclude_terms: Vec<NormalizedTermValue>,
+    /// Only return documents whose title or body matches this expression
+    ///
+    /// Haystacks are searched for any of its terms instead of the search
+    /// term, which is then only used for ranking.
+    #[serde(default)]
+    pub terms: Option<TermExpr>,
+    /// Only search these haystacks of the role, by their location (see
+    /// [`DocumentSource::haystack`]); all of them if empty
+    #[serde(default)]
+    pub haystacks: Vec<String>,
+    /// How the search term is matched
+    #[serde(default)]
+    pub query_type: QueryType,
+    /// Trade-off between relevance and diversity of the top results, from
+    /// 1 (relevance only) to 0 (diversity only); results are ranked by
+    /// relevance only if not set
+    #[serde(default)]
+    pub mmr_lambda: Option<f64>,
+}
+
+impl SearchQuery {
+    /// Check whether a document was last changed in the time range of the
+    /// query
+    ///
+    /// Documents without timestamps are only in the range if the query has
+    /// none.
+    pub fn in_time_range(&self, document: &Document) -> bool {
+        if self.after.is_none() && self.before.is_none() {
+            return true;
+        }
+        let Some(changed) = document.last_changed() else {
+            return false;
+        };
+        self.after.is_none_or(|after| changed >= after)
+            && self.before.is_none_or(|before| changed < before)
+    }
+
+    /// Check whether the haystack at a location is searched for the query
+    pub fn searches_haystack(&self, location: &Path) -> bool {
+        self.haystacks.is_empty()
+            || self
+                .haystacks
+                .iter()
+                .any(|haystack| Path::new(haystack) == location)
+    }
+}
+
+/// Boolean expression over search terms
+///
+/// Terms are words or phrases, which match text containing them, ignoring
+/// case.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TermExpr {
+    Term(String),
+    /// Every expression matches
+    And(Vec<TermExpr>),
+    /// Any expression matches
+    Or(Vec<TermExpr>),
+}
+
+impl TermExpr {
+    /// The terms of the expression, in order
+    pub fn terms(&self) -> Vec<&str> {
+        match self {
+            TermExpr::Term(term) => vec![term.as_str()],
+            TermExpr::And(exprs) | TermExpr::Or(exprs) => {
+                exprs.iter().flat_map(TermExpr::terms).collect()
+            }
+        }
+    }
+}
+
+/// How the search term of a query is matched
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryType {
+    /// The search term is found as it is, ignoring case
+    #[default]
+    Term,
+    /// The search term is a regular expression, matched ignoring case
+    Regex,
+}
+
+/// Order of search results
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// The most relevant first
+    #[default]
+    Relevance,
+    /// The most recently modified first, see [`Document::last_changed`]
+    Modified,
+    /// The most recently created first
+    Created,
+}
+
+/// Facets of the search results to narrow them down to
+///
+/// A document must match every facet which is set.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct FacetFilters {
+    /// The document has all of these tags, ignoring case
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The document was found in one of these haystacks, see
+    /// [`DocumentSource::haystack`]
+    #[serde(default)]
+    pub source_haystack: Vec<String>,
+    /// The URL of the document starts with this prefix
+    #[serde(default)]
+    pub url_prefix: Option<String>,
+}
+
+impl FacetFilters {
+    /// Check whether no facet is selected
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.source_haystack.is_empty() && self.url_prefix.is_none()
+    }
+
+    /// Check whether a document is in all selected facets
+    pub fn matches(&self, document: &Document) -> bool {
+        let tags = document.tags.as_deref().unwrap_or_default();
+        let has_tags = self
+            .tags
+            .iter()
+            .all(|tag| tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+        let in_haystack = self.source_haystack.is_empty()
+            || document
+                .sources
+                .iter()
+                .any(|source| self.source_haystack.contains(&source.haystack));
+        let has_url = self
+            .url_prefix
+            .as_ref()
+            .is_none_or(|prefix| document.url.starts_with(prefix.as_str()));
+        has_tags && in_haystack && has_url
+    }
+}
+
+/// Number of search results with a value of a facet
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Values of the facets of search results with their number of results,
+/// the most frequent first
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct FacetCounts {
+    pub tags: Vec<FacetCount>,
+    pub source_haystack: Vec<FacetCount>,
+}
+
+/// Comparison operator of a [`MetadataFilter`]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataOp {
+    /// The field equals the value, or contains it if the field is an array
+    #[default]
+    Eq,
+    /// The field does not equal the value
+    Ne,
+    /// The field is greater than the value
+    Gt,
+    /// The field is greater than or equal to the value
+    Gte,
+    /// The field is less than the value
+    Lt,
+    /// The field is less than or equal to the value
+    Lte,
+    /// The field contains the value as a case-insensitive substring, or as
+    /// an element if the field is an array
+    Contains,
+    /// The field is present; the value is ignored
+    Exists,
+}
+
+/// Filter on the custom metadata (`Document::extra`) of documents
+///
+/// Numbers are compared numerically, strings lexicographically, so ISO
+/// 8601 dates can be compared with `gt` and `lt`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MetadataFilter {
+    /// Name of the metadata field
+    pub key: String,
+    #[serde(default)]
+    pub op: MetadataOp,
+    #[serde(default)]
+    pub value: Value,
+}
+
+impl MetadataFilter {
+    /// Check whether the given metadata matches the filter
+    pub fn matches(&self, metadata: &Map<String, Value>) -> bool {
+        let Some(field) = metadata.get(&self.key) else {
+            return self.op == MetadataOp::Ne;
+        };
+        match self.op {
+            MetadataOp::Exists => true,
+            MetadataOp::Eq => self.equals(field),
+            MetadataOp::Ne => !self.equals(field),
+            MetadataOp::Gt => self.compare(field).is_some_and(cmp::Ordering::is_gt),
+            MetadataOp::Gte => self.compare(field).is_some_and(cmp::Ordering::is_ge),
+            MetadataOp::Lt => self.compare(field).is_some_and(cmp::Ordering::is_lt),
+            MetadataOp::Lte => self.compare(field).is_some_and(cmp::Ordering::is_le),
+            MetadataOp::Contains => match (field, &self.value) {
+                (Value::String(field), Value::String(value)) => {
+                    field.to_lowercase().contains(&value.to_lowercase())
+                }
+                (Value::Array(items), value) => items.contains(value),
+                _ => false,
+            },
+        }
+    }
+
+    fn equals(&self, field: &Value) -> bool {
+        match field {
+            Value::Array(items) if !self.value.is_array() => items.contains(&self.value),
+            field => self.compare(field) == Some(cmp::Ordering::Equal) || *field == self.value,
+        }
+    }
+
+    /// Compare the field to the value of the filter
+    fn compare(&self, field: &Value) -> Option<cmp::Ordering> {
+        match (field, &self.value) {
+            (Value::Number(field), Value::Number(value)) => {
+                field.as_f64()?.partial_cmp(&value.as_f64()?)
+            }
+            (Value::String(field), Value::String(value)) => Some(field.cmp(value)),
+            _ => None,
+        }
+    }
 }
 
 /// Defines the relevance function (scorer) to be used for ranking search
@@ -469,6 +1250,25 @@ pub enum RelevanceFunction {
     /// Scorer for ranking search results based on the title of a document
     #[serde(rename = "title-scorer")]
     TitleScorer,
+    /// Scorer fusing the rankings of the title scorer, BM25 and the
+    /// Terraphim graph with Reciprocal Rank Fusion
+    #[serde(rename = "hybrid")]
+    Hybrid,
+    /// Scorer for ranking search results by Okapi BM25F over their titles,
+    /// bodies, descriptions and tags
+    #[serde(rename = "bm25")]
+    BM25,
+}
+
+impl RelevanceFunction {
+    /// Whether the relevance function ranks with the knowledge graph of the
+    /// role
+    pub fn uses_graph(self) -> bool {
+        matches!(
+            self,
+            RelevanceFunction::TerraphimGraph | RelevanceFunction::Hybrid
+        )
+    }
 }
 
 /// Defines all supported inputs for the knowledge graph.
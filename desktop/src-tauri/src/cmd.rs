@@ -4,13 +4,22 @@
 use tauri::command;
 use tauri::State;
 
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 
 use terraphim_config::{Config, ConfigState};
+use terraphim_rolegraph::{Community, GraphData, GraphNode};
+use terraphim_service::acceptance::SuggestionAcceptance;
+use terraphim_service::alerts::SavedSearch;
+use terraphim_service::analytics::{AnalyticsReport, Interaction};
+use terraphim_service::facets;
+use terraphim_service::spelling::DidYouMean;
+use terraphim_service::suggest::Suggestion;
 use terraphim_service::TerraphimService;
 use terraphim_settings::DeviceSettings;
 use terraphim_types::Thesaurus;
-use terraphim_types::{Document, SearchQuery};
+use terraphim_types::{Document, FacetCounts, SearchQuery};
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
 use serde::Serializer;
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
@@ -40,6 +49,9 @@ pub enum TerraphimTauriError {
 
     #[error("Service error: {0}")]
     Service(#[from] terraphim_service::ServiceError),
+
+    #[error("Log filter error: {0}")]
+    LogFilter(String),
 }
 
 // Manually implement `Serialize` for our error type because some of the
@@ -76,6 +88,11 @@ pub struct SearchResponse {
     pub status: Status,
     /// The search results
     pub results: Vec<Document>,
+    /// A correction of the search term, if nothing was found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub did_you_mean: Option<DidYouMean>,
+    /// Values of the facets of the results with their number of results
+    pub facets: FacetCounts,
 }
 
 /// Search All TerraphimGraphs defined in a config by query param
@@ -86,13 +103,41 @@ pub async fn search(
 ) -> Result<SearchResponse> {
     log::info!("Search called with {:?}", search_query);
     let mut terraphim_service = TerraphimService::new(config_state.inner().clone());
-    let results = terraphim_service.search(&search_query).await?;
+    let (results, did_you_mean) = terraphim_service
+        .search_with_correction(&search_query)
+        .await?;
+    let facets = facets::count(&results);
     Ok(SearchResponse {
         status: Status::Success,
         results,
+        did_you_mean,
+        facets,
     })
 }
 
+/// Search and emit the results to the window as `search_result` events as
+/// each haystack is searched
+///
+/// Returns the number of results once all are emitted.
+#[command]
+pub async fn search_stream(
+    window: tauri::Window,
+    config_state: State<'_, ConfigState>,
+    search_query: SearchQuery,
+) -> Result<usize> {
+    log::info!("Search stream called with {:?}", search_query);
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    let mut documents = Box::pin(terraphim_service.search_stream(&search_query).await?);
+    let mut results = 0;
+    while let Some(document) = documents.next().await {
+        if let Err(e) = window.emit("search_result", document) {
+            log::warn!("Failed to emit search result: {e}");
+        }
+        results += 1;
+    }
+    Ok(results)
+}
+
 #[command]
 pub async fn get_config(config_state: tauri::State<'_, ConfigState>) -> Result<ConfigResponse> {
     log::info!("Get config called");
@@ -129,7 +174,240 @@ pub async fn publish_thesaurus(
     let thesaurus = terraphim_service
         .ensure_thesaurus_loaded(&role_name.into())
         .await?;
-    Ok(thesaurus)
+    Ok(Thesaurus::clone(&thesaurus))
+}
+
+/// Response type for the rolegraph of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoleGraphResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Nodes and edges of the graph
+    pub graph: GraphData,
+}
+
+/// Command to fetch the rolegraph of a role for the graph view
+///
+/// Falls back to the default role if `role_name` is not set.
+/// If `center` is set, only its neighbourhood up to `depth` hops is returned.
+#[command]
+pub async fn get_rolegraph(
+    config_state: tauri::State<'_, ConfigState>,
+    role_name: Option<String>,
+    center: Option<String>,
+    depth: Option<usize>,
+) -> Result<RoleGraphResponse> {
+    log::info!("Get rolegraph called for role {:?}", role_name);
+    let role_name = match role_name {
+        Some(role_name) => role_name.into(),
+        None => config_state.get_default_role().await,
+    };
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    let graph = terraphim_service
+        .get_graph_data(&role_name, center.as_deref(), depth.unwrap_or(1))
+        .await?;
+    Ok(RoleGraphResponse {
+        status: Status::Success,
+        graph,
+    })
+}
+
+/// Command to find the concepts which co-occur most with the concepts of a
+/// term, for a "related topics" panel next to search results
+///
+/// Falls back to the default role if `role_name` is not set.
+#[command]
+pub async fn related_concepts(
+    config_state: tauri::State<'_, ConfigState>,
+    role_name: Option<String>,
+    term: String,
+    limit: Option<usize>,
+) -> Result<Vec<GraphNode>> {
+    let role_name = match role_name {
+        Some(role_name) => role_name.into(),
+        None => config_state.get_default_role().await,
+    };
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service
+        .related_concepts(&role_name, &term, limit.unwrap_or(10))
+        .await?)
+}
+
+/// Response type for the communities of the concepts of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommunitiesResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Named communities of concepts, the largest first
+    pub communities: Vec<Community>,
+}
+
+/// Command to fetch the communities of the concepts of a role, to group and
+/// color the nodes of the graph view
+///
+/// Falls back to the default role if `role_name` is not set.
+#[command]
+pub async fn get_communities(
+    config_state: tauri::State<'_, ConfigState>,
+    role_name: Option<String>,
+) -> Result<CommunitiesResponse> {
+    log::info!("Get communities called for role {:?}", role_name);
+    let role_name = match role_name {
+        Some(role_name) => role_name.into(),
+        None => config_state.get_default_role().await,
+    };
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    let communities = terraphim_service.communities(&role_name).await?;
+    Ok(CommunitiesResponse {
+        status: Status::Success,
+        communities,
+    })
+}
+
+/// Command to suggest search terms for the search box
+///
+/// Falls back to the default role if `role_name` is not set. `context` is
+/// the text before what is being typed, e.g. the preceding sentence.
+#[command]
+pub async fn suggest(
+    config_state: tauri::State<'_, ConfigState>,
+    role_name: Option<String>,
+    query: String,
+    context: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<Suggestion>> {
+    let role_name = match role_name {
+        Some(role_name) => role_name.into(),
+        None => config_state.get_default_role().await,
+    };
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service
+        .suggest_in_context(
+            &role_name,
+            &query,
+            context.as_deref().unwrap_or_default(),
+            limit.unwrap_or(10),
+        )
+        .await?)
+}
+
+/// Command to record which suggestion was accepted, which re-ranks later
+/// suggestions of the role
+///
+/// Falls back to the default role if `role_name` is not set.
+#[command]
+pub async fn accept_suggestion(
+    config_state: tauri::State<'_, ConfigState>,
+    role_name: Option<String>,
+    acceptance: SuggestionAcceptance,
+) -> Result<()> {
+    let role_name = match role_name {
+        Some(role_name) => role_name.into(),
+        None => config_state.get_default_role().await,
+    };
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service
+        .accept_suggestion(&role_name, acceptance)
+        .await?)
+}
+
+/// Command to save a search to be re-run on a schedule
+///
+/// Alerts of saved searches are emitted to the frontend as `alert` events.
+#[command]
+pub async fn save_search(
+    config_state: tauri::State<'_, ConfigState>,
+    saved_search: SavedSearch,
+) -> Result<SavedSearch> {
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service.save_search(saved_search).await?)
+}
+
+/// Command to list the saved searches, optionally of a single role
+#[command]
+pub async fn list_saved_searches(
+    config_state: tauri::State<'_, ConfigState>,
+    role_name: Option<String>,
+) -> Result<Vec<SavedSearch>> {
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    let role_name = role_name.map(Into::into);
+    Ok(terraphim_service.saved_searches(role_name.as_ref()).await)
+}
+
+/// Command to delete a saved search
+///
+/// Returns `false` if there is no saved search with the given ID.
+#[command]
+pub async fn delete_saved_search(
+    config_state: tauri::State<'_, ConfigState>,
+    id: String,
+) -> Result<bool> {
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service.delete_saved_search(&id).await)
+}
+
+/// Command to record that a search result was opened, copied or dismissed
+#[command]
+pub async fn record_interaction(
+    config_state: tauri::State<'_, ConfigState>,
+    interaction: Interaction,
+) -> Result<()> {
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service.record_interaction(interaction).await?)
+}
+
+/// Command to fetch the analytics report (top queries, zero-result
+/// queries, latency per role and search quality)
+#[command]
+pub async fn get_analytics(
+    config_state: tauri::State<'_, ConfigState>,
+    limit: Option<usize>,
+) -> Result<AnalyticsReport> {
+    let terraphim_service = TerraphimService::new(config_state.inner().clone());
+    Ok(terraphim_service.query_analytics(limit.unwrap_or(10)).await)
+}
+
+/// Handle to swap the log filter of the running app
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Response type for the log filter
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogFilterResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The active filter directives
+    pub filter: String,
+}
+
+/// Command to show the active log filter
+#[command]
+pub async fn get_log_filter(
+    log_filter: tauri::State<'_, LogFilterHandle>,
+) -> Result<LogFilterResponse> {
+    let filter = log_filter
+        .with_current(|filter| filter.to_string())
+        .map_err(|e| TerraphimTauriError::LogFilter(e.to_string()))?;
+    Ok(LogFilterResponse {
+        status: Status::Success,
+        filter,
+    })
+}
+
+/// Command to change the log filter at runtime
+///
+/// `filter` uses the `RUST_LOG` syntax, e.g. `info,terraphim_rolegraph=debug`.
+#[command]
+pub async fn set_log_filter(
+    log_filter: tauri::State<'_, LogFilterHandle>,
+    filter: String,
+) -> Result<LogFilterResponse> {
+    log::info!("Set log filter called with `{}`", filter);
+    let new_filter =
+        EnvFilter::try_new(&filter).map_err(|e| TerraphimTauriError::LogFilter(e.to_string()))?;
+    log_filter
+        .reload(new_filter)
+        .map_err(|e| TerraphimTauriError::LogFilter(e.to_string()))?;
+    get_log_filter(log_filter).await
 }
 
 use std::path::PathBuf;
@@ -9,17 +9,34 @@ use terraphim_persistence::Persistable;
 
 use std::error::Error;
 use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
+use tauri::api::notification::Notification;
 use tauri::{
     CustomMenuItem, GlobalShortcutManager, Manager, RunEvent, SystemTray, SystemTrayEvent,
     SystemTrayMenu, WindowBuilder,
 };
 
 use terraphim_config::ConfigState;
+use terraphim_service::alerts::{spawn_alerts, SavedSearchStore};
+use terraphim_service::enrichment::spawn_refresher;
+use terraphim_service::suggest::spawn_warmup;
+use terraphim_service::thesaurus_cache::watch_sources;
 use terraphim_settings::DeviceSettings;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // The filter can be changed at runtime through the `set_log_filter` command
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, log_filter_handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .try_init()?;
+
     let device_settings = match DeviceSettings::load_from_env_and_file(None) {
         Ok(settings) => settings,
         Err(e) => {
@@ -44,6 +61,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
         Err(e) => panic!("Failed to build config: {:?}", e),
     };
     let config_state = ConfigState::new(&mut config).await?;
+    // Cached thesauri are dropped when their knowledge graph files change
+    if let Err(e) = watch_sources(&config) {
+        log::warn!("Failed to watch thesaurus sources: {e}");
+    }
+    // Cached copies of persisted documents are reloaded in the background
+    let _document_refresher = spawn_refresher(&config);
+    // Saved searches are re-run on their schedules
+    let _alerts = spawn_alerts(config_state.clone());
+    // Autocomplete indices are loaded, or built and persisted, up front
+    let _autocomplete = spawn_warmup(config_state.clone());
     let current_config = config_state.config.lock().await;
     let global_shortcut = current_config.global_shortcut.clone();
     drop(current_config);
@@ -84,11 +111,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
         })
         .manage(config_state.clone())
         .manage(device_settings.clone())
+        .manage(log_filter_handle)
         .invoke_handler(tauri::generate_handler![
             cmd::search,
+            cmd::search_stream,
             cmd::get_config,
             cmd::update_config,
             cmd::publish_thesaurus,
+            cmd::get_rolegraph,
+            cmd::get_communities,
+            cmd::related_concepts,
+            cmd::suggest,
+            cmd::accept_suggestion,
+            cmd::save_search,
+            cmd::list_saved_searches,
+            cmd::delete_saved_search,
+            cmd::record_interaction,
+            cmd::get_analytics,
+            cmd::get_log_filter,
+            cmd::set_log_filter,
             cmd::save_initial_settings,
             cmd::close_splashscreen
        ])
@@ -96,6 +137,37 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let settings = device_settings_read.clone();
             println!("Settings: {:?}", settings);
             let handle = app.handle();
+            // Alerts of saved searches go to the frontend and the desktop
+            let alert_handle = app.handle();
+            let identifier = app.config().tauri.bundle.identifier.clone();
+            tauri::async_runtime::spawn(async move {
+                let mut alerts = SavedSearchStore::instance().await.subscribe();
+                loop {
+                    let alert = match alerts.recv().await {
+                        Ok(alert) => alert,
+                        Err(RecvError::Lagged(missed)) => {
+                            log::warn!("Missed {missed} alerts of saved searches");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    let body = format!(
+                        "{} new and {} changed documents",
+                        alert.new_documents.len(),
+                        alert.changed_documents.len()
+                    );
+                    if let Err(e) = Notification::new(&identifier)
+                        .title(&alert.name)
+                        .body(body)
+                        .show()
+                    {
+                        log::warn!("Failed to show alert notification: {e}");
+                    }
+                    if let Err(e) = alert_handle.emit_all("alert", alert) {
+                        log::warn!("Failed to emit alert: {e}");
+                    }
+                }
+            });
             let main_window = app.get_window("main").unwrap(); 
             if !settings.initialized {           
             tauri::async_runtime::spawn(async move {
@@ -1,3 +1,12 @@
+export interface KgConceptRef {
+  term: string;
+  concept: { id: number; nterm: string };
+  start: number;
+  end: number;
+  url: string;
+  definition?: string;
+}
+
 export interface Document {
   id: string;
   url: string;
@@ -7,6 +16,8 @@ export interface Document {
   stub?: string;
   tags?: string[];
   rank?: number;
+  score?: number;
+  kg_concepts?: KgConceptRef[];
 }
 
 export interface SearchResponse {
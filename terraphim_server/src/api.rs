@@ -1,23 +1,72 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
     Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::Mutex;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
 
-use terraphim_config::Config;
+use terraphim_automata::{ExportFormat, LintIssue};
 use terraphim_config::ConfigState;
-use terraphim_rolegraph::RoleGraph;
-use terraphim_service::TerraphimService;
-use terraphim_types::{Document, IndexedDocument, SearchQuery};
+use terraphim_config::{Config, JobKind};
+use terraphim_rolegraph::{Community, CoverageReport, GraphData, GraphNode, RoleGraph};
+use terraphim_service::acceptance::SuggestionAcceptance;
+use terraphim_service::alerts::{SavedSearch, SavedSearchStore};
+use terraphim_service::analytics::{AnalyticsReport, Interaction};
+use terraphim_service::backlinks::Backlink;
+use terraphim_service::candidates::Candidate;
+use terraphim_service::concepts::{ConceptBacklink, ConceptCount};
+use terraphim_service::facets;
+use terraphim_service::jobs::{JobRun, JobStatus};
+use terraphim_service::pages::Cursor;
+use terraphim_service::query;
+use terraphim_service::sessions::UserSession;
+use terraphim_service::spelling::DidYouMean;
+use terraphim_service::suggest::Suggestion;
+use terraphim_service::{ServiceError, TerraphimService};
+use terraphim_types::{Attachment, Document, FacetCounts, IndexedDocument, RoleName, SearchQuery};
 
-use crate::error::{Result, Status};
+use crate::auth::{RequestAccess, RequestSession};
+use crate::error::{ApiError, Result, Status};
 pub type SearchResultsStream = Sender<IndexedDocument>;
 
+/// Convert a service error into an API error with a fitting status code
+fn service_error(e: ServiceError) -> ApiError {
+    match e {
+        ServiceError::InvalidMetadata(_)
+        | ServiceError::NoSession
+        | ServiceError::InvalidCursor(_)
+        | ServiceError::InvalidQuery(_)
+        | ServiceError::UnknownHaystack(_)
+        | ServiceError::InvalidRegex(_)
+        | ServiceError::UnknownDocument(_) => ApiError(StatusCode::BAD_REQUEST, e.into()),
+        ServiceError::Forbidden(_) => ApiError(StatusCode::FORBIDDEN, e.into()),
+        e => e.into(),
+    }
+}
+
+/// A service with the access and, if any, the session of a request
+fn session_service(
+    config_state: ConfigState,
+    access: RequestAccess,
+    session: RequestSession,
+) -> TerraphimService {
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    match session.0 {
+        Some(session_id) => terraphim_service.with_session(session_id),
+        None => terraphim_service,
+    }
+}
+
 /// Health check endpoint
 pub(crate) async fn health() -> impl IntoResponse {
     (StatusCode::OK, "OK")
@@ -32,14 +81,98 @@ pub struct CreateDocumentResponse {
     pub id: String,
 }
 
+/// Query parameters for uploading an attachment
+#[derive(Debug, Deserialize)]
+pub struct AttachmentQuery {
+    /// File name of the attachment
+    pub name: String,
+}
+
+/// Query parameters for downloading an attachment
+#[derive(Debug, Deserialize)]
+pub struct AttachmentIdQuery {
+    /// ID of the upload, see [`Attachment::id`]
+    #[serde(default)]
+    pub id: String,
+}
+
+/// Response type for uploading an attachment
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentResponse {
+    /// Status of the upload
+    pub status: Status,
+    /// The stored attachment, to be added to a document
+    pub attachment: Attachment,
+}
+
+/// Store the request body as an attachment
+///
+/// The MIME type is taken from the `Content-Type` header.
+pub(crate) async fn upload_attachment(
+    State(config): State<ConfigState>,
+    access: RequestAccess,
+    Query(query): Query<AttachmentQuery>,
+    headers: HeaderMap,
+    content: Bytes,
+) -> Result<Json<AttachmentResponse>> {
+    log::debug!("upload_attachment {} ({} bytes)", query.name, content.len());
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream");
+    let terraphim_service = TerraphimService::new(config).with_access(access.0);
+    let attachment = terraphim_service
+        .add_attachment(&query.name, mime_type, &content)
+        .await?;
+    Ok(Json(AttachmentResponse {
+        status: Status::Success,
+        attachment,
+    }))
+}
+
+/// Return the content of an attachment of a visible document
+///
+/// The content is always served as a download and browsers must not sniff
+/// its type, so an uploaded HTML page cannot run scripts on the origin of
+/// the server.
+pub(crate) async fn get_attachment(
+    State(config): State<ConfigState>,
+    access: RequestAccess,
+    Path(blob_id): Path<String>,
+    Query(query): Query<AttachmentIdQuery>,
+) -> Result<impl IntoResponse> {
+    log::debug!("get_attachment {blob_id} {}", query.id);
+    let terraphim_service = TerraphimService::new(config).with_access(access.0);
+    let (attachment, content) = terraphim_service
+        .get_attachment(&blob_id, &query.id)
+        .await
+        .map_err(service_error)?;
+    let disposition = format!(
+        "attachment; filename=\"{}\"",
+        attachment.name.replace(['"', '\\', '\r', '\n'], "")
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.mime_type),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        content,
+    ))
+}
+
 /// Creates index of the document for each rolegraph
 pub(crate) async fn create_document(
     State(config): State<ConfigState>,
+    access: RequestAccess,
     Json(document): Json<Document>,
 ) -> Result<Json<CreateDocumentResponse>> {
     log::debug!("create_document");
-    let mut terraphim_service = TerraphimService::new(config.clone());
-    let document = terraphim_service.create_document(document).await?;
+    let mut terraphim_service = TerraphimService::new(config.clone()).with_access(access.0);
+    let document = terraphim_service
+        .create_document(document)
+        .await
+        .map_err(service_error)?;
     Ok(Json(CreateDocumentResponse {
         status: Status::Success,
         id: document.id,
@@ -64,24 +197,38 @@ pub struct SearchResponse {
     pub results: Vec<Document>,
     /// The number of documents that match the search query
     pub total: usize,
+    /// A correction of the search term, if nothing was found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub did_you_mean: Option<DidYouMean>,
+    /// Values of the facets of the results with their number of results
+    #[serde(default)]
+    pub facets: FacetCounts,
 }
 
 /// Search for documents in all Terraphim graphs defined in the config via GET params
 pub(crate) async fn search_documents(
     Extension(_tx): Extension<SearchResultsStream>,
     State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
     search_query: Query<SearchQuery>,
 ) -> Result<Json<SearchResponse>> {
     log::debug!("search_document called with {:?}", search_query);
 
-    let mut terraphim_service = TerraphimService::new(config_state);
-    let results = terraphim_service.search(&search_query.0).await?;
+    let mut terraphim_service = session_service(config_state, access, session);
+    let (results, did_you_mean) = terraphim_service
+        .search_with_correction(&search_query.0)
+        .await
+        .map_err(service_error)?;
     let total = results.len();
+    let facets = facets::count(&results);
 
     Ok(Json(SearchResponse {
         status: Status::Success,
         results,
         total,
+        did_you_mean,
+        facets,
     }))
 }
 
@@ -89,13 +236,19 @@ pub(crate) async fn search_documents(
 pub(crate) async fn search_documents_post(
     Extension(_tx): Extension<SearchResultsStream>,
     State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
     search_query: Json<SearchQuery>,
 ) -> Result<Json<SearchResponse>> {
     log::debug!("POST Searching documents with query: {search_query:?}");
 
-    let mut terraphim_service = TerraphimService::new(config_state);
-    let results = terraphim_service.search(&search_query).await?;
+    let mut terraphim_service = session_service(config_state, access, session);
+    let (results, did_you_mean) = terraphim_service
+        .search_with_correction(&search_query)
+        .await
+        .map_err(service_error)?;
     let total = results.len();
+    let facets = facets::count(&results);
 
     if total == 0 {
         log::debug!("No documents found");
@@ -107,6 +260,154 @@ pub(crate) async fn search_documents_post(
         status: Status::Success,
         results,
         total,
+        did_you_mean,
+        facets,
+    }))
+}
+
+/// Stream the results of a search as server-sent events, as each haystack
+/// is searched
+///
+/// Every result is a `document` event with the document as JSON data. An
+/// `end` event follows the last result, so clients know not to reconnect.
+pub(crate) async fn search_documents_stream(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Query(search_query): Query<SearchQuery>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, serde_json::Error>>>> {
+    log::debug!("Streaming search results for {search_query:?}");
+    let terraphim_service = session_service(config_state, access, session);
+    let documents = terraphim_service
+        .search_stream(&search_query)
+        .await
+        .map_err(service_error)?;
+    let events = documents
+        .map(|document| Event::default().event("document").json_data(document))
+        .chain(tokio_stream::once(Ok(Event::default()
+            .event("end")
+            .data(""))));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Query parameters of a search written in the query language, see
+/// [`terraphim_service::query`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryParams {
+    /// The query, e.g. `rust AND (tokio OR async-std) -blocking tag:networking`
+    pub q: String,
+    pub role: Option<RoleName>,
+    pub skip: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl QueryParams {
+    /// The search query of the parameters
+    fn search_query(&self) -> Result<SearchQuery> {
+        let search_query = query::parse(&self.q).map_err(|e| service_error(e.into()))?;
+        Ok(SearchQuery {
+            role: self.role.clone(),
+            skip: self.skip,
+            limit: self.limit,
+            ..search_query
+        })
+    }
+}
+
+/// Search for documents with a query in the query language
+pub(crate) async fn search_documents_query(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Query(params): Query<QueryParams>,
+) -> Result<Json<SearchResponse>> {
+    log::debug!("Searching documents with query {:?}", params.q);
+    let search_query = params.search_query()?;
+    let mut terraphim_service = session_service(config_state, access, session);
+    let (results, did_you_mean) = terraphim_service
+        .search_with_correction(&search_query)
+        .await
+        .map_err(service_error)?;
+    let total = results.len();
+    let facets = facets::count(&results);
+
+    Ok(Json(SearchResponse {
+        status: Status::Success,
+        results,
+        total,
+        did_you_mean,
+        facets,
+    }))
+}
+
+/// Response type for parsing a query
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ParseQueryResponse {
+    /// Status of the parsing
+    pub status: Status,
+    /// The search query the query compiles to
+    pub query: SearchQuery,
+}
+
+/// Parse a query in the query language without searching
+pub(crate) async fn parse_query(
+    Query(params): Query<QueryParams>,
+) -> Result<Json<ParseQueryResponse>> {
+    Ok(Json(ParseQueryResponse {
+        status: Status::Success,
+        query: params.search_query()?,
+    }))
+}
+
+/// Request body for a page of search results
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchPageRequest {
+    #[serde(flatten)]
+    pub query: SearchQuery,
+    /// The `next_cursor` of the previous page; omitted for the first page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+}
+
+/// Response type for a page of search results
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchPageResponse {
+    /// Status of the search
+    pub status: Status,
+    /// The results on this page
+    pub results: Vec<Document>,
+    /// The number of documents that match the search query, on all pages
+    pub total: usize,
+    /// Cursor of the next page; absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+    /// Values of the facets of the results on all pages with their number
+    /// of results
+    #[serde(default)]
+    pub facets: FacetCounts,
+}
+
+/// Search for a page of documents, continuing from the cursor of the
+/// previous page
+pub(crate) async fn search_documents_page(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Json(request): Json<SearchPageRequest>,
+) -> Result<Json<SearchPageResponse>> {
+    log::debug!("Searching page of documents with {request:?}");
+    let mut terraphim_service = session_service(config_state, access, session);
+    let page = terraphim_service
+        .search_page(&request.query, request.cursor)
+        .await
+        .map_err(service_error)?;
+
+    Ok(Json(SearchPageResponse {
+        status: Status::Success,
+        results: page.documents,
+        total: page.total,
+        next_cursor: page.next_cursor,
+        facets: page.facets,
     }))
 }
 
@@ -122,10 +423,18 @@ pub struct ConfigResponse {
 }
 
 /// API handler for Terraphim Config
-pub(crate) async fn get_config(State(config): State<ConfigState>) -> Result<Json<ConfigResponse>> {
+///
+/// Only admins see the principals of the config.
+pub(crate) async fn get_config(
+    State(config): State<ConfigState>,
+    access: RequestAccess,
+) -> Result<Json<ConfigResponse>> {
     log::debug!("Called API endpoint get_config");
     let terraphim_service = TerraphimService::new(config);
-    let config = terraphim_service.fetch_config().await;
+    let mut config = terraphim_service.fetch_config().await;
+    if access.require_admin().is_err() {
+        config.principals.clear();
+    }
     Ok(Json(ConfigResponse {
         status: Status::Success,
         config,
@@ -135,12 +444,922 @@ pub(crate) async fn get_config(State(config): State<ConfigState>) -> Result<Json
 /// API handler for Terraphim Config update
 pub(crate) async fn update_config(
     State(config_state): State<ConfigState>,
+    access: RequestAccess,
     Json(config_new): Json<Config>,
-) -> Json<ConfigResponse> {
-    let mut config = config_state.config.lock().await;
-    *config = config_new.clone();
-    Json(ConfigResponse {
+) -> Result<Json<ConfigResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state);
+    let config = terraphim_service.update_config(config_new).await?;
+    Ok(Json(ConfigResponse {
+        status: Status::Success,
+        config,
+    }))
+}
+
+/// Query parameters for fetching the rolegraph
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoleGraphQuery {
+    /// Role of the graph; the default role is used if not set
+    pub role: Option<RoleName>,
+    /// Only return the neighbourhood of the concepts matched in this text
+    pub center: Option<String>,
+    /// Number of hops around `center` to include (defaults to 1)
+    pub depth: Option<usize>,
+}
+
+/// Response type for the rolegraph of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoleGraphResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Nodes and edges of the graph
+    pub graph: GraphData,
+}
+
+/// Return the rolegraph of a role as layout-ready nodes and edges
+pub(crate) async fn get_rolegraph(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Query(query): Query<RoleGraphQuery>,
+) -> Result<Json<RoleGraphResponse>> {
+    log::debug!("Called API endpoint get_rolegraph with {query:?}");
+    let terraphim_service = session_service(config_state, access, session);
+    let role = match query.role {
+        Some(role) => role,
+        None => terraphim_service.default_role().await,
+    };
+    let graph = terraphim_service
+        .get_graph_data(&role, query.center.as_deref(), query.depth.unwrap_or(1))
+        .await
+        .map_err(service_error)?;
+    Ok(Json(RoleGraphResponse {
+        status: Status::Success,
+        graph,
+    }))
+}
+
+/// Query parameters for the related concepts of a term
+#[derive(Debug, Deserialize)]
+pub struct RelatedQuery {
+    /// Term or text whose concepts to find related concepts of
+    #[serde(default)]
+    pub term: String,
+    /// Maximum number of related concepts (defaults to 10)
+    pub limit: Option<usize>,
+}
+
+/// Response type for the related concepts of a term
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelatedConceptsResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Related concepts, the most strongly connected first; their `rank` is
+    /// the number of co-occurrences with the concepts of the term
+    pub concepts: Vec<GraphNode>,
+}
+
+/// List the concepts of a role which co-occur most with the concepts of a
+/// term, for a "related topics" panel
+pub(crate) async fn related_concepts(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+    Query(query): Query<RelatedQuery>,
+) -> Result<Json<RelatedConceptsResponse>> {
+    log::debug!("Called API endpoint related_concepts for role `{role}` with {query:?}");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let concepts = terraphim_service
+        .related_concepts(
+            &RoleName::new(&role),
+            &query.term,
+            query.limit.unwrap_or(10),
+        )
+        .await
+        .map_err(service_error)?;
+    Ok(Json(RelatedConceptsResponse {
+        status: Status::Success,
+        concepts,
+    }))
+}
+
+/// Response type for the communities of the concepts of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommunitiesResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Named communities of concepts, the largest first
+    pub communities: Vec<Community>,
+}
+
+/// List the communities of the concepts of a role, to group and color the
+/// nodes of the graph view
+pub(crate) async fn list_communities(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<Json<CommunitiesResponse>> {
+    log::debug!("Called API endpoint list_communities for role `{role}`");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let communities = terraphim_service
+        .communities(&RoleName::new(&role))
+        .await
+        .map_err(service_error)?;
+    Ok(Json(CommunitiesResponse {
+        status: Status::Success,
+        communities,
+    }))
+}
+
+/// Query parameters for search box suggestions
+#[derive(Debug, Deserialize)]
+pub struct SuggestQuery {
+    /// What was typed so far
+    #[serde(default)]
+    pub q: String,
+    /// Text before what is being typed, e.g. the preceding sentence
+    #[serde(default)]
+    pub context: String,
+    /// Maximum number of suggestions (defaults to 10)
+    pub limit: Option<usize>,
+}
+
+/// Response type for search box suggestions
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SuggestResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Suggestions, the best first
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Suggest search terms from the thesaurus and related concepts of a role,
+/// and from the recent searches of the session
+pub(crate) async fn suggest(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Path(role): Path<String>,
+    Query(query): Query<SuggestQuery>,
+) -> Result<Json<SuggestResponse>> {
+    log::debug!("Called API endpoint suggest for role `{role}` with {query:?}");
+    let terraphim_service = session_service(config_state, access, session);
+    let suggestions = terraphim_service
+        .suggest_in_context(
+            &RoleName::new(&role),
+            &query.q,
+            &query.context,
+            query.limit.unwrap_or(10),
+        )
+        .await
+        .map_err(service_error)?;
+    Ok(Json(SuggestResponse {
+        status: Status::Success,
+        suggestions,
+    }))
+}
+
+/// Record which suggestion of a role was accepted, which re-ranks later
+/// suggestions of the role
+pub(crate) async fn accept_suggestion(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+    Json(acceptance): Json<SuggestionAcceptance>,
+) -> Result<Json<InteractionResponse>> {
+    log::debug!("Called API endpoint accept_suggestion for role `{role}` with {acceptance:?}");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    terraphim_service
+        .accept_suggestion(&RoleName::new(&role), acceptance)
+        .await
+        .map_err(service_error)?;
+    Ok(Json(InteractionResponse {
+        status: Status::Success,
+    }))
+}
+
+/// The thesaurus of a role serialized for `terraphim_automata_wasm`, so
+/// clients can autocomplete and highlight terms offline
+pub(crate) async fn get_automata(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<impl IntoResponse> {
+    log::debug!("Called API endpoint get_automata for role `{role}`");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let bytes = terraphim_service
+        .automata(&RoleName::new(&role))
+        .await
+        .map_err(service_error)?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes))
+}
+
+/// Query parameters for the backlinks of a document or concept
+#[derive(Debug, Deserialize)]
+pub struct BacklinksQuery {
+    /// ID or URL of a document, or a title or term of a concept
+    pub target: String,
+}
+
+/// Response type for the backlinks of a document or concept
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BacklinksResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Documents linking to the target, by title
+    pub backlinks: Vec<Backlink>,
+}
+
+/// Return the documents of a role linking to a document or concept
+pub(crate) async fn get_backlinks(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+    Query(query): Query<BacklinksQuery>,
+) -> Result<Json<BacklinksResponse>> {
+    log::debug!("Called API endpoint get_backlinks for role `{role}` with {query:?}");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let backlinks = terraphim_service
+        .backlinks(&RoleName::new(&role), &query.target)
+        .await
+        .map_err(service_error)?;
+    Ok(Json(BacklinksResponse {
+        status: Status::Success,
+        backlinks,
+    }))
+}
+
+/// Response type for the candidate concepts of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KgCandidatesResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Pending candidates, the most cited first
+    pub candidates: Vec<Candidate>,
+}
+
+/// Response type for a candidate concept after review
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KgCandidateResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The reviewed candidate
+    pub candidate: Candidate,
+    /// Path of the page written for a promoted candidate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// List the pending candidate concepts of a role
+pub(crate) async fn list_kg_candidates(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<Json<KgCandidatesResponse>> {
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let candidates = terraphim_service
+        .kg_candidates(&RoleName::new(&role))
+        .await
+        .map_err(service_error)?;
+    Ok(Json(KgCandidatesResponse {
+        status: Status::Success,
+        candidates,
+    }))
+}
+
+/// Extract candidate concepts from all documents of a role
+pub(crate) async fn extract_kg_candidates(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<Json<KgCandidatesResponse>> {
+    access.require_admin()?;
+    log::debug!("Called API endpoint extract_kg_candidates for role `{role}`");
+    let terraphim_service = TerraphimService::new(config_state);
+    let candidates = terraphim_service
+        .extract_kg_candidates(&RoleName::new(&role))
+        .await?;
+    Ok(Json(KgCandidatesResponse {
+        status: Status::Success,
+        candidates,
+    }))
+}
+
+/// Write a candidate concept into the knowledge graph of its role
+pub(crate) async fn promote_kg_candidate(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(id): Path<String>,
+) -> Result<Json<KgCandidateResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let Some((candidate, path)) = terraphim_service
+        .promote_kg_candidate(&id)
+        .await
+        .map_err(service_error)?
+    else {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No KG candidate with ID `{id}`"),
+        ));
+    };
+    Ok(Json(KgCandidateResponse {
+        status: Status::Success,
+        candidate,
+        path: Some(path.display().to_string()),
+    }))
+}
+
+/// Reject a candidate concept
+pub(crate) async fn reject_kg_candidate(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(id): Path<String>,
+) -> Result<Json<KgCandidateResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let Some(candidate) = terraphim_service
+        .reject_kg_candidate(&id)
+        .await
+        .map_err(service_error)?
+    else {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No KG candidate with ID `{id}`"),
+        ));
+    };
+    Ok(Json(KgCandidateResponse {
+        status: Status::Success,
+        candidate,
+        path: None,
+    }))
+}
+
+/// Response type for the KG coverage report of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CoverageResponse {
+    /// Status of the request
+    pub status: Status,
+    /// How the knowledge graph of the role covers its haystacks
+    pub report: CoverageReport,
+}
+
+/// Report on how the knowledge graph of a role covers the documents in its
+/// haystacks
+pub(crate) async fn get_coverage(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<Json<CoverageResponse>> {
+    log::debug!("Called API endpoint get_coverage for role `{role}`");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let report = terraphim_service
+        .kg_coverage(&RoleName::new(&role))
+        .await
+        .map_err(service_error)?;
+    Ok(Json(CoverageResponse {
         status: Status::Success,
-        config: config_new,
-    })
+        report,
+    }))
+}
+
+/// Query parameters for exporting the knowledge graph of a role
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// `owl`, `obo` or `jsonld`
+    pub format: ExportFormat,
+    /// Whether to relate concepts which co-occur in documents
+    #[serde(default)]
+    pub relations: bool,
+}
+
+/// Export the knowledge graph of a role as OWL, OBO or JSON-LD
+pub(crate) async fn export_kg(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse> {
+    log::debug!("Called API endpoint export_kg for role `{role}` with {query:?}");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let export = terraphim_service
+        .export_kg(&RoleName::new(&role), query.format, query.relations)
+        .await
+        .map_err(service_error)?;
+    Ok(([(header::CONTENT_TYPE, query.format.media_type())], export))
+}
+
+/// Response type for validating the knowledge graph of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ValidateKgResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Problems of the knowledge graph, none if it is fine
+    pub issues: Vec<LintIssue>,
+}
+
+/// Validate the knowledge graph of a role before it is published
+pub(crate) async fn validate_kg(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<Json<ValidateKgResponse>> {
+    log::debug!("Called API endpoint validate_kg for role `{role}`");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let issues = terraphim_service
+        .validate_kg(&RoleName::new(&role))
+        .await
+        .map_err(service_error)?;
+    Ok(Json(ValidateKgResponse {
+        status: Status::Success,
+        issues,
+    }))
+}
+
+/// Response type for the top-level concepts of a role
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConceptsResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Concepts with their number of documents, the most frequent first
+    pub concepts: Vec<ConceptCount>,
+}
+
+/// List the top-level concepts the documents of a role are tagged with
+pub(crate) async fn list_concepts(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(role): Path<String>,
+) -> Result<Json<ConceptsResponse>> {
+    log::debug!("Called API endpoint list_concepts for role `{role}`");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let concepts = terraphim_service
+        .concept_counts(&RoleName::new(&role))
+        .await
+        .map_err(service_error)?;
+    Ok(Json(ConceptsResponse {
+        status: Status::Success,
+        concepts,
+    }))
+}
+
+/// List the documents of a role tagged with a top-level concept
+pub(crate) async fn browse_concept(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path((role, concept)): Path<(String, String)>,
+) -> Result<Json<SearchResponse>> {
+    log::debug!("Called API endpoint browse_concept for role `{role}` and concept `{concept}`");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let results = terraphim_service
+        .browse_concept(&RoleName::new(&role), &concept)
+        .await
+        .map_err(service_error)?;
+    let total = results.len();
+    let facets = facets::count(&results);
+    Ok(Json(SearchResponse {
+        status: Status::Success,
+        results,
+        total,
+        did_you_mean: None,
+        facets,
+    }))
+}
+
+/// Response type for the backlinks of a concept
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConceptBacklinksResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Documents linking to or mentioning the concept, the most linking and
+    /// mentioning first
+    pub backlinks: Vec<ConceptBacklink>,
+}
+
+/// Return the documents of a role linking to or mentioning a concept
+pub(crate) async fn get_concept_backlinks(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path((role, concept)): Path<(String, String)>,
+) -> Result<Json<ConceptBacklinksResponse>> {
+    log::debug!(
+        "Called API endpoint get_concept_backlinks for role `{role}` and concept `{concept}`"
+    );
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    let backlinks = terraphim_service
+        .get_concept_backlinks(&RoleName::new(&role), &concept)
+        .await
+        .map_err(service_error)?;
+    Ok(Json(ConceptBacklinksResponse {
+        status: Status::Success,
+        backlinks,
+    }))
+}
+
+/// Query parameters for listing saved searches
+#[derive(Debug, Deserialize)]
+pub struct SavedSearchQuery {
+    /// Only list the saved searches of this role
+    pub role: Option<String>,
+}
+
+/// Response type for a saved search
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedSearchResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The saved search
+    pub saved_search: SavedSearch,
+}
+
+/// Response type for listing saved searches
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedSearchesResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The saved searches
+    pub saved_searches: Vec<SavedSearch>,
+}
+
+/// Response type for deleting a saved search
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeleteSavedSearchResponse {
+    /// Status of the deletion
+    pub status: Status,
+}
+
+/// Save a search to be re-run on a schedule
+pub(crate) async fn save_search(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Json(saved_search): Json<SavedSearch>,
+) -> Result<Json<SavedSearchResponse>> {
+    access.require_admin()?;
+    log::debug!("Called API endpoint save_search with {saved_search:?}");
+    let terraphim_service = TerraphimService::new(config_state);
+    let saved_search = terraphim_service
+        .save_search(saved_search)
+        .await
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.into()))?;
+    Ok(Json(SavedSearchResponse {
+        status: Status::Success,
+        saved_search,
+    }))
+}
+
+/// List the saved searches, optionally of a single role
+pub(crate) async fn list_saved_searches(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Query(query): Query<SavedSearchQuery>,
+) -> Result<Json<SavedSearchesResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state);
+    let role = query.role.as_deref().map(RoleName::new);
+    let saved_searches = terraphim_service.saved_searches(role.as_ref()).await;
+    Ok(Json(SavedSearchesResponse {
+        status: Status::Success,
+        saved_searches,
+    }))
+}
+
+/// Run a saved search now
+///
+/// Anyone may run a saved search; its results are those the caller may see.
+pub(crate) async fn run_saved_search(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Path(id): Path<String>,
+) -> Result<Json<SearchResponse>> {
+    log::debug!("Running saved search `{id}`");
+    let mut terraphim_service = session_service(config_state, access, session);
+    let Some(results) = terraphim_service
+        .run_saved_search(&id)
+        .await
+        .map_err(service_error)?
+    else {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No saved search with ID `{id}`"),
+        ));
+    };
+    let total = results.len();
+    let facets = facets::count(&results);
+    Ok(Json(SearchResponse {
+        status: Status::Success,
+        results,
+        total,
+        did_you_mean: None,
+        facets,
+    }))
+}
+
+/// Delete a saved search
+pub(crate) async fn delete_saved_search(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(id): Path<String>,
+) -> Result<Json<DeleteSavedSearchResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state);
+    if !terraphim_service.delete_saved_search(&id).await {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!("No saved search with ID `{id}`"),
+        ));
+    }
+    Ok(Json(DeleteSavedSearchResponse {
+        status: Status::Success,
+    }))
+}
+
+/// Stream the alerts of all saved searches as server-sent events
+///
+/// Every alert is an `alert` event with the alert as JSON data.
+pub(crate) async fn alerts(
+    access: RequestAccess,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, serde_json::Error>>>> {
+    access.require_admin()?;
+    let alerts = BroadcastStream::new(SavedSearchStore::instance().await.subscribe());
+    // Alerts missed by a lagging client are skipped
+    let events = alerts.filter_map(|alert| {
+        alert
+            .ok()
+            .map(|alert| Event::default().event("alert").json_data(alert))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Query parameters for the analytics report
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsQuery {
+    /// Maximum number of entries per query list (defaults to 10)
+    pub limit: Option<usize>,
+}
+
+/// Response type for the analytics report
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnalyticsResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Top queries, zero-result queries and latency percentiles per role
+    pub report: AnalyticsReport,
+}
+
+/// Report on all searches since the query log was started
+pub(crate) async fn get_query_analytics(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<AnalyticsResponse>> {
+    access.require_admin()?;
+    log::debug!("Called API endpoint get_query_analytics with {query:?}");
+    let terraphim_service = TerraphimService::new(config_state);
+    let report = terraphim_service
+        .query_analytics(query.limit.unwrap_or(10))
+        .await;
+    Ok(Json(AnalyticsResponse {
+        status: Status::Success,
+        report,
+    }))
+}
+
+/// Response type for recording a result interaction
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InteractionResponse {
+    /// Status of the request
+    pub status: Status,
+}
+
+/// Record that a search result was opened, copied or dismissed
+pub(crate) async fn record_interaction(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Json(interaction): Json<Interaction>,
+) -> Result<Json<InteractionResponse>> {
+    log::debug!("Called API endpoint record_interaction with {interaction:?}");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    terraphim_service
+        .record_interaction(interaction)
+        .await
+        .map_err(service_error)?;
+    Ok(Json(InteractionResponse {
+        status: Status::Success,
+    }))
+}
+
+/// Record feedback on a search result, which re-ranks later searches of
+/// its role
+pub(crate) async fn record_feedback(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Json(interaction): Json<Interaction>,
+) -> Result<Json<InteractionResponse>> {
+    log::debug!("Called API endpoint record_feedback with {interaction:?}");
+    let terraphim_service = TerraphimService::new(config_state).with_access(access.0);
+    terraphim_service
+        .record_feedback(interaction)
+        .await
+        .map_err(service_error)?;
+    Ok(Json(InteractionResponse {
+        status: Status::Success,
+    }))
+}
+
+/// Request type for changing the user session
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SessionUpdate {
+    /// Role to select; `null` goes back to the default role of the config
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub selected_role: Option<Option<RoleName>>,
+    /// Preferences to merge into those of the session; `null` values
+    /// remove a preference
+    #[serde(default)]
+    pub preferences: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// Tell a field set to `null` from a missing one
+fn present<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Response type for the user session
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The session
+    pub session: UserSession,
+}
+
+/// Get the user session of the request
+pub(crate) async fn get_session(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+) -> Result<Json<SessionResponse>> {
+    let session_id = session.require()?;
+    let terraphim_service = TerraphimService::new(config_state)
+        .with_access(access.0)
+        .with_session(session_id);
+    let session = terraphim_service.session().await.map_err(service_error)?;
+    Ok(Json(SessionResponse {
+        status: Status::Success,
+        session,
+    }))
+}
+
+/// Select the role and change the preferences of the user session of the
+/// request
+pub(crate) async fn update_session(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    session: RequestSession,
+    Json(update): Json<SessionUpdate>,
+) -> Result<Json<SessionResponse>> {
+    log::debug!("Called API endpoint update_session with {update:?}");
+    let session_id = session.require()?;
+    let terraphim_service = TerraphimService::new(config_state)
+        .with_access(access.0)
+        .with_session(session_id);
+    if let Some(role) = update.selected_role {
+        terraphim_service
+            .select_role(role)
+            .await
+            .map_err(service_error)?;
+    }
+    if let Some(preferences) = update.preferences {
+        terraphim_service
+            .update_preferences(preferences)
+            .await
+            .map_err(service_error)?;
+    }
+    let session = terraphim_service.session().await.map_err(service_error)?;
+    Ok(Json(SessionResponse {
+        status: Status::Success,
+        session,
+    }))
+}
+
+/// Request and response type for the log filter
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogFilter {
+    /// Filter directives in `RUST_LOG` syntax, e.g. `info,terraphim_rolegraph=debug`
+    pub filter: String,
+}
+
+/// Response type for the log filter
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogFilterResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The active filter directives
+    pub filter: String,
+}
+
+/// Return the active log filter
+pub(crate) async fn get_log_filter(access: RequestAccess) -> Result<Json<LogFilterResponse>> {
+    access.require_admin()?;
+    Ok(Json(LogFilterResponse {
+        status: Status::Success,
+        filter: crate::telemetry::log_filter()?,
+    }))
+}
+
+/// Change the log filter at runtime
+pub(crate) async fn update_log_filter(
+    access: RequestAccess,
+    Json(log_filter): Json<LogFilter>,
+) -> Result<Json<LogFilterResponse>> {
+    access.require_admin()?;
+    log::debug!("Called API endpoint update_log_filter with {log_filter:?}");
+    let filter = crate::telemetry::set_log_filter(&log_filter.filter)?;
+    Ok(Json(LogFilterResponse {
+        status: Status::Success,
+        filter,
+    }))
+}
+
+/// Response type for listing the maintenance jobs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobsResponse {
+    /// Status of the request
+    pub status: Status,
+    /// All jobs with their schedule and last run
+    pub jobs: Vec<JobStatus>,
+}
+
+/// Response type for the runs of a maintenance job
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobRunsResponse {
+    /// Status of the request
+    pub status: Status,
+    /// Runs of the job, the most recent first
+    pub runs: Vec<JobRun>,
+}
+
+/// Response type for running a maintenance job
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JobRunResponse {
+    /// Status of the request
+    pub status: Status,
+    /// The finished run
+    pub run: JobRun,
+}
+
+/// List the maintenance jobs with their schedule and last run
+pub(crate) async fn list_jobs(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+) -> Result<Json<JobsResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state);
+    Ok(Json(JobsResponse {
+        status: Status::Success,
+        jobs: terraphim_service.jobs().await,
+    }))
+}
+
+/// List the runs of a maintenance job
+pub(crate) async fn list_job_runs(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(job): Path<JobKind>,
+) -> Result<Json<JobRunsResponse>> {
+    access.require_admin()?;
+    let terraphim_service = TerraphimService::new(config_state);
+    Ok(Json(JobRunsResponse {
+        status: Status::Success,
+        runs: terraphim_service.job_runs(job).await,
+    }))
+}
+
+/// Run a maintenance job now and wait for it to finish
+pub(crate) async fn run_job(
+    State(config_state): State<ConfigState>,
+    access: RequestAccess,
+    Path(job): Path<JobKind>,
+) -> Result<Json<JobRunResponse>> {
+    access.require_admin()?;
+    log::debug!("Called API endpoint run_job with {job:?}");
+    let terraphim_service = TerraphimService::new(config_state);
+    let Some(run) = terraphim_service.run_job(job).await else {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            anyhow::anyhow!("Job {job:?} is running already"),
+        ));
+    };
+    Ok(Json(JobRunResponse {
+        status: Status::Success,
+        run,
+    }))
 }
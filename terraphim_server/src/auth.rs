@@ -0,0 +1,115 @@
+//! Access control of API requests, see [`terraphim_config::access`]
+//!
+//! Requests identify their principal by an API key, either in the
+//! `X-Api-Key` header or as bearer token in the `Authorization` header.
+//! Their user session, see [`terraphim_service::sessions`], is the one of
+//! their principal or the one named by the `X-Session-Id` header.
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, StatusCode},
+};
+use terraphim_config::{Access, ConfigState};
+use terraphim_service::sessions::{anonymous_session, principal_session};
+
+use crate::error::{ApiError, Result};
+
+/// Header with the API key of a request
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header with the ID of the user session of a request without API key
+pub const SESSION_HEADER: &str = "x-session-id";
+
+/// Length of the session ID in the [`SESSION_HEADER`] at most, in bytes
+pub const MAX_SESSION_ID_LEN: usize = 128;
+
+/// The access of the principal making a request
+#[derive(Debug, Clone)]
+pub(crate) struct RequestAccess(pub(crate) Access);
+
+impl RequestAccess {
+    /// Fail unless the principal may see everything, i.e. is an admin or
+    /// access control is off
+    pub(crate) fn require_admin(&self) -> Result<()> {
+        if self.0.is_all() {
+            Ok(())
+        } else {
+            Err(ApiError(
+                StatusCode::FORBIDDEN,
+                anyhow::anyhow!("Only admins may do this"),
+            ))
+        }
+    }
+}
+
+/// The user session of a request, if any
+///
+/// Sessions of principals and sessions named by clients have distinct
+/// prefixes, so a client cannot pick the session of a principal.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestSession(pub(crate) Option<String>);
+
+impl RequestSession {
+    /// Fail unless the request has a session
+    pub(crate) fn require(self) -> Result<String> {
+        self.0.ok_or_else(|| {
+            ApiError(
+                StatusCode::BAD_REQUEST,
+                anyhow::anyhow!("Send an API key or an `{SESSION_HEADER}` header"),
+            )
+        })
+    }
+}
+
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(api_key) = headers.get(API_KEY_HEADER) {
+        return api_key.to_str().ok();
+    }
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+#[async_trait]
+impl FromRequestParts<ConfigState> for RequestAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ConfigState) -> Result<Self> {
+        let config = state.config.lock().await;
+        match config.access(api_key(&parts.headers)) {
+            Ok(access) => Ok(RequestAccess(access)),
+            Err(e) => Err(ApiError(StatusCode::UNAUTHORIZED, e.into())),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<ConfigState> for RequestSession {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ConfigState) -> Result<Self> {
+        if let Some(api_key) = api_key(&parts.headers) {
+            let config = state.config.lock().await;
+            if let Some(principal) = config.principal(api_key) {
+                return Ok(RequestSession(Some(principal_session(&principal.name))));
+            }
+        }
+        let session_id = parts
+            .headers
+            .get(SESSION_HEADER)
+            .and_then(|session_id| session_id.to_str().ok())
+            .filter(|session_id| !session_id.is_empty());
+        if session_id.is_some_and(|session_id| session_id.len() > MAX_SESSION_ID_LEN) {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                anyhow::anyhow!(
+                    "The `{SESSION_HEADER}` header is longer than {MAX_SESSION_ID_LEN} bytes"
+                ),
+            ));
+        }
+        Ok(RequestSession(session_id.map(anonymous_session)))
+    }
+}
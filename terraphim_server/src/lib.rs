@@ -1,9 +1,10 @@
 use std::net::SocketAddr;
 
 use axum::{
+    extract::DefaultBodyLimit,
     http::{header, Method, StatusCode, Uri},
     response::{Html, IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Extension, Router,
 };
 use rust_embed::RustEmbed;
@@ -11,13 +12,32 @@ use terraphim_config::ConfigState;
 use terraphim_types::IndexedDocument;
 use tokio::sync::broadcast::channel;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
 
 mod api;
+mod auth;
 mod error;
+mod telemetry;
 
 use api::{create_document, health, search_documents, search_documents_post};
-pub use api::{ConfigResponse, CreateDocumentResponse, SearchResponse};
+pub use api::{
+    AnalyticsQuery, AnalyticsResponse, AttachmentQuery, AttachmentResponse, BacklinksQuery,
+    BacklinksResponse, ConceptBacklinksResponse, ConfigResponse, CoverageResponse,
+    CreateDocumentResponse, DeleteSavedSearchResponse, InteractionResponse, JobRunResponse,
+    JobRunsResponse, JobsResponse, LogFilter, LogFilterResponse, ParseQueryResponse, QueryParams,
+    RoleGraphQuery, RoleGraphResponse, SavedSearchQuery, SavedSearchResponse,
+    SavedSearchesResponse, SearchPageRequest, SearchPageResponse, SearchResponse, SessionResponse,
+    SessionUpdate, SuggestQuery, SuggestResponse,
+};
+pub use auth::{API_KEY_HEADER, SESSION_HEADER};
 pub use error::{Result, Status};
+pub use telemetry::{
+    init_tracing, log_filter, set_log_filter, shutdown_tracing, REQUEST_ID_HEADER,
+};
+
+/// Maximum size of an uploaded attachment in bytes
+const MAX_ATTACHMENT_SIZE: usize = 64 * 1024 * 1024;
 
 // use axum_embed::ServeEmbed;
 static INDEX_HTML: &str = "index.html";
@@ -36,12 +56,71 @@ pub async fn axum_server(server_hostname: SocketAddr, config_state: ConfigState)
         // .route("/documents", get(list_documents))
         .route("/documents", post(create_document))
         .route("/documents/", post(create_document))
+        .route(
+            "/attachments",
+            post(api::upload_attachment).layer(DefaultBodyLimit::max(MAX_ATTACHMENT_SIZE)),
+        )
+        .route("/attachments/:blob_id", get(api::get_attachment))
         .route("/documents/search", get(search_documents))
         .route("/documents/search", post(search_documents_post))
+        .route("/documents/search/page", post(api::search_documents_page))
+        .route("/documents/search/query", get(api::search_documents_query))
+        .route("/documents/search/parse", get(api::parse_query))
+        .route(
+            "/documents/search/stream",
+            get(api::search_documents_stream),
+        )
         .route("/config", get(api::get_config))
         .route("/config/", get(api::get_config))
         .route("/config", post(api::update_config))
         .route("/config/", post(api::update_config))
+        .route("/rolegraph", get(api::get_rolegraph))
+        .route("/rolegraph/", get(api::get_rolegraph))
+        .route("/roles/:role/suggest", get(api::suggest))
+        .route("/roles/:role/suggest/accept", post(api::accept_suggestion))
+        .route("/roles/:role/automata", get(api::get_automata))
+        .route("/roles/:role/coverage", get(api::get_coverage))
+        .route("/roles/:role/export", get(api::export_kg))
+        .route("/roles/:role/validate", get(api::validate_kg))
+        .route("/roles/:role/backlinks", get(api::get_backlinks))
+        .route("/roles/:role/concepts", get(api::list_concepts))
+        .route("/roles/:role/communities", get(api::list_communities))
+        .route("/roles/:role/related", get(api::related_concepts))
+        .route(
+            "/roles/:role/concepts/:concept/documents",
+            get(api::browse_concept),
+        )
+        .route(
+            "/roles/:role/concepts/:concept/backlinks",
+            get(api::get_concept_backlinks),
+        )
+        .route("/roles/:role/kg-candidates", get(api::list_kg_candidates))
+        .route(
+            "/roles/:role/kg-candidates",
+            post(api::extract_kg_candidates),
+        )
+        .route(
+            "/kg-candidates/:id/promote",
+            post(api::promote_kg_candidate),
+        )
+        .route("/kg-candidates/:id/reject", post(api::reject_kg_candidate))
+        .route("/saved-searches", get(api::list_saved_searches))
+        .route("/saved-searches", post(api::save_search))
+        .route("/saved-searches/:id", delete(api::delete_saved_search))
+        .route("/saved-searches/:id/run", post(api::run_saved_search))
+        .route("/alerts", get(api::alerts))
+        .route("/analytics/queries", get(api::get_query_analytics))
+        .route("/analytics/queries/", get(api::get_query_analytics))
+        .route("/analytics/interactions", post(api::record_interaction))
+        .route("/analytics/interactions/", post(api::record_interaction))
+        .route("/feedback", post(api::record_feedback))
+        .route("/session", get(api::get_session))
+        .route("/session", post(api::update_session))
+        .route("/admin/jobs", get(api::list_jobs))
+        .route("/admin/jobs/:job/runs", get(api::list_job_runs))
+        .route("/admin/jobs/:job/run", post(api::run_job))
+        .route("/admin/log-filter", get(api::get_log_filter))
+        .route("/admin/log-filter", post(api::update_log_filter))
         .fallback(static_handler)
         .with_state(config_state)
         .layer(Extension(tx))
@@ -56,7 +135,12 @@ pub async fn axum_server(server_hostname: SocketAddr, config_state: ConfigState)
                     Method::PATCH,
                     Method::DELETE,
                 ]),
-        );
+        )
+        // Tag every request with an ID (unless the caller sent one), trace
+        // it and return the ID to the caller
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::make_request_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
     // Note: Prefixing the host with `http://` makes the URL clickable in some terminals
     println!("listening on http://{server_hostname}");
@@ -17,16 +17,133 @@
 
 
 use anyhow::Context;
+use clap::Parser;
 use std::net::SocketAddr;
-use terraphim_config::{ConfigBuilder, ConfigId};
+use terraphim_automata::{load_thesaurus, AutomataPath, LintIssue};
+use terraphim_config::{Config, ConfigBuilder, ConfigId};
 use terraphim_persistence::Persistable;
+use terraphim_rolegraph::GraphFormat;
 use terraphim_config::ConfigState;
+use terraphim_middleware::thesaurus::{SkosBuilder, ThesaurusBuilder};
 use terraphim_server::{axum_server, Result};
+use terraphim_service::jobs::spawn_jobs;
+use terraphim_service::enrichment::spawn_refresher;
+use terraphim_service::suggest::spawn_warmup;
+use terraphim_service::thesaurus_cache::watch_sources;
+use terraphim_service::{ServiceError, TerraphimService};
 use terraphim_settings::DeviceSettings;
+use terraphim_types::{Document, RoleName, SearchQuery};
+
+/// Terraphim AI server
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Profile the search pipeline against a synthetic corpus, print the
+    /// per-stage timings and exit
+    #[arg(long)]
+    profile_search: bool,
+
+    /// Number of documents in the synthetic corpus
+    #[arg(long, default_value_t = 1000)]
+    profile_documents: usize,
+
+    /// Number of times each canned query is run per role
+    #[arg(long, default_value_t = 5)]
+    profile_iterations: usize,
+
+    /// Print how the knowledge graph of this role covers the documents in
+    /// its haystacks and exit
+    #[arg(long, value_name = "ROLE")]
+    coverage_report: Option<String>,
+
+    /// Print the terms added, removed and retargeted between two versions
+    /// of a thesaurus, files or URLs, and exit
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    thesaurus_diff: Option<Vec<String>>,
+
+    /// Print the problems of a thesaurus, a file or URL, such as terms of
+    /// several concepts, and exit, unsuccessfully if there are any
+    #[arg(long, value_name = "THESAURUS")]
+    validate_thesaurus: Option<String>,
+
+    /// Print the problems of the knowledge graph of this role, including
+    /// concepts without a definition, and exit, unsuccessfully if there
+    /// are any
+    #[arg(long, value_name = "ROLE")]
+    validate_kg: Option<String>,
+
+    /// Print the concepts of this role and their co-occurrences in the
+    /// documents indexed so far for graph tools, and exit
+    #[arg(long, value_name = "ROLE")]
+    export_graph: Option<String>,
+
+    /// Format of the exported graph: `graphml`, `dot` or `cypher`
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "graphml",
+        requires = "export_graph"
+    )]
+    graph_format: GraphFormat,
+
+    /// Print the thesaurus of a SKOS vocabulary, a Turtle or RDF/XML file or
+    /// a directory of them, as JSON for an `automata_path`, and exit
+    #[arg(long, value_name = "PATH")]
+    skos_thesaurus: Option<std::path::PathBuf>,
+
+    /// Only use the SKOS labels in this language, e.g. `en`, or without a
+    /// language tag
+    #[arg(long, value_name = "LANGUAGE", requires = "skos_thesaurus")]
+    skos_language: Option<String>,
+
+    /// Search with a query in the query language, e.g.
+    /// `rust AND (tokio OR async-std) -blocking tag:networking`, print the
+    /// results and exit
+    #[arg(long, value_name = "QUERY")]
+    search: Option<String>,
+
+    /// Run the saved search with this ID, print the results and exit
+    #[arg(long, value_name = "ID")]
+    saved_search: Option<String>,
+
+    /// Role to search with; the default role of the config if not given
+    #[arg(long, value_name = "ROLE", requires = "search")]
+    role: Option<String>,
+
+    /// Shared library with a haystack indexer plugin to load; may be given
+    /// several times
+    #[cfg(feature = "dynamic-indexers")]
+    #[arg(long, value_name = "PATH")]
+    indexer_plugin: Vec<std::path::PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    match run_server().await {
+    let args = Args::parse();
+    #[cfg(feature = "dynamic-indexers")]
+    load_indexer_plugins(&args.indexer_plugin)?;
+    let result = if args.profile_search {
+        profile_search(&args).await
+    } else if let Some(role) = &args.coverage_report {
+        coverage_report(role).await
+    } else if let Some(paths) = &args.thesaurus_diff {
+        thesaurus_diff(&paths[0], &paths[1]).await
+    } else if let Some(path) = &args.validate_thesaurus {
+        validate_thesaurus(path).await
+    } else if let Some(role) = &args.validate_kg {
+        validate_kg(role).await
+    } else if let Some(role) = &args.export_graph {
+        export_graph(role, args.graph_format).await
+    } else if let Some(path) = &args.skos_thesaurus {
+        skos_thesaurus(path, args.skos_language.as_deref()).await
+    } else if let Some(query) = &args.search {
+        search(query, args.role.as_deref()).await
+    } else if let Some(id) = &args.saved_search {
+        run_saved_search(id).await
+    } else {
+        run_server().await
+    };
+    match result {
         Ok(()) => Ok(()),
         Err(e) => {
             log::error!("Error: {e:#?}");
@@ -35,9 +152,184 @@ async fn main() -> Result<()> {
     }
 }
 
+/// Load and register the indexer plugins of shared libraries
+#[cfg(feature = "dynamic-indexers")]
+fn load_indexer_plugins(paths: &[std::path::PathBuf]) -> Result<()> {
+    let registry = terraphim_middleware::indexer::IndexerRegistry::instance();
+    for path in paths {
+        // SAFETY: the libraries are given by whoever starts the server
+        let name = unsafe { registry.load(path)? };
+        log::info!("Loaded indexer plugin `{name}` from {}", path.display());
+    }
+    Ok(())
+}
+
+async fn profile_search(args: &Args) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let dir = std::env::temp_dir().join(format!("terraphim_profile_{}", std::process::id()));
+    log::info!("Writing synthetic corpus to {:?}", dir);
+    let report = terraphim_service::profile::profile_search(
+        &dir,
+        args.profile_documents,
+        args.profile_iterations,
+    )
+    .await;
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        log::warn!("Failed to remove synthetic corpus {:?}: {e}", dir);
+    }
+    println!("{}", report?);
+    Ok(())
+}
+
+async fn coverage_report(role: &str) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let (_, config_state) = load_config().await?;
+    let report = TerraphimService::new(config_state)
+        .kg_coverage(&RoleName::new(role))
+        .await?;
+    println!("{report}");
+    Ok(())
+}
+
+/// The path of a thesaurus file or URL given on the command line
+fn automata_path(path: &str) -> AutomataPath {
+    if path.starts_with("http://") || path.starts_with("https://") {
+        AutomataPath::Remote(path.to_string())
+    } else {
+        AutomataPath::from_local(path)
+    }
+}
+
+async fn thesaurus_diff(old: &str, new: &str) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let old = load_thesaurus(&automata_path(old)).await?;
+    let new = load_thesaurus(&automata_path(new)).await?;
+    print!("{}", terraphim_automata::thesaurus_diff(&old, &new));
+    Ok(())
+}
+
+async fn validate_thesaurus(path: &str) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let thesaurus = load_thesaurus(&automata_path(path)).await?;
+    print_lint_issues(&terraphim_automata::validate_thesaurus(&thesaurus))
+}
+
+async fn validate_kg(role: &str) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let (_, config_state) = load_config().await?;
+    let issues = TerraphimService::new(config_state)
+        .validate_kg(&RoleName::new(role))
+        .await?;
+    print_lint_issues(&issues)
+}
+
+/// Print the problems of a knowledge graph; an error if there are any, so
+/// scripts can tell
+fn print_lint_issues(issues: &[LintIssue]) -> Result<()> {
+    for issue in issues {
+        println!("{issue}");
+    }
+    if issues.is_empty() {
+        println!("No problems");
+        return Ok(());
+    }
+    Err(anyhow::anyhow!("Problems found: {}", issues.len()).into())
+}
+
+async fn export_graph(role: &str, format: GraphFormat) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let (_, config_state) = load_config().await?;
+    let export = TerraphimService::new(config_state)
+        .export_graph(&RoleName::new(role), format)
+        .await?;
+    print!("{export}");
+    Ok(())
+}
+
+async fn skos_thesaurus(path: &std::path::Path, language: Option<&str>) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let builder = match language {
+        Some(language) => SkosBuilder::with_language(language),
+        None => SkosBuilder::default(),
+    };
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let thesaurus = builder.build(name, path).await?;
+    println!("{}", serde_json::to_string_pretty(&thesaurus)?);
+    Ok(())
+}
+
+async fn search(query: &str, role: Option<&str>) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let search_query = SearchQuery {
+        role: role.map(RoleName::new),
+        ..terraphim_service::query::parse(query).map_err(ServiceError::from)?
+    };
+    let (_, config_state) = load_config().await?;
+    let documents = TerraphimService::new(config_state)
+        .search(&search_query)
+        .await?;
+    print_documents(&documents);
+    Ok(())
+}
+
+async fn run_saved_search(id: &str) -> Result<()> {
+    terraphim_server::init_tracing()?;
+
+    let (_, config_state) = load_config().await?;
+    let Some(documents) = TerraphimService::new(config_state)
+        .run_saved_search(id)
+        .await?
+    else {
+        return Err(anyhow::anyhow!("No saved search with ID `{id}`").into());
+    };
+    print_documents(&documents);
+    Ok(())
+}
+
+fn print_documents(documents: &[Document]) {
+    for document in documents {
+        println!(
+            "{}\t{}\t{}",
+            document.rank.unwrap_or_default(),
+            document.title,
+            document.url
+        );
+    }
+}
+
+/// Load the persisted server config, or the default server config if there
+/// is none
+async fn load_config() -> Result<(Config, ConfigState)> {
+    let mut config = match ConfigBuilder::new_with_id(ConfigId::Server).build() {
+        Ok(mut local_config) => match local_config.load().await {
+            Ok(config) => config,
+            Err(e) => {
+                log::info!("Failed to load config: {:?}", e);
+                ConfigBuilder::new().build_default_server().build().unwrap()
+            }
+        },
+        Err(e) => panic!("Failed to build config: {e:?}"),
+    };
+    let config_state = ConfigState::new(&mut config)
+        .await
+        .context("Failed to load config")?;
+    Ok((config, config_state))
+}
+
 async fn run_server() -> Result<()> {
-    // Set up logger for the server
-    env_logger::init();
+    // Set up tracing (and logging) for the server
+    terraphim_server::init_tracing()?;
 
     let server_settings =
         DeviceSettings::load_from_env_and_file(None).context("Failed to load settings")?;
@@ -54,21 +346,17 @@ async fn run_server() -> Result<()> {
             SocketAddr::from(([127, 0, 0, 1], port))
         });
 
-    
-        let mut config = match ConfigBuilder::new_with_id(ConfigId::Server).build() {
-            Ok(mut local_config) => match local_config.load().await {
-                Ok(config) => config,
-                Err(e) => {
-                    log::info!("Failed to load config: {:?}", e);
-                    let config = ConfigBuilder::new().build_default_server().build().unwrap();
-                    config
-                },
-            },
-            Err(e) => panic!("Failed to build config: {:?}", e),
-        };
-    let config_state = ConfigState::new(&mut config)
-        .await
-        .context("Failed to load config")?;
+    let (config, config_state) = load_config().await?;
+    // Cached thesauri are dropped when their knowledge graph files change
+    if let Err(e) = watch_sources(&config) {
+        log::warn!("Failed to watch thesaurus sources: {e}");
+    }
+    // Cached copies of persisted documents are reloaded in the background
+    let _document_refresher = spawn_refresher(&config);
+    // Maintenance jobs and saved searches are run on their schedules
+    let _jobs = spawn_jobs(config_state.clone());
+    // Autocomplete indices are loaded, or built and persisted, up front
+    let _autocomplete = spawn_warmup(config_state.clone());
 
     // Example of adding a role for testing
     // let role = "system operator2".to_string();
@@ -82,7 +370,7 @@ async fn run_server() -> Result<()> {
     //     config_state.roles.keys().collect::<Vec<&String>>()
     // );
 
-    axum_server(server_hostname, config_state).await?;
-
-    Ok(())
+    let result = axum_server(server_hostname, config_state).await;
+    terraphim_server::shutdown_tracing();
+    result
 }
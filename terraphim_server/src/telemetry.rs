@@ -0,0 +1,159 @@
+//! Tracing setup for the server.
+//!
+//! All crates log through `log` or `tracing`; both end up in a single
+//! `tracing` subscriber which is filtered by `RUST_LOG` (default `info`).
+//!
+//! The filter can be changed at runtime with [`set_log_filter`], e.g. to
+//! raise the verbosity of `terraphim_rolegraph` while debugging a
+//! production instance.
+//!
+//! With the `otel` feature enabled and `OTEL_EXPORTER_OTLP_ENDPOINT` set,
+//! spans are additionally exported to an OpenTelemetry collector via OTLP.
+//! Incoming W3C `traceparent` headers are honoured, so a search can be
+//! followed from the caller into the service, middleware and persistence
+//! layers.
+
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use tracing::Span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::error::ApiError;
+use crate::Result;
+
+/// Filter used if `RUST_LOG` is not set
+const DEFAULT_LOG_FILTER: &str = "info";
+
+/// Handle to swap the filter of the global subscriber
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Header carrying the request ID, set by the server if the caller didn't
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Initialize the global tracing subscriber
+///
+/// This also captures records emitted through the `log` crate.
+pub fn init_tracing() -> Result<()> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let (filter, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer());
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel::layer()?);
+
+    registry.try_init()?;
+    let _ = FILTER_HANDLE.set(handle);
+    Ok(())
+}
+
+/// Return the current log filter directives
+pub fn log_filter() -> Result<String> {
+    let handle = FILTER_HANDLE
+        .get()
+        .ok_or_else(|| anyhow!("Tracing is not initialized"))?;
+    Ok(handle.with_current(|filter| filter.to_string())?)
+}
+
+/// Replace the log filter of the running server
+///
+/// `directives` uses the `RUST_LOG` syntax, e.g.
+/// `info,terraphim_rolegraph=debug`. Returns the new filter.
+pub fn set_log_filter(directives: &str) -> Result<String> {
+    let handle = FILTER_HANDLE
+        .get()
+        .ok_or_else(|| anyhow!("Tracing is not initialized"))?;
+    let filter =
+        EnvFilter::try_new(directives).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.into()))?;
+    handle.reload(filter)?;
+    log::info!("Log filter changed to `{directives}`");
+    log_filter()
+}
+
+/// Flush and shut down the span exporter (if any)
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Create the root span for an HTTP request
+///
+/// The span carries the request ID so that every log line emitted while
+/// handling the request can be correlated.
+pub(crate) fn make_request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "http_request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = %request_id,
+    );
+
+    #[cfg(feature = "otel")]
+    otel::set_parent_from_headers(&span, request.headers());
+
+    span
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use axum::http::HeaderMap;
+    use opentelemetry::KeyValue;
+    use opentelemetry_http::HeaderExtractor;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+    use tracing::{Span, Subscriber};
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    use crate::Result;
+
+    /// Environment variable with the OTLP collector endpoint
+    const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+    /// Build the OpenTelemetry layer if an OTLP endpoint is configured
+    pub(super) fn layer<S>() -> Result<Option<OpenTelemetryLayer<S, trace::Tracer>>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let Ok(endpoint) = std::env::var(OTLP_ENDPOINT_ENV) else {
+            return Ok(None);
+        };
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let tracer =
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+                ])))
+                .install_batch(runtime::Tokio)?;
+
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    /// Continue the trace of the caller if it sent a `traceparent` header
+    pub(super) fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(headers))
+        });
+        span.set_parent(parent);
+    }
+}
@@ -5,19 +5,29 @@
 #[cfg(test)]
 mod tests {
     use ahash::AHashMap;
-    use terraphim_automata::AutomataPath;
-    use terraphim_server::{axum_server, CreateDocumentResponse, SearchResponse, Status};
+    use terraphim_automata::{AutomataPath, CompactThesaurus};
+    use terraphim_server::{
+        axum_server, CreateDocumentResponse, SearchResponse, SessionResponse, Status,
+        API_KEY_HEADER, SESSION_HEADER,
+    };
     use terraphim_settings::DeviceSettings;
 
     use reqwest::{Client, StatusCode};
     use std::{net::SocketAddr, path::PathBuf, time::Duration};
     use terraphim_config::{
-        Config, ConfigBuilder, ConfigState, Haystack, KnowledgeGraph, KnowledgeGraphLocal, Role,
-        ServiceType,
+        Config, ConfigBuilder, ConfigState, Freshness, Haystack, JobKind, KnowledgeGraph,
+        KnowledgeGraphLocal, Principal, Role, ServiceType,
+    };
+    use terraphim_types::{Document, KnowledgeGraphInputType, RelevanceFunction, RoleName};
+
+    use terraphim_server::{
+        AnalyticsResponse, AttachmentResponse, BacklinksResponse, ConceptBacklinksResponse,
+        ConfigResponse, CoverageResponse, JobRunResponse, JobRunsResponse, JobsResponse,
+        LogFilterResponse, ParseQueryResponse, RoleGraphResponse, SavedSearchResponse,
+        SavedSearchesResponse, SearchPageResponse, SuggestResponse,
     };
-    use terraphim_types::{KnowledgeGraphInputType, RelevanceFunction, RoleName};
 
-    use terraphim_server::ConfigResponse;
+    use terraphim_service::suggest::SuggestionSource;
 
     use serial_test::serial;
 
@@ -39,7 +49,14 @@ mod tests {
                     haystacks: vec![Haystack {
                         path: haystack.clone(),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -58,11 +75,30 @@ mod tests {
                         }),
                         public: true,
                         publish: true,
+                        include_terms: Vec::new(),
+                        exclude_terms: Vec::new(),
+                        min_term_length: 0,
+                        max_kg_terms: None,
+                        max_links_per_concept: None,
+                        max_links: None,
+                        link_mode: None,
+                        link_template: None,
+                        link_definitions: false,
+                        normalization: None,
+                        centrality: None,
+                        edge_decay: None,
                     }),
                     haystacks: vec![Haystack {
                         path: haystack.clone(),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -81,11 +117,30 @@ mod tests {
                         }),
                         public: true,
                         publish: true,
+                        include_terms: Vec::new(),
+                        exclude_terms: Vec::new(),
+                        min_term_length: 0,
+                        max_kg_terms: None,
+                        max_links_per_concept: None,
+                        max_links: None,
+                        link_mode: None,
+                        link_template: None,
+                        link_definitions: false,
+                        normalization: None,
+                        centrality: None,
+                        edge_decay: None,
                     }),
                     haystacks: vec![Haystack {
                         path: haystack.clone(),
                         service: ServiceType::Ripgrep,
+                        freshness: Freshness::default(),
+                        visibility: Vec::new(),
                     }],
+                    metadata_schema: Vec::new(),
+                    skip_stages: Vec::new(),
+                    scorer: None,
+                    hybrid_weights: None,
+                    scoring: None,
                     extra: AHashMap::new(),
                 },
             )
@@ -94,6 +149,9 @@ mod tests {
     }
 
     async fn start_server() -> SocketAddr {
+        // Only the first call installs the subscriber; later calls fail and
+        // keep the existing one
+        let _ = terraphim_server::init_tracing();
         let server_settings =
             DeviceSettings::load_from_env_and_file(None).expect("Failed to load settings");
         let server_hostname = server_settings
@@ -173,6 +231,100 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_by_page() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let query = serde_json::json!({
+            "search_term": "maintenance",
+            "role": "Default",
+            "limit": 1
+        });
+        let first: SearchPageResponse = client
+            .post(format!("http://{server}/documents/search/page"))
+            .json(&query)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(first.results.len(), 1);
+        assert!(first.total > 1);
+        let cursor = first.next_cursor.unwrap();
+
+        let mut request = query.clone();
+        request["cursor"] = serde_json::json!(cursor);
+        let second: SearchPageResponse = client
+            .post(format!("http://{server}/documents/search/page"))
+            .json(&request)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(second.total, first.total);
+        assert_ne!(second.results[0].id, first.results[0].id);
+
+        request["search_term"] = serde_json::json!("another search");
+        let response = client
+            .post(format!("http://{server}/documents/search/page"))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Empty pages would never reach the end of the results
+        let mut request = query.clone();
+        request["limit"] = serde_json::json!(0);
+        let response = client
+            .post(format!("http://{server}/documents/search/page"))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_stream() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/documents/search/stream?search_term=maintenance&role=Default"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        // The stream ends after the last result
+        let body = response.text().await.unwrap();
+        let documents: Vec<Document> = body
+            .split("\n\n")
+            .filter(|event| event.starts_with("event: document"))
+            .map(|event| {
+                let data = event.split_once("data: ").unwrap().1;
+                serde_json::from_str(data).unwrap()
+            })
+            .collect();
+        assert!(!documents.is_empty());
+        assert!(body.trim_end().ends_with("event: end\ndata:"));
+
+        let search: SearchResponse = reqwest::get(format!(
+            "http://{server}/documents/search?search_term=maintenance&role=Default"
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert_eq!(documents.len(), search.results.len());
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_search_documents() {
@@ -226,6 +378,9 @@ mod tests {
             //     Some(vec!["trained operators and maintainers".to_string()])
             // );
             assert!(document.url.contains("fixtures/haystack/"));
+            assert!(document
+                .score
+                .is_some_and(|score| (0.0..=1.0).contains(&score)));
         }
     }
 
@@ -311,6 +466,1194 @@ mod tests {
         assert_eq!(new_config.config.global_shortcut, "Ctrl+P");
     }
 
+    /// Search results cached before a config update aren't returned after it
+    #[tokio::test]
+    #[serial]
+    async fn test_update_config_haystack() {
+        let server = ensure_server_started().await;
+        let config_url = format!("http://{server}/config");
+        let search_url =
+            format!("http://{server}/documents/search?search_term=zanzibar&role=Default");
+        let search = || async {
+            let response: SearchResponse = reqwest::get(&search_url)
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            response.results
+        };
+        assert!(search().await.is_empty());
+
+        let haystack = tempfile::tempdir().unwrap();
+        std::fs::write(
+            haystack.path().join("travel.md"),
+            "# Travel\n\nNotes on a trip to zanzibar.\n",
+        )
+        .unwrap();
+        let orig_config: ConfigResponse = reqwest::get(&config_url)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let mut new_config = orig_config.config.clone();
+        new_config
+            .roles
+            .get_mut(&RoleName::new("Default"))
+            .unwrap()
+            .haystacks[0]
+            .path = haystack.path().to_path_buf();
+        let client = Client::new();
+        let response = client
+            .post(&config_url)
+            .json(&new_config)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let results = search().await;
+
+        let response = client
+            .post(&config_url)
+            .json(&orig_config.config)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(results.len(), 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_get_rolegraph() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/rolegraph?role=System%20Operator&center=trained%20operators&depth=2"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: RoleGraphResponse = response.json().await.unwrap();
+        assert!(matches!(response.status, Status::Success));
+        // Every edge must connect two nodes which are part of the response
+        for edge in &response.graph.edges {
+            assert!(response.graph.nodes.iter().any(|n| n.id == edge.source));
+            assert!(response.graph.nodes.iter().any(|n| n.id == edge.target));
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_query_analytics() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/documents/search?search_term=trained%20operators&role=System%20Operator"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = reqwest::get(format!("http://{server}/analytics/queries?limit=5"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: AnalyticsResponse = response.json().await.unwrap();
+        assert!(matches!(response.status, Status::Success));
+        assert!(response.report.total_queries >= 1);
+        assert!(response.report.top_queries.len() <= 5);
+        assert!(response
+            .report
+            .latency
+            .iter()
+            .any(|l| l.role == RoleName::new("System Operator")));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_suggest() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/roles/System%20Operator/suggest?q=trained&limit=5"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: SuggestResponse = response.json().await.unwrap();
+        assert!(matches!(response.status, Status::Success));
+        assert!(response.suggestions.len() <= 5);
+        assert!(response
+            .suggestions
+            .iter()
+            .any(|suggestion| suggestion.term.starts_with("trained")));
+
+        // Recent searches are only suggested to the session that made them
+        let client = Client::new();
+        let response = client
+            .get(format!(
+                "http://{server}/documents/search?search_term=trained%20operators&role=System%20Operator"
+            ))
+            .header(SESSION_HEADER, "suggest-bob")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let suggest_url = format!("http://{server}/roles/System%20Operator/suggest?q=trained%20op");
+        let history = |response: SuggestResponse| {
+            response
+                .suggestions
+                .into_iter()
+                .filter(|suggestion| suggestion.source == SuggestionSource::History)
+                .map(|suggestion| suggestion.term)
+                .collect::<Vec<_>>()
+        };
+        let response: SuggestResponse = client
+            .get(&suggest_url)
+            .header(SESSION_HEADER, "suggest-bob")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(history(response), vec!["trained operators"]);
+        for session in [Some("suggest-eve"), None] {
+            let mut request = client.get(&suggest_url);
+            if let Some(session) = session {
+                request = request.header(SESSION_HEADER, session);
+            }
+            let response: SuggestResponse = request.send().await.unwrap().json().await.unwrap();
+            assert!(history(response).is_empty());
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_automata() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!("http://{server}/roles/System%20Operator/automata"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/octet-stream"
+        );
+
+        let bytes = response.bytes().await.unwrap();
+        let thesaurus = CompactThesaurus::from_bytes(&bytes).unwrap();
+        assert!(!thesaurus.is_empty());
+        assert!(!thesaurus.autocomplete("trained", 5).is_empty());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_coverage_report() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!("http://{server}/roles/System%20Operator/coverage"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: CoverageResponse = response.json().await.unwrap();
+        assert!(matches!(response.status, Status::Success));
+        let report = response.report;
+        assert!(report.terms >= report.concepts);
+        assert!(report.unmatched_terms.len() <= report.terms);
+        assert!(report.documents_without_concepts.len() <= report.documents);
+        for concept in &report.matched_concepts {
+            assert!(concept.documents <= report.documents);
+            assert!(concept.documents <= concept.matches);
+        }
+        for haystack in &report.haystacks {
+            assert!(haystack.documents_with_concepts <= haystack.documents);
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backlinks() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/roles/System%20Operator/backlinks?target=Maintenance"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: BacklinksResponse = response.json().await.unwrap();
+        assert!(matches!(response.status, Status::Success));
+        let titles: Vec<&str> = response
+            .backlinks
+            .iter()
+            .map(|backlink| backlink.title.as_str())
+            .collect();
+        assert!(titles.contains(&"Operation"));
+        assert!(titles.contains(&"System Operator"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_concept_backlinks() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/roles/System%20Operator/concepts/maintenance/backlinks"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: ConceptBacklinksResponse = response.json().await.unwrap();
+        assert!(matches!(response.status, Status::Success));
+        assert!(!response.backlinks.is_empty());
+        for backlink in &response.backlinks {
+            assert!(backlink.mentions + backlink.links.len() > 0);
+        }
+        let titles: Vec<&str> = response
+            .backlinks
+            .iter()
+            .map(|backlink| backlink.title.as_str())
+            .collect();
+        assert!(titles.contains(&"Operation"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_record_interaction() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let response = client
+            .post(format!("http://{server}/analytics/interactions"))
+            .json(&serde_json::json!({
+                "role": "System Operator",
+                "search_term": "trained operators",
+                "document_id": "some-document",
+                "kind": "open",
+                "rank": 1
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response: AnalyticsResponse =
+            reqwest::get(format!("http://{server}/analytics/queries"))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert!(response.report.quality.opens >= 1);
+
+        let response = client
+            .post(format!("http://{server}/analytics/interactions"))
+            .json(&serde_json::json!({
+                "role": "System Operator",
+                "search_term": "trained operators",
+                "document_id": "some-document",
+                "kind": "bookmark"
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_client_error());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_saved_searches() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let response = client
+            .post(format!("http://{server}/saved-searches"))
+            .json(&serde_json::json!({
+                "name": "Operators",
+                "query": {
+                    "search_term": "trained operators",
+                    "role": "System Operator"
+                },
+                "interval": 1
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let saved_search = response
+            .json::<SavedSearchResponse>()
+            .await
+            .unwrap()
+            .saved_search;
+        assert!(!saved_search.id.is_empty());
+        // Searches can't run more often than every minute
+        assert_eq!(saved_search.interval, 60);
+
+        let response: SavedSearchesResponse = reqwest::get(format!(
+            "http://{server}/saved-searches?role=System%20Operator"
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert!(response
+            .saved_searches
+            .iter()
+            .any(|search| search.id == saved_search.id));
+
+        let url = format!("http://{server}/saved-searches/{}", saved_search.id);
+        let run_url = format!("{url}/run");
+        let response = client.post(&run_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SearchResponse = response.json().await.unwrap();
+        assert_eq!(response.total, response.results.len());
+
+        let response = client.delete(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.delete(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = client.post(&run_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Saved searches of unknown roles are rejected
+        let response = client
+            .post(format!("http://{server}/saved-searches"))
+            .json(&serde_json::json!({
+                "name": "Unknown",
+                "query": { "search_term": "operators", "role": "No Such Role" },
+                "interval": 60
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_access_control() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let config_url = format!("http://{server}/config");
+        let orig_config: ConfigResponse = reqwest::get(&config_url)
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let mut config = orig_config.config.clone();
+        config.principals = vec![
+            Principal {
+                name: "admin".to_string(),
+                api_key: "admin-key".to_string(),
+                labels: Vec::new(),
+                admin: true,
+            },
+            Principal {
+                name: "reader".to_string(),
+                api_key: "reader-key".to_string(),
+                labels: vec!["public".to_string()],
+                admin: false,
+            },
+        ];
+        for haystack in &mut config
+            .roles
+            .get_mut(&RoleName::new("System Operator"))
+            .unwrap()
+            .haystacks
+        {
+            haystack.visibility = vec!["internal".to_string()];
+        }
+        let response = client.post(&config_url).json(&config).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Principals are only shown to admins
+        let response: ConfigResponse = client
+            .get(&config_url)
+            .header(API_KEY_HEADER, "reader-key")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(response.config.principals.is_empty());
+
+        let response = client
+            .get(format!("http://{server}/analytics/queries"))
+            .header(API_KEY_HEADER, "reader-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = client
+            .get(format!("http://{server}/analytics/queries"))
+            .bearer_auth("wrong-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // The knowledge graph of a role with hidden haystacks is forbidden
+        let response = client
+            .get(format!(
+                "http://{server}/roles/System%20Operator/backlinks?target=Maintenance"
+            ))
+            .header(API_KEY_HEADER, "reader-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Nor may interactions be recorded for it
+        let response = client
+            .post(format!("http://{server}/analytics/interactions"))
+            .header(API_KEY_HEADER, "reader-key")
+            .json(&serde_json::json!({
+                "role": "System Operator",
+                "search_term": "maintenance",
+                "document_id": "document",
+                "kind": "open"
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Nor are corrections from its vocabulary suggested
+        let search = |api_key: &'static str| {
+            client
+                .get(format!(
+                    "http://{server}/documents/search?search_term=maintenanse&role=System%20Operator"
+                ))
+                .header(API_KEY_HEADER, api_key)
+                .send()
+        };
+        let response: SearchResponse = search("admin-key").await.unwrap().json().await.unwrap();
+        assert!(response.did_you_mean.is_some());
+        let response: SearchResponse = search("reader-key").await.unwrap().json().await.unwrap();
+        assert!(response.results.is_empty());
+        assert!(response.did_you_mean.is_none());
+
+        // Attachments are visible with the documents they are attached to
+        let mut attachments = Vec::new();
+        for visibility in ["public", "internal"] {
+            let uploaded: AttachmentResponse = client
+                .post(format!("http://{server}/attachments?name={visibility}.txt"))
+                .header(API_KEY_HEADER, "admin-key")
+                .header("Content-Type", "text/plain")
+                .body(format!("{visibility} notes of the access test"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let response = client
+                .post(format!("http://{server}/documents"))
+                .header(API_KEY_HEADER, "admin-key")
+                .json(&serde_json::json!({
+                    "id": format!("{visibility}-document-with-attachment"),
+                    "title": "Notes",
+                    "body": "Notes for operators",
+                    "url": "notes.md",
+                    "visibility": [visibility],
+                    "attachments": [uploaded.attachment]
+                }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            attachments.push(uploaded.attachment);
+        }
+        for (attachment, status) in attachments
+            .iter()
+            .zip([StatusCode::OK, StatusCode::FORBIDDEN])
+        {
+            let response = client
+                .get(format!(
+                    "http://{server}/attachments/{}?id={}",
+                    attachment.blob_id, attachment.id
+                ))
+                .header(API_KEY_HEADER, "reader-key")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        // Restore the config without principals
+        let response = client
+            .post(&config_url)
+            .bearer_auth("admin-key")
+            .json(&orig_config.config)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_sessions() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let session_url = format!("http://{server}/session");
+
+        let response = client.get(&session_url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = client
+            .get(&session_url)
+            .header(SESSION_HEADER, "a".repeat(129))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response: SessionResponse = client
+            .post(&session_url)
+            .header(SESSION_HEADER, "alice")
+            .json(&serde_json::json!({
+                "selected_role": "Engineer",
+                "preferences": {"theme": "darkly"}
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.session.selected_role, Some("Engineer".into()));
+        assert_eq!(response.session.preferences["theme"], "darkly");
+
+        // Searches without role run as the selected role of the session
+        let response = client
+            .get(format!(
+                "http://{server}/documents/search?search_term=trained"
+            ))
+            .header(SESSION_HEADER, "alice")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SessionResponse = client
+            .get(&session_url)
+            .header(SESSION_HEADER, "alice")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.session.recent_queries, vec!["trained"]);
+
+        // Other sessions keep the default role
+        let response: SessionResponse = client
+            .get(&session_url)
+            .header(SESSION_HEADER, "bob")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.session.selected_role, None);
+
+        let response = client
+            .post(&session_url)
+            .header(SESSION_HEADER, "alice")
+            .json(&serde_json::json!({"selected_role": "Unknown"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_jobs() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+
+        let response: JobsResponse = client
+            .get(format!("http://{server}/admin/jobs"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let saved_searches = response
+            .jobs
+            .iter()
+            .find(|job| job.job == JobKind::SavedSearches)
+            .unwrap();
+        assert_eq!(saved_searches.interval, Some(15));
+
+        let response: JobRunResponse = client
+            .post(format!("http://{server}/admin/jobs/saved_searches/run"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response.run.job, JobKind::SavedSearches);
+        assert!(response.run.error.is_none());
+
+        let response: JobRunsResponse = client
+            .get(format!("http://{server}/admin/jobs/saved_searches/runs"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(!response.runs.is_empty());
+
+        let response = client
+            .post(format!("http://{server}/admin/jobs/unknown/run"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_update_log_filter() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let response = client
+            .post(format!("http://{server}/admin/log-filter"))
+            .json(&serde_json::json!({ "filter": "info,terraphim_rolegraph=debug" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: LogFilterResponse = response.json().await.unwrap();
+        assert!(response.filter.contains("terraphim_rolegraph=debug"));
+
+        let response: LogFilterResponse = reqwest::get(format!("http://{server}/admin/log-filter"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(response.filter.contains("terraphim_rolegraph=debug"));
+
+        let response = client
+            .post(format!("http://{server}/admin/log-filter"))
+            .json(&serde_json::json!({ "filter": "terraphim_rolegraph=loud" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_document_attachment() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let content = b"%PDF-1.4 attachment test".to_vec();
+        let response = client
+            .post(format!("http://{server}/attachments?name=manual.pdf"))
+            .header("Content-Type", "application/pdf")
+            .body(content.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: AttachmentResponse = response.json().await.unwrap();
+        let attachment = response.attachment;
+        assert_eq!(attachment.name, "manual.pdf");
+        assert_eq!(attachment.mime_type, "application/pdf");
+        assert_eq!(attachment.size, content.len() as u64);
+
+        let response = client
+            .post(format!("http://{server}/documents"))
+            .json(&serde_json::json!({
+                "id": "document-with-attachment",
+                "title": "Manual",
+                "body": "Operating manual for trained operators",
+                "url": "manual.md",
+                "attachments": [{
+                    "name": "",
+                    "mime_type": "",
+                    "size": 0,
+                    "blob_id": attachment.blob_id,
+                    "id": attachment.id
+                }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = reqwest::get(format!(
+            "http://{server}/attachments/{}?id={}",
+            attachment.blob_id, attachment.id
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"].to_str().unwrap(),
+            "application/pdf"
+        );
+        assert_eq!(
+            response.headers()["content-disposition"].to_str().unwrap(),
+            "attachment; filename=\"manual.pdf\""
+        );
+        assert_eq!(
+            response.headers()["x-content-type-options"]
+                .to_str()
+                .unwrap(),
+            "nosniff"
+        );
+        assert_eq!(response.bytes().await.unwrap().to_vec(), content);
+
+        let response = reqwest::get(format!("http://{server}/attachments/not-a-blob"))
+            .await
+            .unwrap();
+        assert!(!response.status().is_success());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_by_language() {
+        let server = ensure_server_started().await;
+        let response = reqwest::get(format!(
+            "http://{server}/documents/search?search_term=trained%20operators&role=Default&language=en"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SearchResponse = response.json().await.unwrap();
+        assert!(!response.results.is_empty());
+        assert!(response
+            .results
+            .iter()
+            .all(|document| document.language.as_deref() == Some("en")));
+
+        let response: SearchResponse = reqwest::get(format!(
+            "http://{server}/documents/search?search_term=trained%20operators&role=Default&language=nl"
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert!(response.results.is_empty());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_by_facets() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let response: SearchResponse = client
+            .post(format!("http://{server}/documents/search"))
+            .json(&serde_json::json!({"search_term": "maintenance", "role": "Default"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let haystack = &response.facets.source_haystack[0];
+        assert!(haystack.count <= response.total);
+
+        let narrowed: SearchResponse = client
+            .post(format!("http://{server}/documents/search"))
+            .json(&serde_json::json!({
+                "search_term": "maintenance",
+                "role": "Default",
+                "facets": {"source_haystack": [haystack.value]}
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(narrowed.total, haystack.count);
+        assert!(narrowed.results.iter().all(|document| document
+            .sources
+            .iter()
+            .any(|source| source.haystack == haystack.value)));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_by_time() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let search = |query: serde_json::Value| {
+            let client = client.clone();
+            async move {
+                let response: SearchResponse = client
+                    .post(format!("http://{server}/documents/search"))
+                    .json(&query)
+                    .send()
+                    .await
+                    .unwrap()
+                    .json()
+                    .await
+                    .unwrap();
+                response.results
+            }
+        };
+
+        let results = search(serde_json::json!({
+            "search_term": "maintenance",
+            "role": "Default",
+            "sort_by": "modified"
+        }))
+        .await;
+        assert!(results.len() > 1);
+        let changed: Vec<u64> = results
+            .iter()
+            .map(|document| document.last_changed().unwrap())
+            .collect();
+        assert!(changed.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let recent = search(serde_json::json!({
+            "search_term": "maintenance",
+            "role": "Default",
+            "after": changed[0]
+        }))
+        .await;
+        assert!(!recent.is_empty());
+        assert!(recent
+            .iter()
+            .all(|document| document.last_changed().unwrap() >= changed[0]));
+        let older = search(serde_json::json!({
+            "search_term": "maintenance",
+            "role": "Default",
+            "before": changed[0]
+        }))
+        .await;
+        assert_eq!(recent.len() + older.len(), results.len());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_excluding_terms() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let search = |query: serde_json::Value| {
+            let client = client.clone();
+            async move {
+                let response: SearchResponse = client
+                    .post(format!("http://{server}/documents/search"))
+                    .json(&query)
+                    .send()
+                    .await
+                    .unwrap()
+                    .json()
+                    .await
+                    .unwrap();
+                response.results
+            }
+        };
+
+        let results = search(serde_json::json!({
+            "search_term": "maintenance",
+            "role": "Default"
+        }))
+        .await;
+        let excluded = search(serde_json::json!({
+            "search_term": "maintenance",
+            "role": "Default",
+            "exclude_terms": ["operators"]
+        }))
+        .await;
+        assert!(excluded.len() < results.len());
+        assert!(excluded.iter().all(|document| {
+            !document.title.to_lowercase().contains("operators")
+                && !document.body.to_lowercase().contains("operators")
+        }));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_in_haystacks() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let url = format!("http://{server}/documents/search");
+        let results = client
+            .post(&url)
+            .json(&serde_json::json!({"search_term": "maintenance", "role": "Default"}))
+            .send()
+            .await
+            .unwrap()
+            .json::<SearchResponse>()
+            .await
+            .unwrap()
+            .results;
+        let haystack = results[0].sources[0].haystack.clone();
+
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "search_term": "maintenance",
+                "role": "Default",
+                "haystacks": [haystack]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SearchResponse = response.json().await.unwrap();
+        assert_eq!(response.total, results.len());
+
+        // Haystacks the role doesn't have are rejected
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "search_term": "maintenance",
+                "role": "Default",
+                "haystacks": ["/no/such/haystack"]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_feedback() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let url = format!("http://{server}/documents/search");
+        let query = serde_json::json!({"search_term": "maintenance", "role": "Default"});
+        let search = |client: Client| {
+            let request = client.post(&url).json(&query);
+            async move {
+                request
+                    .send()
+                    .await
+                    .unwrap()
+                    .json::<SearchResponse>()
+                    .await
+                    .unwrap()
+                    .results
+            }
+        };
+        let results = search(client.clone()).await;
+        let clicked = results.last().unwrap().clone();
+
+        for _ in 0..3 {
+            let response = client
+                .post(format!("http://{server}/feedback"))
+                .json(&serde_json::json!({
+                    "role": "Default",
+                    "search_term": "maintenance",
+                    "document_id": clicked.id,
+                    "kind": "open"
+                }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // Feedback on documents that were never returned is rejected
+        let response = client
+            .post(format!("http://{server}/feedback"))
+            .json(&serde_json::json!({
+                "role": "Default",
+                "search_term": "maintenance",
+                "document_id": "no-such-document",
+                "kind": "open"
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // The clicked document ranks higher than before
+        let results = search(client).await;
+        let document = results.iter().find(|d| d.id == clicked.id).unwrap();
+        assert!(document.rank > clicked.rank);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_diversified() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let url = format!("http://{server}/documents/search");
+        let search = |mmr_lambda: Option<f64>| {
+            client
+                .post(&url)
+                .json(&serde_json::json!({
+                    "search_term": "maintenance",
+                    "role": "Default",
+                    "mmr_lambda": mmr_lambda
+                }))
+                .send()
+        };
+        let ranked: SearchResponse = search(None).await.unwrap().json().await.unwrap();
+        let response = search(Some(0.5)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let diversified: SearchResponse = response.json().await.unwrap();
+
+        // The same documents, the most relevant first
+        assert_eq!(diversified.total, ranked.total);
+        assert_eq!(diversified.results[0].id, ranked.results[0].id);
+        let mut ranked_ids: Vec<String> = ranked.results.into_iter().map(|d| d.id).collect();
+        let mut diversified_ids: Vec<String> =
+            diversified.results.into_iter().map(|d| d.id).collect();
+        ranked_ids.sort();
+        diversified_ids.sort();
+        assert_eq!(diversified_ids, ranked_ids);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_regex() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let url = format!("http://{server}/documents/search");
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "search_term": "maint[a-z]+nce",
+                "role": "Default",
+                "query_type": "regex"
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SearchResponse = response.json().await.unwrap();
+        assert!(!response.results.is_empty());
+        assert!(response.results.iter().all(|document| {
+            document.title.to_lowercase().contains("maintenance")
+                || document.body.to_lowercase().contains("maintenance")
+        }));
+
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "search_term": "(maintenance",
+                "role": "Default",
+                "query_type": "regex"
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Patterns are never taken for flags of ripgrep
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "search_term": "--pre=/bin/sh",
+                "role": "Default",
+                "query_type": "regex"
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_query() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let response = client
+            .get(format!("http://{server}/documents/search/parse"))
+            .query(&[(
+                "q",
+                "maintenance AND (operators OR safety) -trained tag:rust",
+            )])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let parsed: ParseQueryResponse = response.json().await.unwrap();
+        assert_eq!(
+            parsed.query.search_term.as_str(),
+            "maintenance operators safety"
+        );
+        assert_eq!(parsed.query.exclude_terms.len(), 1);
+        assert_eq!(parsed.query.facets.tags, vec!["rust"]);
+
+        let response = client
+            .get(format!("http://{server}/documents/search/query"))
+            .query(&[("q", "maintenance AND operators"), ("role", "Default")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SearchResponse = response.json().await.unwrap();
+        assert!(!response.results.is_empty());
+        assert!(response.results.iter().all(|document| {
+            let text = format!("{} {}", document.title, document.body).to_lowercase();
+            text.contains("maintenance") && text.contains("operators")
+        }));
+
+        let response = client
+            .get(format!("http://{server}/documents/search/query"))
+            .query(&[("q", "(maintenance OR operators")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search_documents_by_metadata() {
+        let server = ensure_server_started().await;
+        let client = Client::new();
+        let response = client
+            .post(format!("http://{server}/documents/search"))
+            .json(&serde_json::json!({
+                "search_term": "maintenance",
+                "role": "Default",
+                "metadata": [{ "key": "type", "value": "Business function" }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: SearchResponse = response.json().await.unwrap();
+        assert!(!response.results.is_empty());
+        assert!(response
+            .results
+            .iter()
+            .all(|document| document.extra["type"] == "Business function"));
+
+        let response: SearchResponse = client
+            .post(format!("http://{server}/documents/search"))
+            .json(&serde_json::json!({
+                "search_term": "maintenance",
+                "role": "Default",
+                "metadata": [{ "key": "type", "op": "ne", "value": "Business function" }]
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(response
+            .results
+            .iter()
+            .all(|document| document.extra.get("type") != Some(&"Business function".into())));
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_create_document() {